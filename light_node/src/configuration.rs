@@ -12,9 +12,14 @@ use std::time::Duration;
 
 use clap::{App, Arg};
 
+use crypto::hash::HashType;
+
+use networking::p2p::peer::PeerTimeouts;
+use shell::configuration::{MempoolConfiguration, ShellConfiguration};
 use shell::peer_manager::P2p;
-use shell::PeerConnectionThreshold;
+use shell::{PeerConnectionThreshold, PeerRoles};
 use storage::persistent::{DbConfiguration, DbConfigurationBuilder};
+use tezos_messages::Head;
 use tezos_api::environment;
 use tezos_api::environment::TezosEnvironment;
 use tezos_api::ffi::PatchContext;
@@ -41,6 +46,8 @@ pub struct Storage {
     pub tezos_data_dir: PathBuf,
     pub store_context_actions: bool,
     pub patch_context: Option<PatchContext>,
+    /// Trusted checkpoint, chain is bootstrapped just back to this block
+    pub checkpoint: Option<Head>,
 }
 
 #[derive(Debug, Clone)]
@@ -90,6 +97,8 @@ pub struct Environment {
     pub storage: Storage,
     pub identity: Identity,
     pub ffi: Ffi,
+    pub shell: ShellConfiguration,
+    pub mempool: MempoolConfiguration,
 
     pub tezos_network: TezosEnvironment,
     pub enable_testchain: bool,
@@ -220,6 +229,24 @@ pub fn tezos_app() -> App<'static, 'static> {
             .takes_value(true)
             .value_name("BOOL")
             .help("Enable or disable mempool"))
+        .arg(Arg::with_name("mempool-max-operations")
+            .long("mempool-max-operations")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Max number of operations in the mempool, operations with the lowest priority are dropped, default: 10000")
+            .validator(parse_validator_fn!(usize, "Value must be a valid number")))
+        .arg(Arg::with_name("mempool-max-bytes")
+            .long("mempool-max-bytes")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Max size of operations in the mempool in bytes, operations with the lowest priority are dropped, default: 16777216 means 16MB")
+            .validator(parse_validator_fn!(usize, "Value must be a valid number")))
+        .arg(Arg::with_name("mempool-max-refused-operations")
+            .long("mempool-max-refused-operations")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Max number of the recently refused operations, which are remembered (also across restarts), so they are not downloaded and validated again, default: 1000")
+            .validator(parse_validator_fn!(usize, "Value must be a valid number")))
         .arg(Arg::with_name("private-node")
             .long("private-node")
             .takes_value(true)
@@ -227,6 +254,145 @@ pub fn tezos_app() -> App<'static, 'static> {
             .requires("peers")
             .conflicts_with("bootstrap-lookup-address")
             .help("Enable or disable private node. Use peers to set IP addresses of the peers you want to connect to"))
+        .arg(Arg::with_name("trusted-peers")
+            .long("trusted-peers")
+            .takes_value(true)
+            .value_name("IP:PORT")
+            .help("Trusted peers, which are always connected and never disconnected nor blacklisted. Whole address including port is compared. Peers are delimited by a colon. Format: IP1:PORT1,IP2:PORT2,IP3:PORT3")
+            .validator(|v| {
+                let err_count = v.split(',')
+                    .map(|ip_port| ip_port.parse::<SocketAddr>())
+                    .filter(|v| v.is_err())
+                    .count();
+                if err_count == 0 {
+                    Ok(())
+                } else {
+                    Err(format!("Value '{}' is not valid. Expected format is: IP1:PORT1,IP2:PORT2,IP3:PORT3", v))
+                }
+            }))
+        .arg(Arg::with_name("trusted-peer-ids")
+            .long("trusted-peer-ids")
+            .takes_value(true)
+            .value_name("PEER_ID")
+            .help("Peer ids (public key hashes) of the trusted peers, which can connect from any address. Trust is known just after the handshake. Peer ids are delimited by a colon. Format: ID1,ID2,ID3"))
+        .arg(Arg::with_name("peer-group")
+            .long("peer-group")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("NAME=IP:PORT,IP:PORT")
+            .help("Named group of the peers, addresses of the group members are advertised just to the other members of the group. Can be used multiple times. Format: NAME=IP1:PORT1,IP2:PORT2")
+            .validator(|v| parse_peer_group(&v).map(|_| ())))
+        .arg(Arg::with_name("sentry-node")
+            .long("sentry-node")
+            .takes_value(true)
+            .value_name("BOOL")
+            .help("Enable or disable sentry node. Sentry node never advertises addresses of the trusted peers (e.g. baker) to other peers"))
+        .arg(Arg::with_name("p2p-capture-file")
+            .long("p2p-capture-file")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("Path to the file, where all decrypted p2p messages are recorded. If not provided, messages are not recorded.
+                       In case it starts with ./ or ../, it is relative path to the current dir, otherwise to the --tezos-data-dir"))
+        .arg(Arg::with_name("p2p-connect-timeout-in-secs")
+            .long("p2p-connect-timeout-in-secs")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Number of seconds to wait for outgoing connection to be established, default: 8")
+            .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-handshake-timeout-in-secs")
+            .long("p2p-handshake-timeout-in-secs")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Number of seconds to wait for the whole handshake with the peer, default: 30")
+            .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-idle-timeout-in-secs")
+            .long("p2p-idle-timeout-in-secs")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Number of seconds after which is connection closed, if no message is received from the peer, default: 30")
+            .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-max-pending-incoming-handshakes")
+            .long("p2p-max-pending-incoming-handshakes")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Max number of incoming connections with handshake in progress, default: 20")
+            .validator(parse_validator_fn!(usize, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-max-pending-outgoing-handshakes")
+            .long("p2p-max-pending-outgoing-handshakes")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Max number of outgoing connections with handshake in progress, default: 20")
+            .validator(parse_validator_fn!(usize, "Value must be a valid number")))
+        .args(
+            &[
+                Arg::with_name("shell-block-headers-batch-size")
+                    .long("shell-block-headers-batch-size")
+                    .takes_value(true)
+                    .value_name("NUM")
+                    .help("Max number of blocks requested from the peer in a single message (1-10), default: 10")
+                    .validator(parse_validator_fn!(usize, "Value must be a valid number")),
+                Arg::with_name("shell-mempool-operations-batch-size")
+                    .long("shell-mempool-operations-batch-size")
+                    .takes_value(true)
+                    .value_name("NUM")
+                    .help("Max number of mempool operations requested from the peer in a batch (1-10), default: 10")
+                    .validator(parse_validator_fn!(usize, "Value must be a valid number")),
+                Arg::with_name("shell-check-chain-completeness-interval-in-secs")
+                    .long("shell-check-chain-completeness-interval-in-secs")
+                    .takes_value(true)
+                    .value_name("NUM")
+                    .help("Number of seconds between checks for missing blocks and operations, default: 30")
+                    .validator(parse_validator_fn!(u64, "Value must be a valid number")),
+                Arg::with_name("shell-ask-current-branch-interval-in-secs")
+                    .long("shell-ask-current-branch-interval-in-secs")
+                    .takes_value(true)
+                    .value_name("NUM")
+                    .help("Number of seconds between requests for current branch sent to all connected peers, default: 15")
+                    .validator(parse_validator_fn!(u64, "Value must be a valid number")),
+                Arg::with_name("shell-log-interval-in-secs")
+                    .long("shell-log-interval-in-secs")
+                    .takes_value(true)
+                    .value_name("NUM")
+                    .help("Number of seconds between logging of the chain manager stats, default: 60")
+                    .validator(parse_validator_fn!(u64, "Value must be a valid number")),
+                Arg::with_name("shell-current-head-level-update-timeout-in-secs")
+                    .long("shell-current-head-level-update-timeout-in-secs")
+                    .takes_value(true)
+                    .value_name("NUM")
+                    .help("Number of seconds after which is peer disconnected, if its current head level stays the same, default: 120")
+                    .validator(parse_validator_fn!(u64, "Value must be a valid number")),
+                Arg::with_name("shell-silent-peer-timeout-in-secs")
+                    .long("shell-silent-peer-timeout-in-secs")
+                    .takes_value(true)
+                    .value_name("NUM")
+                    .help("Number of seconds after which is peer disconnected, if it does not respond to our request, default: 30")
+                    .validator(parse_validator_fn!(u64, "Value must be a valid number")),
+                Arg::with_name("shell-stalled-chain-completeness-timeout-in-secs")
+                    .long("shell-stalled-chain-completeness-timeout-in-secs")
+                    .takes_value(true)
+                    .value_name("NUM")
+                    .help("Number of seconds after which is chain state rehydrated, if no new block is applied, default: 240")
+                    .validator(parse_validator_fn!(u64, "Value must be a valid number")),
+                Arg::with_name("shell-mempool-operation-ttl-in-secs")
+                    .long("shell-mempool-operation-ttl-in-secs")
+                    .takes_value(true)
+                    .value_name("NUM")
+                    .help("Number of seconds to wait for the requested mempool operation, default: 60")
+                    .validator(parse_validator_fn!(u64, "Value must be a valid number")),
+                Arg::with_name("shell-mempool-announce-peers")
+                    .long("shell-mempool-announce-peers")
+                    .takes_value(true)
+                    .value_name("NUM")
+                    .help("Max number of peers, to which new mempool operations are announced in a batch, default: 10")
+                    .validator(parse_validator_fn!(usize, "Value must be a valid number")),
+                Arg::with_name("shell-mempool-announce-interval-in-millis")
+                    .long("shell-mempool-announce-interval-in-millis")
+                    .takes_value(true)
+                    .value_name("NUM")
+                    .help("Number of milliseconds between batches of new mempool operations announced to the peers, default: 500")
+                    .validator(parse_validator_fn!(u64, "Value must be a valid number"))
+            ])
         .arg(Arg::with_name("network")
             .long("network")
             .takes_value(true)
@@ -393,7 +559,15 @@ pub fn tezos_app() -> App<'static, 'static> {
             .value_name("PATH")
             .required(false)
             .help("Path to the json file with key-values, which will be added to empty context on startup and commit genesis.")
-            .validator(|v| if Path::new(&v).exists() { Ok(()) } else { Err(format!("Sandbox patch-context json file not found at '{}'", v)) }));
+            .validator(|v| if Path::new(&v).exists() { Ok(()) } else { Err(format!("Sandbox patch-context json file not found at '{}'", v)) }))
+        .arg(Arg::with_name("checkpoint")
+            .long("checkpoint")
+            .takes_value(true)
+            .value_name("BLOCK_HASH,LEVEL")
+            .required(false)
+            .help("Trusted checkpoint, chain is bootstrapped just back to this block and branches contradicting the checkpoint are rejected.
+                       Blocks are applied from the checkpoint, so the checkpoint block with its metadata and context must be imported (e.g. from snapshot). Format: BLOCK_HASH,LEVEL")
+            .validator(|v| parse_checkpoint(&v).map(|_| ())));
     app
 }
 
@@ -423,6 +597,57 @@ fn pool_cfg(args: &clap::ArgMatches, pool_name_discriminator: &str) -> TezosApiC
     }
 }
 
+fn shell_cfg(args: &clap::ArgMatches) -> ShellConfiguration {
+    let default = ShellConfiguration::default();
+    let usize_arg = |name: &str, default: usize| args.value_of(name)
+        .map(|value| value.parse::<usize>().expect("Provided value cannot be converted to number"))
+        .unwrap_or(default);
+    let secs_arg = |name: &str, default: Duration| args.value_of(name)
+        .map(|value| value.parse::<u64>().map(Duration::from_secs).expect("Provided value cannot be converted to number"))
+        .unwrap_or(default);
+
+    let shell_cfg = ShellConfiguration {
+        block_headers_batch_size: usize_arg("shell-block-headers-batch-size", default.block_headers_batch_size),
+        mempool_operations_batch_size: usize_arg("shell-mempool-operations-batch-size", default.mempool_operations_batch_size),
+        check_chain_completeness_interval: secs_arg("shell-check-chain-completeness-interval-in-secs", default.check_chain_completeness_interval),
+        ask_current_branch_interval: secs_arg("shell-ask-current-branch-interval-in-secs", default.ask_current_branch_interval),
+        log_interval: secs_arg("shell-log-interval-in-secs", default.log_interval),
+        current_head_level_update_timeout: secs_arg("shell-current-head-level-update-timeout-in-secs", default.current_head_level_update_timeout),
+        silent_peer_timeout: secs_arg("shell-silent-peer-timeout-in-secs", default.silent_peer_timeout),
+        stalled_chain_completeness_timeout: secs_arg("shell-stalled-chain-completeness-timeout-in-secs", default.stalled_chain_completeness_timeout),
+        mempool_operation_ttl: secs_arg("shell-mempool-operation-ttl-in-secs", default.mempool_operation_ttl),
+        mempool_announce_peers: usize_arg("shell-mempool-announce-peers", default.mempool_announce_peers),
+        mempool_announce_interval: args.value_of("shell-mempool-announce-interval-in-millis")
+            .map(|value| value.parse::<u64>().map(Duration::from_millis).expect("Provided value cannot be converted to number"))
+            .unwrap_or(default.mempool_announce_interval),
+    };
+
+    if let Err(e) = shell_cfg.validate() {
+        panic!("Invalid shell configuration: {}", e);
+    }
+    shell_cfg
+}
+
+fn mempool_cfg(args: &clap::ArgMatches) -> MempoolConfiguration {
+    let default = MempoolConfiguration::default();
+    let mempool_cfg = MempoolConfiguration {
+        max_operations: args.value_of("mempool-max-operations")
+            .map(|value| value.parse::<usize>().expect("Provided value cannot be converted to number"))
+            .unwrap_or(default.max_operations),
+        max_bytes: args.value_of("mempool-max-bytes")
+            .map(|value| value.parse::<usize>().expect("Provided value cannot be converted to number"))
+            .unwrap_or(default.max_bytes),
+        max_refused_operations: args.value_of("mempool-max-refused-operations")
+            .map(|value| value.parse::<usize>().expect("Provided value cannot be converted to number"))
+            .unwrap_or(default.max_refused_operations),
+    };
+
+    if let Err(e) = mempool_cfg.validate() {
+        panic!("Invalid mempool configuration: {}", e);
+    }
+    mempool_cfg
+}
+
 // Explicitly validates all required parameters
 // Flag Required=true must be handled separately as we parse args twice,
 // once to see only if config-file arg is present and second time to parse all args
@@ -483,6 +708,38 @@ pub fn get_final_path(tezos_data_dir: &PathBuf, path: PathBuf) -> PathBuf {
     final_path
 }
 
+// Parses checkpoint in format: <block_hash>,<level>
+fn parse_checkpoint(value: &str) -> Result<Head, String> {
+    let mut parts = value.split(',');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(block_hash), Some(level), None) => {
+            let block_hash = HashType::BlockHash.string_to_bytes(block_hash.trim())
+                .map_err(|e| format!("Value '{}' is not valid block hash: {}", block_hash, e))?;
+            let level = level.trim().parse::<i32>()
+                .map_err(|e| format!("Value '{}' is not valid level: {}", level, e))?;
+            if level < 0 {
+                return Err(format!("Checkpoint level cannot be negative: {}", level));
+            }
+            // checkpoint does not have fitness
+            Ok(Head::new(block_hash, level, vec![]))
+        }
+        _ => Err(format!("Value '{}' is not valid. Expected format is: BLOCK_HASH,LEVEL", value))
+    }
+}
+
+// Parses peer group in format: <name>=<ip1:port1>,<ip2:port2>
+fn parse_peer_group(value: &str) -> Result<(String, Vec<SocketAddr>), String> {
+    let mut parts = value.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(members)) if !name.trim().is_empty() => members.split(',')
+            .map(|ip_port| ip_port.parse::<SocketAddr>())
+            .collect::<Result<Vec<_>, _>>()
+            .map(|members| (name.trim().to_string(), members))
+            .map_err(|_| format!("Value '{}' is not valid. Expected format is: NAME=IP1:PORT1,IP2:PORT2", value)),
+        _ => Err(format!("Value '{}' is not valid. Expected format is: NAME=IP1:PORT1,IP2:PORT2", value)),
+    }
+}
+
 // Parses config file and returns vector of OsString representing all argument strings from file
 // All lines that are empty or begin with "#" or "//" are ignored
 pub fn parse_config(config_path: PathBuf) -> Vec<OsString> {
@@ -597,10 +854,61 @@ impl Environment {
                     .unwrap_or("false")
                     .parse::<bool>()
                     .expect("Provided value cannot be converted to bool"),
+                peer_roles: PeerRoles::new(
+                    args.value_of("trusted-peers")
+                        .map(|peers_str| peers_str
+                            .split(',')
+                            .map(|ip_port| ip_port.parse().expect("Was expecting IP:PORT"))
+                            .collect()
+                        ).unwrap_or_default(),
+                    args.value_of("trusted-peer-ids")
+                        .map(|peer_ids_str| peer_ids_str
+                            .split(',')
+                            .map(|peer_id| peer_id.trim().to_string())
+                            .collect()
+                        ).unwrap_or_default(),
+                    args.values_of("peer-group")
+                        .map(|peer_groups| peer_groups
+                            .map(|peer_group| parse_peer_group(peer_group).expect("Was expecting NAME=IP:PORT,IP:PORT"))
+                            .collect()
+                        ).unwrap_or_default(),
+                    args.value_of("sentry-node")
+                        .unwrap_or("false")
+                        .parse::<bool>()
+                        .expect("Provided value cannot be converted to bool"),
+                ),
                 disable_mempool: args.value_of("disable-mempool")
                     .unwrap_or("false")
                     .parse::<bool>()
                     .expect("Provided value cannot be converted to bool"),
+                capture_file: args.value_of("p2p-capture-file")
+                    .map(|v| v.parse::<PathBuf>().expect("Provided value cannot be converted to path"))
+                    .map(|path| get_final_path(&data_dir, path)),
+                connect_timeout: args.value_of("p2p-connect-timeout-in-secs")
+                    .unwrap_or("8")
+                    .parse::<u64>()
+                    .map(Duration::from_secs)
+                    .expect("Provided value cannot be converted to number"),
+                peer_timeouts: PeerTimeouts {
+                    handshake: args.value_of("p2p-handshake-timeout-in-secs")
+                        .unwrap_or("30")
+                        .parse::<u64>()
+                        .map(Duration::from_secs)
+                        .expect("Provided value cannot be converted to number"),
+                    idle: args.value_of("p2p-idle-timeout-in-secs")
+                        .unwrap_or("30")
+                        .parse::<u64>()
+                        .map(Duration::from_secs)
+                        .expect("Provided value cannot be converted to number"),
+                },
+                max_pending_incoming_handshakes: args.value_of("p2p-max-pending-incoming-handshakes")
+                    .unwrap_or("20")
+                    .parse::<usize>()
+                    .expect("Provided value cannot be converted to number"),
+                max_pending_outgoing_handshakes: args.value_of("p2p-max-pending-outgoing-handshakes")
+                    .unwrap_or("20")
+                    .parse::<usize>()
+                    .expect("Provided value cannot be converted to number"),
             },
             rpc: crate::configuration::Rpc {
                 listener_port: args
@@ -691,6 +999,8 @@ impl Environment {
                         }
                     }
                 },
+                checkpoint: args.value_of("checkpoint")
+                    .map(|checkpoint| parse_checkpoint(checkpoint).expect("Provided value cannot be converted to checkpoint")),
             },
             identity: crate::configuration::Identity {
                 identity_json_file_path: {
@@ -719,6 +1029,8 @@ impl Environment {
                 tezos_readonly_prevalidation_api_pool: pool_cfg(&args, Ffi::TEZOS_READONLY_PREVALIDATION_API_POOL_DISCRIMINATOR),
                 tezos_without_context_api_pool: pool_cfg(&args, Ffi::TEZOS_WITHOUT_CONTEXT_API_POOL_DISCRIMINATOR),
             },
+            shell: shell_cfg(&args),
+            mempool: mempool_cfg(&args),
             tokio_threads: args.value_of("tokio-threads")
                 .unwrap_or("0")
                 .parse::<usize>()
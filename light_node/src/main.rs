@@ -14,15 +14,19 @@ use slog::{crit, debug, Drain, error, info, Logger};
 use logging::detailed_json;
 use logging::file::FileAppenderBuilder;
 use monitoring::{Monitor, WebsocketHandler};
+use networking::p2p::capture::CaptureWriter;
 use networking::p2p::network_channel::NetworkChannel;
 use rpc::rpc_actor::RpcServer;
 use shell::chain_feeder::ChainFeeder;
 use shell::chain_manager::ChainManager;
+use shell::clock::system_clock;
 use shell::context_listener::ContextListener;
+use shell::mempool_filter::MempoolFilter;
 use shell::mempool_prevalidator::MempoolPrevalidator;
 use shell::peer_manager::PeerManager;
 use shell::shell_channel::{ShellChannel, ShellChannelTopic, ShuttingDown};
-use storage::{block_storage, BlockMetaStorage, BlockStorage, ChainMetaStorage, check_database_compatibility, context_action_storage, ContextActionStorage, MempoolStorage, OperationsMetaStorage, OperationsStorage, resolve_storage_init_chain_data, StorageInitInfo, SystemStorage};
+use shell::stats::handshake::HandshakeStats;
+use storage::{block_storage, BlockMetaStorage, BlockStorage, ChainMetaStorage, check_database_compatibility, context_action_storage, ContextActionStorage, initialize_checkpoint, mempool_storage, MempoolStorage, OperationsMetaStorage, OperationsStorage, resolve_storage_init_chain_data, StorageInitInfo, SystemStorage};
 use storage::context::TezedgeContext;
 use storage::persistent::{CommitLogSchema, KeyValueSchema, open_cl, open_kv, PersistentStorage};
 use storage::persistent::sequence::Sequences;
@@ -30,7 +34,7 @@ use tezos_api::environment;
 use tezos_api::environment::TezosEnvironmentConfiguration;
 use tezos_api::ffi::TezosRuntimeConfiguration;
 use tezos_identity::Identity;
-use tezos_messages::p2p::encoding::version::NetworkVersion;
+use tezos_messages::p2p::encoding::version::SupportedNetworkVersions;
 use tezos_wrapper::{TezosApiConnectionPool, TezosApiConnectionPoolConfiguration};
 use tezos_wrapper::service::{ExecutableProtocolRunner, ProtocolEndpointConfiguration, ProtocolRunnerEndpoint};
 
@@ -41,8 +45,8 @@ mod identity;
 mod system;
 
 const DATABASE_VERSION: i64 = 16;
-const SUPPORTED_DISTRIBUTED_DB_VERSION: u16 = 0;
-const SUPPORTED_P2P_VERSION: u16 = 1;
+const SUPPORTED_DISTRIBUTED_DB_VERSIONS: &[u16] = &[0];
+const SUPPORTED_P2P_VERSIONS: &[u16] = &[0, 1];
 
 macro_rules! shutdown_and_exit {
     ($err:expr, $sys:ident) => {{
@@ -200,11 +204,12 @@ fn block_on_actors(
     // if feeding is started, than run chain manager
     let is_sandbox = env.tezos_network == environment::TezosEnvironment::Sandbox;
     // version
-    let network_version = NetworkVersion::new(
+    let supported_versions = SupportedNetworkVersions::new(
         tezos_env.version.clone(),
-        SUPPORTED_DISTRIBUTED_DB_VERSION,
-        SUPPORTED_P2P_VERSION,
+        SUPPORTED_DISTRIBUTED_DB_VERSIONS.to_vec(),
+        SUPPORTED_P2P_VERSIONS.to_vec(),
     );
+    let network_version = supported_versions.announced();
 
     // create pool for ffi protocol runner connections (used just for readonly context)
     let tezos_readonly_api_pool = Arc::new(create_tezos_readonly_api_pool(
@@ -272,6 +277,7 @@ fn block_on_actors(
         .expect("Failed to create context event listener");
     let _ = ChainFeeder::actor(&actor_system, shell_channel.clone(), &persistent_storage, &init_storage_data, &tezos_env, apply_block_protocol_commands, log.clone())
         .expect("Failed to create chain feeder");
+    let shell_config = env.shell.into_ref();
     let _ = ChainManager::actor(
         &actor_system,
         network_channel.clone(),
@@ -280,32 +286,52 @@ fn block_on_actors(
         tezos_readonly_prevalidation_api_pool.clone(),
         &init_storage_data.chain_id,
         is_sandbox,
+        env.enable_testchain,
         &env.p2p.peer_threshold,
+        &env.p2p.peer_roles,
         identity.clone(),
+        system_clock(),
+        shell_config.clone(),
     ).expect("Failed to create chain manager");
 
+    let mempool_filter = MempoolFilter::load(&SystemStorage::new(persistent_storage.kv()))
+        .expect("Failed to load mempool filter")
+        .into_ref();
     let _ = MempoolPrevalidator::actor(
         &actor_system,
         shell_channel.clone(),
         &persistent_storage,
         &init_storage_data,
         tezos_readonly_api_pool.clone(),
+        env.mempool,
+        mempool_filter.clone(),
         log.clone(),
     ).expect("Failed to create chain feeder");
 
     // and than open p2p and others
+    let p2p_capture = match &env.p2p.capture_file {
+        Some(capture_file) => match CaptureWriter::create(capture_file) {
+            Ok(capture) => Some(Arc::new(capture)),
+            Err(e) => shutdown_and_exit!(error!(log, "Failed to create p2p capture file"; "file" => capture_file.as_path().display().to_string(), "reason" => e), actor_system),
+        },
+        None => None,
+    };
+    let handshake_stats = HandshakeStats::new_ref();
     let _ = PeerManager::actor(
         &actor_system,
         network_channel.clone(),
         shell_channel.clone(),
         tokio_runtime.handle().clone(),
         identity,
-        network_version.clone(),
+        supported_versions,
         env.p2p.clone(),
+        p2p_capture,
+        handshake_stats.clone(),
+        system_clock(),
     ).expect("Failed to create peer manager");
     let websocket_handler = WebsocketHandler::actor(&actor_system, env.rpc.websocket_address, log.clone())
         .expect("Failed to start websocket actor");
-    let _ = Monitor::actor(&actor_system, network_channel, websocket_handler, shell_channel.clone(), &persistent_storage, &init_storage_data)
+    let _ = Monitor::actor(&actor_system, network_channel, websocket_handler, shell_channel.clone(), &persistent_storage, &init_storage_data, handshake_stats.clone())
         .expect("Failed to create monitor actor");
     let _ = RpcServer::actor(
         &actor_system,
@@ -320,6 +346,9 @@ fn block_on_actors(
         tezos_env.clone(),
         network_version,
         &init_storage_data,
+        handshake_stats,
+        shell_config,
+        mempool_filter,
         is_sandbox,
     ).expect("Failed to create RPC server");
 
@@ -428,6 +457,8 @@ fn main() {
         SystemStorage::descriptor(&cache),
         Sequences::descriptor(&cache),
         MempoolStorage::descriptor(&cache),
+        mempool_storage::MempoolBlacklistIndex::descriptor(&cache),
+        mempool_storage::MempoolSnapshotIndex::descriptor(&cache),
         ChainMetaStorage::descriptor(&cache),
     ];
 
@@ -477,8 +508,21 @@ fn main() {
             &env.storage.tezos_data_dir,
             &env.storage.patch_context,
             &log) {
-            Ok(init_data) => block_on_actors(env, tezos_env, init_data, Arc::new(tezos_identity), actor_system,
-                                             persistent_storage, tezedge_context, log),
+            Ok(init_data) => {
+                if let Some(checkpoint) = &env.storage.checkpoint {
+                    if let Err(e) = initialize_checkpoint(
+                        &BlockStorage::new(&persistent_storage),
+                        &BlockMetaStorage::new(&persistent_storage),
+                        &ChainMetaStorage::new(&persistent_storage),
+                        &init_data.chain_id,
+                        checkpoint,
+                        &log) {
+                        shutdown_and_exit!(error!(log, "Failed to initialize checkpoint"; "reason" => e), actor_system)
+                    }
+                }
+                block_on_actors(env, tezos_env, init_data, Arc::new(tezos_identity), actor_system,
+                                persistent_storage, tezedge_context, log)
+            }
             Err(e) => shutdown_and_exit!(error!(log, "Failed to resolve init storage chain data."; "reason" => e), actor_system),
         }
     }
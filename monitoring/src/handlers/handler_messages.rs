@@ -6,6 +6,8 @@ use std::iter::FromIterator;
 use serde::Serialize;
 use slog_derive::SerdeValue;
 
+use shell::stats::handshake::HandshakeStats;
+
 use crate::monitors::PeerMonitor;
 use crate::monitors::ChainMonitor;
 
@@ -133,6 +135,9 @@ pub enum HandlerMessage {
     ChainStatus {
         payload:  ChainMonitor,
     },
+    HandshakeStatus {
+        payload: HandshakeStats,
+    },
     NotImplemented(String),
 }
 
@@ -16,6 +16,7 @@ use networking::p2p::{
 };
 use networking::p2p::network_channel::PeerBootstrapped;
 use shell::shell_channel::{ShellChannelMsg, ShellChannelRef, ShellChannelTopic};
+use shell::stats::handshake::HandshakeStatsRef;
 use storage::{BlockMetaStorage, ChainMetaStorage, IteratorMode, StorageInitInfo};
 use storage::chain_meta_storage::ChainMetaStorageReader;
 use storage::persistent::PersistentStorage;
@@ -49,6 +50,8 @@ pub struct Monitor {
     blocks_monitor: BlocksMonitor,
     block_application_monitor: ApplicationMonitor,
     chain_monitor: ChainMonitor,
+    /// Handshake outcome counters collected by the peer manager
+    handshake_stats: HandshakeStatsRef,
 }
 
 impl Monitor {
@@ -56,10 +59,10 @@ impl Monitor {
         "monitor-manager"
     }
 
-    pub fn actor(sys: &impl ActorRefFactory, event_channel: NetworkChannelRef, msg_channel: ActorRef<WebsocketHandlerMsg>, shell_channel: ShellChannelRef, persistent_storage: &PersistentStorage, init_storage_data: &StorageInitInfo) -> Result<MonitorRef, CreateError> {
+    pub fn actor(sys: &impl ActorRefFactory, event_channel: NetworkChannelRef, msg_channel: ActorRef<WebsocketHandlerMsg>, shell_channel: ShellChannelRef, persistent_storage: &PersistentStorage, init_storage_data: &StorageInitInfo, handshake_stats: HandshakeStatsRef) -> Result<MonitorRef, CreateError> {
         sys.actor_of_props::<Monitor>(
             Self::name(),
-            Props::new_args((event_channel, msg_channel, shell_channel, persistent_storage.clone(), init_storage_data.chain_id.clone())),
+            Props::new_args((event_channel, msg_channel, shell_channel, persistent_storage.clone(), init_storage_data.chain_id.clone(), handshake_stats)),
         )
     }
 
@@ -94,8 +97,8 @@ impl Monitor {
     }
 }
 
-impl ActorFactoryArgs<(NetworkChannelRef, ActorRef<WebsocketHandlerMsg>, ShellChannelRef, PersistentStorage, ChainId)> for Monitor {
-    fn create_args((event_channel, msg_channel, shell_channel, persistent_storage, chain_id): (NetworkChannelRef, ActorRef<WebsocketHandlerMsg>, ShellChannelRef, PersistentStorage, ChainId)) -> Self {
+impl ActorFactoryArgs<(NetworkChannelRef, ActorRef<WebsocketHandlerMsg>, ShellChannelRef, PersistentStorage, ChainId, HandshakeStatsRef)> for Monitor {
+    fn create_args((event_channel, msg_channel, shell_channel, persistent_storage, chain_id, handshake_stats): (NetworkChannelRef, ActorRef<WebsocketHandlerMsg>, ShellChannelRef, PersistentStorage, ChainId, HandshakeStatsRef)) -> Self {
         let blocks_meta = BlockMetaStorage::new(&persistent_storage);
         let chain_meta_storage = ChainMetaStorage::new(&persistent_storage);
         // TODO: TE-184 - monitor - count all downloaded blocks - is this necessery?
@@ -122,6 +125,7 @@ impl ActorFactoryArgs<(NetworkChannelRef, ActorRef<WebsocketHandlerMsg>, ShellCh
             blocks_monitor: BlocksMonitor::new(4096, downloaded),
             block_application_monitor: ApplicationMonitor::new(),
             chain_monitor: ChainMonitor::new(),
+            handshake_stats,
         }
     }
 }
@@ -192,6 +196,11 @@ impl Receive<BroadcastSignal> for Monitor {
             BroadcastSignal::PublishPeerStatistics => {
                 let peer_stats: HandlerMessage = self.peer_monitors.values_mut().collect();
                 self.msg_channel.tell(peer_stats, ctx.myself().into());
+
+                if let Ok(handshake_stats) = self.handshake_stats.read() {
+                    let payload = handshake_stats.clone();
+                    self.msg_channel.tell(HandlerMessage::HandshakeStatus { payload }, ctx.myself().into());
+                }
             }
             BroadcastSignal::PublishBlocksStatistics => {
                 let bootstrap_stats: HandlerMessage = self.bootstrap_monitor.snapshot().into();
@@ -242,6 +251,7 @@ impl Receive<NetworkChannelMsg> for Monitor {
             NetworkChannelMsg::PeerMessageReceived(msg) => self.process_peer_message(msg, &ctx.system.log()),
             NetworkChannelMsg::PeerBlacklisted(..) => {},
             NetworkChannelMsg::BlacklistPeer(..) => {}
+            NetworkChannelMsg::PeerIdleTimeout(..) => {}
         }
     }
 }
@@ -0,0 +1,490 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Capture and replay of decrypted p2p messages.
+//!
+//! Every [PeerMessageResponse] exchanged with a remote peer can be recorded into a compact binary capture file.
+//! Capture can be later fed back into the [NetworkChannel](super::network_channel::NetworkChannel), which allows
+//! to reproduce issues in the shell deterministically without the live network.
+//!
+//! Capture file starts with the [CAPTURE_MAGIC] and [CAPTURE_FORMAT_VERSION] followed by records in the format:
+//!
+//! | field     | size                              |
+//! |-----------|-----------------------------------|
+//! | timestamp | 8 bytes (micros since UNIX epoch) |
+//! | direction | 1 byte                            |
+//! | peer_id   | 2 bytes length + bytes            |
+//! | message   | 4 bytes length + bytes            |
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use failure::Fail;
+use riker::actors::*;
+
+use tezos_encoding::binary_reader::BinaryReaderError;
+use tezos_messages::p2p::binary_message::BinaryMessage;
+use tezos_messages::p2p::encoding::peer::PeerMessageResponse;
+
+use super::network_channel::{NetworkChannelRef, NetworkChannelTopic, PeerMessageReceived};
+use super::peer::PeerRef;
+
+/// Magic bytes at the beginning of every capture file
+pub const CAPTURE_MAGIC: &[u8; 4] = b"TZPC";
+/// Version of the capture file format
+pub const CAPTURE_FORMAT_VERSION: u8 = 1;
+
+#[derive(Debug, Fail)]
+pub enum CaptureError {
+    #[fail(display = "Capture I/O error: {}", error)]
+    IoError {
+        error: io::Error
+    },
+    #[fail(display = "Message serialization error")]
+    SerializationError {
+        error: tezos_encoding::ser::Error
+    },
+    #[fail(display = "Message deserialization error: {:?}", error)]
+    DeserializationError {
+        error: BinaryReaderError
+    },
+    #[fail(display = "Invalid capture file: {}", reason)]
+    InvalidFormat {
+        reason: String
+    },
+    #[fail(display = "Capture queue is full, message was not recorded")]
+    QueueFull,
+    #[fail(display = "Capture writer thread is not running")]
+    WriterStopped,
+}
+
+impl From<io::Error> for CaptureError {
+    fn from(error: io::Error) -> Self {
+        CaptureError::IoError { error }
+    }
+}
+
+impl From<tezos_encoding::ser::Error> for CaptureError {
+    fn from(error: tezos_encoding::ser::Error) -> Self {
+        CaptureError::SerializationError { error }
+    }
+}
+
+impl From<BinaryReaderError> for CaptureError {
+    fn from(error: BinaryReaderError) -> Self {
+        CaptureError::DeserializationError { error }
+    }
+}
+
+impl slog::Value for CaptureError {
+    fn serialize(&self, _record: &slog::Record, key: slog::Key, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        serializer.emit_arguments(key, &format_args!("{}", self))
+    }
+}
+
+/// Direction of the captured message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureDirection {
+    /// Message was received from the remote peer
+    Incoming,
+    /// Message was sent to the remote peer
+    Outgoing,
+}
+
+impl From<CaptureDirection> for u8 {
+    fn from(direction: CaptureDirection) -> Self {
+        match direction {
+            CaptureDirection::Incoming => 0,
+            CaptureDirection::Outgoing => 1,
+        }
+    }
+}
+
+impl TryFrom<u8> for CaptureDirection {
+    type Error = CaptureError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(CaptureDirection::Incoming),
+            1 => Ok(CaptureDirection::Outgoing),
+            _ => Err(CaptureError::InvalidFormat { reason: format!("unknown direction: {}", value) }),
+        }
+    }
+}
+
+/// Single captured message.
+#[derive(Debug)]
+pub struct CapturedMessage {
+    /// Time when the message was captured
+    pub timestamp: SystemTime,
+    /// Id (public key hash) of the remote peer
+    pub peer_id: String,
+    pub direction: CaptureDirection,
+    pub message: PeerMessageResponse,
+}
+
+/// How many captured messages can wait for the writer thread, messages over the limit are not recorded
+const CAPTURE_QUEUE_CAPACITY: usize = 16_384;
+
+/// Serialized message waiting for the writer thread
+struct CaptureRecord {
+    timestamp: u64,
+    direction: CaptureDirection,
+    peer_id: String,
+    message: Vec<u8>,
+}
+
+impl CaptureRecord {
+    fn write_to<W: Write>(&self, out: &mut W) -> Result<(), CaptureError> {
+        let peer_id_len = u16::try_from(self.peer_id.len())
+            .map_err(|_| CaptureError::InvalidFormat { reason: format!("peer id is too long: {}", self.peer_id.len()) })?;
+        let message_len = u32::try_from(self.message.len())
+            .map_err(|_| CaptureError::InvalidFormat { reason: format!("message is too long: {}", self.message.len()) })?;
+
+        out.write_all(&self.timestamp.to_be_bytes())?;
+        out.write_all(&[self.direction.into()])?;
+        out.write_all(&peer_id_len.to_be_bytes())?;
+        out.write_all(self.peer_id.as_bytes())?;
+        out.write_all(&message_len.to_be_bytes())?;
+        out.write_all(&self.message)?;
+        Ok(())
+    }
+}
+
+/// Writes captured messages to the capture file.
+///
+/// Writer is shared between all peers. Messages are just serialized by the caller,
+/// blocking file writes are done by the dedicated writer thread, which is joined, when the writer is dropped.
+pub struct CaptureWriter {
+    records: Mutex<Option<SyncSender<CaptureRecord>>>,
+    writer_thread: Option<JoinHandle<()>>,
+}
+
+impl CaptureWriter {
+    /// Create new capture file and start the writer thread. Existing file is truncated.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, CaptureError> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(CAPTURE_MAGIC)?;
+        out.write_all(&[CAPTURE_FORMAT_VERSION])?;
+        out.flush()?;
+
+        let (records_tx, records_rx) = mpsc::sync_channel(CAPTURE_QUEUE_CAPACITY);
+        let writer_thread = thread::Builder::new()
+            .name("p2p-capture-writer".to_string())
+            .spawn(move || {
+                // when the file cannot be written, the thread finishes and recording fails with the [CaptureError::WriterStopped]
+                let _ = write_records(out, records_rx);
+            })?;
+
+        Ok(CaptureWriter {
+            records: Mutex::new(Some(records_tx)),
+            writer_thread: Some(writer_thread),
+        })
+    }
+
+    /// Append message to the capture file, does not block on the file I/O.
+    pub fn record(&self, peer_id: &str, direction: CaptureDirection, message: &PeerMessageResponse) -> Result<(), CaptureError> {
+        let record = CaptureRecord {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64,
+            direction,
+            peer_id: peer_id.to_string(),
+            message: message.as_bytes()?,
+        };
+
+        let records = self.records.lock().map_err(|_| CaptureError::InvalidFormat { reason: "capture writer lock is poisoned".to_string() })?;
+        match records.as_ref().map(|records| records.try_send(record)) {
+            Some(Ok(())) => Ok(()),
+            Some(Err(TrySendError::Full(_))) => Err(CaptureError::QueueFull),
+            Some(Err(TrySendError::Disconnected(_))) | None => Err(CaptureError::WriterStopped),
+        }
+    }
+}
+
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        // closing of the channel finishes the writer thread, after all queued records are written
+        if let Ok(records) = self.records.get_mut() {
+            records.take();
+        }
+        if let Some(writer_thread) = self.writer_thread.take() {
+            let _ = writer_thread.join();
+        }
+    }
+}
+
+fn write_records(mut out: BufWriter<File>, records: Receiver<CaptureRecord>) -> Result<(), CaptureError> {
+    while let Ok(record) = records.recv() {
+        record.write_to(&mut out)?;
+        while let Ok(record) = records.try_recv() {
+            record.write_to(&mut out)?;
+        }
+        // flush, when the queue is empty, so the capture is usable even when node crashes
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Reads captured messages from the capture file in the order they were recorded.
+pub struct CaptureReader<R: Read> {
+    input: R,
+}
+
+impl CaptureReader<BufReader<File>> {
+    /// Open existing capture file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, CaptureError> {
+        CaptureReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Create reader and validate capture header.
+    pub fn new(mut input: R) -> Result<Self, CaptureError> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != CAPTURE_MAGIC {
+            return Err(CaptureError::InvalidFormat { reason: "missing capture magic bytes".to_string() });
+        }
+        let mut version = [0u8; 1];
+        input.read_exact(&mut version)?;
+        if version[0] != CAPTURE_FORMAT_VERSION {
+            return Err(CaptureError::InvalidFormat { reason: format!("unsupported capture version: {}", version[0]) });
+        }
+        Ok(CaptureReader { input })
+    }
+
+    /// Read next record, returns `None` at the end of the capture.
+    fn read_record(&mut self) -> Result<Option<CapturedMessage>, CaptureError> {
+        let mut timestamp = [0u8; 8];
+        match self.input.read_exact(&mut timestamp) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let timestamp = UNIX_EPOCH + Duration::from_micros(u64::from_be_bytes(timestamp));
+
+        let mut direction = [0u8; 1];
+        self.input.read_exact(&mut direction)?;
+        let direction = CaptureDirection::try_from(direction[0])?;
+
+        let mut peer_id_len = [0u8; 2];
+        self.input.read_exact(&mut peer_id_len)?;
+        let mut peer_id = vec![0u8; u16::from_be_bytes(peer_id_len) as usize];
+        self.input.read_exact(&mut peer_id)?;
+        let peer_id = String::from_utf8(peer_id)
+            .map_err(|_| CaptureError::InvalidFormat { reason: "peer id is not valid utf8".to_string() })?;
+
+        let mut message_len = [0u8; 4];
+        self.input.read_exact(&mut message_len)?;
+        let mut message = vec![0u8; u32::from_be_bytes(message_len) as usize];
+        self.input.read_exact(&mut message)?;
+        let message = PeerMessageResponse::from_bytes(message)?;
+
+        Ok(Some(CapturedMessage { timestamp, peer_id, direction, message }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CapturedMessage, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Replay all incoming messages from the capture into the network channel, as if they were received from the live network.
+///
+/// Outgoing messages are skipped, they are what the node under test is expected to produce.
+/// `resolve_peer` maps captured peer id to the peer actor which will be used as a sender of the message,
+/// messages of the unresolved peers are skipped.
+///
+/// Returns number of published messages.
+pub fn replay<R, F>(reader: CaptureReader<R>, network_channel: &NetworkChannelRef, mut resolve_peer: F) -> Result<usize, CaptureError>
+    where
+        R: Read,
+        F: FnMut(&str) -> Option<PeerRef>
+{
+    let mut published = 0;
+    for captured in reader {
+        let captured = captured?;
+        if captured.direction != CaptureDirection::Incoming {
+            continue;
+        }
+        if let Some(peer) = resolve_peer(&captured.peer_id) {
+            network_channel.tell(
+                Publish {
+                    msg: PeerMessageReceived {
+                        peer: peer.clone(),
+                        message: Arc::new(captured.message),
+                    }.into(),
+                    topic: NetworkChannelTopic::NetworkEvents.into(),
+                }, Some(peer.into()));
+            published += 1;
+        }
+    }
+    Ok(published)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::process;
+    use std::thread;
+    use std::time::Instant;
+
+    use riker::system::SystemBuilder;
+    use tezos_messages::p2p::encoding::prelude::*;
+
+    use crate::p2p::network_channel::{NetworkChannel, NetworkChannelMsg};
+    use crate::p2p::peer::{Peer, PeerTimeouts};
+
+    use super::*;
+
+    /// Returns path of the capture file in the empty directory unique for the test and the process
+    fn prepare_capture_file(test_name: &str) -> Result<PathBuf, failure::Error> {
+        let dir = std::env::temp_dir().join(format!("{}_{}", test_name, process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        Ok(dir.join("capture.tzpc"))
+    }
+
+    /// Collects messages received through the network channel
+    #[actor(NetworkChannelMsg)]
+    struct ReceivedMessages {
+        received: Arc<Mutex<Vec<PeerMessage>>>,
+    }
+
+    impl ActorFactoryArgs<Arc<Mutex<Vec<PeerMessage>>>> for ReceivedMessages {
+        fn create_args(received: Arc<Mutex<Vec<PeerMessage>>>) -> Self {
+            ReceivedMessages { received }
+        }
+    }
+
+    impl Actor for ReceivedMessages {
+        type Msg = ReceivedMessagesMsg;
+
+        fn recv(&mut self, ctx: &Context<Self::Msg>, msg: Self::Msg, sender: Sender) {
+            self.receive(ctx, msg, sender);
+        }
+    }
+
+    impl Receive<NetworkChannelMsg> for ReceivedMessages {
+        type Msg = ReceivedMessagesMsg;
+
+        fn receive(&mut self, _: &Context<Self::Msg>, msg: NetworkChannelMsg, _: Sender) {
+            if let NetworkChannelMsg::PeerMessageReceived(received) = msg {
+                self.received.lock().unwrap().extend(received.message.messages().iter().cloned());
+            }
+        }
+    }
+
+    #[test]
+    fn test_capture_write_and_read() -> Result<(), failure::Error> {
+        let capture_file = prepare_capture_file("test_capture_write_and_read")?;
+
+        let writer = CaptureWriter::create(&capture_file)?;
+        writer.record("idsg2wkkDDv2cbEMK4zH49fjgyn7XT", CaptureDirection::Incoming, &PeerMessage::Bootstrap.into())?;
+        writer.record("idsg2wkkDDv2cbEMK4zH49fjgyn7XT", CaptureDirection::Outgoing, &PeerMessage::Disconnect.into())?;
+        drop(writer);
+
+        let records = CaptureReader::open(&capture_file)?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(2, records.len());
+        assert_eq!(CaptureDirection::Incoming, records[0].direction);
+        assert_eq!("idsg2wkkDDv2cbEMK4zH49fjgyn7XT", records[0].peer_id);
+        match records[0].message.messages()[0] {
+            PeerMessage::Bootstrap => (),
+            _ => panic!("Expected bootstrap message"),
+        }
+        assert_eq!(CaptureDirection::Outgoing, records[1].direction);
+        match records[1].message.messages()[0] {
+            PeerMessage::Disconnect => (),
+            _ => panic!("Expected disconnect message"),
+        }
+        assert!(records[0].timestamp <= records[1].timestamp);
+
+        fs::remove_dir_all(capture_file.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_capture_replay() -> Result<(), failure::Error> {
+        let capture_file = prepare_capture_file("test_capture_replay")?;
+        let writer = CaptureWriter::create(&capture_file)?;
+        writer.record("idsg2wkkDDv2cbEMK4zH49fjgyn7XT", CaptureDirection::Incoming, &PeerMessage::Bootstrap.into())?;
+        writer.record("idsg2wkkDDv2cbEMK4zH49fjgyn7XT", CaptureDirection::Outgoing, &PeerMessage::Disconnect.into())?;
+        writer.record("idtqxHUjbjbCfaDn4jczoPGsnhacKX", CaptureDirection::Incoming, &PeerMessage::Bootstrap.into())?;
+        writer.record("idsg2wkkDDv2cbEMK4zH49fjgyn7XT", CaptureDirection::Incoming, &PeerMessage::Disconnect.into())?;
+        // joins the writer thread, so all records are written
+        drop(writer);
+
+        let tokio_runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .expect("Failed to create tokio runtime");
+        let actor_system = SystemBuilder::new().name("test_capture_replay").create().expect("Failed to create actor system");
+        let network_channel = NetworkChannel::actor(&actor_system).expect("Failed to create network channel");
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let collector = actor_system.actor_of_props::<ReceivedMessages>("received-messages", Props::new_args(received.clone()))
+            .expect("Failed to create collector");
+        // subscribed from this thread, so the channel handles subscription before the replayed messages
+        network_channel.tell(Subscribe {
+            actor: Box::new(collector),
+            topic: NetworkChannelTopic::NetworkEvents.into(),
+        }, None);
+        let peer = Peer::actor(
+            &actor_system,
+            network_channel.clone(),
+            3011,
+            "eaef40186db19fd6f56ed5b1af57f9d9c8a1eed85c29f8e4daaa7367869c0f0b",
+            "eaef40186db19fd6f56ed5b1af57f9d9c8a1eed85c29f8e4daaa7367869c0f0b",
+            "000000000000000000000000000000000000000000000000",
+            NetworkVersion::new("testet".to_string(), 0, 0).into(),
+            PeerTimeouts::default(),
+            tokio_runtime.handle().clone(),
+            &"127.0.0.1:3011".parse::<SocketAddr>()?,
+            None,
+        ).expect("Failed to create peer");
+
+        // just incoming messages of the resolved peer are replayed
+        let published = replay(CaptureReader::open(&capture_file)?, &network_channel, |peer_id| {
+            if peer_id == "idsg2wkkDDv2cbEMK4zH49fjgyn7XT" {
+                Some(peer.clone())
+            } else {
+                None
+            }
+        })?;
+        assert_eq!(2, published);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while received.lock().unwrap().len() < 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let received = received.lock().unwrap();
+        assert_eq!(2, received.len());
+        match (&received[0], &received[1]) {
+            (PeerMessage::Bootstrap, PeerMessage::Disconnect) => (),
+            messages => panic!("Unexpected replayed messages: {:?}", messages),
+        }
+
+        fs::remove_dir_all(capture_file.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_capture_invalid_header() {
+        match CaptureReader::new(&b"XXXX\x01"[..]) {
+            Err(CaptureError::InvalidFormat { .. }) => (),
+            _ => panic!("Expected invalid format error"),
+        }
+    }
+}
@@ -6,3 +6,4 @@
 pub mod stream;
 pub mod peer;
 pub mod network_channel;
+pub mod capture;
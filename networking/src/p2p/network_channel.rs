@@ -5,15 +5,17 @@
 
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use riker::actors::*;
 
 use tezos_messages::p2p::encoding::metadata::MetadataMessage;
 use tezos_messages::p2p::encoding::peer::PeerMessageResponse;
+use tezos_messages::p2p::encoding::version::NetworkVersion;
 
 use crate::PeerId;
 
-use super::peer::PeerRef;
+use super::peer::{HandshakeStage, PeerRef};
 
 pub const DEFAULT_TOPIC: &str = "network";
 
@@ -32,11 +34,19 @@ pub enum PeerBootstrapped {
     Success {
         peer_id: Arc<PeerId>,
         peer_metadata: MetadataMessage,
+        /// Network version negotiated with the remote peer
+        network_version: NetworkVersion,
     },
     Failure {
         address: SocketAddr,
         /// List of potential peers to connect to. Is extracted from `Nack`.
         potential_peers_to_connect: Option<Vec<String>>,
+        /// Handshake stage at which bootstrap failed
+        stage: HandshakeStage,
+        /// Short description of the failure, see [PeerError::reason](super::peer::PeerError::reason)
+        reason: &'static str,
+        /// Network version announced by the remote peer, if it was received
+        remote_version: Option<NetworkVersion>,
     },
 }
 
@@ -47,6 +57,13 @@ pub struct PeerMessageReceived {
     pub message: Arc<PeerMessageResponse>,
 }
 
+/// Connection to the peer was closed, because no message was received within the idle timeout
+#[derive(Clone, Debug)]
+pub struct PeerIdleTimeout {
+    pub peer: PeerRef,
+    pub idle_timeout: Duration,
+}
+
 /// Network channel event message.
 #[derive(Clone, Debug)]
 pub enum NetworkChannelMsg {
@@ -55,6 +72,7 @@ pub enum NetworkChannelMsg {
     PeerBlacklisted(Arc<PeerId>),
     BlacklistPeer(Arc<PeerId>, String),
     PeerMessageReceived(PeerMessageReceived),
+    PeerIdleTimeout(PeerIdleTimeout),
 }
 
 impl From<PeerCreated> for NetworkChannelMsg {
@@ -69,6 +87,12 @@ impl From<PeerBootstrapped> for NetworkChannelMsg {
     }
 }
 
+impl From<PeerIdleTimeout> for NetworkChannelMsg {
+    fn from(msg: PeerIdleTimeout) -> Self {
+        NetworkChannelMsg::PeerIdleTimeout(msg)
+    }
+}
+
 impl From<PeerMessageReceived> for NetworkChannelMsg {
     fn from(msg: PeerMessageReceived) -> Self {
         NetworkChannelMsg::PeerMessageReceived(msg)
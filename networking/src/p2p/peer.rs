@@ -1,8 +1,9 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::fmt;
 use std::net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::Duration;
 
@@ -24,11 +25,15 @@ use tezos_messages::p2p::encoding::prelude::*;
 
 use crate::{PeerId, PeerPublicKey};
 
-use super::network_channel::{NetworkChannelRef, NetworkChannelTopic, PeerBootstrapped, PeerMessageReceived};
+use super::capture::{CaptureDirection, CaptureWriter};
+use super::network_channel::{NetworkChannelRef, NetworkChannelTopic, PeerBootstrapped, PeerIdleTimeout, PeerMessageReceived};
 use super::stream::{EncryptedMessageReader, EncryptedMessageWriter, MessageStream, StreamError};
 
 const IO_TIMEOUT: Duration = Duration::from_secs(6);
-const READ_TIMEOUT_LONG: Duration = Duration::from_secs(30);
+/// Default timeout for the whole handshake with the remote peer
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Default timeout for the idle connection, when no message is received from the remote peer
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
 
 static ACTOR_ID_GENERATOR: AtomicU64 = AtomicU64::new(0);
 
@@ -60,6 +65,10 @@ pub enum PeerError {
     DeserializationError {
         error: BinaryReaderError
     },
+    #[fail(display = "Handshake was not finished within {:?}", timeout)]
+    HandshakeTimeout {
+        timeout: Duration
+    },
 }
 
 impl From<tezos_encoding::ser::Error> for PeerError {
@@ -92,6 +101,26 @@ impl From<BinaryChunkError> for PeerError {
     }
 }
 
+impl PeerError {
+    /// Short and stable description of the failure, usable for aggregation
+    pub fn reason(&self) -> &'static str {
+        match self {
+            PeerError::UnsupportedProtocol { .. } => "unsupported_protocol",
+            PeerError::NackReceived => "nack",
+            PeerError::NackWithMotiveReceived { .. } => "nack_with_motive",
+            PeerError::FailedToPrecomputeKey => "failed_to_precompute_key",
+            PeerError::NetworkError { error, .. } => if error.downcast_ref::<tokio::time::Elapsed>().is_some() {
+                "io_timeout"
+            } else {
+                "network_error"
+            },
+            PeerError::SerializationError { .. } => "serialization_error",
+            PeerError::DeserializationError { .. } => "deserialization_error",
+            PeerError::HandshakeTimeout { .. } => "handshake_timeout",
+        }
+    }
+}
+
 impl slog::Value for PeerError {
     fn serialize(&self, _record: &slog::Record, key: slog::Key, serializer: &mut dyn slog::Serializer) -> slog::Result {
         serializer.emit_arguments(key, &format_args!("{}", self))
@@ -107,6 +136,56 @@ impl From<tokio::time::Elapsed> for PeerError {
     }
 }
 
+/// Stages of the handshake with a remote peer, in the order they are executed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HandshakeStage {
+    /// Exchange of the connection messages
+    ConnectionMessage,
+    /// Nonce generation and pre-computation of the encryption key
+    Nonce,
+    /// Exchange of the metadata messages
+    Metadata,
+    /// Check of the network version announced by the remote peer
+    Version,
+    /// Exchange of the ack messages
+    Ack,
+}
+
+impl HandshakeStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HandshakeStage::ConnectionMessage => "connection_message",
+            HandshakeStage::Nonce => "nonce",
+            HandshakeStage::Metadata => "metadata",
+            HandshakeStage::Version => "version",
+            HandshakeStage::Ack => "ack",
+        }
+    }
+}
+
+impl fmt::Display for HandshakeStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Handshake with a remote peer failed.
+#[derive(Debug, Fail)]
+#[fail(display = "Handshake failed at stage: {}, reason: {}", stage, error)]
+pub struct HandshakeError {
+    /// Stage at which handshake failed
+    pub stage: HandshakeStage,
+    /// Network version announced by the remote peer, if connection message was already received
+    pub remote_version: Option<NetworkVersion>,
+    pub error: PeerError,
+}
+
+impl slog::Value for HandshakeError {
+    fn serialize(&self, _record: &slog::Record, key: slog::Key, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        serializer.emit_arguments(key, &format_args!("{}", self))
+    }
+}
+
 /// Commands peer actor to initialize bootstrapping process with a remote peer.
 #[derive(Clone, Debug)]
 pub struct Bootstrap {
@@ -138,6 +217,11 @@ impl SendMessage {
     pub fn new(msg: PeerMessageResponse) -> Self {
         SendMessage { message: Arc::new(msg) }
     }
+
+    /// Message, which should be sent to the remote peer
+    pub fn message(&self) -> &PeerMessageResponse {
+        &self.message
+    }
 }
 
 #[derive(Clone)]
@@ -149,6 +233,43 @@ struct Network {
     tx: Arc<Mutex<Option<EncryptedMessageWriter>>>,
     /// Socket address of the peer
     socket_address: SocketAddr,
+    /// Capture of the decrypted messages, if enabled
+    capture: Option<Arc<CaptureWriter>>,
+    /// Remote peer id, is known after successful bootstrap
+    peer_id_marker: Arc<RwLock<Option<String>>>,
+}
+
+impl Network {
+    /// Record message to the capture, if capture is enabled
+    fn capture(&self, direction: CaptureDirection, message: &PeerMessageResponse, log: &Logger) {
+        if let Some(capture) = &self.capture {
+            if let Ok(peer_id_marker) = self.peer_id_marker.read() {
+                if let Some(peer_id) = peer_id_marker.as_ref() {
+                    if let Err(e) = capture.record(peer_id, direction, message) {
+                        warn!(log, "Failed to capture message"; "reason" => e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Timeouts used for communication with the remote peer
+#[derive(Clone, Copy, Debug)]
+pub struct PeerTimeouts {
+    /// Max duration of the whole handshake
+    pub handshake: Duration,
+    /// Max duration between two messages received from the remote peer
+    pub idle: Duration,
+}
+
+impl Default for PeerTimeouts {
+    fn default() -> Self {
+        PeerTimeouts {
+            handshake: HANDSHAKE_TIMEOUT,
+            idle: IDLE_TIMEOUT,
+        }
+    }
 }
 
 /// Local node info
@@ -161,18 +282,21 @@ pub struct Local {
     secret_key: String,
     /// proof of work
     proof_of_work_stamp: String,
-    /// version of network protocol
-    version: NetworkVersion,
+    /// supported versions of network protocol
+    supported_versions: SupportedNetworkVersions,
+    /// timeouts for communication with the remote peer
+    timeouts: PeerTimeouts,
 }
 
 impl Local {
-    pub fn new(listener_port: u16, public_key: String, secret_key: String, proof_of_work_stamp: String, network_version: NetworkVersion) -> Self {
+    pub fn new(listener_port: u16, public_key: String, secret_key: String, proof_of_work_stamp: String, supported_versions: SupportedNetworkVersions, timeouts: PeerTimeouts) -> Self {
         Local {
             listener_port,
             public_key,
             secret_key,
             proof_of_work_stamp,
-            version: network_version,
+            supported_versions,
+            timeouts,
         }
     }
 }
@@ -202,25 +326,28 @@ impl Peer {
                  public_key: &str,
                  secret_key: &str,
                  proof_of_work_stamp: &str,
-                 version: NetworkVersion,
+                 supported_versions: SupportedNetworkVersions,
+                 timeouts: PeerTimeouts,
                  tokio_executor: Handle,
-                 socket_address: &SocketAddr) -> Result<PeerRef, CreateError>
+                 socket_address: &SocketAddr,
+                 capture: Option<Arc<CaptureWriter>>) -> Result<PeerRef, CreateError>
     {
         let info = Local {
             listener_port,
             proof_of_work_stamp: proof_of_work_stamp.into(),
             public_key: public_key.into(),
             secret_key: secret_key.into(),
-            version,
+            supported_versions,
+            timeouts,
         };
-        let props = Props::new_args::<Peer, _>((network_channel, Arc::new(info), tokio_executor, *socket_address));
+        let props = Props::new_args::<Peer, _>((network_channel, Arc::new(info), tokio_executor, *socket_address, capture));
         let actor_id = ACTOR_ID_GENERATOR.fetch_add(1, Ordering::SeqCst);
         sys.actor_of_props(&format!("peer-{}", actor_id), props)
     }
 }
 
-impl ActorFactoryArgs<(NetworkChannelRef, Arc<Local>, Handle, SocketAddr)> for Peer {
-    fn create_args((event_channel, info, tokio_executor, socket_address): (NetworkChannelRef, Arc<Local>, Handle, SocketAddr)) -> Self {
+impl ActorFactoryArgs<(NetworkChannelRef, Arc<Local>, Handle, SocketAddr, Option<Arc<CaptureWriter>>)> for Peer {
+    fn create_args((event_channel, info, tokio_executor, socket_address, capture): (NetworkChannelRef, Arc<Local>, Handle, SocketAddr, Option<Arc<CaptureWriter>>)) -> Self {
         Peer {
             network_channel: event_channel,
             local: info,
@@ -228,6 +355,8 @@ impl ActorFactoryArgs<(NetworkChannelRef, Arc<Local>, Handle, SocketAddr)> for P
                 rx_run: Arc::new(AtomicBool::new(false)),
                 tx: Arc::new(Mutex::new(None)),
                 socket_address,
+                capture,
+                peer_id_marker: Arc::new(RwLock::new(None)),
             },
             tokio_executor,
             remote_addr: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0)),
@@ -253,6 +382,7 @@ impl Receive<Bootstrap> for Peer {
 
     fn receive(&mut self, ctx: &Context<Self::Msg>, msg: Bootstrap, _sender: Sender) {
         let info = self.local.clone();
+        let idle_timeout = info.timeouts.idle;
         let myself = ctx.myself();
         let system = ctx.system.clone();
         let net = self.net.clone();
@@ -260,7 +390,10 @@ impl Receive<Bootstrap> for Peer {
         self.remote_addr = msg.address;
 
         self.tokio_executor.spawn(async move {
-            async fn setup_net(net: &Network, tx: EncryptedMessageWriter) {
+            async fn setup_net(net: &Network, tx: EncryptedMessageWriter, peer_id_marker: &str) {
+                if let Ok(mut marker) = net.peer_id_marker.write() {
+                    *marker = Some(peer_id_marker.to_string());
+                }
                 net.rx_run.store(true, Ordering::Release);
                 *net.tx.lock().await = Some(tx);
             }
@@ -268,32 +401,34 @@ impl Receive<Bootstrap> for Peer {
             let peer_address = msg.address;
             debug!(system.log(), "Bootstrapping"; "ip" => &peer_address, "peer" => myself.name());
             match bootstrap(msg, info, &system.log()).await {
-                Ok(BootstrapOutput(rx, tx, public_key, peer_metadata)) => {
+                Ok(BootstrapOutput(rx, tx, public_key, peer_metadata, network_version)) => {
                     debug!(system.log(), "Bootstrap successful"; "ip" => &peer_address, "peer" => myself.name(), "peer_metadata" => format!("{:?}", &peer_metadata));
-                    setup_net(&net, tx).await;
-
                     let peer_id = PeerId::new(myself.clone(), public_key, peer_address.clone());
                     let peer_id_marker = peer_id.peer_id_marker.clone();
 
+                    setup_net(&net, tx, &peer_id_marker).await;
+
                     // notify that peer was bootstrapped successfully
                     network_channel.tell(Publish {
                         msg: PeerBootstrapped::Success {
                             peer_id: Arc::new(peer_id),
                             peer_metadata,
+                            network_version,
                         }.into(),
                         topic: NetworkChannelTopic::NetworkEvents.into(),
                     }, Some(myself.clone().into()));
 
                     // begin to process incoming messages in a loop
                     let log = system.log().new(slog::o!("peer_id" => peer_id_marker));
-                    begin_process_incoming(rx, net, myself.clone(), network_channel, log, peer_address).await;
+                    begin_process_incoming(rx, net, myself.clone(), network_channel, log, peer_address, idle_timeout).await;
                     // connection to peer was closed, stop this actor
                     system.stop(myself);
                 }
                 Err(err) => {
                     info!(system.log(), "Connection to peer failed"; "reason" => &err, "ip" => &peer_address, "peer" => myself.name());
 
-                    let potential_peers = match err {
+                    let reason = err.error.reason();
+                    let potential_peers = match err.error {
                         PeerError::NackWithMotiveReceived { nack_info } => Some(nack_info.potential_peers_to_connect().clone()),
                         _ => None
                     };
@@ -303,6 +438,9 @@ impl Receive<Bootstrap> for Peer {
                         msg: PeerBootstrapped::Failure {
                             address: peer_address,
                             potential_peers_to_connect: potential_peers,
+                            stage: err.stage,
+                            reason,
+                            remote_version: err.remote_version,
                         }.into(),
                         topic: NetworkChannelTopic::NetworkEvents.into(),
                     }, Some(myself.clone().into()));
@@ -320,9 +458,9 @@ impl Receive<SendMessage> for Peer {
     fn receive(&mut self, ctx: &Context<Self::Msg>, msg: SendMessage, _sender: Sender) {
         let system = ctx.system.clone();
         let myself = ctx.myself();
-        let tx = self.net.tx.clone();
+        let net = self.net.clone();
         self.tokio_executor.spawn(async move {
-            let mut tx_lock = tx.lock().await;
+            let mut tx_lock = net.tx.lock().await;
             if let Some(tx) = tx_lock.as_mut() {
                 let write_result = timeout(IO_TIMEOUT, tx.write_message(&*msg.message)).await;
                 // release mutex as soon as possible
@@ -330,9 +468,13 @@ impl Receive<SendMessage> for Peer {
 
                 match write_result {
                     Ok(write_result) => {
-                        if let Err(e) = write_result {
-                            warn!(system.log(), "Failed to send message"; "reason" => e);
-                            system.stop(myself);
+                        match write_result {
+                            // just the messages really sent to the peer are captured
+                            Ok(()) => net.capture(CaptureDirection::Outgoing, &msg.message, &system.log()),
+                            Err(e) => {
+                                warn!(system.log(), "Failed to send message"; "reason" => e);
+                                system.stop(myself);
+                            }
                         }
                     }
                     Err(_) => {
@@ -346,12 +488,43 @@ impl Receive<SendMessage> for Peer {
 }
 
 /// Output values of the successful bootstrap process
-pub struct BootstrapOutput(pub EncryptedMessageReader, pub EncryptedMessageWriter, pub PeerPublicKey, pub MetadataMessage);
+pub struct BootstrapOutput(pub EncryptedMessageReader, pub EncryptedMessageWriter, pub PeerPublicKey, pub MetadataMessage, pub NetworkVersion);
+
+/// Progress of the handshake, used to report where the handshake failed
+struct HandshakeProgress {
+    stage: HandshakeStage,
+    remote_version: Option<NetworkVersion>,
+}
 
 pub async fn bootstrap(
     msg: Bootstrap,
     info: Arc<Local>,
     log: &Logger,
+) -> Result<BootstrapOutput, HandshakeError> {
+    let handshake_timeout = info.timeouts.handshake;
+    let mut progress = HandshakeProgress {
+        stage: HandshakeStage::ConnectionMessage,
+        remote_version: None,
+    };
+    let result = match timeout(handshake_timeout, bootstrap_stages(msg, info, log, &mut progress)).await {
+        Ok(result) => result,
+        Err(_) => Err(PeerError::HandshakeTimeout { timeout: handshake_timeout }),
+    };
+    match result {
+        Ok(output) => Ok(output),
+        Err(error) => Err(HandshakeError {
+            stage: progress.stage,
+            remote_version: progress.remote_version,
+            error,
+        })
+    }
+}
+
+async fn bootstrap_stages(
+    msg: Bootstrap,
+    info: Arc<Local>,
+    log: &Logger,
+    progress: &mut HandshakeProgress,
 ) -> Result<BootstrapOutput, PeerError> {
     let (mut msg_rx, mut msg_tx) = {
         let stream = msg.stream.lock().await.take().expect("Someone took ownership of the socket before the Peer");
@@ -359,15 +532,13 @@ pub async fn bootstrap(
         msg_reader.split()
     };
 
-    let supported_protocol_version = info.version.clone();
-
     // send connection message
     let connection_message = ConnectionMessage::new(
         info.listener_port,
         &info.public_key,
         &info.proof_of_work_stamp,
         &Nonce::random().get_bytes(),
-        vec![supported_protocol_version.clone()]);
+        vec![info.supported_versions.announced()]);
     let connection_message_sent = {
         let connection_message_bytes = BinaryChunk::from_content(&connection_message.as_bytes()?)?;
         match timeout(IO_TIMEOUT, msg_tx.write_message(&connection_message_bytes)).await? {
@@ -383,8 +554,10 @@ pub async fn bootstrap(
     };
 
     let connection_message = ConnectionMessage::from_bytes(received_connection_message_bytes.content())?;
+    progress.remote_version = connection_message.versions().first().cloned();
 
     // generate local and remote nonce
+    progress.stage = HandshakeStage::Nonce;
     let NoncePair { local: nonce_local, remote: nonce_remote } = generate_nonces(&connection_message_sent, &received_connection_message_bytes, msg.incoming);
 
     // convert received bytes from remote peer into `ConnectionMessage`
@@ -420,6 +593,7 @@ pub async fn bootstrap(
     }
 
     // send metadata
+    progress.stage = HandshakeStage::Metadata;
     let metadata = MetadataMessage::new(msg.disable_mempool, msg.private_node);
     timeout(IO_TIMEOUT, msg_tx.write_message(&metadata)).await??;
 
@@ -427,20 +601,32 @@ pub async fn bootstrap(
     let metadata_received = timeout(IO_TIMEOUT, msg_rx.read_message::<MetadataMessage>()).await??;
     debug!(log, "Received remote peer metadata"; "disable_mempool" => metadata_received.disable_mempool(), "private_node" => metadata_received.private_node());
 
-    let protocol_not_supported = !connection_message.versions().iter().any(|version| supported_protocol_version.supports(version));
-    if protocol_not_supported {
-        // send nack
-        timeout(IO_TIMEOUT, msg_tx.write_message(&AckMessage::NackV0)).await??;
-
-        return Err(
-            PeerError::UnsupportedProtocol {
-                supported_version: format!("{:?}", &supported_protocol_version),
-                incompatible_versions: format!("{:?}", &connection_message.versions()),
-            }
-        );
-    }
+    // negotiate the best common version
+    progress.stage = HandshakeStage::Version;
+    let negotiated_version = match select_version(&info.supported_versions, connection_message.versions()) {
+        Ok(negotiated_version) => negotiated_version,
+        Err(motive) => {
+            // send nack, nack with motive is supported since p2p_version 1
+            let supports_nack_with_motive = connection_message.versions().iter().any(|version| version.p2p_version() >= 1);
+            let nack = if supports_nack_with_motive {
+                AckMessage::Nack(NackInfo::new(motive, &[]))
+            } else {
+                AckMessage::NackV0
+            };
+            timeout(IO_TIMEOUT, msg_tx.write_message(&nack)).await??;
+
+            return Err(
+                PeerError::UnsupportedProtocol {
+                    supported_version: format!("{:?}", &info.supported_versions),
+                    incompatible_versions: format!("{:?}", &connection_message.versions()),
+                }
+            );
+        }
+    };
+    debug!(log, "Negotiated network version"; "version" => format!("{:?}", &negotiated_version));
 
     // send ack
+    progress.stage = HandshakeStage::Ack;
     timeout(IO_TIMEOUT, msg_tx.write_message(&AckMessage::Ack)).await??;
 
     // receive ack
@@ -449,7 +635,7 @@ pub async fn bootstrap(
     match ack_received {
         AckMessage::Ack => {
             debug!(log, "Received ACK");
-            Ok(BootstrapOutput(msg_rx, msg_tx, peer_public_key.clone(), metadata_received))
+            Ok(BootstrapOutput(msg_rx, msg_tx, peer_public_key.clone(), metadata_received, negotiated_version))
         }
         AckMessage::NackV0 => {
             debug!(log, "Received NACK");
@@ -462,6 +648,21 @@ pub async fn bootstrap(
     }
 }
 
+/// Select the best version supported by both sides from versions announced by the remote peer.
+///
+/// Returns nack motive of the first announced version, if there is no compatible version.
+fn select_version(supported_versions: &SupportedNetworkVersions, remote_versions: &[NetworkVersion]) -> Result<NetworkVersion, NackMotive> {
+    let mut motive = NackMotive::NoMotive;
+    for (idx, remote_version) in remote_versions.iter().enumerate() {
+        match supported_versions.select(remote_version) {
+            Ok(negotiated_version) => return Ok(negotiated_version),
+            Err(nack_motive) => if idx == 0 {
+                motive = nack_motive;
+            }
+        }
+    }
+    Err(motive)
+}
 
 /// Generate nonces (sent and recv encoding must be with length bytes also)
 ///
@@ -472,16 +673,17 @@ fn generate_nonces(sent_msg: &BinaryChunk, recv_msg: &BinaryChunk, incoming: boo
 }
 
 /// Start to process incoming data
-async fn begin_process_incoming(mut rx: EncryptedMessageReader, net: Network, myself: PeerRef, event_channel: NetworkChannelRef, log: Logger, peer_address: SocketAddr) {
+async fn begin_process_incoming(mut rx: EncryptedMessageReader, net: Network, myself: PeerRef, event_channel: NetworkChannelRef, log: Logger, peer_address: SocketAddr, idle_timeout: Duration) {
     info!(log, "Starting to accept messages"; "ip" => format!("{:?}", &peer_address));
 
     while net.rx_run.load(Ordering::Acquire) {
-        match timeout(READ_TIMEOUT_LONG, rx.read_message::<PeerMessageResponse>()).await {
+        match timeout(idle_timeout, rx.read_message::<PeerMessageResponse>()).await {
             Ok(res) => match res {
                 Ok(msg) => {
                     let should_broadcast_message = net.rx_run.load(Ordering::Acquire);
                     if should_broadcast_message {
                         trace!(log, "Message parsed successfully"; "msg" => format!("{:?}", &msg));
+                        net.capture(CaptureDirection::Incoming, &msg, &log);
                         event_channel.tell(
                             Publish {
                                 msg: PeerMessageReceived {
@@ -502,7 +704,15 @@ async fn begin_process_incoming(mut rx: EncryptedMessageReader, net: Network, my
                 }
             }
             Err(_) => {
-                warn!(log, "Peer message read timed out"; "secs" => READ_TIMEOUT_LONG.as_secs());
+                warn!(log, "Peer message read timed out"; "secs" => idle_timeout.as_secs());
+                event_channel.tell(
+                    Publish {
+                        msg: PeerIdleTimeout {
+                            peer: myself.clone(),
+                            idle_timeout,
+                        }.into(),
+                        topic: NetworkChannelTopic::NetworkEvents.into(),
+                    }, Some(myself.clone().into()));
                 break;
             }
         }
@@ -520,3 +730,146 @@ async fn begin_process_incoming(mut rx: EncryptedMessageReader, net: Network, my
 
     info!(log, "Stopped to accept messages"; "ip" => format!("{:?}", &peer_address));
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex as StdMutex;
+
+    use riker::system::SystemBuilder;
+    use tokio::net::TcpListener;
+
+    use crypto::crypto_box::random_keypair;
+
+    use crate::p2p::network_channel::{NetworkChannel, NetworkChannelMsg};
+
+    use super::*;
+
+    /// Collects idle timeouts published to the network channel
+    #[actor(NetworkChannelMsg)]
+    struct IdleTimeouts {
+        network_channel: NetworkChannelRef,
+        timed_out: Arc<StdMutex<Vec<PeerRef>>>,
+    }
+
+    impl ActorFactoryArgs<(NetworkChannelRef, Arc<StdMutex<Vec<PeerRef>>>)> for IdleTimeouts {
+        fn create_args((network_channel, timed_out): (NetworkChannelRef, Arc<StdMutex<Vec<PeerRef>>>)) -> Self {
+            IdleTimeouts { network_channel, timed_out }
+        }
+    }
+
+    impl Actor for IdleTimeouts {
+        type Msg = IdleTimeoutsMsg;
+
+        fn pre_start(&mut self, ctx: &Context<Self::Msg>) {
+            self.network_channel.tell(Subscribe {
+                actor: Box::new(ctx.myself()),
+                topic: NetworkChannelTopic::NetworkEvents.into(),
+            }, None);
+        }
+
+        fn recv(&mut self, ctx: &Context<Self::Msg>, msg: Self::Msg, sender: Sender) {
+            self.receive(ctx, msg, sender);
+        }
+    }
+
+    impl Receive<NetworkChannelMsg> for IdleTimeouts {
+        type Msg = IdleTimeoutsMsg;
+
+        fn receive(&mut self, _: &Context<Self::Msg>, msg: NetworkChannelMsg, _: Sender) {
+            if let NetworkChannelMsg::PeerIdleTimeout(timeout) = msg {
+                self.timed_out.lock().unwrap().push(timeout.peer);
+            }
+        }
+    }
+
+    fn local(timeouts: PeerTimeouts) -> Local {
+        let (secret_key, public_key, _) = random_keypair();
+        Local::new(
+            0,
+            hex::encode((*public_key).0),
+            hex::encode((*secret_key).0),
+            "000000000000000000000000000000000000000000000000".to_string(),
+            NetworkVersion::new("TEST_CHAIN".to_string(), 0, 0).into(),
+            timeouts,
+        )
+    }
+
+    fn create_runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .expect("Failed to create tokio runtime")
+    }
+
+    #[test]
+    fn test_handshake_timeout() -> Result<(), failure::Error> {
+        let log = Logger::root(slog::Discard, slog::o!());
+        let info = Arc::new(local(PeerTimeouts { handshake: Duration::from_millis(200), idle: IDLE_TIMEOUT }));
+
+        create_runtime().block_on(async {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await?;
+            let address = listener.local_addr()?;
+            let stream = TcpStream::connect(address).await?;
+            // remote peer accepts connection, but never answers
+            let (_silent_remote, _) = listener.accept().await?;
+
+            match bootstrap(Bootstrap::outgoing(stream, address, false, false), info, &log).await {
+                Ok(_) => panic!("Handshake with the silent peer should not succeed"),
+                Err(HandshakeError { stage, error, .. }) => {
+                    assert_eq!(HandshakeStage::ConnectionMessage, stage);
+                    // handshake limit is shorter than the io timeout, so it is reported
+                    assert_eq!("handshake_timeout", error.reason());
+                }
+            }
+            Ok::<_, failure::Error>(())
+        })
+    }
+
+    #[test]
+    fn test_idle_timeout() -> Result<(), failure::Error> {
+        let actor_system = SystemBuilder::new().name("test_idle_timeout").create().expect("Failed to create actor system");
+        let network_channel = NetworkChannel::actor(&actor_system).expect("Failed to create network channel");
+        let timed_out = Arc::new(StdMutex::new(Vec::new()));
+        let _ = actor_system.actor_of_props::<IdleTimeouts>("idle-timeouts", Props::new_args((network_channel.clone(), timed_out.clone())))
+            .expect("Failed to create collector");
+        let mut tokio_runtime = create_runtime();
+        let tokio_executor = tokio_runtime.handle().clone();
+
+        // peers do not send any message after the handshake, just the idle one times out
+        let create_peer = |name: &str, idle: Duration, address: &SocketAddr| actor_system.actor_of_props(
+            name,
+            Props::new_args::<Peer, _>((
+                network_channel.clone(),
+                Arc::new(local(PeerTimeouts { handshake: HANDSHAKE_TIMEOUT, idle })),
+                tokio_executor.clone(),
+                *address,
+                None::<Arc<CaptureWriter>>,
+            )),
+        ).expect("Failed to create peer");
+
+        let idle_peer = tokio_runtime.block_on(async {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await?;
+            let address = listener.local_addr()?;
+            let outgoing = TcpStream::connect(address).await?;
+            let (incoming, incoming_address) = listener.accept().await?;
+
+            let idle_peer = create_peer("idle-peer", Duration::from_millis(300), &address);
+            let patient_peer = create_peer("patient-peer", IDLE_TIMEOUT, &incoming_address);
+            idle_peer.tell(Bootstrap::outgoing(outgoing, address, false, false), None);
+            patient_peer.tell(Bootstrap::incoming(Arc::new(Mutex::new(Some(incoming))), incoming_address, false, false), None);
+
+            let mut attempts = 0;
+            while timed_out.lock().unwrap().is_empty() && attempts < 50 {
+                tokio::time::delay_for(Duration::from_millis(100)).await;
+                attempts += 1;
+            }
+            Ok::<_, failure::Error>(idle_peer)
+        })?;
+
+        let timed_out = timed_out.lock().unwrap();
+        assert_eq!(1, timed_out.len());
+        assert_eq!(idle_peer.uri(), timed_out[0].uri());
+        Ok(())
+    }
+}
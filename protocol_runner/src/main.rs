@@ -142,11 +142,12 @@ mod tezos {
     use tezos_api::ffi::{
         ApplyBlockError, ApplyBlockRequest, ApplyBlockResponse, BeginApplicationError,
         BeginApplicationRequest, BeginApplicationResponse, BeginConstructionError,
-        BeginConstructionRequest, CommitGenesisResult, ComputePathError, ComputePathRequest,
-        ComputePathResponse, GenesisChain, GetDataError, HelpersPreapplyError,
-        HelpersPreapplyResponse, InitProtocolContextResult, PatchContext, PrevalidatorWrapper,
-        ProtocolDataError, ProtocolOverrides, ProtocolRpcError, ProtocolRpcRequest,
-        ProtocolRpcResponse, TezosRuntimeConfiguration, TezosRuntimeConfigurationError,
+        BeginConstructionRequest, CommitGenesisResult, CommitTestChainGenesisRequest,
+        CommitTestChainGenesisResult, ComputePathError, ComputePathRequest, ComputePathResponse,
+        GenesisChain, GetDataError, HelpersPreapplyError, HelpersPreapplyResponse,
+        InitProtocolContextResult, PatchContext, PrevalidatorWrapper, ProtocolDataError,
+        ProtocolOverrides, ProtocolRpcError, ProtocolRpcRequest, ProtocolRpcResponse,
+        TestChainGenesisError, TezosRuntimeConfiguration, TezosRuntimeConfigurationError,
         TezosStorageInitError, ValidateOperationError, ValidateOperationRequest,
         ValidateOperationResponse,
     };
@@ -242,6 +243,12 @@ mod tezos {
             )
         }
 
+        fn commit_test_chain_genesis(
+            request: CommitTestChainGenesisRequest,
+        ) -> Result<CommitTestChainGenesisResult, TestChainGenesisError> {
+            commit_test_chain_genesis(request)
+        }
+
         fn assert_encoding_for_protocol_data(
             protocol_hash: ProtocolHash,
             protocol_data: Vec<u8>,
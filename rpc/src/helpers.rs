@@ -406,23 +406,25 @@ pub(crate) fn parse_chain_id(chain_id_param: &str, env: &RpcServiceEnvironment)
             "test" => {
                 // find test chain for main chain
                 let chain_meta_storage = ChainMetaStorage::new(env.persistent_storage());
-                let test_chain = match chain_meta_storage.get_test_chain_id(env.main_chain_id())? {
+                match chain_meta_storage.get_test_chain_id(env.main_chain_id())? {
                     Some(test_chain_id) => test_chain_id,
                     None => bail!("No test chain activated for main_chain_id: {}", HashType::ChainId.bytes_to_string(env.main_chain_id()))
-                };
-
-                bail!("Test chains are not supported yet! main_chain_id: {}, test_chain_id: {}",
-                    HashType::ChainId.bytes_to_string(env.main_chain_id()),
-                    HashType::ChainId.bytes_to_string(&test_chain))
+                }
             }
             chain_id_hash => {
                 let chain_id = HashType::ChainId.string_to_bytes(chain_id_hash)?;
                 if chain_id.eq(env.main_chain_id()) {
                     chain_id
                 } else {
-                    bail!("Multiple chains are not supported yet! requested_chain_id: {} only main_chain_id: {}",
-                        HashType::ChainId.bytes_to_string(&chain_id),
-                        HashType::ChainId.bytes_to_string(env.main_chain_id()))
+                    // besides the main chain, only active chains are supported
+                    let chain_meta_storage = ChainMetaStorage::new(env.persistent_storage());
+                    if chain_meta_storage.get_active_chains()?.iter().any(|(active_chain_id, _)| active_chain_id.eq(&chain_id)) {
+                        chain_id
+                    } else {
+                        bail!("Chain is not active! requested_chain_id: {}, main_chain_id: {}",
+                            HashType::ChainId.bytes_to_string(&chain_id),
+                            HashType::ChainId.bytes_to_string(env.main_chain_id()))
+                    }
                 }
             }
         }
@@ -471,7 +473,15 @@ pub(crate) fn parse_block_hash(chain_id: &ChainId, block_id_param: &str, env: &R
     };
 
     // closure for current head
-    let current_head = || {
+    let current_head = || -> Result<(BlockHash, Level), failure::Error> {
+        if chain_id.ne(env.main_chain_id()) {
+            // test chain has its own current head, which is not tracked in RPC state
+            return match ChainMetaStorage::new(env.persistent_storage()).get_current_head(chain_id)? {
+                Some(current_head) => Ok((current_head.block_hash().clone(), *current_head.level())),
+                None => bail!("Head not initialized for chain_id: {}", HashType::ChainId.bytes_to_string(chain_id))
+            };
+        }
+
         let state_read = env.state().read().unwrap();
         match state_read.current_head().as_ref() {
             Some(current_head) => Ok(
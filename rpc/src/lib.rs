@@ -9,6 +9,7 @@ pub use services::mempool_services::MempoolOperations;
 
 pub mod encoding;
 mod helpers;
+mod operation_tracker;
 pub mod rpc_actor;
 mod server;
 mod services;
@@ -90,11 +91,36 @@ pub(crate) fn not_found() -> ServiceResult {
         .body(Body::from("not found"))?)
 }
 
+/// Generate 403 response
+pub(crate) fn forbidden() -> ServiceResult {
+    Ok(Response::builder()
+        .status(StatusCode::from_u16(403)?)
+        .body(Body::from("forbidden"))?)
+}
+
+/// Generate 405 response
+pub(crate) fn method_not_allowed() -> ServiceResult {
+    Ok(Response::builder()
+        .status(StatusCode::from_u16(405)?)
+        .body(Body::from("method not allowed"))?)
+}
+
 /// Generate 500 error
 pub(crate) fn error(error: failure::Error) -> ServiceResult {
     error_with_message(format!("{:?}", error))
 }
 
+/// Generate 500 error with JSON as body (e.g. protocol errors)
+pub(crate) fn error_with_json<T: serde::Serialize>(content: &T) -> ServiceResult {
+    Ok(Response::builder()
+        .status(StatusCode::from_u16(500)?)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .header(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(hyper::header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type")
+        .header(hyper::header::ACCESS_CONTROL_ALLOW_HEADERS, "content-type")
+        .body(Body::from(serde_json::to_string(content)?))?)
+}
+
 /// Generate 500 error with message as body
 pub(crate) fn error_with_message(error_msg: String) -> ServiceResult {
     Ok(Response::builder()
@@ -0,0 +1,308 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Tracks lifecycle of the operations seen by the node (received, validated by mempool, relayed to the peers, included in the block),
+//! so clients can ask, what happened with the injected operation.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crypto::hash::{BlockHash, HashType, OperationHash};
+use shell::mempool_result::{MempoolValidationResult, OperationClassification};
+use shell::shell_channel::MempoolOperationSource;
+use tezos_api::ffi::Errored;
+
+use crate::encoding::base_types::TimeStamp;
+
+/// How many operations are tracked, the oldest ones are forgotten
+pub(crate) const MAX_TRACKED_OPERATIONS: usize = 10_000;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum OperationEvent {
+    /// Operation was received by the node
+    Received {
+        source: &'static str,
+        peer_id: Option<String>,
+        time: TimeStamp,
+    },
+    /// Mempool prevalidation classified the operation (error is set for refused/branch_refused/branch_delayed)
+    Validated {
+        classification: &'static str,
+        error: Option<Value>,
+        time: TimeStamp,
+    },
+    /// Operation was advertised to the peers
+    Relayed {
+        peers: usize,
+        time: TimeStamp,
+    },
+    /// Operation was banned by rpc
+    Banned {
+        time: TimeStamp,
+    },
+    /// Operation was included in the block of the current head
+    Included {
+        block_hash: String,
+        level: i32,
+        time: TimeStamp,
+    },
+}
+
+/// Current status of the operation with all recorded events
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct OperationStatus {
+    /// Last mempool classification
+    pub classification: Option<&'static str>,
+    /// Block, in which operation was included
+    pub included_in: Option<String>,
+    pub events: Vec<OperationEvent>,
+}
+
+impl OperationStatus {
+    /// Protocol error of the last classification
+    pub fn last_error(&self) -> Option<&Value> {
+        self.events.iter().rev()
+            .find_map(|event| match event {
+                OperationEvent::Validated { error, .. } => Some(error.as_ref()),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    fn is_relayed(&self) -> bool {
+        // just the first relay after (re)receiving is recorded, the whole mempool is advertised on every change
+        self.events.iter().rev()
+            .take_while(|event| match event {
+                OperationEvent::Received { .. } => false,
+                _ => true,
+            })
+            .any(|event| match event {
+                OperationEvent::Relayed { .. } => true,
+                _ => false,
+            })
+    }
+}
+
+/// Bounded storage of the tracked operations
+pub struct OperationTracker {
+    capacity: usize,
+    /// Tracked operations ordered by the first reception
+    order: VecDeque<OperationHash>,
+    operations: HashMap<OperationHash, OperationStatus>,
+    /// Tracked operations, which are not yet included in the block
+    awaiting_inclusion: HashSet<OperationHash>,
+}
+
+impl OperationTracker {
+    pub fn new(capacity: usize) -> Self {
+        OperationTracker {
+            capacity,
+            order: VecDeque::new(),
+            operations: HashMap::new(),
+            awaiting_inclusion: HashSet::new(),
+        }
+    }
+
+    pub fn get(&self, operation_hash: &OperationHash) -> Option<&OperationStatus> {
+        self.operations.get(operation_hash)
+    }
+
+    /// Starts tracking of the operation (or records repeated reception, e.g. after reorganization)
+    pub fn received(&mut self, operation_hash: &OperationHash, source: &MempoolOperationSource, time: TimeStamp) {
+        let (source_name, peer_id) = match source {
+            MempoolOperationSource::Rpc => ("rpc", None),
+            MempoolOperationSource::P2p(peer_id) => ("p2p", Some(peer_id.clone())),
+            MempoolOperationSource::Reinjected => ("reinjected", None),
+        };
+
+        if !self.operations.contains_key(operation_hash) {
+            if self.order.len() >= self.capacity {
+                if let Some(forgotten) = self.order.pop_front() {
+                    self.operations.remove(&forgotten);
+                    self.awaiting_inclusion.remove(&forgotten);
+                }
+            }
+            self.order.push_back(operation_hash.clone());
+        }
+
+        let status = self.operations.entry(operation_hash.clone()).or_default();
+        if let MempoolOperationSource::Reinjected = source {
+            // block with the operation is not on the main chain anymore
+            status.included_in = None;
+        }
+        status.classification = None;
+        status.events.push(OperationEvent::Received { source: source_name, peer_id, time });
+        if status.included_in.is_none() {
+            self.awaiting_inclusion.insert(operation_hash.clone());
+        }
+    }
+
+    /// Records changed classifications of the tracked operations, returns true, if anything changed
+    pub fn validated(&mut self, result: &MempoolValidationResult, time: TimeStamp) -> bool {
+        let mut changed = false;
+        for operation_hash in &self.awaiting_inclusion {
+            let status = match self.operations.get_mut(operation_hash) {
+                Some(status) => status,
+                None => continue,
+            };
+            // error is looked up just for the changed classification
+            let classification = result.classification(operation_hash).map(classification_name);
+            if classification.is_none() || status.classification == classification {
+                continue;
+            }
+            if let Some((classification, error)) = classify(result, operation_hash) {
+                status.classification = Some(classification);
+                status.events.push(OperationEvent::Validated { classification, error, time: time.clone() });
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Records relay of the tracked operations, returns true, if anything changed
+    pub fn relayed(&mut self, operation_hashes: &[OperationHash], peers: usize, time: TimeStamp) -> bool {
+        let mut changed = false;
+        for operation_hash in operation_hashes {
+            if let Some(status) = self.operations.get_mut(operation_hash) {
+                if !status.is_relayed() {
+                    status.events.push(OperationEvent::Relayed { peers, time: time.clone() });
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Records ban of the tracked operation, returns true, if operation is tracked
+    pub fn banned(&mut self, operation_hash: &OperationHash, time: TimeStamp) -> bool {
+        match self.operations.get_mut(operation_hash) {
+            Some(status) => {
+                status.events.push(OperationEvent::Banned { time });
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Returns true, if any tracked operation is not yet included in the block
+    pub fn awaits_inclusion(&self) -> bool {
+        !self.awaiting_inclusion.is_empty()
+    }
+
+    /// Records inclusion of the tracked operations in the block, returns true, if anything changed
+    pub fn included(&mut self, block_hash: &BlockHash, level: i32, operation_hashes: &[OperationHash], time: TimeStamp) -> bool {
+        let block_hash = HashType::BlockHash.bytes_to_string(block_hash);
+        let mut changed = false;
+        for operation_hash in operation_hashes {
+            if let Some(status) = self.operations.get_mut(operation_hash) {
+                if status.included_in.is_none() {
+                    self.awaiting_inclusion.remove(operation_hash);
+                    status.included_in = Some(block_hash.clone());
+                    status.events.push(OperationEvent::Included { block_hash: block_hash.clone(), level, time: time.clone() });
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// Returns classification of the operation with the protocol error (for refused/branch_refused/branch_delayed)
+pub(crate) fn classify(result: &MempoolValidationResult, operation_hash: &OperationHash) -> Option<(&'static str, Option<Value>)> {
+    let classification = result.classification(operation_hash)?;
+    let error = match classification {
+        OperationClassification::Applied => None,
+        OperationClassification::Refused => find_error(result.refused(), operation_hash),
+        OperationClassification::BranchRefused => find_error(result.branch_refused(), operation_hash),
+        OperationClassification::BranchDelayed => find_error(result.branch_delayed(), operation_hash),
+    };
+    Some((classification_name(classification), error))
+}
+
+fn classification_name(classification: OperationClassification) -> &'static str {
+    match classification {
+        OperationClassification::Applied => "applied",
+        OperationClassification::Refused => "refused",
+        OperationClassification::BranchRefused => "branch_refused",
+        OperationClassification::BranchDelayed => "branch_delayed",
+    }
+}
+
+fn find_error(errored: &[Errored], operation_hash: &OperationHash) -> Option<Value> {
+    errored.iter()
+        .find(|errored| errored.hash.eq(operation_hash))
+        .and_then(|errored| serde_json::from_str(&errored.protocol_data_json_with_error_json.error_json).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use tezos_api::ffi::{Applied, OperationProtocolDataJsonWithErrorListJson, ValidateOperationResult};
+
+    use super::*;
+
+    #[test]
+    fn test_operation_lifecycle() {
+        let operation_hash: OperationHash = vec![1; 32];
+        let block_hash: BlockHash = vec![2; 32];
+        let time = TimeStamp::Integral(0);
+        let mut tracker = OperationTracker::new(1);
+
+        tracker.received(&operation_hash, &MempoolOperationSource::P2p("idtqxHUjbjbCfaDn4jczoPGsnhacKX".to_string()), time.clone());
+
+        let refused = ValidateOperationResult {
+            refused: vec![Errored {
+                hash: operation_hash.clone(),
+                is_endorsement: None,
+                protocol_data_json_with_error_json: OperationProtocolDataJsonWithErrorListJson {
+                    protocol_data_json: "{}".to_string(),
+                    error_json: "[{\"kind\":\"temporary\"}]".to_string(),
+                },
+            }],
+            ..Default::default()
+        };
+        assert!(tracker.validated(&refused.into(), time.clone()));
+        assert!(tracker.get(&operation_hash).and_then(OperationStatus::last_error).is_some());
+        let applied = ValidateOperationResult {
+            applied: vec![Applied { hash: operation_hash.clone(), protocol_data_json: "{}".to_string() }],
+            ..Default::default()
+        };
+        let applied: MempoolValidationResult = applied.into();
+        assert!(tracker.validated(&applied, time.clone()));
+        // unchanged classification is not recorded
+        assert!(!tracker.validated(&applied, time.clone()));
+
+        // just the first relay is recorded
+        assert!(tracker.relayed(&[operation_hash.clone()], 3, time.clone()));
+        assert!(!tracker.relayed(&[operation_hash.clone()], 3, time.clone()));
+
+        assert!(tracker.awaits_inclusion());
+        assert!(tracker.included(&block_hash, 5, &[operation_hash.clone()], time.clone()));
+        assert!(!tracker.awaits_inclusion());
+
+        let status = tracker.get(&operation_hash).expect("Operation is tracked");
+        assert_eq!(Some("applied"), status.classification);
+        // error of the previous classification is not reported
+        assert!(status.last_error().is_none());
+        assert_eq!(Some(HashType::BlockHash.bytes_to_string(&block_hash)), status.included_in);
+        assert_eq!(5, status.events.len());
+        match &status.events[1] {
+            OperationEvent::Validated { classification, error, .. } => {
+                assert_eq!("refused", *classification);
+                assert!(error.is_some());
+            }
+            event => panic!("Unexpected event: {:?}", event),
+        }
+
+        // reinjected operation awaits inclusion again
+        tracker.received(&operation_hash, &MempoolOperationSource::Reinjected, time.clone());
+        assert!(tracker.awaits_inclusion());
+        assert!(tracker.validated(&applied, time.clone()));
+
+        // the oldest operation is forgotten
+        tracker.received(&vec![3; 32], &MempoolOperationSource::Rpc, time);
+        assert!(tracker.get(&operation_hash).is_none());
+    }
+}
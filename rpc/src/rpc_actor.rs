@@ -3,23 +3,29 @@
 
 use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
+use std::task::Waker;
 
 use getset::{CopyGetters, Getters, Setters};
 use riker::actors::*;
 use slog::{Logger, warn};
 use tokio::runtime::Handle;
 
-use crypto::hash::ChainId;
+use crypto::hash::{BlockHash, ChainId, OperationHash};
 use shell::shell_channel::{BlockApplied, CurrentMempoolState, ShellChannelMsg, ShellChannelRef, ShellChannelTopic};
+use shell::configuration::ShellConfigurationRef;
+use shell::mempool_filter::MempoolFilterRef;
+use shell::stats::handshake::HandshakeStatsRef;
+use storage::{OperationsStorage, OperationsStorageReader, StorageInitInfo};
 use storage::persistent::PersistentStorage;
 use storage::context::TezedgeContext;
-use storage::StorageInitInfo;
 use tezos_api::environment::TezosEnvironmentConfiguration;
+use tezos_messages::p2p::binary_message::MessageHash;
 use tezos_messages::p2p::encoding::version::NetworkVersion;
 use tezos_wrapper::TezosApiConnectionPool;
 
 use crate::encoding::base_types::TimeStamp;
 use crate::helpers::current_time_timestamp;
+use crate::operation_tracker::{MAX_TRACKED_OPERATIONS, OperationTracker};
 use crate::server::{RpcServiceEnvironment, spawn_server};
 
 
@@ -40,6 +46,33 @@ pub struct RpcCollectedState {
     head_update_time: TimeStamp,
     #[get_copy = "pub(crate)"]
     is_sandbox: bool,
+    /// Streams waiting for the next mempool state
+    mempool_state_wakers: Vec<Waker>,
+    /// Lifecycle of the recently received operations
+    #[get = "pub(crate)"]
+    operation_tracker: OperationTracker,
+    /// Streams waiting for the next change of the tracked operations
+    operation_status_wakers: Vec<Waker>,
+}
+
+impl RpcCollectedState {
+    /// Registers stream to be woken up, when mempool state changes
+    pub(crate) fn wake_on_mempool_state_changed(&mut self, waker: &Waker) {
+        if !self.mempool_state_wakers.iter().any(|registered| registered.will_wake(waker)) {
+            self.mempool_state_wakers.push(waker.clone());
+        }
+    }
+
+    /// Registers stream to be woken up, when status of any tracked operation changes
+    pub(crate) fn wake_on_operation_status_changed(&mut self, waker: &Waker) {
+        if !self.operation_status_wakers.iter().any(|registered| registered.will_wake(waker)) {
+            self.operation_status_wakers.push(waker.clone());
+        }
+    }
+
+    fn operation_status_changed(&mut self) {
+        self.operation_status_wakers.drain(..).for_each(Waker::wake);
+    }
 }
 
 /// Actor responsible for managing HTTP REST API and server, and to share parts of inner actor
@@ -48,6 +81,7 @@ pub struct RpcCollectedState {
 pub struct RpcServer {
     shell_channel: ShellChannelRef,
     state: RpcCollectedStateRef,
+    operations_storage: OperationsStorage,
 }
 
 impl RpcServer {
@@ -66,16 +100,22 @@ impl RpcServer {
         tezos_env: TezosEnvironmentConfiguration,
         network_version: NetworkVersion,
         init_storage_data: &StorageInitInfo,
+        handshake_stats: HandshakeStatsRef,
+        shell_config: ShellConfigurationRef,
+        mempool_filter: MempoolFilterRef,
         is_sandbox: bool) -> Result<RpcServerRef, CreateError> {
         let shared_state = Arc::new(RwLock::new(RpcCollectedState {
             current_head: load_current_head(persistent_storage, &init_storage_data.chain_id, &sys.log()),
             current_mempool_state: None,
             head_update_time: current_time_timestamp(),
             is_sandbox,
+            mempool_state_wakers: Vec::new(),
+            operation_tracker: OperationTracker::new(MAX_TRACKED_OPERATIONS),
+            operation_status_wakers: Vec::new(),
         }));
         let actor_ref = sys.actor_of_props::<RpcServer>(
             Self::name(),
-            Props::new_args((shell_channel.clone(), shared_state.clone())),
+            Props::new_args((shell_channel.clone(), shared_state.clone(), OperationsStorage::new(persistent_storage))),
         )?;
 
         // spawn RPC JSON server
@@ -94,6 +134,9 @@ impl RpcServer {
                 init_storage_data.chain_id.clone(),
                 init_storage_data.genesis_block_header_hash.clone(),
                 shared_state,
+                handshake_stats,
+                shell_config,
+                mempool_filter,
                 &sys.log(),
             );
             let inner_log = sys.log();
@@ -109,9 +152,9 @@ impl RpcServer {
     }
 }
 
-impl ActorFactoryArgs<(ShellChannelRef, RpcCollectedStateRef)> for RpcServer {
-    fn create_args((shell_channel, state): (ShellChannelRef, RpcCollectedStateRef)) -> Self {
-        Self { shell_channel, state }
+impl ActorFactoryArgs<(ShellChannelRef, RpcCollectedStateRef, OperationsStorage)> for RpcServer {
+    fn create_args((shell_channel, state, operations_storage): (ShellChannelRef, RpcCollectedStateRef, OperationsStorage)) -> Self {
+        Self { shell_channel, state, operations_storage }
     }
 }
 
@@ -133,22 +176,66 @@ impl Actor for RpcServer {
 impl Receive<ShellChannelMsg> for RpcServer {
     type Msg = RpcServerMsg;
 
-    fn receive(&mut self, _ctx: &Context<Self::Msg>, msg: ShellChannelMsg, _sender: Sender) {
+    fn receive(&mut self, ctx: &Context<Self::Msg>, msg: ShellChannelMsg, _sender: Sender) {
         match msg {
             ShellChannelMsg::NewCurrentHead(_, block) => {
                 let current_head_ref = &mut *self.state.write().unwrap();
+                if current_head_ref.operation_tracker.awaits_inclusion() {
+                    match load_operation_hashes(&self.operations_storage, &block.header().hash) {
+                        Ok(operation_hashes) => {
+                            let level = block.header().header.level();
+                            if current_head_ref.operation_tracker.included(&block.header().hash, level, &operation_hashes, current_time_timestamp()) {
+                                current_head_ref.operation_status_changed();
+                            }
+                        }
+                        Err(e) => warn!(ctx.system.log(), "Failed to load operations of the new current head"; "reason" => format!("{}", e)),
+                    }
+                }
                 current_head_ref.current_head = Some(block);
                 current_head_ref.head_update_time = current_time_timestamp();
             }
             ShellChannelMsg::MempoolStateChanged(result) => {
                 let current_state = &mut *self.state.write().unwrap();
+                let validated = current_state.operation_tracker.validated(&result.read().unwrap().result, current_time_timestamp());
+                if validated {
+                    current_state.operation_status_changed();
+                }
                 current_state.current_mempool_state = Some(result);
+                current_state.mempool_state_wakers.drain(..).for_each(Waker::wake);
+            }
+            ShellChannelMsg::MempoolOperationReceived(operation) => {
+                let current_state = &mut *self.state.write().unwrap();
+                current_state.operation_tracker.received(&operation.operation_hash, &operation.source, current_time_timestamp());
+                current_state.operation_status_changed();
+            }
+            ShellChannelMsg::MempoolOperationsAdvertised(advertised) => {
+                let current_state = &mut *self.state.write().unwrap();
+                if current_state.operation_tracker.relayed(&advertised.operation_hashes, advertised.peers, current_time_timestamp()) {
+                    current_state.operation_status_changed();
+                }
+            }
+            ShellChannelMsg::MempoolOperationBanned(banned) => {
+                let current_state = &mut *self.state.write().unwrap();
+                if current_state.operation_tracker.banned(&banned.operation_hash, current_time_timestamp()) {
+                    current_state.operation_status_changed();
+                }
             }
             _ => (/* Not yet implemented, do nothing */),
         }
     }
 }
 
+/// Load hashes of all operations of the block
+fn load_operation_hashes(operations_storage: &OperationsStorage, block_hash: &BlockHash) -> Result<Vec<OperationHash>, failure::Error> {
+    let mut operation_hashes = Vec::new();
+    for operations in operations_storage.get_operations(block_hash)? {
+        for operation in operations.operations() {
+            operation_hashes.push(operation.message_hash()?);
+        }
+    }
+    Ok(operation_hashes)
+}
+
 /// Load local head (block with highest level) from dedicated storage
 fn load_current_head(persistent_storage: &PersistentStorage, chain_id: &ChainId, log: &Logger) -> Option<BlockApplied> {
     use storage::{BlockStorage, BlockStorageReader, ChainMetaStorage, StorageError};
@@ -1,10 +1,12 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use hyper::{Body, Request};
+use hyper::{Body, Method, Request};
 use slog::warn;
 
-use crate::{empty, make_json_response, result_to_json_response, ServiceResult};
+use shell::stats::mempool::MempoolStats;
+
+use crate::{empty, forbidden, make_json_response, make_json_stream_response, method_not_allowed, result_option_to_json_response, result_to_json_response, ServiceResult};
 use crate::helpers::{parse_block_hash, parse_chain_id};
 use crate::server::{HasSingleValue, Params, Query, RpcServiceEnvironment};
 use crate::services::{base_services, dev_services};
@@ -108,9 +110,62 @@ pub async fn dev_stats_memory(_: Request<Body>, _: Params, _: Query, env: RpcSer
     }
 }
 
+pub async fn dev_stats_handshakes(_: Request<Body>, _: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    let handshake_stats = env.handshake_stats().read().unwrap().clone();
+    make_json_response(&handshake_stats)
+}
+
+pub async fn dev_stats_mempool(_: Request<Body>, _: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    let mempool_stats = match env.state().read().unwrap().current_mempool_state() {
+        Some(mempool_state) => mempool_state.read().unwrap().stats.clone(),
+        None => MempoolStats::default(),
+    };
+    make_json_response(&mempool_stats)
+}
+
+/// Returns shell configuration (GET) or changes its runtime configurable subset (POST/PUT)
+///
+/// Route is not authenticated, so configuration can be changed just in sandbox mode.
+pub async fn dev_shell_configuration(req: Request<Body>, _: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    match *req.method() {
+        Method::GET => {
+            let shell_config = *env.shell_config().read().unwrap();
+            return make_json_response(&shell_config);
+        }
+        Method::PUT | Method::POST => {
+            if !env.state().read().unwrap().is_sandbox() {
+                warn!(env.log(), "Shell configuration can be changed just in sandbox mode");
+                return forbidden();
+            }
+        }
+        _ => return method_not_allowed(),
+    }
+
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    result_to_json_response(
+        dev_services::update_shell_configuration(&body, env.shell_config(), env.log()),
+        env.log(),
+    )
+}
+
 pub async fn database_memstats(_: Request<Body>, _: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
     result_to_json_response(
         dev_services::get_database_memstats(env.tezedge_context()),
         env.log(),
     )
-}
\ No newline at end of file
+}
+
+/// Returns lifecycle of the operation (received, validated, relayed, included), if the node still tracks it
+pub async fn dev_operation_status(_: Request<Body>, params: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    result_option_to_json_response(
+        dev_services::get_operation_status(params.get_str("operation_hash").unwrap(), env.state()),
+        env.log(),
+    )
+}
+
+/// Streams status of the operation on every change, until the operation is included in the block
+pub async fn dev_operation_status_monitor(_: Request<Body>, params: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    make_json_stream_response(
+        dev_services::get_operation_status_stream(params.get_str("operation_hash").unwrap(), env.state())?
+    )
+}
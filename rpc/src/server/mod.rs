@@ -15,6 +15,9 @@ use slog::{error, Logger};
 
 use crypto::hash::{BlockHash, ChainId};
 use shell::shell_channel::ShellChannelRef;
+use shell::configuration::ShellConfigurationRef;
+use shell::mempool_filter::MempoolFilterRef;
+use shell::stats::handshake::HandshakeStatsRef;
 use storage::context::TezedgeContext;
 use storage::persistent::PersistentStorage;
 use tezos_api::environment::TezosEnvironmentConfiguration;
@@ -49,6 +52,12 @@ pub struct RpcServiceEnvironment {
     #[get = "pub(crate)"]
     network_version: NetworkVersion,
     #[get = "pub(crate)"]
+    handshake_stats: HandshakeStatsRef,
+    #[get = "pub(crate)"]
+    shell_config: ShellConfigurationRef,
+    #[get = "pub(crate)"]
+    mempool_filter: MempoolFilterRef,
+    #[get = "pub(crate)"]
     log: Logger,
 
     #[get = "pub(crate)"]
@@ -79,6 +88,9 @@ impl RpcServiceEnvironment {
         main_chain_id: ChainId,
         main_chain_genesis_hash: BlockHash,
         state: RpcCollectedStateRef,
+        handshake_stats: HandshakeStatsRef,
+        shell_config: ShellConfigurationRef,
+        mempool_filter: MempoolFilterRef,
         log: &Logger) -> Self {
         Self {
             sys,
@@ -91,6 +103,9 @@ impl RpcServiceEnvironment {
             main_chain_id,
             main_chain_genesis_hash,
             state,
+            handshake_stats,
+            shell_config,
+            mempool_filter,
             log: log.clone(),
             tezos_readonly_api,
             tezos_readonly_prevalidation_api,
@@ -189,6 +204,15 @@ pub trait HasSingleValue {
     fn contains_key(&self, key: &str) -> bool {
         self.get_str(key).is_some()
     }
+
+    /// Parses boolean flag like the OCaml node, flag without value (e.g. `?refused`) is true
+    fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get_str(key)? {
+            "" | "true" | "yes" => Some(true),
+            "false" | "no" => Some(false),
+            _ => None,
+        }
+    }
 }
 
 impl HasSingleValue for Params {
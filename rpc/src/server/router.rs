@@ -27,6 +27,13 @@ pub(crate) fn create_routes(is_sandbox: bool) -> PathTree<Handler> {
     routes.handle("/chains/:chain_id/blocks/:block_id/header", shell_handler::chains_block_id_header);
     routes.handle("/chains/:chain_id/blocks/:block_id/header/shell", shell_handler::chains_block_id_header_shell);
     routes.handle("/chains/:chain_id/mempool/pending_operations", shell_handler::mempool_pending_operations);
+    routes.handle("/chains/:chain_id/mempool/baking_operations", shell_handler::mempool_baking_operations);
+    routes.handle("/chains/:chain_id/mempool/monitor_operations", shell_handler::mempool_monitor_operations);
+    routes.handle("/chains/:chain_id/mempool/filter", shell_handler::mempool_filter);
+    routes.handle("/chains/:chain_id/mempool/ban_operation", shell_handler::mempool_ban_operation);
+    routes.handle("/chains/:chain_id/mempool/unban_operation", shell_handler::mempool_unban_operation);
+    routes.handle("/chains/:chain_id/mempool/unban_all_operations", shell_handler::mempool_unban_all_operations);
+    routes.handle("/chains/:chain_id/mempool/request_operations", shell_handler::mempool_request_operations);
     routes.handle("/chains/:chain_id/blocks/:block_id/protocols", shell_handler::get_block_protocols);
     routes.handle("/chains/:chain_id/blocks/:block_id/hash", shell_handler::get_block_hash);
     routes.handle("/chains/:chain_id/blocks/:block_id/operation_hashes", shell_handler::get_block_operation_hashes);
@@ -57,6 +64,11 @@ pub(crate) fn create_routes(is_sandbox: bool) -> PathTree<Handler> {
     routes.handle("/dev/chains/main/actions/contracts/:contract_address", dev_handler::dev_action_cursor);
     routes.handle("/stats/memory", dev_handler::dev_stats_memory);
     routes.handle("/stats/database_mem", dev_handler::database_memstats);
+    routes.handle("/stats/handshakes", dev_handler::dev_stats_handshakes);
+    routes.handle("/stats/mempool", dev_handler::dev_stats_mempool);
+    routes.handle("/dev/shell/configuration", dev_handler::dev_shell_configuration);
+    routes.handle("/dev/operations/:operation_hash/status", dev_handler::dev_operation_status);
+    routes.handle("/dev/operations/:operation_hash/status/monitor", dev_handler::dev_operation_status_monitor);
     //routes.handle("/stats/storage", dev_handler::dev_stats_storage);
 
     routes
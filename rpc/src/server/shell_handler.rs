@@ -2,8 +2,9 @@
 // SPDX-License-Identifier: MIT
 
 use bytes::buf::BufExt;
-use hyper::{Body, Request};
+use hyper::{Body, Method, Request};
 use serde::Serialize;
+use slog::warn;
 
 use crypto::hash::{chain_id_to_b58_string, HashType};
 use shell::shell_channel::BlockApplied;
@@ -17,6 +18,7 @@ use crate::{
         base_types::*,
         monitor::BootstrapInfo,
     },
+    error_with_json,
     make_json_response,
     make_json_stream_response,
     result_option_to_json_response,
@@ -27,6 +29,7 @@ use crate::{
 use crate::helpers::{create_rpc_request, parse_block_hash, parse_chain_id};
 use crate::server::{HasSingleValue, HResult, Params, Query, RpcServiceEnvironment};
 use crate::services::base_services;
+use crate::services::mempool_services::{InjectionError, MonitorOperationsFilter};
 
 #[derive(Serialize)]
 pub struct ErrorMessage {
@@ -55,8 +58,11 @@ pub async fn commit_hash(_: Request<Body>, _: Params, _: Query, _: RpcServiceEnv
     make_json_response(&resp)
 }
 
-pub async fn active_chains(_: Request<Body>, _: Params, _: Query, _: RpcServiceEnvironment) -> HResult {
-    empty()
+pub async fn active_chains(_: Request<Body>, _: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    result_to_json_response(
+        base_services::get_active_chains(env.main_chain_id(), env.persistent_storage()),
+        env.log(),
+    )
 }
 
 pub async fn protocols(_: Request<Body>, _: Params, _: Query, _: RpcServiceEnvironment) -> HResult {
@@ -141,27 +147,107 @@ pub async fn mempool_pending_operations(_: Request<Body>, params: Params, _: Que
     )
 }
 
-pub async fn inject_operation(req: Request<Body>, _: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
-    let operation_data_raw = hyper::body::aggregate(req).await?;
-    let operation_data: String = serde_json::from_reader(&mut operation_data_raw.reader())?;
+pub async fn mempool_baking_operations(_: Request<Body>, params: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    let chain_id = parse_chain_id(params.get_str("chain_id").unwrap(), &env)?;
+    result_to_json_response(
+        services::mempool_services::get_baking_operations(&chain_id, &env),
+        env.log(),
+    )
+}
 
-    let shell_channel = env.shell_channel();
+pub async fn mempool_monitor_operations(_: Request<Body>, params: Params, query: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    let chain_id = parse_chain_id(params.get_str("chain_id").unwrap(), &env)?;
 
-    // TODO: TE-221 - add optional chain_id to params mapping
-    let chain_id_param = "main";
-    let chain_id = parse_chain_id(chain_id_param, &env)?;
+    let default = MonitorOperationsFilter::default();
+    let filter = MonitorOperationsFilter {
+        applied: query.get_bool("applied").unwrap_or(default.applied),
+        refused: query.get_bool("refused").unwrap_or(default.refused),
+        branch_refused: query.get_bool("branch_refused").unwrap_or(default.branch_refused),
+        branch_delayed: query.get_bool("branch_delayed").unwrap_or(default.branch_delayed),
+    };
+
+    make_json_stream_response(
+        services::mempool_services::get_mempool_monitor_operations(&chain_id, filter, &env)?
+    )
+}
 
+pub async fn mempool_filter(req: Request<Body>, params: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    let chain_id = parse_chain_id(params.get_str("chain_id").unwrap(), &env)?;
+    if req.method() == Method::GET {
+        return result_to_json_response(
+            services::mempool_services::get_mempool_filter(&chain_id, &env),
+            env.log(),
+        );
+    }
+
+    let body = hyper::body::to_bytes(req.into_body()).await?;
     result_to_json_response(
-        services::mempool_services::inject_operation(
-            chain_id,
-            &operation_data,
-            &env,
-            shell_channel,
-        ),
+        services::mempool_services::set_mempool_filter(&chain_id, &body, &env),
         env.log(),
     )
 }
 
+pub async fn mempool_ban_operation(req: Request<Body>, params: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    let chain_id = parse_chain_id(params.get_str("chain_id").unwrap(), &env)?;
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    result_to_json_response(
+        services::mempool_services::ban_operation(&chain_id, &body, &env),
+        env.log(),
+    )
+}
+
+pub async fn mempool_unban_operation(req: Request<Body>, params: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    let chain_id = parse_chain_id(params.get_str("chain_id").unwrap(), &env)?;
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    result_to_json_response(
+        services::mempool_services::unban_operation(&chain_id, &body, &env),
+        env.log(),
+    )
+}
+
+pub async fn mempool_unban_all_operations(_: Request<Body>, params: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    let chain_id = parse_chain_id(params.get_str("chain_id").unwrap(), &env)?;
+    result_to_json_response(
+        services::mempool_services::unban_all_operations(&chain_id, &env),
+        env.log(),
+    )
+}
+
+pub async fn mempool_request_operations(_: Request<Body>, params: Params, query: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    let chain_id = parse_chain_id(params.get_str("chain_id").unwrap(), &env)?;
+    let peer_id = query.get_str("peer_id").map(|peer_id| peer_id.to_string());
+    result_to_json_response(
+        services::mempool_services::request_operations(&chain_id, peer_id, &env),
+        env.log(),
+    )
+}
+
+pub async fn inject_operation(req: Request<Body>, _: Params, query: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    let operation_data_raw = hyper::body::aggregate(req).await?;
+    let operation_data: String = serde_json::from_reader(&mut operation_data_raw.reader())?;
+
+    let chain_id = parse_chain_id(query.get_str("chain").unwrap_or("main"), &env)?;
+    let is_async = query.get_bool("async").unwrap_or(false);
+    let wait_for_inclusion = query.get_bool("wait_for_inclusion").unwrap_or(false);
+
+    let result = services::mempool_services::inject_operation_with_options(
+        chain_id,
+        &operation_data,
+        is_async,
+        wait_for_inclusion,
+        &env,
+    ).await;
+
+    // operation refused by protocol, respond with the protocol errors like the OCaml node
+    if let Err(e) = &result {
+        if let Some(InjectionError::NotApplied { error, .. }) = e.downcast_ref::<InjectionError>() {
+            warn!(env.log(), "Injected operation was not applied"; "reason" => format!("{}", e));
+            return error_with_json(error);
+        }
+    }
+    result_to_json_response(result, env.log())
+}
+
 pub async fn inject_block(req: Request<Body>, _: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
     let body = hyper::body::to_bytes(req.into_body()).await?;
     let body = String::from_utf8(body.to_vec())?;
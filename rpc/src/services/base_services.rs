@@ -7,11 +7,15 @@ use crypto::hash::{BlockHash, chain_id_to_b58_string, ChainId, HashType};
 use shell::shell_channel::BlockApplied;
 use storage::{BlockHeaderWithHash, BlockMetaStorage, BlockMetaStorageReader, BlockStorage, BlockStorageReader, context_key};
 use storage::block_storage::BlockJsonData;
+use storage::chain_meta_storage::{ChainMetaStorage, ChainMetaStorageReader};
 use storage::context::ContextApi;
 use storage::merkle_storage::StringTree;
 use storage::persistent::PersistentStorage;
 use tezos_messages::p2p::encoding::version::NetworkVersion;
+use tezos_messages::ts_to_rfc3339;
 
+use crate::encoding::base_types::TimeStamp;
+use crate::encoding::monitor::{ActiveChains, ChainStatus};
 use crate::helpers::{BlockHeaderInfo, BlockHeaderShellInfo, FullBlockInfo, get_context_hash, MonitorHeadStream, NodeVersion, Protocols};
 use crate::rpc_actor::RpcCollectedStateRef;
 use crate::server::RpcServiceEnvironment;
@@ -135,6 +139,34 @@ pub(crate) fn get_node_version(network_version: &NetworkVersion) -> Result<NodeV
     Ok(NodeVersion::new(network_version))
 }
 
+/// Get main chain and all other active chains (e.g. test chain) synchronized by node
+pub(crate) fn get_active_chains(main_chain_id: &ChainId, persistent_storage: &PersistentStorage) -> Result<ActiveChains, failure::Error> {
+    let chain_meta_storage = ChainMetaStorage::new(persistent_storage);
+
+    let mut active_chains = vec![ChainStatus::basic(chain_id_to_b58_string(main_chain_id))];
+    for (chain_id, _) in chain_meta_storage.get_active_chains()? {
+        if chain_id.eq(main_chain_id) {
+            continue;
+        }
+
+        // test chain is reported with its protocol and expiration, when already resolved
+        let status = match chain_meta_storage.get_test_chain_data(&chain_id)? {
+            Some(data) => match (data.test_protocol, data.expiration) {
+                (Some(test_protocol), Some(expiration)) => ChainStatus::detailed(
+                    chain_id_to_b58_string(&chain_id),
+                    HashType::ProtocolHash.bytes_to_string(&test_protocol),
+                    TimeStamp::Rfc(ts_to_rfc3339(expiration)),
+                ),
+                _ => ChainStatus::basic(chain_id_to_b58_string(&chain_id)),
+            },
+            None => ChainStatus::basic(chain_id_to_b58_string(&chain_id)),
+        };
+        active_chains.push(status);
+    }
+
+    Ok(active_chains)
+}
+
 pub(crate) fn get_block_by_block_id(chain_id: &ChainId, block_hash: &BlockHash, persistent_storage: &PersistentStorage) -> Result<Option<FullBlockInfo>, failure::Error> {
     Ok(
         BlockStorage::new(persistent_storage)
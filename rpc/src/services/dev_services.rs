@@ -1,9 +1,15 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use slog::Logger;
+use std::pin::Pin;
 
-use crypto::hash::{BlockHash, HashType};
+use failure::format_err;
+use futures::Stream;
+use futures::task::{Context, Poll};
+use slog::{info, Logger};
+
+use crypto::hash::{BlockHash, HashType, OperationHash};
+use shell::configuration::{ShellConfiguration, ShellConfigurationRef, ShellConfigurationUpdate};
 use shell::stats::memory::{Memory, MemoryData, MemoryStatsResult};
 use storage::{ContextActionRecordValue, ContextActionStorage};
 use storage::context::{ContextApi, TezedgeContext};
@@ -14,6 +20,8 @@ use tezos_context::channel::ContextAction;
 use tezos_messages::protocol::UniversalValue;
 
 use crate::helpers::{get_action_types, PagedResult};
+use crate::operation_tracker::OperationStatus;
+use crate::rpc_actor::RpcCollectedStateRef;
 use crate::server::RpcServiceEnvironment;
 use crate::services::protocol::get_context_protocol_params;
 
@@ -92,4 +100,77 @@ pub(crate) fn get_cycle_length_for_block(block_hash: &BlockHash, env: &RpcServic
         slog::warn!(log, "Cycle length missing"; "block" => HashType::BlockHash.bytes_to_string(block_hash));
         Ok(4096)
     }
-}
\ No newline at end of file
+}
+
+/// Applies runtime changes to the shell configuration, configuration is changed only if the result is valid.
+pub(crate) fn update_shell_configuration(body: &[u8], shell_config: &ShellConfigurationRef, log: &Logger) -> Result<ShellConfiguration, failure::Error> {
+    let update: ShellConfigurationUpdate = serde_json::from_slice(body)?;
+
+    let mut shell_config = shell_config.write()
+        .map_err(|e| format_err!("Failed to lock shell configuration: {}", e))?;
+    let updated = shell_config.update(&update)?;
+    *shell_config = updated;
+
+    info!(log, "Shell configuration changed"; "update" => format!("{:?}", update));
+    Ok(updated)
+}
+
+/// Returns lifecycle of the operation, if it is tracked
+pub(crate) fn get_operation_status(operation_hash: &str, state: &RpcCollectedStateRef) -> Result<Option<OperationStatus>, failure::Error> {
+    let operation_hash = HashType::OperationHash.string_to_bytes(operation_hash)?;
+    let state = state.read().unwrap();
+    Ok(state.operation_tracker().get(&operation_hash).cloned())
+}
+
+pub(crate) fn get_operation_status_stream(operation_hash: &str, state: &RpcCollectedStateRef) -> Result<OperationStatusStream, failure::Error> {
+    let operation_hash = HashType::OperationHash.string_to_bytes(operation_hash)?;
+    Ok(OperationStatusStream {
+        state: state.clone(),
+        operation_hash,
+        streamed_events: 0,
+        finished: false,
+    })
+}
+
+/// Streams the whole status of the operation, every time a new event is recorded.
+/// Stream waits, until the operation is received, and ends after the operation is included in the block.
+pub(crate) struct OperationStatusStream {
+    state: RpcCollectedStateRef,
+    operation_hash: OperationHash,
+    streamed_events: usize,
+    finished: bool,
+}
+
+impl Stream for OperationStatusStream {
+    type Item = Result<String, serde_json::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+
+        // write lock, so the status cannot change between check and registration of the waker
+        let state = self.state.clone();
+        let mut state = state.write().unwrap();
+        let changed_status = state.operation_tracker().get(&self.operation_hash)
+            .filter(|status| status.events.len() > self.streamed_events)
+            .cloned();
+        let status = match changed_status {
+            Some(status) => status,
+            None => {
+                state.wake_on_operation_status_changed(cx.waker());
+                return Poll::Pending;
+            }
+        };
+        self.streamed_events = status.events.len();
+        self.finished = status.included_in.is_some();
+
+        let mut chunk = match serde_json::to_string(&status) {
+            Ok(chunk) => chunk,
+            Err(e) => return Poll::Ready(Some(Err(e))),
+        };
+        // push a newline character to the stream to improve readability
+        chunk.push('\n');
+        Poll::Ready(Some(Ok(chunk)))
+    }
+}
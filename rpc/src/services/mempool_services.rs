@@ -1,25 +1,34 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime};
 
-use failure::format_err;
+use failure::{Fail, format_err};
+use futures::Stream;
+use futures::task::{Context, Poll};
 use riker::actors::*;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
+use slog::{info, Logger, warn};
 
-use crypto::hash::{ChainId, HashType, OperationHash, ProtocolHash};
-use shell::shell_channel::{CurrentMempoolState, InjectBlock, MempoolOperationReceived, ShellChannelRef, ShellChannelTopic};
+use crypto::hash::{BlockHash, ChainId, HashType, OperationHash, ProtocolHash};
+use shell::mempool_filter::MempoolFilter;
+use shell::shell_channel::{CurrentMempoolState, InjectBlock, MempoolOperationBanned, MempoolOperationReceived, MempoolOperationSource, RequestMempoolOperations, ShellChannelRef, ShellChannelTopic};
 use shell::validation;
-use storage::{BlockMetaStorage, BlockMetaStorageReader, BlockStorage, BlockStorageReader, MempoolStorage};
-use storage::mempool_storage::MempoolOperationType;
+use storage::{BlockMetaStorage, BlockMetaStorageReader, BlockStorage, BlockStorageReader, MempoolStorage, SystemStorage};
+use storage::mempool_storage::{MempoolBlacklistReason, MempoolOperationType};
 use tezos_api::ffi::{Applied, ComputePathRequest, Errored};
 use tezos_messages::p2p::binary_message::{BinaryMessage, MessageHash};
 use tezos_messages::p2p::encoding::operation::DecodedOperation;
 use tezos_messages::p2p::encoding::prelude::{BlockHeader, Operation};
+use tezos_messages::protocol::{RpcJsonMap, UniversalValue};
 
+use crate::operation_tracker::{classify, OperationStatus};
+use crate::services::protocol::get_context_constants_just_for_rpc;
 use crate::rpc_actor::{RpcCollectedState, RpcCollectedStateRef};
 use crate::server::RpcServiceEnvironment;
 
@@ -58,10 +67,10 @@ pub fn get_pending_operations(
             };
 
             Ok(MempoolOperations {
-                applied: convert_applied(&mempool.result.applied, &mempool.operations)?,
-                refused: convert_errored(&mempool.result.refused, &mempool.operations, &protocol)?,
-                branch_refused: convert_errored(&mempool.result.branch_refused, &mempool.operations, &protocol)?,
-                branch_delayed: convert_errored(&mempool.result.branch_delayed, &mempool.operations, &protocol)?,
+                applied: convert_applied(mempool.result.applied(), &mempool.operations)?,
+                refused: convert_errored(mempool.result.refused(), &mempool.operations, &protocol)?,
+                branch_refused: convert_errored(mempool.result.branch_refused(), &mempool.operations, &protocol)?,
+                branch_delayed: convert_errored(mempool.result.branch_delayed(), &mempool.operations, &protocol)?,
                 unprocessed: vec![],
             })
         }
@@ -69,54 +78,315 @@ pub fn get_pending_operations(
     }
 }
 
+/// Applied mempool operations selected for the next block, split by validation pass like the block operations
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct BakingOperations {
+    pub consensus: Vec<HashMap<String, Value>>,
+    pub votes: Vec<HashMap<String, Value>>,
+    pub anonymous: Vec<HashMap<String, Value>>,
+    /// Sorted by fee per gas unit (the most profitable first)
+    pub manager: Vec<HashMap<String, Value>>,
+    /// Sum of gas limits of the selected manager operations
+    pub gas: u64,
+    /// Sum of sizes of the selected operations (bytes)
+    pub size: usize,
+}
+
+/// Limits of one validation pass (`validation_passes` of the protocol)
+#[derive(Copy, Clone, Debug)]
+struct ValidationPassLimits {
+    max_size: usize,
+    max_operations: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+struct BakingLimits {
+    /// consensus, votes, anonymous, manager
+    passes: [ValidationPassLimits; 4],
+    hard_gas_limit_per_block: u64,
+    max_operation_data_length: usize,
+}
+
+impl Default for BakingLimits {
+    /// Limits of the protocols 005 - 007, used also for missing constants
+    fn default() -> Self {
+        BakingLimits {
+            passes: [
+                ValidationPassLimits { max_size: 32 * 1024, max_operations: Some(32) },
+                ValidationPassLimits { max_size: 32 * 1024, max_operations: None },
+                ValidationPassLimits { max_size: 132 * 1024, max_operations: Some(132) },
+                ValidationPassLimits { max_size: 512 * 1024, max_operations: None },
+            ],
+            hard_gas_limit_per_block: 10_400_000,
+            max_operation_data_length: 16 * 1024,
+        }
+    }
+}
+
+impl BakingLimits {
+    fn from_constants(constants: &RpcJsonMap) -> Self {
+        let mut limits = BakingLimits::default();
+        if let Some(endorsers_per_block) = constant_as_u64(constants, "endorsers_per_block") {
+            limits.passes[0].max_operations = Some(endorsers_per_block as usize);
+        }
+        if let Some(hard_gas_limit_per_block) = constant_as_u64(constants, "hard_gas_limit_per_block") {
+            limits.hard_gas_limit_per_block = hard_gas_limit_per_block;
+        }
+        if let Some(max_operation_data_length) = constant_as_u64(constants, "max_operation_data_length") {
+            limits.max_operation_data_length = max_operation_data_length as usize;
+        }
+        limits
+    }
+}
+
+fn constant_as_u64(constants: &RpcJsonMap, name: &str) -> Option<u64> {
+    match constants.get(name)? {
+        UniversalValue::Number(value) => Some(*value as u64),
+        UniversalValue::NumberI64(value) => Some(*value as u64),
+        UniversalValue::BigNumber(value) => value.0.to_string().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Applied operation considered for the block
+#[derive(Debug)]
+struct BakingCandidate {
+    validation_pass: usize,
+    fee: u64,
+    gas: u64,
+    size: usize,
+    operation: HashMap<String, Value>,
+}
+
+impl BakingCandidate {
+    fn new(operation: HashMap<String, Value>, size: usize) -> Self {
+        let contents = operation.get("contents").and_then(Value::as_array).cloned().unwrap_or_default();
+        let validation_pass = contents.first()
+            .and_then(|content| content["kind"].as_str())
+            .map(validation_pass_of_kind)
+            .unwrap_or(3);
+        let sum = |name: &str| contents.iter()
+            .filter_map(|content| content[name].as_str())
+            .filter_map(|value| value.parse::<u64>().ok())
+            .sum::<u64>();
+
+        BakingCandidate {
+            validation_pass,
+            fee: sum("fee"),
+            gas: sum("gas_limit"),
+            size,
+            operation,
+        }
+    }
+
+    /// Compares fee per gas unit (fee / gas) without rounding, operation with the higher ratio is less
+    fn cmp_profitability(&self, other: &Self) -> std::cmp::Ordering {
+        let ratio = |candidate: &Self, other: &Self| candidate.fee as u128 * other.gas.max(1) as u128;
+        ratio(other, self).cmp(&ratio(self, other))
+            .then_with(|| other.fee.cmp(&self.fee))
+    }
+}
+
+/// Validation pass of the operation resolved by the kind of its (first) content
+fn validation_pass_of_kind(kind: &str) -> usize {
+    match kind {
+        "endorsement" | "endorsement_with_slot" => 0,
+        "proposals" | "ballot" => 1,
+        "seed_nonce_revelation" | "double_endorsement_evidence" | "double_baking_evidence" | "activate_account" => 2,
+        _ => 3,
+    }
+}
+
+/// Selects operations for the block within the limits, manager operations are sorted by fee per gas unit first.
+/// Operation, which does not fit, is skipped and the next ones are still considered.
+fn select_baking_operations(mut candidates: Vec<BakingCandidate>, limits: &BakingLimits) -> BakingOperations {
+    candidates.sort_by(|a, b| if a.validation_pass == 3 && b.validation_pass == 3 {
+        a.cmp_profitability(b)
+    } else {
+        a.validation_pass.cmp(&b.validation_pass)
+    });
+
+    let mut selected: [Vec<HashMap<String, Value>>; 4] = Default::default();
+    let mut pass_sizes = [0; 4];
+    let mut result = BakingOperations::default();
+    for candidate in candidates {
+        let pass_limits = &limits.passes[candidate.validation_pass];
+        let pass_operations = &mut selected[candidate.validation_pass];
+        if candidate.size > limits.max_operation_data_length
+            || pass_sizes[candidate.validation_pass] + candidate.size > pass_limits.max_size
+            || pass_limits.max_operations.map_or(false, |max_operations| pass_operations.len() >= max_operations)
+            || result.gas + candidate.gas > limits.hard_gas_limit_per_block {
+            continue;
+        }
+
+        pass_sizes[candidate.validation_pass] += candidate.size;
+        result.size += candidate.size;
+        result.gas += candidate.gas;
+        pass_operations.push(candidate.operation);
+    }
+
+    let [consensus, votes, anonymous, manager] = selected;
+    BakingOperations { consensus, votes, anonymous, manager, ..result }
+}
+
+/// Returns applied mempool operations selected for the next block (like the baker does),
+/// limits are taken from the protocol constants of the mempool head
+pub fn get_baking_operations(chain_id: &ChainId, env: &RpcServiceEnvironment) -> Result<BakingOperations, failure::Error> {
+    ensure_main_chain(chain_id, env)?;
+
+    let (head, candidates) = {
+        let state = env.state().read().unwrap();
+        let mempool = match state.current_mempool_state() {
+            Some(mempool) => mempool.read().unwrap(),
+            None => return Ok(BakingOperations::default()),
+        };
+        let head = match &mempool.head {
+            Some(head) => head.clone(),
+            None => return Ok(BakingOperations::default()),
+        };
+
+        let mut candidates = Vec::with_capacity(mempool.result.applied().len());
+        for applied in mempool.result.applied() {
+            let size = match mempool.operations.get(&applied.hash) {
+                Some(operation) => operation.as_bytes()?.len(),
+                None => continue,
+            };
+            candidates.push(BakingCandidate::new(convert_applied_operation(applied, &mempool.operations)?, size));
+        }
+        (head, candidates)
+    };
+
+    let limits = match get_context_constants_just_for_rpc(&head, env)? {
+        Some(constants) => BakingLimits::from_constants(&constants),
+        None => BakingLimits::default(),
+    };
+    Ok(select_baking_operations(candidates, &limits))
+}
+
+/// Returns mempool filter in the format of the OCaml node
+pub fn get_mempool_filter(chain_id: &ChainId, env: &RpcServiceEnvironment) -> Result<Value, failure::Error> {
+    ensure_main_chain(chain_id, env)?;
+    let filter = *env.mempool_filter().read().unwrap();
+    Ok(filter.to_json())
+}
+
+/// Replaces mempool filter (missing values are set to default) and stores it, so it survives restart,
+/// new filter is applied to the operations validated afterwards
+pub fn set_mempool_filter(chain_id: &ChainId, body: &[u8], env: &RpcServiceEnvironment) -> Result<Value, failure::Error> {
+    ensure_main_chain(chain_id, env)?;
+    let filter = MempoolFilter::from_json(body)?;
+    filter.store(&mut SystemStorage::new(env.persistent_storage().kv()))?;
+
+    *env.mempool_filter().write().unwrap() = filter;
+    info!(env.log(), "Mempool filter changed"; "filter" => filter.to_json().to_string());
+    Ok(filter.to_json())
+}
+
+/// Bans operation (body is a json string with operation hash), banned operation is removed from the mempool
+/// and it is not accepted again (neither from peers nor from rpc), until it is unbanned
+pub fn ban_operation(chain_id: &ChainId, body: &[u8], env: &RpcServiceEnvironment) -> Result<Value, failure::Error> {
+    ensure_main_chain(chain_id, env)?;
+    let operation_hash = parse_operation_hash(body)?;
+    let mempool_storage = MempoolStorage::new(env.persistent_storage());
+    mempool_storage.put_blacklisted(&operation_hash, MempoolBlacklistReason::Banned, SystemTime::now())?;
+
+    // remove operation from the mempool
+    env.shell_channel().tell(
+        Publish {
+            msg: MempoolOperationBanned {
+                operation_hash: operation_hash.clone(),
+            }.into(),
+            topic: ShellChannelTopic::ShellEvents.into(),
+        }, None);
+
+    info!(env.log(), "Mempool operation banned"; "hash" => HashType::OperationHash.bytes_to_string(&operation_hash));
+    Ok(json!({}))
+}
+
+/// Removes operation (body is a json string with operation hash) from the blacklist, so it can be received again (also refused operation)
+pub fn unban_operation(chain_id: &ChainId, body: &[u8], env: &RpcServiceEnvironment) -> Result<Value, failure::Error> {
+    ensure_main_chain(chain_id, env)?;
+    let operation_hash = parse_operation_hash(body)?;
+    MempoolStorage::new(env.persistent_storage()).delete_blacklisted(&operation_hash)?;
+
+    info!(env.log(), "Mempool operation unbanned"; "hash" => HashType::OperationHash.bytes_to_string(&operation_hash));
+    Ok(json!({}))
+}
+
+/// Removes all banned operations from the blacklist, recently refused operations stay refused
+pub fn unban_all_operations(chain_id: &ChainId, env: &RpcServiceEnvironment) -> Result<Value, failure::Error> {
+    ensure_main_chain(chain_id, env)?;
+    let mempool_storage = MempoolStorage::new(env.persistent_storage());
+    let mut unbanned = 0;
+    for (operation_hash, reason, _) in mempool_storage.iter_blacklisted()? {
+        if reason == MempoolBlacklistReason::Banned {
+            mempool_storage.delete_blacklisted(&operation_hash)?;
+            unbanned += 1;
+        }
+    }
+
+    info!(env.log(), "All mempool operations unbanned"; "count" => unbanned);
+    Ok(json!({}))
+}
+
+/// Asks peers (or just the peer with `peer_id`) for their mempool operations
+pub fn request_operations(chain_id: &ChainId, peer_id: Option<String>, env: &RpcServiceEnvironment) -> Result<Value, failure::Error> {
+    ensure_main_chain(chain_id, env)?;
+    env.shell_channel().tell(
+        Publish {
+            msg: RequestMempoolOperations {
+                peer_id,
+            }.into(),
+            topic: ShellChannelTopic::ShellEvents.into(),
+        }, None);
+    Ok(json!({}))
+}
+
+/// Parses json string with operation hash, e.g. `"opJ4FdKumPfykAP9ZqwY7rNB8y1SiMupt44RqBDMWL7cmb4xbNr"`
+fn parse_operation_hash(body: &[u8]) -> Result<OperationHash, failure::Error> {
+    let operation_hash: String = serde_json::from_slice(body)?;
+    Ok(HashType::OperationHash.string_to_bytes(&operation_hash)?)
+}
+
+/// Mempool is maintained just for the main chain
+fn ensure_main_chain(chain_id: &ChainId, env: &RpcServiceEnvironment) -> Result<(), failure::Error> {
+    if chain_id != env.main_chain_id() {
+        return Err(format_err!("Mempool is not maintained for chain: {}", HashType::ChainId.bytes_to_string(chain_id)));
+    }
+    Ok(())
+}
+
 fn convert_applied(applied: &Vec<Applied>, operations: &HashMap<OperationHash, Operation>) -> Result<Vec<HashMap<String, Value>>, failure::Error> {
     let mut result: Vec<HashMap<String, Value>> = Vec::new();
     for a in applied {
-        let operation_hash = HashType::OperationHash.bytes_to_string(&a.hash);
-        let protocol_data: HashMap<String, Value> = serde_json::from_str(&a.protocol_data_json)?;
-        let operation = match operations.get(&a.hash) {
-            Some(b) => b,
-            None => return Err(format_err!("missing operation data for operation_hash: {}", &operation_hash))
-        };
-
-        let mut m = HashMap::new();
-        m.insert(String::from("hash"), Value::String(operation_hash));
-        m.insert(String::from("branch"), Value::String(HashType::BlockHash.bytes_to_string(&operation.branch())));
-        m.extend(protocol_data);
-        result.push(m);
+        result.push(convert_applied_operation(a, operations)?);
     }
 
     Ok(result)
 }
 
+fn convert_applied_operation(a: &Applied, operations: &HashMap<OperationHash, Operation>) -> Result<HashMap<String, Value>, failure::Error> {
+    let operation_hash = HashType::OperationHash.bytes_to_string(&a.hash);
+    let protocol_data: HashMap<String, Value> = serde_json::from_str(&a.protocol_data_json)?;
+    let operation = match operations.get(&a.hash) {
+        Some(b) => b,
+        None => return Err(format_err!("missing operation data for operation_hash: {}", &operation_hash))
+    };
+
+    let mut m = HashMap::new();
+    m.insert(String::from("hash"), Value::String(operation_hash));
+    m.insert(String::from("branch"), Value::String(HashType::BlockHash.bytes_to_string(&operation.branch())));
+    m.extend(protocol_data);
+    Ok(m)
+}
+
 fn convert_errored(errored: &Vec<Errored>, operations: &HashMap<OperationHash, Operation>, protocol: &ProtocolHash) -> Result<Vec<Value>, failure::Error> {
     let mut result: Vec<Value> = Vec::new();
     let protocol = HashType::ProtocolHash.bytes_to_string(&protocol);
 
     for e in errored {
         let operation_hash = HashType::OperationHash.bytes_to_string(&e.hash);
-        let operation = match operations.get(&e.hash) {
-            Some(b) => b,
-            None => return Err(format_err!("missing operation data for operation_hash: {}", &operation_hash))
-        };
-
-        let protocol_data: HashMap<String, Value> = if e.protocol_data_json_with_error_json.protocol_data_json.is_empty() {
-            HashMap::new()
-        } else {
-            serde_json::from_str(&e.protocol_data_json_with_error_json.protocol_data_json)?
-        };
-
-        let error = if e.protocol_data_json_with_error_json.error_json.is_empty() {
-            Value::Null
-        } else {
-            serde_json::from_str(&e.protocol_data_json_with_error_json.error_json)?
-        };
-
-        let mut m = HashMap::new();
-        m.insert(String::from("protocol"), Value::String(protocol.clone()));
-        m.insert(String::from("branch"), Value::String(HashType::BlockHash.bytes_to_string(&operation.branch())));
-        m.extend(protocol_data);
-        m.insert(String::from("error"), error);
+        let m = convert_errored_operation(e, operations, &protocol)?;
 
         result.push(
             Value::Array(
@@ -131,6 +401,278 @@ fn convert_errored(errored: &Vec<Errored>, operations: &HashMap<OperationHash, O
     Ok(result)
 }
 
+fn convert_errored_operation(e: &Errored, operations: &HashMap<OperationHash, Operation>, protocol: &str) -> Result<HashMap<String, Value>, failure::Error> {
+    let operation = match operations.get(&e.hash) {
+        Some(b) => b,
+        None => return Err(format_err!("missing operation data for operation_hash: {}", HashType::OperationHash.bytes_to_string(&e.hash)))
+    };
+
+    let protocol_data: HashMap<String, Value> = if e.protocol_data_json_with_error_json.protocol_data_json.is_empty() {
+        HashMap::new()
+    } else {
+        serde_json::from_str(&e.protocol_data_json_with_error_json.protocol_data_json)?
+    };
+
+    let error = if e.protocol_data_json_with_error_json.error_json.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(&e.protocol_data_json_with_error_json.error_json)?
+    };
+
+    let mut m = HashMap::new();
+    m.insert(String::from("protocol"), Value::String(protocol.to_string()));
+    m.insert(String::from("branch"), Value::String(HashType::BlockHash.bytes_to_string(&operation.branch())));
+    m.extend(protocol_data);
+    m.insert(String::from("error"), error);
+    Ok(m)
+}
+
+/// Which validation results of the mempool operations are streamed by [MonitorOperationsStream]
+#[derive(Copy, Clone, Debug)]
+pub struct MonitorOperationsFilter {
+    pub applied: bool,
+    pub refused: bool,
+    pub branch_refused: bool,
+    pub branch_delayed: bool,
+}
+
+impl Default for MonitorOperationsFilter {
+    fn default() -> Self {
+        MonitorOperationsFilter {
+            applied: true,
+            refused: false,
+            branch_refused: false,
+            branch_delayed: false,
+        }
+    }
+}
+
+/// Streams operations of the mempool like the OCaml node `monitor_operations` rpc.
+///
+/// At first, operations already in the mempool are streamed, then every new mempool state (see `ShellChannelMsg::MempoolStateChanged`)
+/// streams operations not streamed yet, each chunk is a json list of operations.
+/// Stream ends, when mempool is reset for the new head, so the client should reconnect.
+pub struct MonitorOperationsStream {
+    state: RpcCollectedStateRef,
+    filter: MonitorOperationsFilter,
+    /// Head of the mempool at the first streamed state
+    head: Option<Option<BlockHash>>,
+    last_mempool_state: Option<Arc<RwLock<CurrentMempoolState>>>,
+    streamed: HashSet<OperationHash>,
+    finished: bool,
+    log: Logger,
+}
+
+impl MonitorOperationsStream {
+    fn new(state: RpcCollectedStateRef, filter: MonitorOperationsFilter, log: Logger) -> Self {
+        MonitorOperationsStream {
+            state,
+            filter,
+            head: None,
+            last_mempool_state: None,
+            streamed: HashSet::new(),
+            finished: false,
+            log,
+        }
+    }
+}
+
+impl Stream for MonitorOperationsStream {
+    type Item = Result<String, serde_json::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+
+        // write lock, so the mempool state cannot change between check and registration of the waker
+        let state = self.state.clone();
+        let mut state = state.write().unwrap();
+        let changed_mempool_state = state.current_mempool_state().as_ref()
+            .filter(|mempool_state| !self.last_mempool_state.as_ref().map_or(false, |last| Arc::ptr_eq(last, mempool_state)))
+            .cloned();
+        let mempool_state = match changed_mempool_state {
+            Some(mempool_state) => mempool_state,
+            None => {
+                state.wake_on_mempool_state_changed(cx.waker());
+                return Poll::Pending;
+            }
+        };
+        self.last_mempool_state = Some(mempool_state.clone());
+
+        let mempool = mempool_state.read().unwrap();
+        let head_changed = self.head.get_or_insert_with(|| mempool.head.clone()) != &mempool.head;
+        if head_changed {
+            // mempool was reset for the new head
+            self.finished = true;
+            return Poll::Ready(None);
+        }
+
+        let filter = self.filter;
+        match collect_new_operations(&filter, &mut self.streamed, &mempool) {
+            Ok(operations) if operations.is_empty() => {
+                state.wake_on_mempool_state_changed(cx.waker());
+                Poll::Pending
+            }
+            Ok(operations) => {
+                let mut chunk = match serde_json::to_string(&operations) {
+                    Ok(chunk) => chunk,
+                    Err(e) => return Poll::Ready(Some(Err(e))),
+                };
+                // push a newline character to the stream to improve readability
+                chunk.push('\n');
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Err(e) => {
+                // inconsistent mempool state (e.g. missing operation data), wait for the next one
+                warn!(self.log, "Failed to convert mempool operations for monitor"; "reason" => format!("{}", e));
+                state.wake_on_mempool_state_changed(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Returns not yet streamed operations of the mempool state, which pass the filter
+fn collect_new_operations(filter: &MonitorOperationsFilter, streamed: &mut HashSet<OperationHash>, mempool: &CurrentMempoolState) -> Result<Vec<Value>, failure::Error> {
+    let protocol = mempool.protocol.as_ref().map(|protocol| HashType::ProtocolHash.bytes_to_string(protocol));
+    let mut result = Vec::new();
+
+    if filter.applied {
+        for applied in mempool.result.applied().iter().filter(|applied| !streamed.contains(&applied.hash)) {
+            let mut m = convert_applied_operation(applied, &mempool.operations)?;
+            if let Some(protocol) = &protocol {
+                m.insert(String::from("protocol"), Value::String(protocol.clone()));
+            }
+            result.push((applied.hash.clone(), serde_json::to_value(m)?));
+        }
+    }
+
+    // errored operations are not converted without protocol
+    if let Some(protocol) = &protocol {
+        let errored = [
+            (filter.refused, mempool.result.refused()),
+            (filter.branch_refused, mempool.result.branch_refused()),
+            (filter.branch_delayed, mempool.result.branch_delayed()),
+        ];
+        for (_, errored) in errored.iter().filter(|(enabled, _)| *enabled) {
+            for e in errored.iter().filter(|e| !streamed.contains(&e.hash)) {
+                let mut m = convert_errored_operation(e, &mempool.operations, protocol)?;
+                m.insert(String::from("hash"), Value::String(HashType::OperationHash.bytes_to_string(&e.hash)));
+                result.push((e.hash.clone(), serde_json::to_value(m)?));
+            }
+        }
+    }
+
+    Ok(result.into_iter()
+        .filter_map(|(operation_hash, operation)| if streamed.insert(operation_hash) { Some(operation) } else { None })
+        .collect())
+}
+
+/// Creates stream of the mempool operations, see [MonitorOperationsStream]
+pub fn get_mempool_monitor_operations(chain_id: &ChainId, filter: MonitorOperationsFilter, env: &RpcServiceEnvironment) -> Result<MonitorOperationsStream, failure::Error> {
+    ensure_main_chain(chain_id, env)?;
+    Ok(MonitorOperationsStream::new(env.state().clone(), filter, env.log().clone()))
+}
+
+/// How long synchronous injection (`async=false`) waits for the mempool classification
+const INJECTION_VALIDATION_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long injection with `wait_for_inclusion` waits for the block with the operation
+const INJECTION_INCLUSION_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Injected operation was not accepted by the mempool
+#[derive(Debug, Fail)]
+pub enum InjectionError {
+    #[fail(display = "Operation {} was classified as {}, error: {}", operation_hash, classification, error)]
+    NotApplied {
+        operation_hash: String,
+        classification: &'static str,
+        /// Protocol error (json list of errors)
+        error: Value,
+    },
+    #[fail(display = "Operation {} was not {} in {}s", operation_hash, expected, timeout_secs)]
+    Timeout {
+        operation_hash: String,
+        expected: &'static str,
+        timeout_secs: u64,
+    },
+}
+
+/// Injects operation like the OCaml node `/injection/operation`.
+///
+/// With `is_async`, returns right after the prevalidation, otherwise waits, until mempool classifies the operation as applied,
+/// and with `wait_for_inclusion` also until the operation is included in the block of the current head.
+pub async fn inject_operation_with_options(
+    chain_id: ChainId,
+    operation_data: &str,
+    is_async: bool,
+    wait_for_inclusion: bool,
+    env: &RpcServiceEnvironment) -> Result<String, failure::Error> {
+    ensure_main_chain(&chain_id, env)?;
+    let operation_hash = inject_operation(chain_id, operation_data, env, env.shell_channel())?;
+    if is_async {
+        return Ok(operation_hash);
+    }
+    let operation_hash_bytes = HashType::OperationHash.string_to_bytes(&operation_hash)?;
+
+    let status = wait_for_operation_status(env.state(), &operation_hash_bytes, "classified", INJECTION_VALIDATION_TIMEOUT, |status| status.classification.is_some()).await?;
+    match status.classification {
+        Some("applied") => (),
+        Some(classification) => return Err(InjectionError::NotApplied {
+            operation_hash,
+            classification,
+            error: status.last_error().cloned().unwrap_or(Value::Null),
+        }.into()),
+        None => unreachable!("Operation status is checked for classification"),
+    }
+
+    if wait_for_inclusion {
+        wait_for_operation_status(env.state(), &operation_hash_bytes, "included", INJECTION_INCLUSION_TIMEOUT, |status| status.included_in.is_some()).await?;
+    }
+    Ok(operation_hash)
+}
+
+async fn wait_for_operation_status<F>(state: &RpcCollectedStateRef, operation_hash: &OperationHash, expected: &'static str, timeout: Duration, condition: F) -> Result<OperationStatus, failure::Error>
+    where F: Fn(&OperationStatus) -> bool + Unpin {
+    let wait = OperationStatusFuture {
+        state: state.clone(),
+        operation_hash: operation_hash.clone(),
+        condition,
+    };
+    tokio::time::timeout(timeout, wait).await
+        .map_err(|_| InjectionError::Timeout {
+            operation_hash: HashType::OperationHash.bytes_to_string(operation_hash),
+            expected,
+            timeout_secs: timeout.as_secs(),
+        }.into())
+}
+
+/// Resolves with the status of the tracked operation, once it satisfies the condition
+struct OperationStatusFuture<F> {
+    state: RpcCollectedStateRef,
+    operation_hash: OperationHash,
+    condition: F,
+}
+
+impl<F: Fn(&OperationStatus) -> bool + Unpin> Future for OperationStatusFuture<F> {
+    type Output = OperationStatus;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // write lock, so the status cannot change between check and registration of the waker
+        let mut state = self.state.write().unwrap();
+        let status = state.operation_tracker().get(&self.operation_hash)
+            .filter(|status| (self.condition)(status))
+            .cloned();
+        match status {
+            Some(status) => Poll::Ready(status),
+            None => {
+                state.wake_on_operation_status_changed(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}
+
 pub fn inject_operation(
     chain_id: ChainId,
     operation_data: &str,
@@ -159,7 +701,14 @@ pub fn inject_operation(
 
     // can accpect operation ?
     if !validation::can_accept_operation_from_rpc(&operation_hash, &result) {
-        return Err(format_err!("Operation from rpc ({}) was not added to mempool. Reason: {:?}", HashType::OperationHash.bytes_to_string(&operation_hash), result));
+        if let Some((classification, Some(error))) = classify(&result, &operation_hash) {
+            return Err(InjectionError::NotApplied {
+                operation_hash: HashType::OperationHash.bytes_to_string(&operation_hash),
+                classification,
+                error,
+            }.into());
+        }
+        return Err(format_err!("Operation from rpc ({}) was not added to mempool. Reason: {:?}", HashType::OperationHash.bytes_to_string(&operation_hash), result.as_result()));
     }
 
     // store operation in mempool storage
@@ -174,6 +723,7 @@ pub fn inject_operation(
             msg: MempoolOperationReceived {
                 operation_hash,
                 operation_type: MempoolOperationType::Pending,
+                source: MempoolOperationSource::Rpc,
             }.into(),
             topic: ShellChannelTopic::ShellEvents.into(),
         }, None);
@@ -211,24 +761,8 @@ pub fn inject_block(
                 for vp in vps {
                     let oph: OperationHash = vp.message_hash()?;
 
-                    // remove from applied
-                    if let Some(pos) = mempool.result.applied.iter().position(|x| oph.eq(&x.hash)) {
-                        mempool.result.applied.remove(pos);
-                        mempool.operations.remove(&oph);
-                    }
-                    // remove from branch_delayed
-                    if let Some(pos) = mempool.result.branch_delayed.iter().position(|x| oph.eq(&x.hash)) {
-                        mempool.result.branch_delayed.remove(pos);
-                        mempool.operations.remove(&oph);
-                    }
-                    // remove from branch_refused
-                    if let Some(pos) = mempool.result.branch_refused.iter().position(|x| oph.eq(&x.hash)) {
-                        mempool.result.branch_refused.remove(pos);
-                        mempool.operations.remove(&oph);
-                    }
-                    // remove from refused
-                    if let Some(pos) = mempool.result.refused.iter().position(|x| oph.eq(&x.hash)) {
-                        mempool.result.refused.remove(pos);
+                    // remove from results (applied, branch_delayed, branch_refused, refused)
+                    if mempool.result.remove(&oph).is_some() {
                         mempool.operations.remove(&oph);
                     }
                 }
@@ -265,17 +799,19 @@ pub fn inject_block(
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
 
     use assert_json_diff::assert_json_eq;
     use serde_json::json;
 
     use crypto::hash::HashType;
-    use tezos_api::ffi::{Applied, Errored, OperationProtocolDataJsonWithErrorListJson};
+    use shell::shell_channel::CurrentMempoolState;
+    use shell::stats::mempool::MempoolStats;
+    use tezos_api::ffi::{Applied, Errored, OperationProtocolDataJsonWithErrorListJson, ValidateOperationResult};
     use tezos_messages::p2p::binary_message::BinaryMessage;
     use tezos_messages::p2p::encoding::prelude::Operation;
 
-    use crate::services::mempool_services::{convert_applied, convert_errored};
+    use crate::services::mempool_services::{BakingCandidate, BakingLimits, collect_new_operations, convert_applied, convert_errored, MonitorOperationsFilter, parse_operation_hash, select_baking_operations};
 
     #[test]
     fn test_convert_applied() -> Result<(), failure::Error> {
@@ -403,4 +939,101 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_collect_new_operations() -> Result<(), failure::Error> {
+        let applied_hash = HashType::OperationHash.string_to_bytes("onvN8U6QJ6DGJKVYkHXYRtFm3tgBJScj9P5bbPjSZUuFaGzwFuJ")?;
+        let refused_hash = HashType::OperationHash.string_to_bytes("opJ4FdKumPfykAP9ZqwY7rNB8y1SiMupt44RqBDMWL7cmb4xbNr")?;
+        let operation = Operation::from_bytes(hex::decode("10490b79070cf19175cd7e3b9c1ee66f6e85799980404b119132ea7e58a4a97e000008c387fa065a181d45d47a9b78ddc77e92a881779ff2cbabbf9646eade4bf1405a08e00b725ed849eea46953b10b5cdebc518e6fd47e69b82d2ca18c4cf6d2f312dd08")?)?;
+
+        let mut operations = HashMap::new();
+        operations.insert(applied_hash.clone(), operation.clone());
+        operations.insert(refused_hash.clone(), operation);
+        let mempool = CurrentMempoolState {
+            head: None,
+            protocol: Some(HashType::ProtocolHash.string_to_bytes("PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb")?),
+            fitness: None,
+            result: ValidateOperationResult {
+                applied: vec![Applied { hash: applied_hash, protocol_data_json: "{ \"contents\": [ { \"kind\": \"endorsement\", \"level\": 459020 } ] }".to_string() }],
+                refused: vec![Errored {
+                    hash: refused_hash,
+                    is_endorsement: None,
+                    protocol_data_json_with_error_json: OperationProtocolDataJsonWithErrorListJson {
+                        protocol_data_json: "".to_string(),
+                        error_json: "".to_string(),
+                    },
+                }],
+                ..Default::default()
+            }.into(),
+            operations,
+            pending: HashSet::new(),
+            stats: MempoolStats::default(),
+        };
+
+        // just applied by default
+        let mut streamed = HashSet::new();
+        let collected = collect_new_operations(&MonitorOperationsFilter::default(), &mut streamed, &mempool)?;
+        assert_eq!(1, collected.len());
+        assert_eq!("onvN8U6QJ6DGJKVYkHXYRtFm3tgBJScj9P5bbPjSZUuFaGzwFuJ", collected[0]["hash"]);
+        assert_eq!("PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb", collected[0]["protocol"]);
+
+        // already streamed operations are skipped
+        let filter = MonitorOperationsFilter { refused: true, ..Default::default() };
+        let collected = collect_new_operations(&filter, &mut streamed, &mempool)?;
+        assert_eq!(1, collected.len());
+        assert_eq!("opJ4FdKumPfykAP9ZqwY7rNB8y1SiMupt44RqBDMWL7cmb4xbNr", collected[0]["hash"]);
+
+        assert!(collect_new_operations(&filter, &mut streamed, &mempool)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_operation_hash() -> Result<(), failure::Error> {
+        assert_eq!(
+            HashType::OperationHash.string_to_bytes("onvN8U6QJ6DGJKVYkHXYRtFm3tgBJScj9P5bbPjSZUuFaGzwFuJ")?,
+            parse_operation_hash(br#""onvN8U6QJ6DGJKVYkHXYRtFm3tgBJScj9P5bbPjSZUuFaGzwFuJ""#)?,
+        );
+        assert!(parse_operation_hash(b"onvN8U6QJ6DGJKVYkHXYRtFm3tgBJScj9P5bbPjSZUuFaGzwFuJ").is_err());
+        assert!(parse_operation_hash(br#""invalid""#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_baking_operations() -> Result<(), failure::Error> {
+        let candidate = |hash: &str, kind: &str, fee: &str, gas_limit: &str, size: usize| -> Result<BakingCandidate, failure::Error> {
+            let operation = serde_json::from_value(json!({
+                "hash": hash,
+                "contents": [{ "kind": kind, "fee": fee, "gas_limit": gas_limit }],
+            }))?;
+            Ok(BakingCandidate::new(operation, size))
+        };
+        let hashes = |operations: &Vec<HashMap<String, serde_json::Value>>| operations.iter()
+            .map(|operation| operation["hash"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+
+        let mut limits = BakingLimits::default();
+        limits.passes[0].max_operations = Some(1);
+        limits.hard_gas_limit_per_block = 25_000;
+        limits.max_operation_data_length = 1000;
+
+        let selected = select_baking_operations(vec![
+            candidate("cheap", "transaction", "1000", "10000", 100)?,
+            candidate("endorsement1", "endorsement", "0", "0", 50)?,
+            candidate("too_big", "transaction", "100000", "100", 2000)?,
+            candidate("profitable", "transaction", "3000", "10000", 100)?,
+            candidate("endorsement2", "endorsement", "0", "0", 50)?,
+            candidate("too_much_gas", "origination", "5000", "20000", 100)?,
+            candidate("ballot", "ballot", "0", "0", 60)?,
+        ], &limits);
+
+        // count of endorsements is limited
+        assert_eq!(vec!["endorsement1"], hashes(&selected.consensus));
+        assert_eq!(vec!["ballot"], hashes(&selected.votes));
+        assert!(selected.anonymous.is_empty());
+        // sorted by fee per gas unit, operations exceeding the operation size or the remaining block gas are skipped
+        assert_eq!(vec!["profitable", "cheap"], hashes(&selected.manager));
+        assert_eq!(20_000, selected.gas);
+        assert_eq!(310, selected.size);
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -0,0 +1,95 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use crypto::hash::OperationHash;
+use shell::mempool_result::MempoolValidationResult;
+use tezos_api::ffi::{Applied, Errored, OperationProtocolDataJsonWithErrorListJson, ValidateOperationResult};
+
+const OPERATIONS_COUNT: u32 = 10_000;
+
+fn operation_hash(index: u32) -> OperationHash {
+    let mut hash = vec![0; 32];
+    hash[28..].copy_from_slice(&index.to_be_bytes());
+    hash
+}
+
+/// Result of one validated operation, every 4th operation is branch_delayed
+fn validation_result(index: u32) -> ValidateOperationResult {
+    let hash = operation_hash(index);
+    if index % 4 == 0 {
+        ValidateOperationResult {
+            branch_delayed: vec![Errored {
+                hash,
+                is_endorsement: Some(true),
+                protocol_data_json_with_error_json: OperationProtocolDataJsonWithErrorListJson {
+                    protocol_data_json: "{}".to_string(),
+                    error_json: "[]".to_string(),
+                },
+            }],
+            ..Default::default()
+        }
+    } else {
+        ValidateOperationResult {
+            applied: vec![Applied { hash, protocol_data_json: "{}".to_string() }],
+            ..Default::default()
+        }
+    }
+}
+
+/// Operations are validated one by one, every operation is checked first, if it was not already validated
+fn mempool_benchmark(c: &mut Criterion) {
+    let results: Vec<ValidateOperationResult> = (0..OPERATIONS_COUNT).map(validation_result).collect();
+
+    let mut group = c.benchmark_group("mempool_10k_operations");
+    group.sample_size(10);
+
+    group.bench_function("merge_indexed", |b| b.iter(|| {
+        let mut mempool_result = MempoolValidationResult::default();
+        for (index, result) in results.iter().enumerate() {
+            if !mempool_result.contains(&operation_hash(index as u32)) {
+                mempool_result.merge(result.clone());
+            }
+        }
+        black_box(mempool_result)
+    }));
+
+    // previous implementation, which scans the lists
+    group.bench_function("merge_lists", |b| b.iter(|| {
+        let mut mempool_result = ValidateOperationResult::default();
+        for (index, result) in results.iter().enumerate() {
+            let operation_hash = operation_hash(index as u32);
+            let already_validated = mempool_result.applied.iter().any(|op| op.hash.eq(&operation_hash))
+                || mempool_result.branch_delayed.iter().any(|op| op.hash.eq(&operation_hash))
+                || mempool_result.branch_refused.iter().any(|op| op.hash.eq(&operation_hash))
+                || mempool_result.refused.iter().any(|op| op.hash.eq(&operation_hash));
+            if !already_validated {
+                mempool_result.merge(result.clone());
+            }
+        }
+        black_box(mempool_result)
+    }));
+
+    let mut mempool_result = MempoolValidationResult::default();
+    results.iter().for_each(|result| {
+        mempool_result.merge(result.clone());
+    });
+    group.bench_function("classification_indexed", |b| b.iter(|| {
+        for index in 0..OPERATIONS_COUNT {
+            black_box(mempool_result.classification(&operation_hash(index)));
+        }
+    }));
+
+    group.bench_function("remove_indexed", |b| b.iter(|| {
+        let mut mempool_result = mempool_result.clone();
+        for index in (0..OPERATIONS_COUNT).step_by(10) {
+            black_box(mempool_result.remove(&operation_hash(index)));
+        }
+    }));
+
+    group.finish();
+}
+
+criterion_group!(benches, mempool_benchmark);
+criterion_main!(benches);
@@ -4,8 +4,13 @@
 //! Sends blocks to the `protocol_runner`.
 //! This actor is responsible for correct applying of blocks with Tezos protocol in context
 //! This actor is aslo responsible for correct initialization of genesis in storage.
+//!
+//! Block application is pipelined - while block is being applied by the `protocol_runner`,
+//! apply requests for its successors are prepared from storage in a separate thread,
+//! so the request is ready, when the `chain_manager` decides to apply the successor.
 
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver as QueueReceiver, Sender as QueueSender};
 use std::thread;
@@ -15,19 +20,54 @@ use failure::{Error, Fail};
 use riker::actors::*;
 use slog::{debug, info, Logger, trace, warn};
 
-use crypto::hash::{BlockHash, HashType};
-use storage::{BlockMetaStorage, BlockStorage, BlockStorageReader, ChainMetaStorage, initialize_storage_with_genesis_block, OperationsMetaStorage, StorageError, StorageInitInfo, store_applied_block_result, store_commit_genesis_result};
-use storage::chain_meta_storage::ChainMetaStorageReader;
+use crypto::hash::{BlockHash, ChainId, ContextHash, HashType};
+use storage::{BlockHeaderWithHash, BlockMetaStorage, BlockStorage, BlockStorageReader, ChainMetaStorage, initialize_storage_with_genesis_block, OperationsMetaStorage, OperationsStorage, OperationsStorageReader, StorageError, StorageInitInfo, store_applied_block_result, store_commit_genesis_result, store_test_chain_genesis};
+use storage::chain_meta_storage::{ApplyBlockIntent, ChainMetaStorageReader};
+use storage::context::{ContextApi, ContextError, TezedgeContext};
+use storage::context_key;
 use storage::persistent::PersistentStorage;
 use tezos_api::environment::TezosEnvironmentConfiguration;
-use tezos_api::ffi::ApplyBlockRequest;
+use tezos_api::ffi::{ApplyBlockRequest, CommitTestChainGenesisRequest};
+use tezos_messages::Head;
 use tezos_wrapper::service::{IpcCmdServer, ProtocolController, ProtocolServiceError};
 
-use crate::shell_channel::{BlockApplied, ShellChannelMsg, ShellChannelRef, ShellChannelTopic};
+use crate::shell_channel::{BlockApplied, ShellChannelMsg, ShellChannelRef, ShellChannelTopic, TestChainForked};
+use crate::state::test_chain::test_chain_genesis;
 use crate::subscription::subscribe_to_shell_events;
 
 type SharedJoinHandle = Arc<Mutex<Option<JoinHandle<Result<(), Error>>>>>;
 
+/// How many levels of successors of currently applied block are prepared for application
+const APPLY_BLOCK_PREFETCH_DEPTH: usize = 10;
+/// Limit of prepared requests (more successors can be prepared for the same level)
+const APPLY_BLOCK_PREFETCH_MAX_REQUESTS: usize = 4 * APPLY_BLOCK_PREFETCH_DEPTH;
+
+/// Apply block requests prepared in advance by prefetch thread.
+///
+/// Cache is just warmed by prefetching, which block is applied is always decided by the `chain_manager`.
+/// Prepared request does not have valid `max_operations_ttl`, which is taken from the result of predecessor application.
+#[derive(Clone)]
+struct ApplyBlockRequestCache {
+    requests: Arc<Mutex<HashMap<BlockHash, ApplyBlockRequest>>>,
+}
+
+impl ApplyBlockRequestCache {
+    fn new() -> Self {
+        ApplyBlockRequestCache {
+            requests: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Cache is just an optimization, so it is still usable, even if other thread panicked while holding the lock
+    fn requests(&self) -> MutexGuard<HashMap<BlockHash, ApplyBlockRequest>> {
+        self.requests.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn take(&self, block_hash: &BlockHash) -> Option<ApplyBlockRequest> {
+        self.requests().remove(block_hash)
+    }
+}
+
 /// Feeds blocks and operations to the tezos protocol (ocaml code).
 #[actor(ShellChannelMsg)]
 pub struct ChainFeeder {
@@ -43,7 +83,7 @@ pub struct ChainFeeder {
 }
 
 enum Event {
-    ApplyBlock(BlockHash, Arc<ApplyBlockRequest>),
+    ApplyBlock(BlockHash),
     ShuttingDown,
 }
 
@@ -68,6 +108,36 @@ impl ChainFeeder {
         ipc_server: IpcCmdServer,
         log: Logger) -> Result<ChainFeederRef, CreateError> {
 
+        // spawn thread which prepares apply requests for successors of applied blocks
+        let (prefetch_sender, prefetch_receiver) = channel();
+        let apply_request_cache = ApplyBlockRequestCache::new();
+        let prefetch_thread = {
+            let persistent_storage = persistent_storage.clone();
+            let apply_request_cache = apply_request_cache.clone();
+            let log = log.clone();
+
+            thread::spawn(move || {
+                let block_storage = BlockStorage::new(&persistent_storage);
+                let block_meta_storage = BlockMetaStorage::new(&persistent_storage);
+                let operations_storage = OperationsStorage::new(&persistent_storage);
+                let operations_meta_storage = OperationsMetaStorage::new(&persistent_storage);
+
+                // thread finishes, when block applier thread drops sender
+                while let Ok(block_hash) = prefetch_receiver.recv() {
+                    if let Err(e) = prefetch_apply_block_requests(
+                        &block_hash,
+                        &block_storage,
+                        &block_meta_storage,
+                        &operations_storage,
+                        &operations_meta_storage,
+                        &apply_request_cache,
+                    ) {
+                        warn!(log, "Failed to prefetch apply block requests"; "block" => HashType::BlockHash.bytes_to_string(&block_hash), "reason" => format!("{:?}", e));
+                    }
+                }
+            })
+        };
+
         // spawn thread which processes event
         let (block_applier_event_sender, mut block_applier_event_receiver) = channel();
         let block_applier_run = Arc::new(AtomicBool::new(true));
@@ -82,7 +152,9 @@ impl ChainFeeder {
                 let block_storage = BlockStorage::new(&persistent_storage);
                 let block_meta_storage = BlockMetaStorage::new(&persistent_storage);
                 let chain_meta_storage = ChainMetaStorage::new(&persistent_storage);
+                let operations_storage = OperationsStorage::new(&persistent_storage);
                 let operations_meta_storage = OperationsMetaStorage::new(&persistent_storage);
+                let context = TezedgeContext::new(BlockStorage::new(&persistent_storage), persistent_storage.merkle());
                 let mut ipc_server = ipc_server;
 
                 while apply_block_run.load(Ordering::Acquire) {
@@ -96,9 +168,13 @@ impl ChainFeeder {
                                 &block_storage,
                                 &block_meta_storage,
                                 &chain_meta_storage,
+                                &operations_storage,
                                 &operations_meta_storage,
+                                &context,
                                 protocol_controller,
                                 &mut block_applier_event_receiver,
+                                &prefetch_sender,
+                                &apply_request_cache,
                                 &log,
                             ) {
                                 Ok(()) => debug!(log, "Feed chain to protocol finished"),
@@ -112,6 +188,12 @@ impl ChainFeeder {
                     }
                 }
 
+                // prefetch thread finishes, when sender is dropped
+                drop(prefetch_sender);
+                if prefetch_thread.join().is_err() {
+                    warn!(log, "Failed to join prefetch thread");
+                }
+
                 Ok(())
             })
         };
@@ -132,12 +214,9 @@ impl ChainFeeder {
 
     fn process_shell_channel_message(&mut self, _ctx: &Context<ChainFeederMsg>, msg: ShellChannelMsg) -> Result<(), Error> {
         match msg {
-            ShellChannelMsg::ApplyBlock(block_hash, apply_block_request) => {
+            ShellChannelMsg::ApplyBlock(block_hash) => {
                 self.block_applier_event_sender.lock().unwrap().send(
-                    Event::ApplyBlock(
-                        block_hash,
-                        apply_block_request,
-                    )
+                    Event::ApplyBlock(block_hash)
                 )?;
             }
             ShellChannelMsg::ShuttingDown(_) => {
@@ -211,6 +290,10 @@ pub enum FeedChainError {
     ProtocolServiceError {
         error: ProtocolServiceError
     },
+    #[fail(display = "Context error! Reason: {:?}", error)]
+    ContextError {
+        error: ContextError
+    },
 }
 
 impl From<StorageError> for FeedChainError {
@@ -225,6 +308,12 @@ impl From<ProtocolServiceError> for FeedChainError {
     }
 }
 
+impl From<ContextError> for FeedChainError {
+    fn from(error: ContextError) -> Self {
+        FeedChainError::ContextError { error }
+    }
+}
+
 fn feed_chain_to_protocol(
     tezos_env: &TezosEnvironmentConfiguration,
     init_storage_data: &StorageInitInfo,
@@ -233,21 +322,24 @@ fn feed_chain_to_protocol(
     block_storage: &BlockStorage,
     block_meta_storage: &BlockMetaStorage,
     chain_meta_storage: &ChainMetaStorage,
+    operations_storage: &OperationsStorage,
     operations_meta_storage: &OperationsMetaStorage,
+    context: &dyn ContextApi,
     protocol_controller: ProtocolController,
     block_applier_event_receiver: &mut QueueReceiver<Event>,
+    prefetch_sender: &QueueSender<BlockHash>,
+    apply_request_cache: &ApplyBlockRequestCache,
     log: &Logger,
 ) -> Result<(), FeedChainError> {
-    let block_hash_encoding = HashType::BlockHash;
-
     // at first we initialize protocol runtime and ffi context
-    initialize_protocol_context(
+    let recovery = initialize_protocol_context(
         &apply_block_run,
         &shell_channel,
         block_storage,
         block_meta_storage,
         chain_meta_storage,
         operations_meta_storage,
+        context,
         &protocol_controller,
         &log,
         &tezos_env,
@@ -262,67 +354,46 @@ fn feed_chain_to_protocol(
         return Err(FeedChainError::UnknownCurrentHeadError);
     };
 
+    // block, which application was interrupted, is applied again before other events
+    let mut interrupted_block = match recovery {
+        ApplyBlockRecovery::ReApply(block_hash) => Some(block_hash),
+        _ => None,
+    };
+
     // now we can start applying block
     while apply_block_run.load(Ordering::Acquire) {
+        let event = match interrupted_block.take() {
+            Some(block_hash) => Ok(Event::ApplyBlock(block_hash)),
+            None => block_applier_event_receiver.recv(),
+        };
+
         // let's handle event, if any
-        if let Ok(event) = block_applier_event_receiver.recv() {
+        if let Ok(event) = event {
             match event {
-                Event::ApplyBlock(block_hash, request) => {
-                    debug!(log, "Applying block"; "block_header_hash" => block_hash_encoding.bytes_to_string(&block_hash));
-
+                Event::ApplyBlock(block_hash) => {
                     // check if block is already applied (not necessray here)
-                    match block_meta_storage.get(&block_hash)? {
+                    let block_chain_id = match block_meta_storage.get(&block_hash)? {
                         Some(meta) => {
                             if meta.is_applied() {
                                 // block already applied - ok, doing nothing
                                 debug!(log, "Block is already applied (feeder)"; "block" => HashType::BlockHash.bytes_to_string(&block_hash));
                                 continue;
                             }
+                            meta.chain_id().clone()
                         }
                         None => {
                             warn!(log, "Block metadata not found (feeder)"; "block" => HashType::BlockHash.bytes_to_string(&block_hash));
                             continue;
                         }
-                    }
+                    };
 
-                    // try apply block
-                    match protocol_controller.apply_block((&*request).clone()) {
-                        Ok(apply_block_result) => {
-                            debug!(log, "Block was applied";
-                                "block_header_hash" => block_hash_encoding.bytes_to_string(&block_hash),
-                                "context_hash" => HashType::ContextHash.bytes_to_string(&apply_block_result.context_hash),
-                                "validation_result_message" => &apply_block_result.validation_result_message);
-
-                            // Lets mark header as applied and store result
-                            let mut current_head_meta = block_meta_storage.get(&block_hash)?.unwrap();
-
-                            // store success result
-                            let (block_json_data, _) = store_applied_block_result(
-                                block_storage,
-                                block_meta_storage,
-                                &block_hash,
-                                apply_block_result,
-                                &mut current_head_meta,
-                            )?;
-
-                            // notify other actors/listeners
-                            if apply_block_run.load(Ordering::Acquire) {
-                                let current_head = block_storage.get(&block_hash)?.unwrap();
-
-                                // notify others that the block successfully applied
-                                shell_channel.tell(
-                                    Publish {
-                                        msg: BlockApplied::new(current_head, block_json_data).into(),
-                                        topic: ShellChannelTopic::ShellEvents.into(),
-                                    }, None);
-                            }
-                        }
-                        Err(err) => {
-                            warn!(log, "Failed to apply block";
-                                       "block" => HashType::BlockHash.bytes_to_string(&block_hash),
-                                       "reason" => format!("{:?}", err));
-                        }
-                    }
+                    // collect data (prefetched request is used, if available), block is applied for its own chain (main chain or test chain)
+                    let request = prepare_apply_request(&block_hash, &block_chain_id, block_storage, operations_storage, apply_request_cache)?;
+
+                    // prepare successors, while this block is being applied
+                    let _ = prefetch_sender.send(block_hash.clone());
+
+                    apply_block(&block_hash, request, chain_id, apply_block_run, shell_channel, block_storage, block_meta_storage, chain_meta_storage, operations_meta_storage, &protocol_controller, log)?;
                 }
                 Event::ShuttingDown => {
                     apply_block_run.store(false, Ordering::Release);
@@ -334,9 +405,250 @@ fn feed_chain_to_protocol(
     Ok(())
 }
 
+/// Applies block with protocol, stores result and notifies other actors.
+///
+/// Block is recorded in [ApplyBlockIntent] until the result is stored, so interrupted application is detected after crash (see [recover_block_application]).
+/// Intent is stored for the chain of the feeder, because blocks of all chains are applied one by one.
+fn apply_block(
+    block_hash: &BlockHash,
+    request: ApplyBlockRequest,
+    chain_id: &ChainId,
+    apply_block_run: &AtomicBool,
+    shell_channel: &ShellChannelRef,
+    block_storage: &BlockStorage,
+    block_meta_storage: &BlockMetaStorage,
+    chain_meta_storage: &ChainMetaStorage,
+    operations_meta_storage: &OperationsMetaStorage,
+    protocol_controller: &ProtocolController,
+    log: &Logger,
+) -> Result<(), FeedChainError> {
+    let block_hash_encoding = HashType::BlockHash;
+    debug!(log, "Applying block"; "block_header_hash" => block_hash_encoding.bytes_to_string(&block_hash));
+
+    // write-ahead intent, before context is changed by protocol
+    let block_chain_id = request.chain_id.clone();
+    chain_meta_storage.set_apply_block_intent(chain_id, ApplyBlockIntent::new(block_hash.clone(), request.block_header.level()))?;
+
+    // try apply block
+    match protocol_controller.apply_block(request) {
+        Ok(apply_block_result) => {
+            debug!(log, "Block was applied";
+                "block_header_hash" => block_hash_encoding.bytes_to_string(&block_hash),
+                "context_hash" => HashType::ContextHash.bytes_to_string(&apply_block_result.context_hash),
+                "validation_result_message" => &apply_block_result.validation_result_message);
+            let forking_testchain_data = if apply_block_result.forking_testchain {
+                apply_block_result.forking_testchain_data.clone()
+            } else {
+                None
+            };
+
+            // Lets mark header as applied and store result
+            let mut current_head_meta = block_meta_storage.get(&block_hash)?.unwrap();
+
+            // store success result
+            let (block_json_data, _) = store_applied_block_result(
+                block_storage,
+                block_meta_storage,
+                &block_hash,
+                apply_block_result,
+                &mut current_head_meta,
+            )?;
+            chain_meta_storage.remove_apply_block_intent(chain_id)?;
+
+            // genesis of the test chain must be applied, before blocks of the test chain are applied
+            if let Some(forking_testchain_data) = &forking_testchain_data {
+                if protocol_controller.is_testchain_enabled() {
+                    if let Err(e) = commit_test_chain_genesis(
+                        &forking_testchain_data.forking_block_hash,
+                        &forking_testchain_data.test_chain_id,
+                        block_storage,
+                        block_meta_storage,
+                        chain_meta_storage,
+                        operations_meta_storage,
+                        protocol_controller,
+                        log,
+                    ) {
+                        warn!(log, "Failed to commit test chain genesis";
+                                   "test_chain_id" => HashType::ChainId.bytes_to_string(&forking_testchain_data.test_chain_id),
+                                   "reason" => format!("{:?}", e));
+                    }
+                }
+            }
+
+            // notify other actors/listeners
+            if apply_block_run.load(Ordering::Acquire) {
+                let current_head = block_storage.get(&block_hash)?.unwrap();
+
+                // notify others that the block successfully applied
+                shell_channel.tell(
+                    Publish {
+                        msg: BlockApplied::new(current_head, block_json_data).into(),
+                        topic: ShellChannelTopic::ShellEvents.into(),
+                    }, None);
+
+                // notify others that the block forked test chain
+                if let Some(forking_testchain_data) = forking_testchain_data {
+                    info!(log, "Block forked test chain";
+                               "block" => HashType::BlockHash.bytes_to_string(&block_hash),
+                               "test_chain_id" => HashType::ChainId.bytes_to_string(&forking_testchain_data.test_chain_id));
+                    shell_channel.tell(
+                        Publish {
+                            msg: TestChainForked {
+                                chain_id: block_chain_id,
+                                test_chain_id: forking_testchain_data.test_chain_id,
+                                forking_block_hash: forking_testchain_data.forking_block_hash,
+                            }.into(),
+                            topic: ShellChannelTopic::ShellEvents.into(),
+                        }, None);
+                }
+            }
+
+            Ok(())
+        }
+        Err(err) => {
+            warn!(log, "Failed to apply block";
+                       "block" => HashType::BlockHash.bytes_to_string(&block_hash),
+                       "reason" => format!("{:?}", err));
+            chain_meta_storage.remove_apply_block_intent(chain_id)?;
+            Ok(())
+        }
+    }
+}
+
+/// Commits genesis of the test chain to the context of the forking block and stores it as the applied block of the test chain
+fn commit_test_chain_genesis(
+    forking_block_hash: &BlockHash,
+    test_chain_id: &ChainId,
+    block_storage: &BlockStorage,
+    block_meta_storage: &BlockMetaStorage,
+    chain_meta_storage: &ChainMetaStorage,
+    operations_meta_storage: &OperationsMetaStorage,
+    protocol_controller: &ProtocolController,
+    log: &Logger,
+) -> Result<(), FeedChainError> {
+    let forking_block = block_storage.get(forking_block_hash)?.ok_or(StorageError::MissingKey)?;
+    let result = protocol_controller.commit_test_chain_genesis(CommitTestChainGenesisRequest {
+        forking_block_header: (*forking_block.header).clone(),
+    })?;
+
+    // genesis is identified by the hash derived from the forking block, not by the hash of its header
+    let genesis = BlockHeaderWithHash {
+        hash: test_chain_genesis(forking_block_hash),
+        header: Arc::new(result.genesis_block_header),
+    };
+    store_test_chain_genesis(block_storage, block_meta_storage, chain_meta_storage, operations_meta_storage, test_chain_id, &genesis, log)?;
+
+    info!(log, "Test chain genesis committed";
+               "test_chain_id" => HashType::ChainId.bytes_to_string(test_chain_id),
+               "genesis" => HashType::BlockHash.bytes_to_string(&genesis.hash),
+               "context_hash" => HashType::ContextHash.bytes_to_string(genesis.header.context()));
+    Ok(())
+}
+
+/// Takes prefetched request for the block or prepares it from storage and completes it with predecessor max_operations_ttl
+fn prepare_apply_request(
+    block_hash: &BlockHash,
+    chain_id: &ChainId,
+    block_storage: &BlockStorage,
+    operations_storage: &OperationsStorage,
+    apply_request_cache: &ApplyBlockRequestCache,
+) -> Result<ApplyBlockRequest, StorageError> {
+    let mut request = match apply_request_cache.take(block_hash) {
+        Some(request) => request,
+        None => read_apply_request(block_hash, chain_id, block_storage, operations_storage)?,
+    };
+
+    // predecessor is already applied, so we have its metadata
+    let (_, predecessor_additional_data) = block_storage.get_with_additional_data(request.block_header.predecessor())?
+        .ok_or(StorageError::MissingKey)?;
+    request.max_operations_ttl = predecessor_additional_data.max_operations_ttl() as i32;
+
+    Ok(request)
+}
+
+/// Reads header, operations and predecessor header of the block, `max_operations_ttl` is not resolved here
+fn read_apply_request(
+    block_hash: &BlockHash,
+    chain_id: &ChainId,
+    block_storage: &BlockStorage,
+    operations_storage: &OperationsStorage,
+) -> Result<ApplyBlockRequest, StorageError> {
+    let block = block_storage.get(block_hash)?.ok_or(StorageError::MissingKey)?;
+    let predecessor = block_storage.get(block.header.predecessor())?.ok_or(StorageError::MissingKey)?;
+    let operations = operations_storage.get_operations(block_hash)?;
+
+    Ok(
+        ApplyBlockRequest {
+            chain_id: chain_id.clone(),
+            block_header: (*block.header).clone(),
+            pred_header: (*predecessor.header).clone(),
+            operations: ApplyBlockRequest::convert_operations(operations),
+            // resolved from predecessor application result
+            max_operations_ttl: 0,
+        }
+    )
+}
+
+/// Prepares apply block requests (header, operations and predecessor header) for not applied successors of the block,
+/// which have all operations downloaded, up to [APPLY_BLOCK_PREFETCH_DEPTH] levels
+fn prefetch_apply_block_requests(
+    block_hash: &BlockHash,
+    block_storage: &BlockStorage,
+    block_meta_storage: &BlockMetaStorage,
+    operations_storage: &OperationsStorage,
+    operations_meta_storage: &OperationsMetaStorage,
+    apply_request_cache: &ApplyBlockRequestCache,
+) -> Result<(), StorageError> {
+    // remove requests for already applied blocks (e.g. other branch was applied)
+    let prefetched_blocks = apply_request_cache.requests().keys().cloned().collect::<Vec<_>>();
+    for prefetched_block in prefetched_blocks {
+        let is_applied = block_meta_storage.get(&prefetched_block)?.map(|meta| meta.is_applied()).unwrap_or(true);
+        if is_applied {
+            apply_request_cache.requests().remove(&prefetched_block);
+        }
+    }
+
+    let mut to_visit = vec![(block_hash.clone(), 0)];
+    while let Some((block_hash, depth)) = to_visit.pop() {
+        if depth >= APPLY_BLOCK_PREFETCH_DEPTH {
+            continue;
+        }
+
+        let successors = match block_meta_storage.get(&block_hash)? {
+            Some(meta) => meta.successors().clone(),
+            None => continue,
+        };
+
+        for successor in successors {
+            if apply_request_cache.requests().len() >= APPLY_BLOCK_PREFETCH_MAX_REQUESTS {
+                return Ok(());
+            }
+
+            let successor_chain_id = match block_meta_storage.get(&successor)? {
+                Some(successor_meta) if !successor_meta.is_applied() => successor_meta.chain_id().clone(),
+                _ => continue,
+            };
+            if !operations_meta_storage.is_complete(&successor)? {
+                continue;
+            }
+
+            if !apply_request_cache.requests().contains_key(&successor) {
+                let request = read_apply_request(&successor, &successor_chain_id, block_storage, operations_storage)?;
+                apply_request_cache.requests().insert(successor.clone(), request);
+            }
+
+            to_visit.push((successor, depth + 1));
+        }
+    }
+
+    Ok(())
+}
+
 /// This initializes ocaml runtime and protocol context,
 /// if we start with new databazes without genesis,
 /// it ensures correct initialization of storage with genesis and his data.
+///
+/// Returns recovery of the block application interrupted by crash, see [recover_block_application].
 pub(crate) fn initialize_protocol_context(
     apply_block_run: &AtomicBool,
     shell_channel: &ShellChannelRef,
@@ -344,10 +656,11 @@ pub(crate) fn initialize_protocol_context(
     block_meta_storage: &BlockMetaStorage,
     chain_meta_storage: &ChainMetaStorage,
     operations_meta_storage: &OperationsMetaStorage,
+    context: &dyn ContextApi,
     protocol_controller: &ProtocolController,
     log: &Logger,
     tezos_env: &TezosEnvironmentConfiguration,
-    init_storage_data: &StorageInitInfo) -> Result<(), FeedChainError> {
+    init_storage_data: &StorageInitInfo) -> Result<ApplyBlockRecovery, FeedChainError> {
 
     // we must check if genesis is applied, if not then we need "commit_genesis" to context
     let need_commit_genesis = match block_meta_storage.get(&init_storage_data.genesis_block_header_hash)? {
@@ -360,6 +673,13 @@ pub(crate) fn initialize_protocol_context(
     let context_init_info = protocol_controller.init_protocol_for_write(need_commit_genesis, &init_storage_data.patch_context)?;
     info!(log, "Protocol context initialized"; "context_init_info" => format!("{:?}", &context_init_info));
 
+    // detect block application, which could be interrupted by crash
+    let recovery = if need_commit_genesis {
+        ApplyBlockRecovery::Clean
+    } else {
+        recover_block_application(block_storage, block_meta_storage, chain_meta_storage, context, &init_storage_data.chain_id, log)?
+    };
+
     if need_commit_genesis {
 
         // if we needed commit_genesis, it means, that it is apply of 0 block,
@@ -401,5 +721,117 @@ pub(crate) fn initialize_protocol_context(
         }
     }
 
-    Ok(())
-}
\ No newline at end of file
+    Ok(recovery)
+}
+
+/// Result of the recovery of interrupted block application
+#[derive(Clone, Debug, PartialEq)]
+pub enum ApplyBlockRecovery {
+    /// There was no interrupted block application
+    Clean,
+    /// Apply result was already stored, just intent record was not removed
+    AlreadyStored(BlockHash),
+    /// Result of the block application was not stored, so block is applied again by the feeder before other blocks
+    /// (application from predecessor context is deterministic, so already committed context does not matter)
+    ReApply(BlockHash),
+}
+
+/// Compares interrupted block application intent (if any) with block metadata
+/// and leaves not stored block to be applied again.
+///
+/// Also current head is moved back to the last applied block, if it points to the block, which is not applied.
+/// Context hashes of the applied blocks (intent block and current head) are checked against the context storage,
+/// missing context is reported as a warning.
+pub fn recover_block_application(
+    block_storage: &BlockStorage,
+    block_meta_storage: &BlockMetaStorage,
+    chain_meta_storage: &ChainMetaStorage,
+    context: &dyn ContextApi,
+    chain_id: &ChainId,
+    log: &Logger) -> Result<ApplyBlockRecovery, FeedChainError> {
+    let recovery = match chain_meta_storage.get_apply_block_intent(chain_id)? {
+        None => ApplyBlockRecovery::Clean,
+        Some(intent) => {
+            let block_hash = intent.block_hash;
+            let recovery = match block_meta_storage.get(&block_hash)? {
+                Some(meta) if meta.is_applied() => ApplyBlockRecovery::AlreadyStored(block_hash.clone()),
+                Some(_) => ApplyBlockRecovery::ReApply(block_hash.clone()),
+                None => {
+                    warn!(log, "Interrupted block application for unknown block"; "block" => HashType::BlockHash.bytes_to_string(&block_hash));
+                    ApplyBlockRecovery::ReApply(block_hash.clone())
+                }
+            };
+
+            info!(log, "Recovered interrupted block application";
+                       "block" => HashType::BlockHash.bytes_to_string(&block_hash),
+                       "level" => intent.level,
+                       "recovery" => format!("{:?}", &recovery));
+            chain_meta_storage.remove_apply_block_intent(chain_id)?;
+            recovery
+        }
+    };
+
+    if let ApplyBlockRecovery::AlreadyStored(block_hash) = &recovery {
+        check_applied_block_context(block_storage, context, block_hash, log)?;
+    }
+
+    // current head must be applied, if not, move it back to the last applied predecessor
+    if let Some(current_head) = chain_meta_storage.get_current_head(chain_id)? {
+        let mut block_hash = current_head.block_hash().clone();
+        loop {
+            match block_meta_storage.get(&block_hash)? {
+                Some(meta) if meta.is_applied() => break,
+                Some(meta) => match meta.predecessor() {
+                    Some(predecessor) if *predecessor != block_hash => block_hash = predecessor.clone(),
+                    _ => return Ok(recovery),
+                },
+                None => return Ok(recovery),
+            }
+        }
+
+        if block_hash != *current_head.block_hash() {
+            if let Some(block) = block_storage.get(&block_hash)? {
+                warn!(log, "Current head is not applied - moving current head back to the last applied block";
+                           "current_head" => HashType::BlockHash.bytes_to_string(current_head.block_hash()),
+                           "new_current_head" => HashType::BlockHash.bytes_to_string(&block_hash),
+                           "level" => block.header.level());
+                chain_meta_storage.set_current_head(
+                    chain_id,
+                    Head::new(block_hash.clone(), block.header.level(), block.header.fitness().clone()),
+                )?;
+            }
+        }
+
+        // successors are applied on the context of the current head
+        if recovery != ApplyBlockRecovery::AlreadyStored(block_hash.clone()) {
+            check_applied_block_context(block_storage, context, &block_hash, log)?;
+        }
+    }
+
+    Ok(recovery)
+}
+
+/// Checks, that context hash stored with the applied block is known to the context storage,
+/// returns context hash, which is missing
+pub fn check_applied_block_context(
+    block_storage: &BlockStorage,
+    context: &dyn ContextApi,
+    block_hash: &BlockHash,
+    log: &Logger) -> Result<Option<ContextHash>, FeedChainError> {
+    let context_hash = match block_storage.get(block_hash)? {
+        Some(block) => block.header.context().clone(),
+        None => return Ok(None),
+    };
+
+    // any key can be read, just commit with the context hash must exist
+    match context.get_key_from_history(&context_hash, &context_key!("protocol")) {
+        Ok(_) => Ok(None),
+        Err(ContextError::UnknownContextHashError { .. }) => {
+            warn!(log, "Context hash of the applied block does not match context storage";
+                       "block" => HashType::BlockHash.bytes_to_string(block_hash),
+                       "context_hash" => HashType::ContextHash.bytes_to_string(&context_hash));
+            Ok(Some(context_hash))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
@@ -7,12 +7,13 @@
 //!
 //! Also responsible for:
 //! -- managing attribute current head (BlockApplied event is trigger)
-//! -- start test chain (if needed)
+//! -- start test chain (if enabled), which is synchronized in its own state until expiration
+//! -- synchronize other active chains (registered in chain meta storage) besides the main chain
 //!
 //! see more description in [process_shell_channel_message][ShellChannelMsg::BlockApplied]
 
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant, SystemTime};
 
@@ -27,10 +28,10 @@ use networking::p2p::network_channel::{NetworkChannelMsg, NetworkChannelRef, Net
 use networking::p2p::peer::SendMessage;
 use networking::PeerId;
 use storage::{BlockHeaderWithHash, BlockMetaStorage, BlockMetaStorageReader, BlockStorage, BlockStorageReader, ChainMetaStorage, MempoolStorage, OperationsStorage, OperationsStorageReader, StorageError};
-use storage::chain_meta_storage::ChainMetaStorageReader;
+use storage::chain_meta_storage::{ActiveChain, ChainMetaStorageReader, TestChainData};
+use storage::context::TezedgeContext;
 use storage::mempool_storage::MempoolOperationType;
 use storage::persistent::PersistentStorage;
-use tezos_api::ffi::ApplyBlockRequest;
 use tezos_identity::Identity;
 use tezos_messages::Head;
 use tezos_messages::p2p::binary_message::MessageHash;
@@ -38,35 +39,20 @@ use tezos_messages::p2p::encoding::block_header::Level;
 use tezos_messages::p2p::encoding::prelude::*;
 use tezos_wrapper::TezosApiConnectionPool;
 
-use crate::{PeerConnectionThreshold, validation};
-use crate::shell_channel::{AllBlockOperationsReceived, BlockReceived, CurrentMempoolState, MempoolOperationReceived, ShellChannelMsg, ShellChannelRef, ShellChannelTopic};
-use crate::state::block_state::{BlockAcceptanceResult, BlockchainState, HeadResult, MissingBlock};
+use crate::{PeerConnectionThreshold, PeerRoles, validation};
+use crate::clock::{ClockRef, Ping, Pong};
+use crate::configuration::{ShellConfiguration, ShellConfigurationRef};
+use crate::shell_channel::{AllBlockOperationsReceived, BlockApplied, BlockReceived, ChainReorganized, CurrentMempoolState, MempoolOperationReceived, MempoolOperationsAdvertised, MempoolOperationSource, ShellChannelMsg, ShellChannelRef, ShellChannelTopic, TestChainForked};
+use crate::state::block_state::{BlockAcceptanceResult, BlockchainState, BlockPrevalidationResult, HeadResult, MissingBlock, Reorganization};
+use crate::state::download_scheduler::{InFlightRequests, PeerThroughput};
+use crate::state::mempool_propagation::{KnownOperations, MempoolPropagation};
 use crate::state::operations_state::{MissingOperations, OperationsState};
+use crate::state::test_chain::{get_test_chain_status, test_chain_genesis};
 use crate::subscription::*;
 
-/// Limit to how many blocks to request in a batch
-const BLOCK_HEADERS_BATCH_SIZE: usize = 10;
-/// Limit to how many block operations to request in a batch
-const BLOCK_OPERATIONS_BATCH_SIZE: usize = 10;
-/// Limit to how many mempool operations to request in a batch
-const MEMPOOL_OPERATIONS_BATCH_SIZE: usize = 10;
-/// How often to check chain completeness
-const CHECK_CHAIN_COMPLETENESS_INTERVAL: Duration = Duration::from_secs(30);
-/// How often to ask all connected peers for current branch
-const ASK_CURRENT_BRANCH_INTERVAL: Duration = Duration::from_secs(15);
-/// How often to print stats in logs
-const LOG_INTERVAL: Duration = Duration::from_secs(60);
-/// After this time we will disconnect peer if his current head level stays the same
-const CURRENT_HEAD_LEVEL_UPDATE_TIMEOUT: Duration = Duration::from_secs(120);
-/// After this time peer will be disconnected if it fails to respond to our request
-const SILENT_PEER_TIMEOUT: Duration = Duration::from_secs(30);
 /// Maximum timeout duration in sandbox mode (do not disconnect peers in sandbox mode)
 const SILENT_PEER_TIMEOUT_SANDBOX: Duration = Duration::from_secs(31_536_000);
-/// After this interval we will rehydrate state if no new blocks are applied
-const STALLED_CHAIN_COMPLETENESS_TIMEOUT: Duration = Duration::from_secs(240);
 const BLOCK_HASH_ENCODING: HashType = HashType::BlockHash;
-/// Mempool operation time to live
-const MEMPOOL_OPERATION_TTL: Duration = Duration::from_secs(60);
 
 /// Message commands [`ChainManager`] to disconnect stalled peers.
 #[derive(Clone, Debug)]
@@ -80,6 +66,10 @@ pub struct CheckChainCompleteness;
 #[derive(Clone, Debug)]
 pub struct CheckMempoolCompleteness;
 
+/// Message commands [`ChainManager`] to announce new mempool operations to the peers (batched).
+#[derive(Clone, Debug)]
+pub struct AnnounceMempoolOperations;
+
 /// Message commands [`ChainManager`] to apply completed blocks.
 #[derive(Clone, Debug)]
 pub struct ApplyCompletedBlock {
@@ -90,6 +80,15 @@ pub struct ApplyCompletedBlock {
 #[derive(Clone, Debug)]
 pub struct AskPeersAboutCurrentBranch;
 
+/// Message commands [`ChainManager`] to synchronize another chain next to the main chain (e.g. private sandbox chain).
+/// Registration is persisted, so the chain is restored after restart.
+#[derive(Clone, Debug)]
+pub struct RegisterChain {
+    pub chain_id: ChainId,
+    /// Genesis is not downloaded from peers, so it has to be known in advance
+    pub genesis: Option<Head>,
+}
+
 /// Message commands [`ChainManager`] to log its internal stats.
 #[derive(Clone, Debug)]
 pub struct LogStats;
@@ -153,10 +152,69 @@ struct Stats {
     applied_block_last: Option<Instant>,
     /// Last time state was hydrated
     hydrated_state_last: Option<Instant>,
+    /// Count of all processed messages, answered to [Ping]
+    processed_messages: u64,
+}
+
+/// Chain synchronized besides the main chain, e.g. test chain forked from the main chain,
+/// or independent chain registered as active in the chain meta storage.
+///
+/// Chain is downloaded from the same peers as the main chain, but into its own state.
+/// Received block headers do not contain chain_id, so requests made for the chain are remembered.
+/// Blocks are applied by the same chain feeder as the main chain blocks, as soon as their predecessor is applied,
+/// and applied blocks move current head of the chain. Chain is either forked test chain, or chain registered by [RegisterChain].
+///
+/// Mempool is maintained just for the main chain, because there is just one mempool prevalidator,
+/// so mempool operations of the secondary chains are not validated nor propagated.
+///
+/// Genesis of the forked test chain is committed to the context by the chain feeder right after the forking block is applied,
+/// so it is the first applied block and the current head of the test chain.
+struct SecondaryChain {
+    /// Genesis of the chain is not downloaded from peers
+    genesis: Option<BlockHash>,
+    /// Forking block, genesis and expiration, if this is the test chain forked from the main chain
+    test_chain: Option<TestChainData>,
+    /// Holds state of the chain
+    chain_state: BlockchainState,
+    /// Holds state of the chain operations
+    operations_state: OperationsState,
+    /// Current head of the chain, if any block of the chain was applied
+    current_head: Option<Head>,
+    /// Block headers requested for the chain
+    requested_blocks: HashSet<BlockHash>,
+    /// Block operations requested for the chain
+    requested_operations: HashSet<BlockHash>,
+}
+
+impl SecondaryChain {
+    fn new(persistent_storage: &PersistentStorage, chain_id: ChainId, genesis: Option<BlockHash>, test_chain: Option<TestChainData>) -> Self {
+        SecondaryChain {
+            genesis,
+            test_chain,
+            chain_state: BlockchainState::new(persistent_storage, chain_id.clone()),
+            operations_state: OperationsState::new(persistent_storage, chain_id),
+            current_head: None,
+            requested_blocks: HashSet::new(),
+            requested_operations: HashSet::new(),
+        }
+    }
+
+    #[inline]
+    fn chain_id(&self) -> &ChainId {
+        self.chain_state.get_chain_id()
+    }
+
+    /// Returns true, if block with the timestamp was applied after expiration of the test chain
+    fn is_expired(&self, timestamp: i64) -> bool {
+        self.test_chain.as_ref()
+            .and_then(|test_chain| test_chain.expiration)
+            .map(|expiration| timestamp >= expiration)
+            .unwrap_or(false)
+    }
 }
 
 /// Purpose of this actor is to perform chain synchronization.
-#[actor(DisconnectStalledPeers, CheckChainCompleteness, ApplyCompletedBlock, CheckMempoolCompleteness, AskPeersAboutCurrentBranch, LogStats, NetworkChannelMsg, ShellChannelMsg, SystemEvent, DeadLetter)]
+#[actor(DisconnectStalledPeers, CheckChainCompleteness, ApplyCompletedBlock, CheckMempoolCompleteness, AnnounceMempoolOperations, AskPeersAboutCurrentBranch, RegisterChain, LogStats, Ping, NetworkChannelMsg, ShellChannelMsg, SystemEvent, DeadLetter)]
 pub struct ChainManager {
     /// All events generated by the network layer will end up in this channel
     network_channel: NetworkChannelRef,
@@ -167,7 +225,7 @@ pub struct ChainManager {
     /// Block meta storage
     block_meta_storage: Box<dyn BlockMetaStorageReader>,
     /// Chain meta storage
-    chain_meta_storage: Box<dyn ChainMetaStorageReader>,
+    chain_meta_storage: ChainMetaStorage,
     /// Operations storage
     operations_storage: Box<dyn OperationsStorageReader>,
     /// Mempool operation storage
@@ -176,6 +234,12 @@ pub struct ChainManager {
     chain_state: BlockchainState,
     /// Holds state of the operations
     operations_state: OperationsState,
+    /// Chains synchronized besides the main chain (test chain, other active chains)
+    chains: HashMap<ChainId, SecondaryChain>,
+    /// Context is used to resolve expiration of the test chain
+    context: TezedgeContext,
+    /// Persistent storage is used to create state of the secondary chains
+    persistent_storage: PersistentStorage,
 
     /// Node's identity public key - e.g. used for history computation
     identity_peer_id: CryptoboxPublicKeyHash,
@@ -186,6 +250,10 @@ pub struct ChainManager {
     current_head: CurrentHead,
     // current last known mempool state
     current_mempool_state: Option<Arc<RwLock<CurrentMempoolState>>>,
+    /// Indicates, that some mempool operations were not announced to all peers yet
+    mempool_announce_pending: bool,
+    /// Measures propagation of the mempool operations
+    mempool_propagation: MempoolPropagation,
     /// Internal stats
     stats: Stats,
 
@@ -193,13 +261,22 @@ pub struct ChainManager {
     shutting_down: bool,
     /// Indicates node mode
     is_sandbox: bool,
+    /// Indicates that forked test chain should be synchronized
+    enable_testchain: bool,
     /// Indicates that [chain_manager] is bootstrapped, which means, that can broadcast stuff (new branch, new head) to the network
     is_bootstrapped: bool,
     /// Indicates threshold for minimal count of bootstrapped peers to mark chain_manager as bootstrapped
     num_of_peers_for_bootstrap_threshold: usize,
+    /// Trusted peers are never disconnected as stalled
+    peer_roles: PeerRoles,
 
     /// Protocol runner pool dedicated to prevalidation
     tezos_readonly_prevalidation_api: Arc<TezosApiConnectionPool>,
+
+    /// Source of time, can be simulated in tests
+    clock: ClockRef,
+    /// Policy constants, some of them can be changed at runtime
+    shell_config: ShellConfigurationRef,
 }
 
 /// Reference to [chain manager](ChainManager) actor.
@@ -215,8 +292,12 @@ impl ChainManager {
         tezos_readonly_prevalidation_api: Arc<TezosApiConnectionPool>,
         chain_id: &ChainId,
         is_sandbox: bool,
+        enable_testchain: bool,
         peers_threshold: &PeerConnectionThreshold,
-        identity: Arc<Identity>) -> Result<ChainManagerRef, CreateError> {
+        peer_roles: &PeerRoles,
+        identity: Arc<Identity>,
+        clock: ClockRef,
+        shell_config: ShellConfigurationRef) -> Result<ChainManagerRef, CreateError> {
         sys.actor_of_props::<ChainManager>(
             ChainManager::name(),
             Props::new_args((
@@ -226,35 +307,128 @@ impl ChainManager {
                 tezos_readonly_prevalidation_api,
                 chain_id.clone(),
                 is_sandbox,
+                enable_testchain,
                 peers_threshold.num_of_peers_for_bootstrap_threshold(),
+                peer_roles.clone(),
                 identity.calculated_peer_id().map_err(|e| {
                     error!(sys.log(), "Failed to decode peer_id from identity"; "reason" => format!("{}", e));
                     CreateError::Panicked
                 })?,
+                clock,
+                shell_config,
             )),
         )
     }
 
+    /// Periodic messages of the chain manager as (initial delay, interval, message)
+    pub fn timers(shell_config: &ShellConfiguration, is_sandbox: bool) -> Vec<(Duration, Duration, ChainManagerMsg)> {
+        let peer_timeout = if is_sandbox {
+            SILENT_PEER_TIMEOUT_SANDBOX
+        } else {
+            shell_config.silent_peer_timeout / 2
+        };
+
+        vec![
+            (shell_config.check_chain_completeness_interval / 4, shell_config.check_chain_completeness_interval, CheckChainCompleteness.into()),
+            (shell_config.ask_current_branch_interval, shell_config.ask_current_branch_interval, AskPeersAboutCurrentBranch.into()),
+            (shell_config.log_interval / 2, shell_config.log_interval, LogStats.into()),
+            (shell_config.mempool_announce_interval, shell_config.mempool_announce_interval, AnnounceMempoolOperations.into()),
+            (peer_timeout, peer_timeout, DisconnectStalledPeers.into()),
+        ]
+    }
+
     /// The `ChainManager` is intended to serve as a singleton actor so that's why
     /// we won't support multiple names per instance.
     fn name() -> &'static str {
         "chain-manager"
     }
 
+    /// Announces mempool operations unknown to the peers (in `CurrentHead`).
+    ///
+    /// At most `mempool_announce_peers` peers are served in one batch, peers announced the longest time ago first,
+    /// remaining peers are served in the next batches.
+    fn announce_mempool_operations(&mut self, ctx: &Context<ChainManagerMsg>) -> Result<(), Error> {
+        let ChainManager { peers, chain_state, shell_channel, block_storage, current_mempool_state, mempool_announce_pending, mempool_propagation, clock, shell_config, .. } = self;
+        let announce_peers = shell_config.read().unwrap().mempool_announce_peers;
+        let now = clock.now();
+
+        // prepare mempool/header to send to peers
+        let (mempool_to_send, header_to_send) = match current_mempool_state {
+            Some(mempool_state) => {
+                let mempool_state = mempool_state.read().unwrap();
+                match &mempool_state.head {
+                    Some(head_hash) => match block_storage.get(&head_hash)? {
+                        Some(header) => (resolve_mempool_to_send(&mempool_state), (*header.header).clone()),
+                        None => return Ok(()),
+                    }
+                    None => return Ok(()),
+                }
+            }
+            None => return Ok(()),
+        };
+
+        // resolve operations unknown to the peers (just peers with enabled mempool)
+        let mut skipped_known = 0;
+        let mut peers_to_announce = peers.values_mut()
+            .filter(|peer| peer.mempool_enabled)
+            .filter_map(|peer| {
+                let known_valid = peer.known_mempool_operations.unknown(mempool_to_send.known_valid());
+                let pending = peer.known_mempool_operations.unknown(mempool_to_send.pending());
+                skipped_known += mempool_to_send.known_valid().len() + mempool_to_send.pending().len() - known_valid.len() - pending.len();
+                if known_valid.is_empty() && pending.is_empty() {
+                    None
+                } else {
+                    Some((peer, Mempool::new(known_valid, pending)))
+                }
+            })
+            .collect::<Vec<_>>();
+        mempool_propagation.skipped_known(skipped_known);
+
+        // send CurrentHead with just the unknown operations
+        peers_to_announce.sort_by_key(|(peer, _)| peer.mempool_announce_last);
+        let has_remaining_peers = peers_to_announce.len() > announce_peers;
+        let mut announced_operations = HashSet::new();
+        let mut announced_to = 0;
+        for (peer, mempool) in peers_to_announce.into_iter().take(announce_peers) {
+            peer.known_mempool_operations.extend(mempool.known_valid().iter().chain(mempool.pending().iter()));
+            peer.mempool_announce_last = now;
+            announced_operations.extend(mempool.known_valid().iter().chain(mempool.pending().iter()).cloned());
+            tell_peer(CurrentHeadMessage::new(chain_state.get_chain_id().clone(), header_to_send.clone(), mempool).into(), peer);
+            announced_to += 1;
+        }
+        *mempool_announce_pending = has_remaining_peers;
+
+        if announced_to > 0 {
+            mempool_propagation.announced(&announced_operations, now);
+            shell_channel.tell(
+                Publish {
+                    msg: MempoolOperationsAdvertised {
+                        operation_hashes: announced_operations.into_iter().collect(),
+                        peers: announced_to,
+                    }.into(),
+                    topic: ShellChannelTopic::ShellEvents.into(),
+                }, Some(ctx.myself().into()));
+        }
+
+        Ok(())
+    }
+
     fn check_mempool_completeness(&mut self, _ctx: &Context<ChainManagerMsg>) {
-        let ChainManager { peers, .. } = self;
+        let ChainManager { peers, clock, shell_config, .. } = self;
+        let config = *shell_config.read().unwrap();
+        let now = clock.now();
 
         // check for missing mempool operations
         peers.values_mut()
             .filter(|peer| !peer.missing_mempool_operations.is_empty())
-            .filter(|peer| peer.available_block_operations_queue_capacity() > 0)
+            .filter(|peer| peer.available_block_operations_queue_capacity(now) > 0)
             .for_each(|peer| {
-                let num_opts_to_get = cmp::min(peer.missing_mempool_operations.len(), peer.available_mempool_operations_queue_capacity());
+                let num_opts_to_get = cmp::min(peer.missing_mempool_operations.len(), peer.available_mempool_operations_queue_capacity(config.mempool_operations_batch_size));
                 let ops_to_enqueue = peer.missing_mempool_operations
                     .drain(0..num_opts_to_get)
                     .collect::<Vec<_>>();
 
-                let ttl = SystemTime::now() + MEMPOOL_OPERATION_TTL;
+                let ttl = clock.system_now() + config.mempool_operation_ttl;
                 ops_to_enqueue.iter().cloned()
                     .for_each(|(op_hash, op_type)| {
                         peer.queued_mempool_operations.insert(op_hash, (op_type, ttl));
@@ -264,79 +438,59 @@ impl ChainManager {
                     .map(|(op_hash, _)| op_hash)
                     .collect();
 
-                peer.mempool_operations_request_last = Instant::now();
+                peer.mempool_operations_request_last = now;
                 tell_peer(GetOperationsMessage::new(ops_to_get).into(), peer);
             });
     }
 
     /// Check for missing blocks in local chain copy, and schedule downloading for those blocks
     fn check_chain_completeness(&mut self, ctx: &Context<ChainManagerMsg>) -> Result<(), Error> {
-        let ChainManager { peers, chain_state, operations_state, stats, .. } = self;
-
-        // check for missing blocks
-        if chain_state.has_missing_blocks() {
-            peers.values_mut()
-                .filter(|peer| peer.current_head_level.is_some())
-                .filter(|peer| peer.available_block_queue_capacity() > 0)
-                .sorted_by_key(|peer| peer.available_block_queue_capacity()).rev()
-                .for_each(|peer| {
-                    let mut missing_blocks = chain_state.drain_missing_blocks(peer.available_block_queue_capacity(), peer.current_head_level.unwrap());
-                    if !missing_blocks.is_empty() {
-                        let queued_blocks = missing_blocks.drain(..)
-                            .map(|missing_block| {
-                                let missing_block_hash = missing_block.block_hash.clone();
-                                if peer.queued_block_headers.insert(missing_block_hash.clone(), missing_block).is_none() {
-                                    // block was not already present in queue
-                                    Some(missing_block_hash)
-                                } else {
-                                    // block was already in queue
-                                    None
-                                }
-                            })
-                            .filter_map(|missing_block_hash| missing_block_hash)
-                            .collect::<Vec<_>>();
+        let ChainManager { peers, chain_state, operations_state, chains, stats, clock, shell_config, .. } = self;
+        let config = *shell_config.read().unwrap();
+        let now = clock.now();
+
+        // reschedule timed out requests, so they can be retried by other peers
+        for peer in peers.values_mut() {
+            let timed_out_blocks = peer.queued_block_headers.drain_timed_out(now);
+            if !timed_out_blocks.is_empty() {
+                debug!(ctx.system.log(), "Peer did not respond to block header requests on time - rescheduling"; "peer" => format!("{}", peer.peer_id.peer_ref), "count" => timed_out_blocks.len());
+                peer.block_throughput.record_timeout(now);
+                reschedule_missing_blocks(timed_out_blocks, chain_state, chains)?;
+            }
 
-                        if !queued_blocks.is_empty() {
-                            peer.block_request_last = Instant::now();
-                            tell_peer(GetBlockHeadersMessage::new(queued_blocks).into(), peer);
-                        }
-                    }
-                });
+            let timed_out_operations = peer.queued_block_operations.drain_timed_out(now);
+            if !timed_out_operations.is_empty() {
+                debug!(ctx.system.log(), "Peer did not respond to block operations requests on time - rescheduling"; "peer" => format!("{}", peer.peer_id.peer_ref), "count" => timed_out_operations.len());
+                peer.block_operations_throughput.record_timeout(now);
+                reschedule_missing_operations(timed_out_operations, operations_state, chains)?;
+            }
         }
 
+        // check for missing blocks
+        schedule_missing_blocks(peers, chain_state, now, config.block_headers_batch_size, |peer| peer.current_head_level, |_| true);
         // check for missing block operations
-        if operations_state.has_missing_block_operations() {
-            peers.values_mut()
-                .filter(|peer| peer.current_head_level.is_some())
-                .filter(|peer| peer.available_block_operations_queue_capacity() > 0)
-                .sorted_by_key(|peer| peer.available_block_operations_queue_capacity()).rev()
-                .for_each(|peer| {
-                    let missing_operations = operations_state.drain_missing_block_operations(peer.available_block_operations_queue_capacity(), peer.current_head_level.unwrap());
-                    if !missing_operations.is_empty() {
-                        let queued_operations = missing_operations.iter()
-                            .map(|missing_operation| {
-                                if peer.queued_block_operations.insert(missing_operation.block_hash.clone(), missing_operation.clone()).is_none() {
-                                    // operations were not already present in queue
-                                    Some(missing_operation)
-                                } else {
-                                    // operations were already in queue
-                                    None
-                                }
-                            })
-                            .filter_map(|missing_operation| missing_operation)
-                            .collect::<Vec<_>>();
-
-                        if !queued_operations.is_empty() {
-                            peer.block_operations_request_last = Instant::now();
-                            queued_operations.iter()
-                                .for_each(|&missing_operation| tell_peer(GetOperationsForBlocksMessage::new(missing_operation.into()).into(), peer));
-                        }
-                    }
-                });
+        schedule_missing_operations(peers, operations_state, now, |peer| peer.current_head_level, |_| true);
+
+        // secondary chains are downloaded by the same peers
+        for (chain_id, chain) in chains.iter_mut() {
+            let SecondaryChain { genesis, chain_state, operations_state, requested_blocks, requested_operations, .. } = chain;
+            schedule_missing_blocks(peers, chain_state, now, config.block_headers_batch_size, |peer| peer.chain_head_levels.get(chain_id).cloned(), |missing_block| {
+                // genesis is not downloaded (e.g. genesis of the test chain is created by the forking block)
+                if genesis.as_ref() == Some(&missing_block.block_hash) {
+                    false
+                } else {
+                    requested_blocks.insert(missing_block.block_hash.clone());
+                    true
+                }
+            });
+            schedule_missing_operations(peers, operations_state, now, |peer| peer.chain_head_levels.get(chain_id).cloned(), |missing_operations| {
+                requested_operations.insert(missing_operations.block_hash.clone());
+                true
+            });
         }
 
         if let (Some(applied_block_last), Some(hydrated_state_last)) = (stats.applied_block_last, stats.hydrated_state_last) {
-            if (applied_block_last.elapsed() > STALLED_CHAIN_COMPLETENESS_TIMEOUT) && (hydrated_state_last.elapsed() > STALLED_CHAIN_COMPLETENESS_TIMEOUT) {
+            if (now.saturating_duration_since(applied_block_last) > config.stalled_chain_completeness_timeout) && (now.saturating_duration_since(hydrated_state_last) > config.stalled_chain_completeness_timeout) {
                 self.hydrate_state(ctx);
             }
         }
@@ -346,6 +500,25 @@ impl ChainManager {
             self.check_successors_for_apply(ctx, &block_hash)?;
         }
 
+        // secondary chains continue from their head, or from genesis, until first block is applied
+        let secondary_chain_blocks = self.chains.values()
+            .filter_map(|chain| chain.current_head.as_ref().map(|head| head.block_hash().clone()).or_else(|| chain.genesis.clone()))
+            .collect::<Vec<_>>();
+        for block_hash in secondary_chain_blocks {
+            self.check_successors_for_apply(ctx, &block_hash)?;
+        }
+
+        // if we bootstrap from checkpoint, first blocks are applied on top of the imported checkpoint
+        if let Some(checkpoint) = self.chain_state.get_checkpoint() {
+            let below_checkpoint = self.current_head.local.as_ref()
+                .map(|current_head_local| current_head_local.level() < checkpoint.level())
+                .unwrap_or(true);
+            if below_checkpoint {
+                let block_hash = checkpoint.block_hash().clone();
+                self.check_successors_for_apply(ctx, &block_hash)?;
+            }
+        }
+
         Ok(())
     }
 
@@ -354,6 +527,7 @@ impl ChainManager {
             peers,
             chain_state,
             operations_state,
+            chains,
             shell_channel,
             block_storage,
             block_meta_storage,
@@ -362,12 +536,14 @@ impl ChainManager {
             mempool_storage,
             current_head,
             identity_peer_id,
+            clock,
             ..
         } = self;
+        let now = clock.now();
 
         match msg {
-            NetworkChannelMsg::PeerBootstrapped(PeerBootstrapped::Success { peer_id, peer_metadata }) => {
-                let peer = PeerState::new(peer_id, peer_metadata);
+            NetworkChannelMsg::PeerBootstrapped(PeerBootstrapped::Success { peer_id, peer_metadata, network_version }) => {
+                let peer = PeerState::new(peer_id, peer_metadata, network_version, now);
                 // store peer
                 let actor_uri = peer.peer_id.peer_ref.uri().clone();
                 self.peers.insert(actor_uri.clone(), peer);
@@ -375,8 +551,10 @@ impl ChainManager {
                 let peer = self.peers.get_mut(&actor_uri).unwrap();
 
                 let log = ctx.system.log().new(slog::o!("peer_id" => peer.peer_id.as_ref().peer_id_marker.clone()));
-                debug!(log, "Requesting current branch");
+                debug!(log, "Requesting current branch"; "network_version" => format!("{:?}", &peer.network_version));
                 tell_peer(GetCurrentBranchMessage::new(chain_state.get_chain_id().clone()).into(), peer);
+                chains.keys()
+                    .for_each(|chain_id| tell_peer(GetCurrentBranchMessage::new(chain_id.clone()).into(), peer));
             }
             NetworkChannelMsg::PeerMessageReceived(received) => {
                 match peers.get_mut(received.peer.uri()) {
@@ -386,6 +564,22 @@ impl ChainManager {
                         for message in received.message.messages() {
                             match message {
                                 PeerMessage::CurrentBranch(message) => {
+                                    // branch of the secondary chain is scheduled to its own state
+                                    if let Some(chain) = chains.get_mut(message.chain_id()) {
+                                        if chain.chain_state.can_accept_branch(&message, &chain.current_head) {
+                                            let message_current_head = BlockHeaderWithHash::new(message.current_branch().current_head().clone())?;
+                                            chain.chain_state.schedule_branch_bootstrap(
+                                                &message_current_head,
+                                                message.current_branch().history(),
+                                            )?;
+                                            peer.update_chain_head(message.chain_id(), &message_current_head);
+
+                                            // trigger CheckChainCompleteness
+                                            ctx.myself().tell(CheckChainCompleteness, None);
+                                        }
+                                        continue;
+                                    }
+
                                     // at first, check if we can accept branch or just ignore it
                                     if !chain_state.can_accept_branch(&message, &current_head.local) {
                                         let head = message.current_branch().current_head();
@@ -403,7 +597,7 @@ impl ChainManager {
 
                                         // update remote heads
                                         current_head.update_remote_head(&message_current_head);
-                                        peer.update_current_head(&message_current_head);
+                                        peer.update_current_head(&message_current_head, now);
 
                                         // notify others that new block was received
                                         shell_channel.tell(
@@ -420,8 +614,15 @@ impl ChainManager {
                                     }
                                 }
                                 PeerMessage::GetCurrentBranch(message) => {
-                                    if chain_state.get_chain_id() == &message.chain_id {
-                                        if let Some(current_head_local) = &current_head.local {
+                                    // resolve requested chain (main chain or secondary chain)
+                                    let requested_chain = if chain_state.get_chain_id() == &message.chain_id {
+                                        Some((&*chain_state, current_head.local.as_ref()))
+                                    } else {
+                                        chains.get(&message.chain_id).map(|chain| (&chain.chain_state, chain.current_head.as_ref()))
+                                    };
+
+                                    match requested_chain {
+                                        Some((chain_state, Some(current_head_local))) => {
                                             if let Some(current_head) = block_storage.get(current_head_local.block_hash())? {
                                                 // calculate history
                                                 let history = chain_state.get_history(
@@ -439,28 +640,81 @@ impl ChainManager {
                                                 tell_peer(msg.into(), peer);
                                             }
                                         }
-                                    } else {
-                                        warn!(log, "Peer is requesting current branch from unsupported chain_id"; "chain_id" => HashType::ChainId.bytes_to_string(chain_state.get_chain_id()));
+                                        Some((_, None)) => {
+                                            debug!(log, "Peer is requesting current branch of the chain without applied head"; "chain_id" => HashType::ChainId.bytes_to_string(&message.chain_id));
+                                        }
+                                        None => {
+                                            warn!(log, "Peer is requesting current branch from unsupported chain_id"; "chain_id" => HashType::ChainId.bytes_to_string(&message.chain_id));
+                                        }
                                     }
                                 }
                                 PeerMessage::BlockHeader(message) => {
                                     let block_header_with_hash = BlockHeaderWithHash::new(message.block_header().clone()).unwrap();
-                                    match peer.queued_block_headers.remove(&block_header_with_hash.hash) {
-                                        Some(_) => {
-                                            peer.block_response_last = Instant::now();
-                                            Self::process_downloaded_header(
-                                                block_header_with_hash,
-                                                ctx.myself(),
-                                                &log,
-                                                chain_state,
-                                                operations_state,
-                                                stats,
-                                                shell_channel,
-                                            )?;
+                                    let header_was_expected = match peer.queued_block_headers.remove(&block_header_with_hash.hash) {
+                                        Some((_, requested_at)) => {
+                                            peer.block_response_last = now;
+                                            peer.block_throughput.record_response(requested_at, now);
+                                            true
+                                        }
+                                        None if peer.queued_block_headers.is_late_response(&block_header_with_hash.hash) => {
+                                            // request was already rescheduled, but late response still carries valid data (if not received from other peer meanwhile)
+                                            debug!(log, "Received block header after timeout - processing late response"; "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&block_header_with_hash.hash));
+                                            !block_storage.contains(&block_header_with_hash.hash)?
                                         }
                                         None => {
                                             warn!(log, "Received unexpected block header"; "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&block_header_with_hash.hash));
+                                            false
+                                        }
+                                    };
+
+                                    if header_was_expected {
+                                        if chain_state.contradicts_checkpoint(&block_header_with_hash.hash, &block_header_with_hash.header)? {
+                                            warn!(log, "Received block header contradicts checkpoint - blacklisting peer";
+                                                       "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&block_header_with_hash.hash),
+                                                       "level" => block_header_with_hash.header.level());
+                                            blacklist_peer(&self.network_channel, peer, String::from("block header contradicts checkpoint"));
+                                            return Ok(());
                                         }
+
+                                        // headers requested for the secondary chain are stored to its own state
+                                        let requested_chain = chains.values_mut()
+                                            .find(|chain| chain.requested_blocks.contains(&block_header_with_hash.hash));
+                                        let (chain_state, operations_state) = match requested_chain {
+                                            Some(chain) => {
+                                                chain.requested_blocks.remove(&block_header_with_hash.hash);
+                                                (&mut chain.chain_state, &mut chain.operations_state)
+                                            }
+                                            None => (&mut *chain_state, &mut *operations_state),
+                                        };
+
+                                        // check header in rust, before we store it and spend protocol time on it,
+                                        // header was requested by hash, so peer just delivers it and is not blamed for its content
+                                        match chain_state.prevalidate_block_header(&block_header_with_hash)? {
+                                            BlockPrevalidationResult::Valid => (),
+                                            BlockPrevalidationResult::InvalidBlock(error) => {
+                                                warn!(log, "Received block header failed prevalidation - ignoring block";
+                                                           "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&block_header_with_hash.hash),
+                                                           "reason" => format!("{}", error));
+                                                continue;
+                                            }
+                                            BlockPrevalidationResult::InvalidSuccessor(successor, error) => {
+                                                warn!(log, "Stored successor of received block header failed prevalidation";
+                                                           "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&successor),
+                                                           "reason" => format!("{}", error));
+                                            }
+                                        }
+
+                                        Self::process_downloaded_header(
+                                            block_header_with_hash,
+                                            ctx.myself(),
+                                            &log,
+                                            chain_state,
+                                            operations_state,
+                                            stats,
+                                            now,
+                                            shell_channel,
+                                            &self.network_channel,
+                                        )?;
                                     }
                                 }
                                 PeerMessage::GetBlockHeaders(message) => {
@@ -475,66 +729,117 @@ impl ChainManager {
                                     if chain_state.get_chain_id() == message.chain_id() {
                                         if let Some(current_head_local) = &current_head.local {
                                             if let Some(current_head) = block_storage.get(current_head_local.block_hash())? {
+                                                let mempool = resolve_mempool_to_send_to_peer(&peer, &self.current_mempool_state, &current_head_local);
+                                                peer.known_mempool_operations.extend(mempool.known_valid().iter().chain(mempool.pending().iter()));
                                                 let msg = CurrentHeadMessage::new(
                                                     chain_state.get_chain_id().clone(),
                                                     (*current_head.header).clone(),
-                                                    resolve_mempool_to_send_to_peer(&peer, &self.current_mempool_state, &current_head_local),
+                                                    mempool,
                                                 );
                                                 tell_peer(msg.into(), peer);
                                             }
                                         }
+                                    } else if let Some(current_head_local) = chains.get(message.chain_id()).and_then(|chain| chain.current_head.as_ref()) {
+                                        if let Some(current_head) = block_storage.get(current_head_local.block_hash())? {
+                                            // mempool is maintained only for the main chain
+                                            let msg = CurrentHeadMessage::new(
+                                                message.chain_id().clone(),
+                                                (*current_head.header).clone(),
+                                                Mempool::default(),
+                                            );
+                                            tell_peer(msg.into(), peer);
+                                        }
                                     }
                                 }
                                 PeerMessage::OperationsForBlocks(operations) => {
                                     let block_hash = operations.operations_for_block().hash().clone();
-                                    match peer.queued_block_operations.get_mut(&block_hash) {
-                                        Some(missing_operations) => {
-                                            let operation_was_expected = missing_operations.validation_passes.remove(&operations.operations_for_block().validation_pass());
-                                            if operation_was_expected {
-                                                peer.block_operations_response_last = Instant::now();
-                                                trace!(log, "Received operations validation pass"; "validation_pass" => operations.operations_for_block().validation_pass(), "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&block_hash));
-
-                                                if operations_state.process_block_operations(&operations)? {
-                                                    // update stats
-                                                    stats.unseen_block_operations_last = Instant::now();
-
-                                                    // notify others that new all operations for block were received
-                                                    let block_meta = block_meta_storage.get(&block_hash)?.ok_or(StorageError::MissingKey)?;
-
-                                                    // check if block can be applied
-                                                    if chain_state.can_apply_block((&block_hash, &block_meta), |_| Ok(true))? {
-                                                        ctx.myself().tell(
-                                                            ApplyCompletedBlock {
-                                                                block_hash: block_hash.clone()
-                                                            },
-                                                            None,
-                                                        );
-                                                    }
-
-                                                    // trigger CheckChainCompleteness
-                                                    ctx.myself().tell(CheckChainCompleteness, None);
-
-                                                    // notify others that new all operations for block were received
-                                                    shell_channel.tell(
-                                                        Publish {
-                                                            msg: AllBlockOperationsReceived {
-                                                                hash: block_hash.clone(),
-                                                                level: block_meta.level(),
-                                                            }.into(),
-                                                            topic: ShellChannelTopic::ShellEvents.into(),
-                                                        }, Some(ctx.myself().into()));
-
-                                                    // remove operations from queue
-                                                    peer.queued_block_operations.remove(&block_hash);
-                                                }
+                                    let operation_was_expected = match peer.queued_block_operations.get_mut(&block_hash) {
+                                        Some((missing_operations, requested_at)) => {
+                                            let requested_at = *requested_at;
+                                            if missing_operations.validation_passes.remove(&operations.operations_for_block().validation_pass()) {
+                                                peer.block_operations_response_last = now;
+                                                peer.block_operations_throughput.record_response(requested_at, now);
+                                                true
                                             } else {
                                                 warn!(log, "Received unexpected validation pass"; "validation_pass" => operations.operations_for_block().validation_pass(), "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&block_hash));
                                                 ctx.system.stop(received.peer.clone());
+                                                false
                                             }
                                         }
+                                        None if peer.queued_block_operations.is_late_response(&block_hash) => {
+                                            // request was already rescheduled, but late response still carries valid data (if not received from other peer meanwhile)
+                                            debug!(log, "Received operations after timeout - processing late response"; "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&block_hash));
+                                            !operations_state.are_operations_complete(&block_hash)?
+                                        }
                                         None => {
                                             warn!(log, "Received unexpected operations");
                                             ctx.system.stop(received.peer.clone());
+                                            false
+                                        }
+                                    };
+
+                                    if operation_was_expected {
+                                        trace!(log, "Received operations validation pass"; "validation_pass" => operations.operations_for_block().validation_pass(), "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&block_hash));
+
+                                        // operations requested for the secondary chain are stored to its own state
+                                        let (chain_state, operations_state) = match chains.values_mut().find(|chain| chain.requested_operations.contains(&block_hash)) {
+                                            Some(chain) => (&chain.chain_state, &mut chain.operations_state),
+                                            None => (&*chain_state, &mut *operations_state),
+                                        };
+
+                                        // operations must match operations_hash of the block header
+                                        match block_storage.get(&block_hash)? {
+                                            Some(block) => if let Err(error) = validation::prevalidate_block_operations(&block.header, &operations) {
+                                                warn!(log, "Received operations failed prevalidation - blacklisting peer";
+                                                           "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&block_hash),
+                                                           "reason" => format!("{}", error));
+                                                blacklist_peer(&self.network_channel, peer, format!("{}", error));
+                                                return Ok(());
+                                            }
+                                            None => {
+                                                // operations are validated and stored, when block header arrives (see process_downloaded_header)
+                                                if !operations_state.add_unvalidated_operations(peer.peer_id.clone(), operations.clone()) {
+                                                    debug!(log, "Too many operations wait for block header - ignoring operations"; "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&block_hash));
+                                                }
+                                                continue;
+                                            }
+                                        }
+
+                                        if operations_state.process_block_operations(&operations)? {
+                                            // update stats
+                                            stats.unseen_block_operations_last = now;
+
+                                            // notify others that new all operations for block were received
+                                            let block_meta = block_meta_storage.get(&block_hash)?.ok_or(StorageError::MissingKey)?;
+
+                                            // check if block can be applied (block of the secondary chain is applied, when its predecessor is applied)
+                                            let can_apply_block = block_meta.chain_id() == chain_state.get_chain_id() && chain_state.can_apply_block((&block_hash, &block_meta), |_| Ok(true))?;
+                                            chains.values_mut()
+                                                .for_each(|chain| { chain.requested_operations.remove(&block_hash); });
+                                            if can_apply_block {
+                                                ctx.myself().tell(
+                                                    ApplyCompletedBlock {
+                                                        block_hash: block_hash.clone()
+                                                    },
+                                                    None,
+                                                );
+                                            }
+
+                                            // trigger CheckChainCompleteness
+                                            ctx.myself().tell(CheckChainCompleteness, None);
+
+                                            // notify others that new all operations for block were received
+                                            shell_channel.tell(
+                                                Publish {
+                                                    msg: AllBlockOperationsReceived {
+                                                        hash: block_hash.clone(),
+                                                        level: block_meta.level(),
+                                                    }.into(),
+                                                    topic: ShellChannelTopic::ShellEvents.into(),
+                                                }, Some(ctx.myself().into()));
+
+                                            // remove operations from queue
+                                            peer.queued_block_operations.remove(&block_hash);
                                         }
                                     }
                                 }
@@ -551,6 +856,44 @@ impl ChainManager {
                                     }
                                 }
                                 PeerMessage::CurrentHead(message) => {
+                                    // head of the secondary chain is just downloaded to its own state
+                                    if let Some(chain) = chains.get_mut(message.chain_id()) {
+                                        let message_current_head = BlockHeaderWithHash::new(message.current_block_header().clone())?;
+                                        match chain.chain_state.prevalidate_block_header(&message_current_head)? {
+                                            BlockPrevalidationResult::Valid => (),
+                                            BlockPrevalidationResult::InvalidBlock(error) => {
+                                                warn!(log, "Received current head failed prevalidation - blacklisting peer";
+                                                           "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&message_current_head.hash),
+                                                           "reason" => format!("{}", error));
+                                                blacklist_peer(&self.network_channel, peer, format!("{}", error));
+                                                return Ok(());
+                                            }
+                                            BlockPrevalidationResult::InvalidSuccessor(successor, error) => {
+                                                warn!(log, "Stored successor of received current head failed prevalidation";
+                                                           "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&successor),
+                                                           "reason" => format!("{}", error));
+                                            }
+                                        }
+
+                                        peer.update_chain_head(message.chain_id(), &message_current_head);
+                                        Self::process_downloaded_header(
+                                            message_current_head,
+                                            ctx.myself(),
+                                            &log,
+                                            &mut chain.chain_state,
+                                            &mut chain.operations_state,
+                                            stats,
+                                            now,
+                                            shell_channel,
+                                            &self.network_channel,
+                                        )?;
+                                        continue;
+                                    }
+
+                                    // peer knows operations of its mempool, so they are not announced back to the peer
+                                    let peer_current_mempool = message.current_mempool();
+                                    peer.known_mempool_operations.extend(peer_current_mempool.known_valid().iter().chain(peer_current_mempool.pending().iter()));
+
                                     // process current head only if we are bootstrapped
                                     if !self.is_bootstrapped {
                                         continue;
@@ -565,9 +908,26 @@ impl ChainManager {
                                         BlockAcceptanceResult::AcceptBlock => {
                                             let message_current_head = BlockHeaderWithHash::new(message.current_block_header().clone())?;
 
+                                            // check header in rust, before we store it and spend protocol time on it
+                                            match chain_state.prevalidate_block_header(&message_current_head)? {
+                                                BlockPrevalidationResult::Valid => (),
+                                                BlockPrevalidationResult::InvalidBlock(error) => {
+                                                    warn!(log, "Received current head failed prevalidation - blacklisting peer";
+                                                               "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&message_current_head.hash),
+                                                               "reason" => format!("{}", error));
+                                                    blacklist_peer(&self.network_channel, peer, format!("{}", error));
+                                                    return Ok(());
+                                                }
+                                                BlockPrevalidationResult::InvalidSuccessor(successor, error) => {
+                                                    warn!(log, "Stored successor of received current head failed prevalidation";
+                                                               "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&successor),
+                                                               "reason" => format!("{}", error));
+                                                }
+                                            }
+
                                             // update remote heads
                                             current_head.update_remote_head(&message_current_head);
-                                            peer.update_current_head(&message_current_head);
+                                            peer.update_current_head(&message_current_head, now);
 
                                             // schedule header/operations download
                                             Self::process_downloaded_header(
@@ -577,22 +937,19 @@ impl ChainManager {
                                                 chain_state,
                                                 operations_state,
                                                 stats,
+                                                now,
                                                 shell_channel,
+                                                &self.network_channel,
                                             )?;
 
                                             // schedule mempool download
-                                            let peer_current_mempool = message.current_mempool();
-
                                             // all operations (known_valid + pending) should be added to pending and validated afterwards
-                                            // enqueue mempool operations for retrieval
-                                            peer_current_mempool.known_valid().iter().cloned()
-                                                .for_each(|operation_hash| {
-                                                    peer.missing_mempool_operations.push((operation_hash, MempoolOperationType::Pending));
-                                                });
-                                            peer_current_mempool.pending().iter().cloned()
-                                                .for_each(|operation_hash| {
-                                                    peer.missing_mempool_operations.push((operation_hash, MempoolOperationType::Pending));
-                                                });
+                                            // enqueue mempool operations for retrieval, banned and recently refused operations are not downloaded again
+                                            for operation_hash in peer_current_mempool.known_valid().iter().chain(peer_current_mempool.pending().iter()) {
+                                                if !mempool_storage.is_blacklisted(operation_hash)? {
+                                                    peer.missing_mempool_operations.push((operation_hash.clone(), MempoolOperationType::Pending));
+                                                }
+                                            }
 
                                             // trigger CheckMempoolCompleteness
                                             ctx.myself().tell(CheckMempoolCompleteness, None);
@@ -609,21 +966,7 @@ impl ChainManager {
                                         }
                                         BlockAcceptanceResult::MutlipassValidationError(error) => {
                                             warn!(log, "Mutlipass validation error detected - blacklisting peer"; "reason" => &error);
-
-                                            // clear peer stuff immediatelly
-                                            peer.clear();
-
-                                            // blacklist peer
-                                            self.network_channel.tell(
-                                                Publish {
-                                                    msg: NetworkChannelMsg::BlacklistPeer(
-                                                        peer.peer_id.clone(),
-                                                        format!("{:?}", error),
-                                                    ),
-                                                    topic: NetworkChannelTopic::NetworkEvents.into(),
-                                                },
-                                                None,
-                                            );
+                                            blacklist_peer(&self.network_channel, peer, format!("{:?}", error));
                                         }
                                     };
                                 }
@@ -642,6 +985,7 @@ impl ChainManager {
                                     // parse operation data
                                     let operation = message.operation();
                                     let operation_hash = operation.message_hash()?;
+                                    peer.known_mempool_operations.insert(operation_hash.clone());
 
                                     match peer.queued_mempool_operations.remove(&operation_hash) {
                                         Some((operation_type, op_ttl)) => {
@@ -672,11 +1016,11 @@ impl ChainManager {
 
                                             // can accpect operation ?
                                             if !validation::can_accept_operation_from_p2p(&operation_hash, &result) {
-                                                return Err(format_err!("Operation from p2p ({}) was not added to mempool. Reason: {:?}", HashType::OperationHash.bytes_to_string(&operation_hash), result));
+                                                return Err(format_err!("Operation from p2p ({}) was not added to mempool. Reason: {:?}", HashType::OperationHash.bytes_to_string(&operation_hash), result.as_result()));
                                             }
 
                                             // store mempool operation
-                                            peer.mempool_operations_response_last = Instant::now();
+                                            peer.mempool_operations_response_last = now;
                                             mempool_storage.put(operation_type.clone(), message.clone(), op_ttl)?;
 
                                             // trigger CheckMempoolCompleteness
@@ -688,6 +1032,7 @@ impl ChainManager {
                                                     msg: MempoolOperationReceived {
                                                         operation_hash,
                                                         operation_type,
+                                                        source: MempoolOperationSource::P2p(peer.peer_id.peer_id_marker.clone()),
                                                     }.into(),
                                                     topic: ShellChannelTopic::ShellEvents.into(),
                                                 }, Some(ctx.myself().into()));
@@ -721,10 +1066,24 @@ impl ChainManager {
                 // - set current head
                 // - set bootstrapped flag
                 // - broadcast new current head/branch to peers (if bootstrapped)
-                // - start test chain (if needed) (TODO: TE-123 - not implemented yet)
+                // - start test chain (if needed) (see [ShellChannelMsg::TestChainForked])
                 // - update checkpoint (TODO: TE-210 - not implemented yet)
                 // - reset mempool_prevalidator
 
+                // block of the secondary chain just moves current head of its own chain
+                let block_chain_id = self.block_meta_storage.get(&message.header().hash)?.map(|meta| meta.chain_id().clone());
+                if let Some(chain) = block_chain_id.and_then(|chain_id| self.chains.get_mut(&chain_id)) {
+                    if let Some((new_head, _)) = chain.chain_state.try_update_new_current_head(&message, &chain.current_head, &None)? {
+                        debug!(ctx.system.log(), "New current head of the secondary chain";
+                                                 "chain_id" => HashType::ChainId.bytes_to_string(chain.chain_id()),
+                                                 "block_header_hash" => HashType::BlockHash.bytes_to_string(new_head.block_hash()),
+                                                 "level" => new_head.level());
+                        chain.current_head = Some(new_head);
+                    }
+                    self.check_successors_for_apply(ctx, &message.header().hash)?;
+                    return Ok(());
+                }
+
                 // we try to set it as "new current head", if some means set, if none means just ignore block
                 if let Some((new_head, new_head_result)) = self.chain_state.try_update_new_current_head(&message, &self.current_head.local, &self.current_mempool_state)? {
                     debug!(ctx.system.log(), "New current head";
@@ -733,9 +1092,41 @@ impl ChainManager {
                                              "result" => format!("{}", new_head_result)
                     );
 
+                    // resolve fork point, if current head was switched to another branch
+                    let reorganization = match (&new_head_result, &self.current_head.local) {
+                        (HeadResult::BranchSwitch, Some(previous_head)) => self.chain_state.find_reorganization(previous_head.block_hash(), new_head.block_hash())?,
+                        _ => None,
+                    };
+
                     // update internal state with new head
                     self.update_local_current_head(new_head.clone(), &ctx.system.log());
 
+                    // return operations from orphaned blocks back to the mempool
+                    let reinjected_operations = match &reorganization {
+                        Some(reorganization) => {
+                            info!(ctx.system.log(), "Chain reorganization";
+                                                    "fork_point" => HashType::BlockHash.bytes_to_string(&reorganization.fork_point),
+                                                    "removed_blocks" => reorganization.removed.len(),
+                                                    "added_blocks" => reorganization.added.len());
+                            let reinjected_operations = self.reinject_orphaned_operations(reorganization)?;
+
+                            // notify other actors, that blocks above fork point were undone
+                            self.shell_channel.tell(
+                                Publish {
+                                    msg: ChainReorganized {
+                                        fork_point: reorganization.fork_point.clone(),
+                                        removed: reorganization.removed.clone(),
+                                        added: reorganization.added.clone(),
+                                        reinjected_operations: reinjected_operations.clone(),
+                                    }.into(),
+                                    topic: ShellChannelTopic::ShellEvents.into(),
+                                }, Some(ctx.myself().into()));
+
+                            reinjected_operations
+                        }
+                        None => Vec::new(),
+                    };
+
                     // notify other actors that new current head was changed
                     // (this also notifies [mempool_prevalidator])
                     self.shell_channel.tell(
@@ -744,6 +1135,21 @@ impl ChainManager {
                             topic: ShellChannelTopic::ShellEvents.into(),
                         }, Some(ctx.myself().into()));
 
+                    // reinjected operations are validated by [mempool_prevalidator] against the new current head
+                    reinjected_operations
+                        .into_iter()
+                        .for_each(|operation_hash| {
+                            self.shell_channel.tell(
+                                Publish {
+                                    msg: MempoolOperationReceived {
+                                        operation_hash,
+                                        operation_type: MempoolOperationType::Pending,
+                                        source: MempoolOperationSource::Reinjected,
+                                    }.into(),
+                                    topic: ShellChannelTopic::ShellEvents.into(),
+                                }, Some(ctx.myself().into()));
+                        });
+
                     // broadcast new head/branch to other peers
                     // we can do this, only if we are bootstrapped,
                     // e.g. if we just start to bootstrap from the scratch, we dont want to spam other nodes (with higher level)
@@ -773,46 +1179,48 @@ impl ChainManager {
 
                 // check successors, if can be applied
                 self.check_successors_for_apply(ctx, &message.header().hash)?;
+
+                // stop test chain, if expired
+                self.check_test_chain_expiration(&message, &ctx.system.log())?;
+            }
+            ShellChannelMsg::TestChainForked(forked) => {
+                self.start_test_chain(ctx, forked)?;
             }
             ShellChannelMsg::MempoolStateChanged(new_mempool_state) => {
-                // set current mempool state
-                self.current_mempool_state = Some(new_mempool_state);
-
-                // prepare mempool/header to send to peers
-                let (mempool_to_send, header_to_send) = if let Some(mempool_state) = &self.current_mempool_state {
-                    let mempool_state = mempool_state.read().unwrap();
-                    match &mempool_state.head {
-                        Some(head_hash) => {
-                            if let Some(header) = self.block_storage.get(&head_hash)? {
-                                (resolve_mempool_to_send(&mempool_state), Some((*header.header).clone()))
-                            } else {
-                                (Mempool::default(), None)
-                            }
-                        }
-                        None => (Mempool::default(), None)
-                    }
-                } else {
-                    (Mempool::default(), None)
-                };
-
-                // send CurrentHead, only if we have anything in mempool (just to peers with enabled mempool)
-                if let Some(header_to_send) = header_to_send {
-                    if !mempool_to_send.is_empty() {
-                        let ChainManager { peers, chain_state, .. } = self;
-                        peers.iter_mut()
-                            .filter(|(_, peer)| peer.mempool_enabled)
-                            .for_each(|(_, peer)| {
-                                tell_peer(
-                                    CurrentHeadMessage::new(
-                                        chain_state.get_chain_id().clone(),
-                                        header_to_send.clone(),
-                                        mempool_to_send.clone(),
-                                    ).into(),
-                                    peer,
-                                )
+                {
+                    let mempool_state = new_mempool_state.read().unwrap();
+                    let mempool_operations: HashSet<OperationHash> = mempool_state.result.applied().iter().map(|a| a.hash.clone())
+                        .chain(mempool_state.pending.iter().cloned())
+                        .collect();
+                    self.mempool_propagation.mempool_changed(&mempool_operations, self.clock.now());
+
+                    // mempool was reset for the new head, so peers can forget operations, which are not relevant anymore
+                    let previous_head = self.current_mempool_state.as_ref().and_then(|previous| previous.read().unwrap().head.clone());
+                    if previous_head != mempool_state.head {
+                        self.peers.values_mut()
+                            .for_each(|peer| {
+                                let PeerState { known_mempool_operations, queued_mempool_operations, missing_mempool_operations, .. } = peer;
+                                known_mempool_operations.retain(|operation_hash| mempool_operations.contains(operation_hash)
+                                    || queued_mempool_operations.contains_key(operation_hash)
+                                    || missing_mempool_operations.iter().any(|(missing, _)| missing == operation_hash));
                             });
                     }
                 }
+
+                // set current mempool state, new operations are announced to the peers in the next batch
+                self.current_mempool_state = Some(new_mempool_state);
+                self.mempool_announce_pending = true;
+            }
+            ShellChannelMsg::RequestMempoolOperations(request) => {
+                // peers respond with their current head and mempool
+                let ChainManager { peers, chain_state, .. } = self;
+                peers.iter_mut()
+                    .filter(|(_, peer)| peer.mempool_enabled)
+                    .filter(|(_, peer)| match &request.peer_id {
+                        Some(peer_id) => peer.peer_id.peer_id_marker.eq(peer_id),
+                        None => true,
+                    })
+                    .for_each(|(_, peer)| tell_peer(GetCurrentHeadMessage::new(chain_state.get_chain_id().clone()).into(), peer));
             }
             ShellChannelMsg::InjectBlock(inject_data) => {
                 let level = inject_data.block_header.level();
@@ -832,7 +1240,7 @@ impl ChainManager {
 
                 if is_new_block {
                     // update stats
-                    self.stats.unseen_block_last = Instant::now();
+                    self.stats.unseen_block_last = self.clock.now();
                     self.stats.unseen_block_count += 1;
 
                     // notify others that new block (header) was received
@@ -862,7 +1270,7 @@ impl ChainManager {
                                 are_operations_complete = true;
 
                                 // update stats
-                                self.stats.unseen_block_operations_last = Instant::now();
+                                self.stats.unseen_block_operations_last = self.clock.now();
 
                                 // notify others that new all operations for block were received
                                 self.shell_channel.tell(
@@ -911,11 +1319,13 @@ impl ChainManager {
         chain_state: &mut BlockchainState,
         operations_state: &mut OperationsState,
         stats: &mut Stats,
+        now: Instant,
         shell_channel: &ShellChannelRef,
+        network_channel: &NetworkChannelRef,
     ) -> Result<(), Error> {
 
         // stored header and operations
-        let (block_metadata, is_new_block, are_operations_complete) =
+        let (block_metadata, is_new_block, mut are_operations_complete) =
             chain_state.process_block_header(&received_block, log)
                 .and_then(|(block_metadata, is_new_block)| {
                     operations_state
@@ -923,6 +1333,20 @@ impl ChainManager {
                         .map(|are_operations_complete| (block_metadata, is_new_block, are_operations_complete))
                 })?;
 
+        // operations received before the block header can be validated now
+        for (peer_id, operations) in operations_state.take_unvalidated_operations(&received_block.hash) {
+            match validation::prevalidate_block_operations(&received_block.header, &operations) {
+                Ok(()) => are_operations_complete = operations_state.process_block_operations(&operations)?,
+                Err(error) => {
+                    warn!(log, "Received operations failed prevalidation - blacklisting peer";
+                               "block_header_hash" => BLOCK_HASH_ENCODING.bytes_to_string(&received_block.hash),
+                               "peer_id" => peer_id.peer_id_marker.clone(),
+                               "reason" => format!("{}", error));
+                    blacklist_peer_id(network_channel, peer_id, format!("{}", error));
+                }
+            }
+        }
+
         // check if block can be applied
         if chain_state.can_apply_block((&received_block.hash, &block_metadata), |_| Ok(are_operations_complete))? {
             myself.tell(
@@ -935,7 +1359,7 @@ impl ChainManager {
 
         if is_new_block {
             // update stats
-            stats.unseen_block_last = Instant::now();
+            stats.unseen_block_last = now;
             stats.unseen_block_count += 1;
 
             // TODO: preverit vsetky tieto pingovacky
@@ -966,6 +1390,29 @@ impl ChainManager {
         info!(ctx.system.log(), "Hydrating operations state");
         self.operations_state.hydrate().expect("Failed to hydrate operations state");
 
+        // restore secondary chains registered as active
+        let active_chains = self.chain_meta_storage.get_active_chains().expect("Failed to load active chains");
+        for (chain_id, active_chain) in active_chains {
+            if &chain_id == self.chain_state.get_chain_id() || self.chains.contains_key(&chain_id) {
+                continue;
+            }
+            let test_chain = self.chain_meta_storage.get_test_chain_data(&chain_id).expect("Failed to load test chain data");
+            if active_chain.forked_from.is_some() && !self.enable_testchain {
+                debug!(ctx.system.log(), "Test chain is not enabled - not restored"; "chain_id" => HashType::ChainId.bytes_to_string(&chain_id));
+                continue;
+            }
+            let genesis = self.chain_meta_storage.get_genesis(&chain_id).expect("Failed to load genesis")
+                .map(|genesis| genesis.block_hash().clone());
+            info!(ctx.system.log(), "Restoring active chain"; "chain_id" => HashType::ChainId.bytes_to_string(&chain_id), "is_test_chain" => test_chain.is_some());
+            self.chains.insert(chain_id.clone(), SecondaryChain::new(&self.persistent_storage, chain_id, genesis, test_chain));
+        }
+        for (chain_id, chain) in self.chains.iter_mut() {
+            info!(ctx.system.log(), "Hydrating secondary chain state"; "chain_id" => HashType::ChainId.bytes_to_string(chain_id));
+            chain.current_head = self.chain_meta_storage.get_current_head(chain_id).expect("Failed to load current head");
+            chain.chain_state.hydrate().expect("Failed to hydrate chain state");
+            chain.operations_state.hydrate().expect("Failed to hydrate operations state");
+        }
+
         let (local_head, local_head_level, local_fitness) = self.current_head.local_debug_info();
         info!(
             ctx.system.log(),
@@ -976,7 +1423,193 @@ impl ChainManager {
             "missing_blocks" => self.chain_state.missing_blocks_count(),
             "missing_block_operations" => self.operations_state.missing_block_operations_count(),
         );
-        self.stats.hydrated_state_last = Some(Instant::now());
+        self.stats.hydrated_state_last = Some(self.clock.now());
+    }
+
+    /// Starts synchronization of the test chain forked by the applied block of the main chain
+    fn start_test_chain(&mut self, ctx: &Context<ChainManagerMsg>, forked: TestChainForked) -> Result<(), Error> {
+        let log = ctx.system.log();
+        if &forked.chain_id != self.chain_state.get_chain_id() {
+            // test chain is forked only from the main chain
+            return Ok(());
+        }
+        if !self.enable_testchain {
+            info!(log, "Test chain forked, but test chain is not enabled - ignoring"; "test_chain_id" => HashType::ChainId.bytes_to_string(&forked.test_chain_id));
+            return Ok(());
+        }
+        if self.chains.contains_key(&forked.test_chain_id) {
+            // already running
+            return Ok(());
+        }
+
+        // main chain has just one test chain, previous one is replaced
+        let previous_test_chains = self.chains.values()
+            .filter(|chain| chain.test_chain.is_some())
+            .map(|chain| chain.chain_id().clone())
+            .collect::<Vec<_>>();
+        for previous_test_chain_id in previous_test_chains {
+            self.stop_chain(&previous_test_chain_id, &log)?;
+        }
+
+        // genesis of the test chain is on the level of the forking block
+        let forking_level = self.block_meta_storage.get(&forked.forking_block_hash)?
+            .ok_or(StorageError::MissingKey)?
+            .level();
+        let data = TestChainData {
+            genesis_block_hash: test_chain_genesis(&forked.forking_block_hash),
+            forking_block_hash: forked.forking_block_hash,
+            expiration: None,
+            test_protocol: None,
+        };
+        self.chain_meta_storage.set_test_chain_id(&forked.chain_id, &forked.test_chain_id)?;
+        self.chain_meta_storage.set_test_chain_data(&forked.test_chain_id, data.clone())?;
+        if self.chain_meta_storage.get_genesis(&forked.test_chain_id)?.is_none() {
+            // genesis was not committed by the chain feeder
+            self.chain_meta_storage.set_genesis(&forked.test_chain_id, Head::new(data.genesis_block_hash.clone(), forking_level, vec![]))?;
+        }
+        self.chain_meta_storage.set_active_chain(&forked.test_chain_id, ActiveChain::new(Some(forked.chain_id.clone())))?;
+
+        info!(log, "Starting test chain";
+                   "test_chain_id" => HashType::ChainId.bytes_to_string(&forked.test_chain_id),
+                   "forking_block" => HashType::BlockHash.bytes_to_string(&data.forking_block_hash),
+                   "genesis" => HashType::BlockHash.bytes_to_string(&data.genesis_block_hash));
+        let genesis = Some(data.genesis_block_hash.clone());
+        let mut chain = SecondaryChain::new(&self.persistent_storage, forked.test_chain_id.clone(), genesis, Some(data));
+        chain.current_head = self.chain_meta_storage.get_current_head(&forked.test_chain_id)?;
+        self.chains.insert(forked.test_chain_id.clone(), chain);
+        self.resolve_test_chain_expiration(&log)?;
+
+        // ask peers about test chain branch
+        self.peers.values()
+            .for_each(|peer| tell_peer(GetCurrentBranchMessage::new(forked.test_chain_id.clone()).into(), peer));
+
+        Ok(())
+    }
+
+    /// Starts synchronization of the registered chain, which is not forked from the main chain
+    fn register_chain(&mut self, msg: RegisterChain, log: &Logger) -> Result<(), Error> {
+        if &msg.chain_id == self.chain_state.get_chain_id() || self.chains.contains_key(&msg.chain_id) {
+            // already running
+            return Ok(());
+        }
+
+        if let Some(genesis) = &msg.genesis {
+            self.chain_meta_storage.set_genesis(&msg.chain_id, genesis.clone())?;
+        }
+        self.chain_meta_storage.set_active_chain(&msg.chain_id, ActiveChain::new(None))?;
+
+        info!(log, "Registering chain";
+                   "chain_id" => HashType::ChainId.bytes_to_string(&msg.chain_id),
+                   "genesis" => msg.genesis.as_ref().map(|genesis| HashType::BlockHash.bytes_to_string(genesis.block_hash())));
+        let genesis = msg.genesis.map(|genesis| genesis.block_hash().clone());
+        let mut chain = SecondaryChain::new(&self.persistent_storage, msg.chain_id.clone(), genesis, None);
+        chain.current_head = self.chain_meta_storage.get_current_head(&msg.chain_id)?;
+        chain.chain_state.hydrate()?;
+        chain.operations_state.hydrate()?;
+        self.chains.insert(msg.chain_id.clone(), chain);
+
+        // ask peers about chain branch
+        self.peers.values()
+            .for_each(|peer| tell_peer(GetCurrentBranchMessage::new(msg.chain_id.clone()).into(), peer));
+
+        Ok(())
+    }
+
+    /// Stops synchronization of the secondary chain and unregisters it from active chains (downloaded blocks are kept)
+    fn stop_chain(&mut self, chain_id: &ChainId, log: &Logger) -> Result<(), Error> {
+        if let Some(chain) = self.chains.remove(chain_id) {
+            // in-flight requests for the chain are not rescheduled
+            for peer in self.peers.values_mut() {
+                chain.requested_blocks.iter().for_each(|block_hash| { peer.queued_block_headers.remove(block_hash); });
+                chain.requested_operations.iter().for_each(|block_hash| { peer.queued_block_operations.remove(block_hash); });
+                peer.chain_head_levels.remove(chain_id);
+            }
+
+            self.chain_meta_storage.remove_active_chain(chain_id)?;
+            if chain.test_chain.is_some() {
+                self.chain_meta_storage.remove_test_chain_id(self.chain_state.get_chain_id())?;
+                self.chain_meta_storage.remove_test_chain_data(chain_id)?;
+            }
+            info!(log, "Chain stopped";
+                       "chain_id" => HashType::ChainId.bytes_to_string(chain_id),
+                       "expiration" => chain.test_chain.and_then(|test_chain| test_chain.expiration));
+        }
+        Ok(())
+    }
+
+    /// Expiration of the test chain is stored by protocol in the context of the forking block.
+    /// Context is stored asynchronously, so this is retried until expiration is resolved.
+    fn resolve_test_chain_expiration(&mut self, log: &Logger) -> Result<(), Error> {
+        let ChainManager { chains, block_storage, chain_meta_storage, context, .. } = self;
+        for (chain_id, chain) in chains.iter_mut() {
+            let test_chain = match chain.test_chain.as_mut().filter(|test_chain| test_chain.expiration.is_none()) {
+                Some(test_chain) => test_chain,
+                None => continue,
+            };
+
+            if let Some(forking_block) = block_storage.get(&test_chain.forking_block_hash)? {
+                match get_test_chain_status(&*context, forking_block.header.context()) {
+                    Ok(Some(status)) => if let Some(expiration) = status.expiration() {
+                        test_chain.expiration = Some(expiration);
+                        test_chain.test_protocol = status.protocol().cloned();
+                        chain_meta_storage.set_test_chain_data(chain_id, test_chain.clone())?;
+                        info!(log, "Test chain expiration resolved";
+                                   "test_chain_id" => HashType::ChainId.bytes_to_string(chain_id),
+                                   "expiration" => expiration);
+                    }
+                    Ok(None) => (),
+                    Err(e) => warn!(log, "Failed to read test chain status from context"; "reason" => format!("{:?}", e)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Test chain is stopped, when main chain block with timestamp after expiration is applied
+    fn check_test_chain_expiration(&mut self, applied_block: &BlockApplied, log: &Logger) -> Result<(), Error> {
+        if self.chains.is_empty() {
+            return Ok(());
+        }
+
+        self.resolve_test_chain_expiration(log)?;
+        let expired_chains = self.chains.values()
+            .filter(|chain| chain.is_expired(applied_block.header().header.timestamp()))
+            .map(|chain| chain.chain_id().clone())
+            .collect::<Vec<_>>();
+        for chain_id in expired_chains {
+            self.stop_chain(&chain_id, log)?;
+        }
+        Ok(())
+    }
+
+    /// Stores operations from blocks removed by chain reorganization back to the mempool as pending.
+    /// Operations, which are also included in added blocks, stay in the chain and are skipped.
+    fn reinject_orphaned_operations(&mut self, reorganization: &Reorganization) -> Result<Vec<OperationHash>, Error> {
+        let mut included_operations = HashSet::new();
+        for block_hash in &reorganization.added {
+            for operations in self.operations_storage.get_operations(block_hash)? {
+                for operation in operations.operations() {
+                    included_operations.insert(operation.message_hash()?);
+                }
+            }
+        }
+
+        let ttl = self.clock.system_now() + self.shell_config.read().unwrap().mempool_operation_ttl;
+        let mut reinjected_operations = Vec::new();
+        for block_hash in &reorganization.removed {
+            for operations in self.operations_storage.get_operations(block_hash)? {
+                for operation in operations.operations() {
+                    let operation_hash = operation.message_hash()?;
+                    if included_operations.contains(&operation_hash) {
+                        continue;
+                    }
+                    self.mempool_storage.put(MempoolOperationType::Pending, operation.clone().into(), ttl)?;
+                    reinjected_operations.push(operation_hash);
+                }
+            }
+        }
+
+        Ok(reinjected_operations)
     }
 
     /// Updates currnet local head and some stats.
@@ -985,7 +1618,7 @@ impl ChainManager {
         let new_level = new_head.level().clone();
         self.current_head.local = Some(new_head);
         self.stats.applied_block_level = Some(new_level);
-        self.stats.applied_block_last = Some(Instant::now());
+        self.stats.applied_block_last = Some(self.clock.now());
         self.resolve_is_bootstrapped(log);
     }
 
@@ -1031,7 +1664,12 @@ impl ChainManager {
             for successor in metadata.successors() {
                 // check if block can be applied
                 if let Some(successor_metadata) = self.block_meta_storage.get(&successor)? {
-                    if self.chain_state.can_apply_block((&successor, &successor_metadata), |bh| self.operations_state.are_operations_complete(bh))? {
+                    // block is applied within its own chain
+                    let (chain_state, operations_state) = match self.chains.get(successor_metadata.chain_id()) {
+                        Some(chain) => (&chain.chain_state, &chain.operations_state),
+                        None => (&self.chain_state, &self.operations_state),
+                    };
+                    if chain_state.can_apply_block((&successor, &successor_metadata), |bh| operations_state.are_operations_complete(bh))? {
                         ctx.myself().tell(
                             ApplyCompletedBlock {
                                 block_hash: successor.clone()
@@ -1060,86 +1698,61 @@ impl ChainManager {
             None => return Err(format_err!("Block metadata not found for block_hash: {}", HashType::BlockHash.bytes_to_string(&msg.block_hash))),
         }
 
-        // collect data
-        let request = self.prepare_apply_request(&msg.block_hash)?;
-
-        // ping chain_feeder
+        // ping chain_feeder, which collects data for application
         self.shell_channel.tell(
             Publish {
-                msg: ShellChannelMsg::ApplyBlock(msg.block_hash, Arc::new(request)),
+                msg: ShellChannelMsg::ApplyBlock(msg.block_hash),
                 topic: ShellChannelTopic::ShellEvents.into(),
             }, Some(ctx.myself().into()));
 
         Ok(())
     }
-
-    /// Collects complete data for applying block, if not complete, return None
-    fn prepare_apply_request(&self, block_hash: &BlockHash) -> Result<ApplyBlockRequest, StorageError> {
-
-        // chain_id
-        let chain_id = self.chain_state.get_chain_id();
-
-        // get block header
-        let current_head = match self.block_storage.get(block_hash)? {
-            Some(block) => block,
-            None => return Err(StorageError::MissingKey)
-        };
-
-        // get operations
-        let operations = self.operations_storage.get_operations(block_hash)?;
-
-        // get predecessor metadata
-        let (predecessor, predecessor_additional_data) = match self.block_storage.get_with_additional_data(&current_head.header.predecessor())? {
-            Some((predecessor, predecessor_additional_data)) => (predecessor, predecessor_additional_data),
-            None => return Err(StorageError::MissingKey)
-        };
-
-        Ok(
-            ApplyBlockRequest {
-                chain_id: chain_id.clone(),
-                block_header: (&*current_head.header).clone(),
-                pred_header: (&*predecessor.header).clone(),
-                operations: ApplyBlockRequest::convert_operations(operations),
-                max_operations_ttl: predecessor_additional_data.max_operations_ttl() as i32,
-            }
-        )
-    }
 }
 
-impl ActorFactoryArgs<(NetworkChannelRef, ShellChannelRef, PersistentStorage, Arc<TezosApiConnectionPool>, ChainId, bool, usize, CryptoboxPublicKeyHash)> for ChainManager {
+impl ActorFactoryArgs<(NetworkChannelRef, ShellChannelRef, PersistentStorage, Arc<TezosApiConnectionPool>, ChainId, bool, bool, usize, PeerRoles, CryptoboxPublicKeyHash, ClockRef, ShellConfigurationRef)> for ChainManager {
     fn create_args(
-        (network_channel, shell_channel, persistent_storage, tezos_readonly_prevalidation_api, chain_id, is_sandbox, num_of_peers_for_bootstrap_threshold, identity_peer_id):
-        (NetworkChannelRef, ShellChannelRef, PersistentStorage, Arc<TezosApiConnectionPool>, ChainId, bool, usize, CryptoboxPublicKeyHash)) -> Self {
+        (network_channel, shell_channel, persistent_storage, tezos_readonly_prevalidation_api, chain_id, is_sandbox, enable_testchain, num_of_peers_for_bootstrap_threshold, peer_roles, identity_peer_id, clock, shell_config):
+        (NetworkChannelRef, ShellChannelRef, PersistentStorage, Arc<TezosApiConnectionPool>, ChainId, bool, bool, usize, PeerRoles, CryptoboxPublicKeyHash, ClockRef, ShellConfigurationRef)) -> Self {
         ChainManager {
             network_channel,
             shell_channel,
             block_storage: Box::new(BlockStorage::new(&persistent_storage)),
             block_meta_storage: Box::new(BlockMetaStorage::new(&persistent_storage)),
-            chain_meta_storage: Box::new(ChainMetaStorage::new(&persistent_storage)),
+            chain_meta_storage: ChainMetaStorage::new(&persistent_storage),
             operations_storage: Box::new(OperationsStorage::new(&persistent_storage)),
             mempool_storage: MempoolStorage::new(&persistent_storage),
             chain_state: BlockchainState::new(&persistent_storage, chain_id.clone()),
             operations_state: OperationsState::new(&persistent_storage, chain_id),
+            chains: HashMap::new(),
+            context: TezedgeContext::new(BlockStorage::new(&persistent_storage), persistent_storage.merkle()),
+            persistent_storage,
             peers: HashMap::new(),
             current_head: CurrentHead {
                 local: None,
                 remote: None,
             },
             current_mempool_state: None,
+            mempool_announce_pending: false,
+            mempool_propagation: MempoolPropagation::default(),
             shutting_down: false,
             stats: Stats {
                 unseen_block_count: 0,
-                unseen_block_last: Instant::now(),
-                unseen_block_operations_last: Instant::now(),
+                unseen_block_last: clock.now(),
+                unseen_block_operations_last: clock.now(),
                 applied_block_last: None,
                 applied_block_level: None,
                 hydrated_state_last: None,
+                processed_messages: 0,
             },
             is_sandbox,
+            enable_testchain,
             identity_peer_id,
             is_bootstrapped: false,
             num_of_peers_for_bootstrap_threshold,
+            peer_roles,
             tezos_readonly_prevalidation_api,
+            clock,
+            shell_config,
         }
     }
 }
@@ -1155,62 +1768,48 @@ impl Actor for ChainManager {
 
         self.hydrate_state(ctx);
 
-        ctx.schedule::<Self::Msg, _>(
-            CHECK_CHAIN_COMPLETENESS_INTERVAL / 4,
-            CHECK_CHAIN_COMPLETENESS_INTERVAL,
-            ctx.myself(),
-            None,
-            CheckChainCompleteness.into());
-        ctx.schedule::<Self::Msg, _>(
-            ASK_CURRENT_BRANCH_INTERVAL,
-            ASK_CURRENT_BRANCH_INTERVAL,
-            ctx.myself(),
-            None,
-            AskPeersAboutCurrentBranch.into());
-        ctx.schedule::<Self::Msg, _>(
-            LOG_INTERVAL / 2,
-            LOG_INTERVAL,
-            ctx.myself(),
-            None,
-            LogStats.into());
-
-        let peer_timeout = if self.is_sandbox {
-            SILENT_PEER_TIMEOUT_SANDBOX
-        } else {
-            SILENT_PEER_TIMEOUT / 2
-        };
-        ctx.schedule::<Self::Msg, _>(
-            peer_timeout,
-            peer_timeout,
-            ctx.myself(),
-            None,
-            DisconnectStalledPeers.into());
+        // with simulated clock, timer messages are sent by the driver of the simulation
+        if !self.clock.is_simulated() {
+            let shell_config = *self.shell_config.read().unwrap();
+            for (initial_delay, interval, msg) in Self::timers(&shell_config, self.is_sandbox) {
+                ctx.schedule::<Self::Msg, _>(initial_delay, interval, ctx.myself(), None, msg);
+            }
+        }
     }
 
     fn sys_recv(&mut self, ctx: &Context<Self::Msg>, msg: SystemMsg, sender: Option<BasicActorRef>) {
         if let SystemMsg::Event(evt) = msg {
+            self.stats.processed_messages += 1;
             self.receive(ctx, evt, sender);
         }
     }
 
     fn recv(&mut self, ctx: &Context<Self::Msg>, msg: Self::Msg, sender: Sender) {
+        self.stats.processed_messages += 1;
         self.receive(ctx, msg, sender);
     }
 }
 
+impl Receive<Ping> for ChainManager {
+    type Msg = ChainManagerMsg;
+
+    fn receive(&mut self, _ctx: &Context<Self::Msg>, _msg: Ping, sender: Sender) {
+        if let Some(sender) = sender {
+            let _ = sender.try_tell(Pong { processed_messages: self.stats.processed_messages }, None);
+        }
+    }
+}
+
 impl Receive<SystemEvent> for ChainManager {
     type Msg = ChainManagerMsg;
 
     fn receive(&mut self, _ctx: &Context<Self::Msg>, msg: SystemEvent, _sender: Option<BasicActorRef>) {
         if let SystemEvent::ActorTerminated(evt) = msg {
             if let Some(mut peer) = self.peers.remove(evt.actor.uri()) {
-                peer.queued_block_headers
-                    .drain()
-                    .for_each(|(_, missing_block)| {
-                        self.chain_state.push_missing_block(missing_block).expect("Failed to re-schedule block hash");
-                    });
+                reschedule_missing_blocks(peer.queued_block_headers.drain(), &mut self.chain_state, &mut self.chains)
+                    .expect("Failed to re-schedule block hash");
 
-                self.operations_state.push_missing_block_operations(peer.queued_block_operations.drain().map(|(_, op)| op))
+                reschedule_missing_operations(peer.queued_block_operations.drain(), &mut self.operations_state, &mut self.chains)
                     .expect("Failed to return to queue")
             }
         }
@@ -1230,6 +1829,7 @@ impl Receive<LogStats> for ChainManager {
 
     fn receive(&mut self, ctx: &Context<Self::Msg>, _msg: LogStats, _sender: Sender) {
         let log = ctx.system.log();
+        let now = self.clock.now();
         let (local, local_level, local_fitness) = &self.current_head.local_debug_info();
         let (remote, remote_level, remote_fitness) = &self.current_head.remote_debug_info();
         info!(log, "Head info";
@@ -1243,25 +1843,44 @@ impl Receive<LogStats> for ChainManager {
             "block_count" => self.stats.unseen_block_count,
             "missing_blocks" => self.chain_state.missing_blocks_count(),
             "missing_block_operations" => self.operations_state.missing_block_operations_count(),
-            "last_block_secs" => self.stats.unseen_block_last.elapsed().as_secs(),
-            "last_block_operations_secs" => self.stats.unseen_block_operations_last.elapsed().as_secs(),
+            "last_block_secs" => now.saturating_duration_since(self.stats.unseen_block_last).as_secs(),
+            "last_block_operations_secs" => now.saturating_duration_since(self.stats.unseen_block_operations_last).as_secs(),
             "applied_block_level" => self.stats.applied_block_level,
-            "applied_block_secs" => self.stats.applied_block_last.map(|i| i.elapsed().as_secs()));
+            "applied_block_secs" => self.stats.applied_block_last.map(|i| now.saturating_duration_since(i).as_secs()));
+        for (chain_id, chain) in self.chains.iter() {
+            info!(log, "Secondary chain info";
+                "chain_id" => HashType::ChainId.bytes_to_string(chain_id),
+                "is_test_chain" => chain.test_chain.is_some(),
+                "expiration" => chain.test_chain.as_ref().and_then(|test_chain| test_chain.expiration),
+                "remote_level" => self.peers.values().filter_map(|peer| peer.chain_head_levels.get(chain_id)).max().cloned(),
+                "missing_blocks" => chain.chain_state.missing_blocks_count(),
+                "missing_block_operations" => chain.operations_state.missing_block_operations_count());
+        }
         for peer in self.peers.values() {
             debug!(log, "Peer state info";
                 "actor_ref" => format!("{}", peer.peer_id.peer_ref),
                 "queued_block_headers" => peer.queued_block_headers.len(),
                 "queued_block_operations" => peer.queued_block_operations.len(),
-                "block_request_secs" => peer.block_request_last.elapsed().as_secs(),
-                "block_response_secs" => peer.block_response_last.elapsed().as_secs(),
-                "block_operations_request_secs" => peer.block_operations_request_last.elapsed().as_secs(),
-                "block_operations_response_secs" => peer.block_operations_response_last.elapsed().as_secs(),
-                "mempool_operations_request_secs" => peer.mempool_operations_request_last.elapsed().as_secs(),
-                "mempool_operations_response_secs" => peer.mempool_operations_response_last.elapsed().as_secs(),
+                "block_response_avg_millis" => peer.block_throughput.average_response_time().map(|t| t.as_millis() as u64),
+                "block_operations_response_avg_millis" => peer.block_operations_throughput.average_response_time().map(|t| t.as_millis() as u64),
+                "block_request_secs" => now.saturating_duration_since(peer.block_request_last).as_secs(),
+                "block_response_secs" => now.saturating_duration_since(peer.block_response_last).as_secs(),
+                "block_operations_request_secs" => now.saturating_duration_since(peer.block_operations_request_last).as_secs(),
+                "block_operations_response_secs" => now.saturating_duration_since(peer.block_operations_response_last).as_secs(),
+                "mempool_operations_request_secs" => now.saturating_duration_since(peer.mempool_operations_request_last).as_secs(),
+                "mempool_operations_response_secs" => now.saturating_duration_since(peer.mempool_operations_response_last).as_secs(),
+                "known_mempool_operations" => peer.known_mempool_operations.len(),
+                "mempool_announce_secs" => now.saturating_duration_since(peer.mempool_announce_last).as_secs(),
                 "current_head_level" => peer.current_head_level,
-                "current_head_update_secs" => peer.current_head_update_last.elapsed().as_secs());
+                "current_head_update_secs" => now.saturating_duration_since(peer.current_head_update_last).as_secs());
         }
-        info!(log, "Various info"; "peer_count" => self.peers.len(), "hydrated_state_secs" => self.stats.hydrated_state_last.map(|i| i.elapsed().as_secs()));
+        info!(log, "Mempool propagation info";
+            "announced_operations" => self.mempool_propagation.announced_count(),
+            "unannounced_operations" => self.mempool_propagation.unannounced_count(),
+            "skipped_known_announcements" => self.mempool_propagation.skipped_known_count(),
+            "announce_latency_avg_millis" => self.mempool_propagation.average_latency().map(|t| t.as_millis() as u64),
+            "announce_latency_max_millis" => self.mempool_propagation.max_latency().as_millis() as u64);
+        info!(log, "Various info"; "peer_count" => self.peers.len(), "hydrated_state_secs" => self.stats.hydrated_state_last.map(|i| now.saturating_duration_since(i).as_secs()));
     }
 }
 
@@ -1269,36 +1888,43 @@ impl Receive<DisconnectStalledPeers> for ChainManager {
     type Msg = ChainManagerMsg;
 
     fn receive(&mut self, ctx: &Context<Self::Msg>, _msg: DisconnectStalledPeers, _sender: Sender) {
+        let now = self.clock.now();
+        let config = *self.shell_config.read().unwrap();
+        let peer_roles = &self.peer_roles;
         self.peers.iter()
             .for_each(|(uri, state)| {
                 let block_response_pending = state.block_request_last > state.block_response_last;
                 let block_operations_response_pending = state.block_operations_request_last > state.block_operations_response_last;
                 let mempool_operations_response_pending = state.mempool_operations_request_last > state.mempool_operations_response_last;
 
-                let should_disconnect = if state.current_head_update_last.elapsed() > CURRENT_HEAD_LEVEL_UPDATE_TIMEOUT {
+                let should_disconnect = if now.saturating_duration_since(state.current_head_update_last) > config.current_head_level_update_timeout {
                     warn!(ctx.system.log(), "Peer failed to update its current head"; "peer" => format!("{}", uri));
                     true
-                } else if block_response_pending && (state.block_request_last - state.block_response_last > SILENT_PEER_TIMEOUT) {
-                    warn!(ctx.system.log(), "Peer did not respond to our request for block on time"; "peer" => format!("{}", uri), "request_secs" => state.block_request_last.elapsed().as_secs(), "response_secs" => state.block_response_last.elapsed().as_secs());
+                } else if block_response_pending && (state.block_request_last - state.block_response_last > config.silent_peer_timeout) {
+                    warn!(ctx.system.log(), "Peer did not respond to our request for block on time"; "peer" => format!("{}", uri), "request_secs" => now.saturating_duration_since(state.block_request_last).as_secs(), "response_secs" => now.saturating_duration_since(state.block_response_last).as_secs());
                     true
-                } else if block_operations_response_pending && (state.block_operations_request_last - state.block_operations_response_last > SILENT_PEER_TIMEOUT) {
-                    warn!(ctx.system.log(), "Peer did not respond to our request for block operations on time"; "peer" => format!("{}", uri), "request_secs" => state.block_operations_request_last.elapsed().as_secs(), "response_secs" => state.block_operations_response_last.elapsed().as_secs());
+                } else if block_operations_response_pending && (state.block_operations_request_last - state.block_operations_response_last > config.silent_peer_timeout) {
+                    warn!(ctx.system.log(), "Peer did not respond to our request for block operations on time"; "peer" => format!("{}", uri), "request_secs" => now.saturating_duration_since(state.block_operations_request_last).as_secs(), "response_secs" => now.saturating_duration_since(state.block_operations_response_last).as_secs());
                     true
-                } else if block_response_pending && !state.queued_block_headers.is_empty() && (state.block_response_last.elapsed() > SILENT_PEER_TIMEOUT) {
-                    warn!(ctx.system.log(), "Peer is not providing requested blocks"; "peer" => format!("{}", uri), "queued_count" => state.queued_block_headers.len(), "response_secs" => state.block_response_last.elapsed().as_secs());
+                } else if block_response_pending && !state.queued_block_headers.is_empty() && (now.saturating_duration_since(state.block_response_last) > config.silent_peer_timeout) {
+                    warn!(ctx.system.log(), "Peer is not providing requested blocks"; "peer" => format!("{}", uri), "queued_count" => state.queued_block_headers.len(), "response_secs" => now.saturating_duration_since(state.block_response_last).as_secs());
                     true
-                } else if block_operations_response_pending && !state.queued_block_operations.is_empty() && (state.block_operations_response_last.elapsed() > SILENT_PEER_TIMEOUT) {
-                    warn!(ctx.system.log(), "Peer is not providing requested block operations"; "peer" => format!("{}", uri), "queued_count" => state.queued_block_operations.len(), "response_secs" => state.block_operations_response_last.elapsed().as_secs());
+                } else if block_operations_response_pending && !state.queued_block_operations.is_empty() && (now.saturating_duration_since(state.block_operations_response_last) > config.silent_peer_timeout) {
+                    warn!(ctx.system.log(), "Peer is not providing requested block operations"; "peer" => format!("{}", uri), "queued_count" => state.queued_block_operations.len(), "response_secs" => now.saturating_duration_since(state.block_operations_response_last).as_secs());
                     true
-                } else if mempool_operations_response_pending && !state.queued_mempool_operations.is_empty() && (state.mempool_operations_response_last.elapsed() > SILENT_PEER_TIMEOUT) {
-                    warn!(ctx.system.log(), "Peer is not providing requested mempool operations"; "peer" => format!("{}", uri), "queued_count" => state.queued_mempool_operations.len(), "response_secs" => state.mempool_operations_response_last.elapsed().as_secs());
+                } else if mempool_operations_response_pending && !state.queued_mempool_operations.is_empty() && (now.saturating_duration_since(state.mempool_operations_response_last) > config.silent_peer_timeout) {
+                    warn!(ctx.system.log(), "Peer is not providing requested mempool operations"; "peer" => format!("{}", uri), "queued_count" => state.queued_mempool_operations.len(), "response_secs" => now.saturating_duration_since(state.mempool_operations_response_last).as_secs());
                     true
                 } else {
                     false
                 };
 
                 if should_disconnect {
-                    ctx.system.stop(state.peer_id.peer_ref.clone());
+                    if peer_roles.is_trusted(&state.peer_id.peer_address, Some(&state.peer_id.peer_id_marker)) {
+                        info!(ctx.system.log(), "Trusted peer is stalled - will not disconnect"; "peer" => format!("{}", uri));
+                    } else {
+                        ctx.system.stop(state.peer_id.peer_ref.clone());
+                    }
                 }
             });
     }
@@ -1314,6 +1940,21 @@ impl Receive<CheckMempoolCompleteness> for ChainManager {
     }
 }
 
+impl Receive<AnnounceMempoolOperations> for ChainManager {
+    type Msg = ChainManagerMsg;
+
+    fn receive(&mut self, ctx: &Context<Self::Msg>, _msg: AnnounceMempoolOperations, _sender: Sender) {
+        if self.shutting_down || !self.mempool_announce_pending {
+            return;
+        }
+
+        match self.announce_mempool_operations(ctx) {
+            Ok(_) => (),
+            Err(e) => warn!(ctx.system.log(), "Failed to announce mempool operations"; "reason" => format!("{:?}", e)),
+        }
+    }
+}
+
 impl Receive<CheckChainCompleteness> for ChainManager {
     type Msg = ChainManagerMsg;
 
@@ -1366,13 +2007,31 @@ impl Receive<ShellChannelMsg> for ChainManager {
     }
 }
 
+impl Receive<RegisterChain> for ChainManager {
+    type Msg = ChainManagerMsg;
+
+    fn receive(&mut self, ctx: &Context<Self::Msg>, msg: RegisterChain, _sender: Sender) {
+        if self.shutting_down {
+            return;
+        }
+
+        if let Err(e) = self.register_chain(msg, &ctx.system.log()) {
+            warn!(ctx.system.log(), "Failed to register chain"; "reason" => format!("{:?}", e));
+        }
+    }
+}
+
 impl Receive<AskPeersAboutCurrentBranch> for ChainManager {
     type Msg = ChainManagerMsg;
 
     fn receive(&mut self, _ctx: &Context<Self::Msg>, _msg: AskPeersAboutCurrentBranch, _sender: Sender) {
-        let ChainManager { peers, chain_state, .. } = self;
+        let ChainManager { peers, chain_state, chains, .. } = self;
         peers.iter_mut()
-            .for_each(|(_, peer)| tell_peer(GetCurrentBranchMessage::new(chain_state.get_chain_id().clone()).into(), peer))
+            .for_each(|(_, peer)| {
+                tell_peer(GetCurrentBranchMessage::new(chain_state.get_chain_id().clone()).into(), peer);
+                chains.keys()
+                    .for_each(|chain_id| tell_peer(GetCurrentBranchMessage::new(chain_id.clone()).into(), peer));
+            })
     }
 }
 
@@ -1380,17 +2039,25 @@ impl Receive<AskPeersAboutCurrentBranch> for ChainManager {
 struct PeerState {
     /// PeerId identification (actor_ref + public key)
     peer_id: Arc<PeerId>,
+    /// Network version negotiated with the peer
+    network_version: NetworkVersion,
     /// Has peer enabled mempool
     mempool_enabled: bool,
     /// Is bootstrapped flag
     is_bootstrapped: bool,
 
     /// Queued blocks
-    queued_block_headers: HashMap<BlockHash, MissingBlock>,
+    queued_block_headers: InFlightRequests<MissingBlock>,
     /// Queued block operations
-    queued_block_operations: HashMap<BlockHash, MissingOperations>,
+    queued_block_operations: InFlightRequests<MissingOperations>,
+    /// Measured throughput of block headers download, resolves how many requests can be in-flight
+    block_throughput: PeerThroughput,
+    /// Measured throughput of block operations download, resolves how many requests can be in-flight
+    block_operations_throughput: PeerThroughput,
     /// Level of the current head received from peer
     current_head_level: Option<i32>,
+    /// Levels of the current heads of the secondary chains received from peer
+    chain_head_levels: HashMap<ChainId, Level>,
     /// Last time we received updated head from peer
     current_head_update_last: Instant,
     /// Last time we requested block from the peer
@@ -1412,61 +2079,69 @@ struct PeerState {
     /// Queued mempool operations. This map holds an operation hash and
     /// a tuple of type of a mempool operation with its time to live.
     queued_mempool_operations: HashMap<OperationHash, (MempoolOperationType, SystemTime)>,
+    /// Mempool operations known by the peer, they are not announced to the peer again
+    known_mempool_operations: KnownOperations,
+    /// Last time we announced mempool operations to the peer
+    mempool_announce_last: Instant,
 }
 
 impl PeerState {
-    fn new(peer_id: Arc<PeerId>, peer_metadata: MetadataMessage) -> Self {
+    fn new(peer_id: Arc<PeerId>, peer_metadata: MetadataMessage, network_version: NetworkVersion, now: Instant) -> Self {
         PeerState {
             peer_id,
+            network_version,
             mempool_enabled: !peer_metadata.disable_mempool(),
             is_bootstrapped: false,
-            queued_block_headers: HashMap::new(),
-            queued_block_operations: HashMap::new(),
+            queued_block_headers: InFlightRequests::new(),
+            queued_block_operations: InFlightRequests::new(),
+            block_throughput: PeerThroughput::new(),
+            block_operations_throughput: PeerThroughput::new(),
             missing_mempool_operations: Vec::new(),
             queued_mempool_operations: HashMap::default(),
+            known_mempool_operations: KnownOperations::default(),
+            mempool_announce_last: now,
             current_head_level: None,
-            current_head_update_last: Instant::now(),
-            block_request_last: Instant::now(),
-            block_response_last: Instant::now(),
-            block_operations_request_last: Instant::now(),
-            block_operations_response_last: Instant::now(),
-            mempool_operations_request_last: Instant::now(),
-            mempool_operations_response_last: Instant::now(),
+            chain_head_levels: HashMap::new(),
+            current_head_update_last: now,
+            block_request_last: now,
+            block_response_last: now,
+            block_operations_request_last: now,
+            block_operations_response_last: now,
+            mempool_operations_request_last: now,
+            mempool_operations_response_last: now,
         }
     }
 
-    fn available_block_queue_capacity(&self) -> usize {
-        let queued_count = self.queued_block_headers.len();
-        if queued_count < BLOCK_HEADERS_BATCH_SIZE {
-            BLOCK_HEADERS_BATCH_SIZE - queued_count
-        } else {
-            0
-        }
+    fn available_block_queue_capacity(&self, now: Instant) -> usize {
+        self.queued_block_headers.available_capacity(self.block_throughput.target_in_flight(now))
     }
 
-    fn available_block_operations_queue_capacity(&self) -> usize {
-        let queued_count = self.queued_block_operations.len();
-        if queued_count < BLOCK_OPERATIONS_BATCH_SIZE {
-            BLOCK_OPERATIONS_BATCH_SIZE - queued_count
-        } else {
-            0
-        }
+    fn available_block_operations_queue_capacity(&self, now: Instant) -> usize {
+        self.queued_block_operations.available_capacity(self.block_operations_throughput.target_in_flight(now))
     }
 
-    fn available_mempool_operations_queue_capacity(&self) -> usize {
+    fn available_mempool_operations_queue_capacity(&self, batch_size: usize) -> usize {
         let queued_count = self.queued_mempool_operations.len();
-        if queued_count < MEMPOOL_OPERATIONS_BATCH_SIZE {
-            MEMPOOL_OPERATIONS_BATCH_SIZE - queued_count
+        if queued_count < batch_size {
+            batch_size - queued_count
         } else {
             0
         }
     }
 
-    fn update_current_head(&mut self, block_header: &BlockHeaderWithHash) {
+    fn update_current_head(&mut self, block_header: &BlockHeaderWithHash, now: Instant) {
         // TODO: maybe fitness check?
         if self.current_head_level.is_none() || (block_header.header.level() > self.current_head_level.unwrap()) {
             self.current_head_level = Some(block_header.header.level());
-            self.current_head_update_last = Instant::now();
+            self.current_head_update_last = now;
+        }
+    }
+
+    /// Updates level of the current head of the secondary chain
+    fn update_chain_head(&mut self, chain_id: &ChainId, block_header: &BlockHeaderWithHash) {
+        let level = self.chain_head_levels.entry(chain_id.clone()).or_insert_with(|| block_header.header.level());
+        if block_header.header.level() > *level {
+            *level = block_header.header.level();
         }
     }
 
@@ -1482,9 +2157,30 @@ fn tell_peer(msg: PeerMessageResponse, peer: &PeerState) {
     peer.peer_id.peer_ref.tell(SendMessage::new(msg), None);
 }
 
+fn blacklist_peer(network_channel: &NetworkChannelRef, peer: &mut PeerState, reason: String) {
+    // clear peer stuff immediatelly
+    peer.clear();
+
+    // blacklist peer
+    blacklist_peer_id(network_channel, peer.peer_id.clone(), reason);
+}
+
+fn blacklist_peer_id(network_channel: &NetworkChannelRef, peer_id: Arc<PeerId>, reason: String) {
+    network_channel.tell(
+        Publish {
+            msg: NetworkChannelMsg::BlacklistPeer(
+                peer_id,
+                reason,
+            ),
+            topic: NetworkChannelTopic::NetworkEvents.into(),
+        },
+        None,
+    );
+}
+
 fn resolve_mempool_to_send(mempool_state: &CurrentMempoolState) -> Mempool {
     // collect for mempool
-    let known_valid = mempool_state.result.applied.iter().map(|a| a.hash.clone()).collect::<Vec<OperationHash>>();
+    let known_valid = mempool_state.result.applied().iter().map(|a| a.hash.clone()).collect::<Vec<OperationHash>>();
     let pending = mempool_state.pending.iter().cloned().collect::<Vec<OperationHash>>();
 
     Mempool::new(known_valid, pending)
@@ -1511,6 +2207,115 @@ fn resolve_mempool_to_send_to_peer(peer: &PeerState, mempool_state: &Option<Arc<
     }
 }
 
+/// Schedules download of missing blocks to the peers (peers with the highest measured throughput are served first).
+///
+/// Peers are asked for blocks up to the level of their current head of the chain resolved by `peer_level`.
+/// Block is requested only if `on_request` returns true. Requests are sent in messages with at most `batch_size` blocks.
+fn schedule_missing_blocks<L, F>(peers: &mut HashMap<ActorUri, PeerState>, chain_state: &mut BlockchainState, now: Instant, batch_size: usize, peer_level: L, mut on_request: F)
+    where L: Fn(&PeerState) -> Option<Level>,
+          F: FnMut(&MissingBlock) -> bool {
+    if !chain_state.has_missing_blocks() {
+        return;
+    }
+
+    peers.values_mut()
+        .filter(|peer| peer_level(peer).is_some())
+        .filter(|peer| peer.available_block_queue_capacity(now) > 0)
+        .sorted_by_key(|peer| peer.block_throughput.expected_response_time())
+        .for_each(|peer| {
+            let level_max = peer_level(peer).unwrap();
+            let mut missing_blocks = chain_state.drain_missing_blocks(peer.available_block_queue_capacity(now), level_max);
+            if !missing_blocks.is_empty() {
+                let queued_blocks = missing_blocks.drain(..)
+                    .filter(|missing_block| on_request(missing_block))
+                    .map(|missing_block| {
+                        let missing_block_hash = missing_block.block_hash.clone();
+                        if peer.queued_block_headers.insert(missing_block, now) {
+                            // block was not already present in queue
+                            Some(missing_block_hash)
+                        } else {
+                            // block was already in queue
+                            None
+                        }
+                    })
+                    .filter_map(|missing_block_hash| missing_block_hash)
+                    .collect::<Vec<_>>();
+
+                if !queued_blocks.is_empty() {
+                    peer.block_request_last = now;
+                    // pipeline requests to the peer in several messages
+                    queued_blocks.chunks(batch_size)
+                        .for_each(|queued_blocks| tell_peer(GetBlockHeadersMessage::new(queued_blocks.to_vec()).into(), peer));
+                }
+            }
+        });
+}
+
+/// Schedules download of missing block operations to the peers (peers with the highest measured throughput are served first).
+///
+/// Peers are asked for operations of blocks up to the level of their current head of the chain resolved by `peer_level`.
+/// Operations are requested only if `on_request` returns true.
+fn schedule_missing_operations<L, F>(peers: &mut HashMap<ActorUri, PeerState>, operations_state: &mut OperationsState, now: Instant, peer_level: L, mut on_request: F)
+    where L: Fn(&PeerState) -> Option<Level>,
+          F: FnMut(&MissingOperations) -> bool {
+    if !operations_state.has_missing_block_operations() {
+        return;
+    }
+
+    peers.values_mut()
+        .filter(|peer| peer_level(peer).is_some())
+        .filter(|peer| peer.available_block_operations_queue_capacity(now) > 0)
+        .sorted_by_key(|peer| peer.block_operations_throughput.expected_response_time())
+        .for_each(|peer| {
+            let level_max = peer_level(peer).unwrap();
+            let missing_operations = operations_state.drain_missing_block_operations(peer.available_block_operations_queue_capacity(now), level_max);
+            if !missing_operations.is_empty() {
+                let queued_operations = missing_operations.iter()
+                    .filter(|missing_operation| on_request(missing_operation))
+                    .map(|missing_operation| {
+                        if peer.queued_block_operations.insert(missing_operation.clone(), now) {
+                            // operations were not already present in queue
+                            Some(missing_operation)
+                        } else {
+                            // operations were already in queue
+                            None
+                        }
+                    })
+                    .filter_map(|missing_operation| missing_operation)
+                    .collect::<Vec<_>>();
+
+                if !queued_operations.is_empty() {
+                    peer.block_operations_request_last = now;
+                    queued_operations.iter()
+                        .for_each(|&missing_operation| tell_peer(GetOperationsForBlocksMessage::new(missing_operation.into()).into(), peer));
+                }
+            }
+        });
+}
+
+/// Returns not received block requests back to the state of the chain, which requested them
+fn reschedule_missing_blocks(missing_blocks: Vec<MissingBlock>, chain_state: &mut BlockchainState, chains: &mut HashMap<ChainId, SecondaryChain>) -> Result<(), StorageError> {
+    for missing_block in missing_blocks {
+        match chains.values_mut().find(|chain| chain.requested_blocks.contains(&missing_block.block_hash)) {
+            Some(chain) => chain.chain_state.push_missing_block(missing_block)?,
+            None => chain_state.push_missing_block(missing_block)?,
+        }
+    }
+    Ok(())
+}
+
+/// Returns not received block operations requests back to the state of the chain, which requested them
+fn reschedule_missing_operations(missing_operations: Vec<MissingOperations>, operations_state: &mut OperationsState, chains: &mut HashMap<ChainId, SecondaryChain>) -> Result<(), StorageError> {
+    let mut missing_operations = missing_operations;
+    for chain in chains.values_mut() {
+        let (chain_operations, other_operations): (Vec<_>, Vec<_>) = missing_operations.into_iter()
+            .partition(|missing_operation| chain.requested_operations.contains(&missing_operation.block_hash));
+        chain.operations_state.push_missing_block_operations(chain_operations.into_iter())?;
+        missing_operations = other_operations;
+    }
+    operations_state.push_missing_block_operations(missing_operations.into_iter())
+}
+
 #[cfg(test)]
 pub mod tests {
     use std::net::SocketAddr;
@@ -1520,7 +2325,7 @@ pub mod tests {
 
     use crypto::hash::CryptoboxPublicKeyHash;
     use networking::p2p::network_channel::NetworkChannel;
-    use networking::p2p::peer::Peer;
+    use networking::p2p::peer::{Peer, PeerTimeouts};
     use storage::tests_common::TmpStorage;
     use tezos_api::environment::{TEZOS_ENV, TezosEnvironment, TezosEnvironmentConfiguration};
     use tezos_api::ffi::TezosRuntimeConfiguration;
@@ -1552,6 +2357,8 @@ pub mod tests {
     fn peer(sys: &impl ActorRefFactory, network_channel: NetworkChannelRef, tokio_runtime: &tokio::runtime::Runtime) -> PeerState {
         let socket_address: SocketAddr = "127.0.0.1:3011".parse().expect("Expected valid ip:port address");
 
+        let network_version = NetworkVersion::new("testet".to_string(), 0, 0);
+
         let peer_ref = Peer::actor(
             sys,
             network_channel,
@@ -1559,9 +2366,11 @@ pub mod tests {
             "eaef40186db19fd6f56ed5b1af57f9d9c8a1eed85c29f8e4daaa7367869c0f0b",
             "eaef40186db19fd6f56ed5b1af57f9d9c8a1eed85c29f8e4daaa7367869c0f0b",
             "000000000000000000000000000000000000000000000000",
-            NetworkVersion::new("testet".to_string(), 0, 0),
+            network_version.clone().into(),
+            PeerTimeouts::default(),
             tokio_runtime.handle().clone(),
             &socket_address,
+            None,
         ).unwrap();
         let peer_public_key: CryptoboxPublicKeyHash = HashType::CryptoboxPublicKeyHash.string_to_bytes("idsg2wkkDDv2cbEMK4zH49fjgyn7XT").expect("Failed to create public key hash");
 
@@ -1574,6 +2383,8 @@ pub mod tests {
                 )
             ),
             MetadataMessage::new(false, false),
+            network_version,
+            Instant::now(),
         )
     }
 
@@ -1629,8 +2440,12 @@ pub mod tests {
             pool,
             chain_id,
             false,
+            false,
             1,
+            PeerRoles::default(),
             tezos_identity::Identity::generate(0f64).calculated_peer_id()?,
+            crate::clock::system_clock(),
+            crate::configuration::ShellConfiguration::default().into_ref(),
         ));
 
         // empty chain_manager
@@ -0,0 +1,133 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Source of time for the shell actors.
+//!
+//! Actors read time only through [Clock], so time can be simulated in tests:
+//! [SimulatedClock] moves forward only when it is explicitly advanced,
+//! which makes timeouts and periodic checks deterministic and fast to test.
+//! Simulated actors answer [Ping], so the driver of the simulation knows, when they are idle.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Source of monotonic and wall-clock time
+pub trait Clock: Send + Sync {
+    /// Current monotonic time
+    fn now(&self) -> Instant;
+
+    /// Current wall-clock time
+    fn system_now(&self) -> SystemTime;
+
+    /// If true, time does not run by itself, so actors should not schedule their timers,
+    /// instead timer messages are sent by the driver of the simulation
+    fn is_simulated(&self) -> bool;
+}
+
+/// Shared reference to the clock
+pub type ClockRef = Arc<dyn Clock>;
+
+/// Returns clock backed by the system time
+pub fn system_clock() -> ClockRef {
+    Arc::new(SystemClock)
+}
+
+/// Real time clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn is_simulated(&self) -> bool {
+        false
+    }
+}
+
+/// Clock, which moves forward only by [SimulatedClock::advance]
+#[derive(Clone)]
+pub struct SimulatedClock {
+    started_at: Instant,
+    started_at_system: SystemTime,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl SimulatedClock {
+    pub fn new() -> Self {
+        SimulatedClock {
+            started_at: Instant::now(),
+            started_at_system: SystemTime::now(),
+            elapsed: Arc::new(Mutex::new(Duration::from_secs(0))),
+        }
+    }
+
+    /// Moves simulated time forward
+    pub fn advance(&self, duration: Duration) {
+        let mut elapsed = self.elapsed.lock().unwrap();
+        *elapsed += duration;
+    }
+
+    /// Simulated time elapsed since the clock was created
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for SimulatedClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> Instant {
+        self.started_at + self.elapsed()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.started_at_system + self.elapsed()
+    }
+
+    fn is_simulated(&self) -> bool {
+        true
+    }
+}
+
+/// Asks simulated actor to answer with [Pong], after it processed all messages received before this one.
+///
+/// Driver of the simulation waits for the answers instead of sleeping, until actors have nothing more to process.
+#[derive(Clone, Debug)]
+pub struct Ping;
+
+/// Answer to the [Ping]
+#[derive(Clone, Debug)]
+pub struct Pong {
+    /// Count of all messages processed by the actor, including the ping
+    pub processed_messages: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulated_clock() {
+        let clock = SimulatedClock::new();
+        let shared: ClockRef = Arc::new(clock.clone());
+
+        let before = shared.now();
+        let before_system = shared.system_now();
+        assert_eq!(before, shared.now());
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(Duration::from_secs(30), shared.now() - before);
+        assert_eq!(Duration::from_secs(30), shared.system_now().duration_since(before_system).unwrap());
+        assert!(shared.is_simulated());
+        assert!(!system_clock().is_simulated());
+    }
+}
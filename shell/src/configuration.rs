@@ -0,0 +1,279 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Tuning of the chain synchronization policy.
+//!
+//! [ShellConfiguration] is loaded on startup from the config file/cli and validated.
+//! Intervals of the periodic checks are fixed for the lifetime of the node,
+//! other values can be changed at runtime by [ShellConfigurationUpdate] (see admin rpc),
+//! they are read by the chain manager on every use.
+//!
+//! [MempoolConfiguration] limits the size of the mempool and of the refused operations cache, it is fixed for the lifetime of the node.
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use failure::Fail;
+use serde::{Deserialize, Serialize, Serializer};
+
+/// Thread safe reference to the shared shell configuration
+pub type ShellConfigurationRef = Arc<RwLock<ShellConfiguration>>;
+
+/// Max count of block hashes in one GetBlockHeaders message accepted by the remote peers
+pub const MAX_BLOCK_HEADERS_BATCH_SIZE: usize = 10;
+/// Max count of operation hashes in one GetOperations message accepted by the remote peers
+pub const MAX_MEMPOOL_OPERATIONS_BATCH_SIZE: usize = 10;
+/// Max size of the operation accepted by the protocol (max_operation_data_length)
+pub const MAX_OPERATION_SIZE: usize = 32 * 1024;
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum ShellConfigurationError {
+    #[fail(display = "Invalid value of {}: {}", name, reason)]
+    InvalidValue {
+        name: &'static str,
+        reason: String,
+    },
+}
+
+impl ShellConfigurationError {
+    fn invalid(name: &'static str, reason: String) -> Self {
+        ShellConfigurationError::InvalidValue { name, reason }
+    }
+}
+
+/// Policy constants of the chain manager.
+#[derive(Serialize, Copy, Clone, Debug, PartialEq)]
+pub struct ShellConfiguration {
+    /// Limit to how many blocks to request in a single message, more messages can be in-flight (see `download_scheduler`)
+    pub block_headers_batch_size: usize,
+    /// Limit to how many mempool operations to request in a batch
+    pub mempool_operations_batch_size: usize,
+    /// How often to check chain completeness (startup only)
+    #[serde(rename = "check_chain_completeness_interval_in_secs", serialize_with = "serialize_secs")]
+    pub check_chain_completeness_interval: Duration,
+    /// How often to ask all connected peers for current branch (startup only)
+    #[serde(rename = "ask_current_branch_interval_in_secs", serialize_with = "serialize_secs")]
+    pub ask_current_branch_interval: Duration,
+    /// How often to print stats in logs (startup only)
+    #[serde(rename = "log_interval_in_secs", serialize_with = "serialize_secs")]
+    pub log_interval: Duration,
+    /// After this time we will disconnect peer if his current head level stays the same
+    #[serde(rename = "current_head_level_update_timeout_in_secs", serialize_with = "serialize_secs")]
+    pub current_head_level_update_timeout: Duration,
+    /// After this time peer will be disconnected if it fails to respond to our request,
+    /// stalled peers are checked in the half of this interval, which is fixed on startup
+    #[serde(rename = "silent_peer_timeout_in_secs", serialize_with = "serialize_secs")]
+    pub silent_peer_timeout: Duration,
+    /// After this interval we will rehydrate state if no new blocks are applied
+    #[serde(rename = "stalled_chain_completeness_timeout_in_secs", serialize_with = "serialize_secs")]
+    pub stalled_chain_completeness_timeout: Duration,
+    /// Mempool operation time to live
+    #[serde(rename = "mempool_operation_ttl_in_secs", serialize_with = "serialize_secs")]
+    pub mempool_operation_ttl: Duration,
+    /// Max count of peers, to which new mempool operations are announced in one batch, other peers are served in the next batches
+    pub mempool_announce_peers: usize,
+    /// How often to announce new mempool operations to the peers in a batch (startup only)
+    #[serde(rename = "mempool_announce_interval_in_millis", serialize_with = "serialize_millis")]
+    pub mempool_announce_interval: Duration,
+}
+
+impl Default for ShellConfiguration {
+    fn default() -> Self {
+        ShellConfiguration {
+            block_headers_batch_size: 10,
+            mempool_operations_batch_size: 10,
+            check_chain_completeness_interval: Duration::from_secs(30),
+            ask_current_branch_interval: Duration::from_secs(15),
+            log_interval: Duration::from_secs(60),
+            current_head_level_update_timeout: Duration::from_secs(120),
+            silent_peer_timeout: Duration::from_secs(30),
+            stalled_chain_completeness_timeout: Duration::from_secs(240),
+            mempool_operation_ttl: Duration::from_secs(60),
+            mempool_announce_peers: 10,
+            mempool_announce_interval: Duration::from_millis(500),
+        }
+    }
+}
+
+impl ShellConfiguration {
+    pub fn into_ref(self) -> ShellConfigurationRef {
+        Arc::new(RwLock::new(self))
+    }
+
+    /// Checks, that values are in the allowed ranges and consistent with each other
+    pub fn validate(&self) -> Result<(), ShellConfigurationError> {
+        if self.block_headers_batch_size == 0 || self.block_headers_batch_size > MAX_BLOCK_HEADERS_BATCH_SIZE {
+            return Err(ShellConfigurationError::invalid("block_headers_batch_size", format!("must be between 1 and {}", MAX_BLOCK_HEADERS_BATCH_SIZE)));
+        }
+        if self.mempool_operations_batch_size == 0 || self.mempool_operations_batch_size > MAX_MEMPOOL_OPERATIONS_BATCH_SIZE {
+            return Err(ShellConfigurationError::invalid("mempool_operations_batch_size", format!("must be between 1 and {}", MAX_MEMPOOL_OPERATIONS_BATCH_SIZE)));
+        }
+
+        let non_zero = [
+            ("check_chain_completeness_interval", self.check_chain_completeness_interval),
+            ("ask_current_branch_interval", self.ask_current_branch_interval),
+            ("log_interval", self.log_interval),
+            ("silent_peer_timeout", self.silent_peer_timeout),
+            ("mempool_operation_ttl", self.mempool_operation_ttl),
+        ];
+        if let Some((name, _)) = non_zero.iter().find(|(_, value)| value.as_secs() == 0) {
+            return Err(ShellConfigurationError::invalid(name, "must be at least 1 second".to_string()));
+        }
+        if self.mempool_announce_interval.as_millis() == 0 {
+            return Err(ShellConfigurationError::invalid("mempool_announce_interval", "must be at least 1 millisecond".to_string()));
+        }
+        if self.mempool_announce_peers == 0 {
+            return Err(ShellConfigurationError::invalid("mempool_announce_peers", "must be at least 1".to_string()));
+        }
+
+        // peers are asked for their current head periodically, so they have a chance to update it
+        if self.current_head_level_update_timeout <= self.ask_current_branch_interval {
+            return Err(ShellConfigurationError::invalid("current_head_level_update_timeout", format!("must be greater than ask_current_branch_interval ({}s)", self.ask_current_branch_interval.as_secs())));
+        }
+        // chain completeness is checked periodically, so stalled chain can be detected only by this check
+        if self.stalled_chain_completeness_timeout <= self.check_chain_completeness_interval {
+            return Err(ShellConfigurationError::invalid("stalled_chain_completeness_timeout", format!("must be greater than check_chain_completeness_interval ({}s)", self.check_chain_completeness_interval.as_secs())));
+        }
+
+        Ok(())
+    }
+
+    /// Returns new validated configuration with applied runtime changes
+    pub fn update(&self, update: &ShellConfigurationUpdate) -> Result<ShellConfiguration, ShellConfigurationError> {
+        let mut updated = *self;
+        if let Some(value) = update.block_headers_batch_size {
+            updated.block_headers_batch_size = value;
+        }
+        if let Some(value) = update.mempool_operations_batch_size {
+            updated.mempool_operations_batch_size = value;
+        }
+        if let Some(value) = update.current_head_level_update_timeout_in_secs {
+            updated.current_head_level_update_timeout = Duration::from_secs(value);
+        }
+        if let Some(value) = update.silent_peer_timeout_in_secs {
+            updated.silent_peer_timeout = Duration::from_secs(value);
+        }
+        if let Some(value) = update.stalled_chain_completeness_timeout_in_secs {
+            updated.stalled_chain_completeness_timeout = Duration::from_secs(value);
+        }
+        if let Some(value) = update.mempool_operation_ttl_in_secs {
+            updated.mempool_operation_ttl = Duration::from_secs(value);
+        }
+        if let Some(value) = update.mempool_announce_peers {
+            updated.mempool_announce_peers = value;
+        }
+        updated.validate()?;
+        Ok(updated)
+    }
+}
+
+/// Subset of [ShellConfiguration], which can be changed without restart, missing values are not changed.
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ShellConfigurationUpdate {
+    pub block_headers_batch_size: Option<usize>,
+    pub mempool_operations_batch_size: Option<usize>,
+    pub current_head_level_update_timeout_in_secs: Option<u64>,
+    pub silent_peer_timeout_in_secs: Option<u64>,
+    pub stalled_chain_completeness_timeout_in_secs: Option<u64>,
+    pub mempool_operation_ttl_in_secs: Option<u64>,
+    pub mempool_announce_peers: Option<usize>,
+}
+
+/// Limits of the mempool, when reached, operations with the lowest priority are dropped.
+#[derive(Serialize, Copy, Clone, Debug, PartialEq)]
+pub struct MempoolConfiguration {
+    /// Max count of operations in the mempool
+    pub max_operations: usize,
+    /// Max size of operations in the mempool
+    pub max_bytes: usize,
+    /// Max count of the recently refused operations, which are not downloaded and validated again
+    pub max_refused_operations: usize,
+}
+
+impl Default for MempoolConfiguration {
+    fn default() -> Self {
+        MempoolConfiguration {
+            max_operations: 10_000,
+            max_bytes: 16 * 1024 * 1024,
+            max_refused_operations: 1000,
+        }
+    }
+}
+
+impl MempoolConfiguration {
+    /// Checks, that every operation accepted by the protocol fits into the mempool
+    pub fn validate(&self) -> Result<(), ShellConfigurationError> {
+        if self.max_operations == 0 {
+            return Err(ShellConfigurationError::invalid("max_operations", "must be at least 1".to_string()));
+        }
+        if self.max_bytes < MAX_OPERATION_SIZE {
+            return Err(ShellConfigurationError::invalid("max_bytes", format!("must be at least max operation size ({} bytes)", MAX_OPERATION_SIZE)));
+        }
+        Ok(())
+    }
+}
+
+fn serialize_secs<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(value.as_secs())
+}
+
+fn serialize_millis<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(value.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_is_valid() {
+        assert_eq!(Ok(()), ShellConfiguration::default().validate());
+    }
+
+    #[test]
+    fn test_validate_mempool_configuration() {
+        assert_eq!(Ok(()), MempoolConfiguration::default().validate());
+        assert!(MempoolConfiguration { max_operations: 0, ..Default::default() }.validate().is_err());
+        assert!(MempoolConfiguration { max_bytes: MAX_OPERATION_SIZE - 1, ..Default::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut cfg = ShellConfiguration::default();
+        cfg.block_headers_batch_size = MAX_BLOCK_HEADERS_BATCH_SIZE + 1;
+        assert!(cfg.validate().is_err());
+
+        let mut cfg = ShellConfiguration::default();
+        cfg.silent_peer_timeout = Duration::from_secs(0);
+        assert!(cfg.validate().is_err());
+
+        let mut cfg = ShellConfiguration::default();
+        cfg.current_head_level_update_timeout = cfg.ask_current_branch_interval;
+        assert!(cfg.validate().is_err());
+
+        let mut cfg = ShellConfiguration::default();
+        cfg.mempool_announce_peers = 0;
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn test_update() -> Result<(), failure::Error> {
+        let cfg = ShellConfiguration::default();
+
+        let update: ShellConfigurationUpdate = serde_json::from_str(r#"{"silent_peer_timeout_in_secs": 45, "block_headers_batch_size": 5}"#)?;
+        let updated = cfg.update(&update)?;
+        assert_eq!(Duration::from_secs(45), updated.silent_peer_timeout);
+        assert_eq!(5, updated.block_headers_batch_size);
+        assert_eq!(cfg.mempool_operation_ttl, updated.mempool_operation_ttl);
+
+        // invalid update does not produce configuration
+        let update = ShellConfigurationUpdate { mempool_operations_batch_size: Some(0), ..Default::default() };
+        assert!(cfg.update(&update).is_err());
+
+        // intervals cannot be changed at runtime
+        assert!(serde_json::from_str::<ShellConfigurationUpdate>(r#"{"log_interval_in_secs": 10}"#).is_err());
+        assert!(serde_json::from_str::<ShellConfigurationUpdate>(r#"{"mempool_announce_interval_in_millis": 100}"#).is_err());
+        Ok(())
+    }
+}
@@ -7,6 +7,8 @@
 mod collections;
 mod state;
 
+pub mod clock;
+pub mod configuration;
 pub mod stats;
 pub mod shell_channel;
 pub mod chain_feeder;
@@ -14,8 +16,13 @@ pub mod context_listener;
 pub mod chain_manager;
 pub mod peer_manager;
 pub mod mempool_prevalidator;
+pub mod mempool_filter;
+pub mod mempool_result;
 pub mod validation;
 
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+
 /// Simple threshold, for representing integral ranges.
 #[derive(Copy, Clone, Debug)]
 pub struct PeerConnectionThreshold {
@@ -44,6 +51,72 @@ impl PeerConnectionThreshold {
     }
 }
 
+/// Roles of the remote peers in the private network topology.
+///
+/// Trusted peers are never disconnected nor blacklisted and we always try to reconnect them.
+/// Peer is trusted, if it is connected to the trusted address (exact ip and port, so just our outgoing connections),
+/// or if its peer id (known after the handshake) is trusted.
+///
+/// If the node is a sentry, addresses of the trusted peers (e.g. the baker hidden behind the sentry nodes)
+/// are never advertised to other peers. Addresses of the members of a peer group are advertised just to the other members of the group.
+#[derive(Clone, Debug, Default)]
+pub struct PeerRoles {
+    trusted_peers: HashSet<SocketAddr>,
+    trusted_peer_ids: HashSet<String>,
+    peer_groups: HashMap<String, HashSet<SocketAddr>>,
+    sentry: bool,
+}
+
+impl PeerRoles {
+    /// Create new peer roles.
+    ///
+    /// # Arguments
+    /// * `trusted_peers` - Addresses of the trusted peers
+    /// * `trusted_peer_ids` - Peer ids (public key hashes) of the trusted peers, which can connect from any address
+    /// * `peer_groups` - Named groups of the peer addresses
+    /// * `sentry` - If true, addresses of the trusted peers are never advertised
+    pub fn new(trusted_peers: Vec<SocketAddr>, trusted_peer_ids: Vec<String>, peer_groups: HashMap<String, Vec<SocketAddr>>, sentry: bool) -> Self {
+        PeerRoles {
+            trusted_peers: trusted_peers.into_iter().collect(),
+            trusted_peer_ids: trusted_peer_ids.into_iter().collect(),
+            peer_groups: peer_groups.into_iter()
+                .map(|(name, members)| (name, members.into_iter().collect()))
+                .collect(),
+            sentry,
+        }
+    }
+
+    /// Addresses of the trusted peers, which should be always connected
+    pub fn trusted_peers(&self) -> &HashSet<SocketAddr> {
+        &self.trusted_peers
+    }
+
+    /// Node acts as a sentry of the trusted peers
+    pub fn is_sentry(&self) -> bool {
+        self.sentry
+    }
+
+    /// Check if the peer is trusted.
+    ///
+    /// # Arguments
+    /// * `address` - Address of the connection, compared with the trusted addresses including port
+    /// * `peer_id` - Peer id of the remote peer, if the handshake is already done
+    pub fn is_trusted(&self, address: &SocketAddr, peer_id: Option<&str>) -> bool {
+        self.trusted_peers.contains(address) || peer_id.map_or(false, |peer_id| self.trusted_peer_ids.contains(peer_id))
+    }
+
+    /// Check if the address of the peer can be sent in the advertise message to the recipient
+    pub fn is_advertisable(&self, address: &SocketAddr, peer_id: Option<&str>, recipient: &SocketAddr) -> bool {
+        if self.sentry && self.is_trusted(address, peer_id) {
+            return false;
+        }
+        let groups = self.peer_groups.values()
+            .filter(|members| members.contains(address))
+            .collect::<Vec<_>>();
+        groups.is_empty() || groups.iter().any(|members| members.contains(recipient))
+    }
+}
+
 pub(crate) mod subscription {
     use riker::actors::*;
 
@@ -122,3 +195,36 @@ pub(crate) mod subscription {
             }, None);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peer_roles() {
+        let baker: SocketAddr = "10.0.0.1:9732".parse().unwrap();
+        let sentry: SocketAddr = "10.0.0.2:9732".parse().unwrap();
+        let public: SocketAddr = "1.2.3.4:9732".parse().unwrap();
+        let mut peer_groups = HashMap::new();
+        peer_groups.insert("sentries".to_string(), vec![sentry]);
+        let roles = PeerRoles::new(vec![baker], vec!["idtqxHUjbjbCfaDn4jczoPGsnhacKX".to_string()], peer_groups, true);
+
+        // the whole address is compared, other connections from the same ip are not trusted
+        assert!(roles.is_trusted(&baker, None));
+        assert!(!roles.is_trusted(&"10.0.0.1:51234".parse().unwrap(), None));
+        // trusted peer id can connect from any address
+        assert!(roles.is_trusted(&public, Some("idtqxHUjbjbCfaDn4jczoPGsnhacKX")));
+        assert!(!roles.is_trusted(&public, Some("idsg2wkkDDv2cbEMK4zH49fjgyn7XT")));
+
+        // sentry never advertises trusted peers
+        assert!(!roles.is_advertisable(&baker, None, &public));
+        assert!(!roles.is_advertisable(&public, Some("idtqxHUjbjbCfaDn4jczoPGsnhacKX"), &public));
+        assert!(roles.is_advertisable(&public, None, &sentry));
+        // members of the group are advertised just inside the group
+        assert!(!roles.is_advertisable(&sentry, None, &public));
+        assert!(roles.is_advertisable(&sentry, None, &sentry));
+
+        let roles = PeerRoles::new(vec![baker], vec![], HashMap::new(), false);
+        assert!(roles.is_advertisable(&baker, None, &public));
+    }
+}
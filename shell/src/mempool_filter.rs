@@ -0,0 +1,207 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Minimal fees required from the manager operations to be accepted to the mempool and propagated to the peers.
+//!
+//! Configuration is compatible with the OCaml node `/chains/:chain_id/mempool/filter` rpc, e.g.:
+//! `{"minimal_fees": "100", "minimal_nanotez_per_gas_unit": ["100", "1"], "minimal_nanotez_per_byte": ["1000", "1"]}`,
+//! rates are accepted also as a single number (older protocols), missing values are set to default.
+//! Filter is stored in the [SystemStorage], so it survives restart of the node.
+
+use std::sync::{Arc, RwLock};
+
+use failure::Fail;
+use serde_json::{json, Value};
+
+use storage::{StorageError, SystemStorage};
+
+/// Thread safe reference to the shared mempool filter
+pub type MempoolFilterRef = Arc<RwLock<MempoolFilter>>;
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum MempoolFilterError {
+    #[fail(display = "Invalid mempool filter json: {}", reason)]
+    InvalidJson {
+        reason: String,
+    },
+    #[fail(display = "Invalid value of {}: {}", name, reason)]
+    InvalidValue {
+        name: &'static str,
+        reason: String,
+    },
+}
+
+/// Rational amount of nanotez, e.g. per gas unit
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NanotezRate {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl NanotezRate {
+    pub fn new(numerator: u64, denominator: u64) -> Self {
+        NanotezRate { numerator, denominator }
+    }
+
+    fn to_json(&self) -> Value {
+        json!([self.numerator.to_string(), self.denominator.to_string()])
+    }
+
+    /// Parses `"100"`, `100` or `["100", "1"]`
+    fn from_json(name: &'static str, value: &Value) -> Result<Self, MempoolFilterError> {
+        let rate = match value {
+            Value::Array(values) if values.len() == 2 => NanotezRate::new(parse_number(name, &values[0])?, parse_number(name, &values[1])?),
+            Value::Array(_) => return Err(invalid(name, "expected [numerator, denominator]".to_string())),
+            value => NanotezRate::new(parse_number(name, value)?, 1),
+        };
+        if rate.denominator == 0 {
+            return Err(invalid(name, "denominator must not be zero".to_string()));
+        }
+        Ok(rate)
+    }
+}
+
+/// Fee policy of the mempool, the same as the default policy of the OCaml baker.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MempoolFilter {
+    /// Minimal fee of the operation in mutez
+    pub minimal_fees: u64,
+    pub minimal_nanotez_per_gas_unit: NanotezRate,
+    pub minimal_nanotez_per_byte: NanotezRate,
+}
+
+impl Default for MempoolFilter {
+    fn default() -> Self {
+        MempoolFilter {
+            minimal_fees: 100,
+            minimal_nanotez_per_gas_unit: NanotezRate::new(100, 1),
+            minimal_nanotez_per_byte: NanotezRate::new(1000, 1),
+        }
+    }
+}
+
+impl MempoolFilter {
+    pub fn into_ref(self) -> MempoolFilterRef {
+        Arc::new(RwLock::new(self))
+    }
+
+    /// Checks, that fee of the manager operation (whole batch) covers minimal fees for its gas and size:
+    /// `fee >= minimal_fees + minimal_nanotez_per_gas_unit * gas_limit + minimal_nanotez_per_byte * size`
+    pub fn accepts(&self, fee_mutez: u64, gas_limit: u64, size: usize) -> bool {
+        let per_gas = &self.minimal_nanotez_per_gas_unit;
+        let per_byte = &self.minimal_nanotez_per_byte;
+
+        // compare in nanotez multiplied by both denominators, so no rounding is needed
+        let denominators = (per_gas.denominator as u128).saturating_mul(per_byte.denominator as u128);
+        let to_nanotez = |mutez: u64| (mutez as u128).saturating_mul(1000).saturating_mul(denominators);
+        let required = to_nanotez(self.minimal_fees)
+            .saturating_add((per_gas.numerator as u128).saturating_mul(gas_limit as u128).saturating_mul(per_byte.denominator as u128))
+            .saturating_add((per_byte.numerator as u128).saturating_mul(size as u128).saturating_mul(per_gas.denominator as u128));
+
+        to_nanotez(fee_mutez) >= required
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "minimal_fees": self.minimal_fees.to_string(),
+            "minimal_nanotez_per_gas_unit": self.minimal_nanotez_per_gas_unit.to_json(),
+            "minimal_nanotez_per_byte": self.minimal_nanotez_per_byte.to_json(),
+        })
+    }
+
+    /// Parses filter like the OCaml node, missing values are set to default, unknown fields are ignored
+    pub fn from_json(json: &[u8]) -> Result<Self, MempoolFilterError> {
+        let json: Value = serde_json::from_slice(json)
+            .map_err(|e| MempoolFilterError::InvalidJson { reason: e.to_string() })?;
+        if !json.is_object() {
+            return Err(MempoolFilterError::InvalidJson { reason: "expected object".to_string() });
+        }
+
+        let mut filter = MempoolFilter::default();
+        if let Some(value) = json.get("minimal_fees") {
+            filter.minimal_fees = parse_number("minimal_fees", value)?;
+        }
+        if let Some(value) = json.get("minimal_nanotez_per_gas_unit") {
+            filter.minimal_nanotez_per_gas_unit = NanotezRate::from_json("minimal_nanotez_per_gas_unit", value)?;
+        }
+        if let Some(value) = json.get("minimal_nanotez_per_byte") {
+            filter.minimal_nanotez_per_byte = NanotezRate::from_json("minimal_nanotez_per_byte", value)?;
+        }
+        Ok(filter)
+    }
+
+    /// Loads filter stored by [MempoolFilter::store], or default filter, if none was stored yet
+    pub fn load(system_storage: &SystemStorage) -> Result<Self, failure::Error> {
+        match system_storage.get_mempool_filter()? {
+            Some(json) => Ok(MempoolFilter::from_json(json.as_bytes())?),
+            None => Ok(MempoolFilter::default()),
+        }
+    }
+
+    pub fn store(&self, system_storage: &mut SystemStorage) -> Result<(), StorageError> {
+        system_storage.set_mempool_filter(&self.to_json().to_string())
+    }
+}
+
+fn parse_number(name: &'static str, value: &Value) -> Result<u64, MempoolFilterError> {
+    let number = match value {
+        Value::String(value) => value.parse::<u64>().ok(),
+        Value::Number(value) => value.as_u64(),
+        _ => None,
+    };
+    number.ok_or_else(|| invalid(name, format!("expected non-negative integer, but found: {}", value)))
+}
+
+fn invalid(name: &'static str, reason: String) -> MempoolFilterError {
+    MempoolFilterError::InvalidValue { name, reason }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts() {
+        let filter = MempoolFilter::default();
+        // 100 mutez + 10000 gas * 100 nanotez + 200 bytes * 1000 nanotez = 1300 mutez
+        assert!(filter.accepts(1300, 10000, 200));
+        assert!(!filter.accepts(1299, 10000, 200));
+
+        let zero = MempoolFilter {
+            minimal_fees: 0,
+            minimal_nanotez_per_gas_unit: NanotezRate::new(0, 1),
+            minimal_nanotez_per_byte: NanotezRate::new(0, 1),
+        };
+        assert!(zero.accepts(0, 10000, 200));
+
+        // 1/3 nanotez per gas unit: 3000 gas = 1 mutez
+        let rational = MempoolFilter {
+            minimal_fees: 0,
+            minimal_nanotez_per_gas_unit: NanotezRate::new(1, 3),
+            minimal_nanotez_per_byte: NanotezRate::new(0, 1),
+        };
+        assert!(rational.accepts(1, 3000, 100));
+        assert!(!rational.accepts(1, 3001, 100));
+    }
+
+    #[test]
+    fn test_json() -> Result<(), failure::Error> {
+        let filter = MempoolFilter::default();
+        assert_eq!(filter, MempoolFilter::from_json(filter.to_json().to_string().as_bytes())?);
+        assert_eq!(
+            r#"{"minimal_fees":"100","minimal_nanotez_per_byte":["1000","1"],"minimal_nanotez_per_gas_unit":["100","1"]}"#,
+            filter.to_json().to_string()
+        );
+
+        // single numbers and missing values
+        let parsed = MempoolFilter::from_json(br#"{"minimal_fees": "0", "minimal_nanotez_per_gas_unit": "50", "allow_script_failure": true}"#)?;
+        assert_eq!(0, parsed.minimal_fees);
+        assert_eq!(NanotezRate::new(50, 1), parsed.minimal_nanotez_per_gas_unit);
+        assert_eq!(filter.minimal_nanotez_per_byte, parsed.minimal_nanotez_per_byte);
+
+        assert!(MempoolFilter::from_json(br#"{"minimal_fees": "-1"}"#).is_err());
+        assert!(MempoolFilter::from_json(br#"{"minimal_nanotez_per_byte": ["1", "0"]}"#).is_err());
+        assert!(MempoolFilter::from_json(b"[]").is_err());
+        Ok(())
+    }
+}
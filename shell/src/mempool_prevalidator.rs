@@ -6,6 +6,18 @@
 //!
 //! This actor listens on shell events (see [process_shell_channel_message]) and schedules it to internal queue/channel for validation processing.
 //!
+//! Mempool is limited by count and size of operations (see [MempoolConfiguration]), operations with the lowest priority are dropped,
+//! pending operations are validated from the highest priority (see [PrioritizedOperations]).
+//!
+//! Applied manager operations, which do not pay minimal fees (see [MempoolFilter]), are removed from the mempool,
+//! before the new state is published, so they are never propagated to the peers.
+//!
+//! Banned (by rpc) and recently refused operations are blacklisted in the [MempoolStorage], so they are not validated again,
+//! count of the refused operations is limited (see [RefusedOperations]).
+//!
+//! On shutdown, whole mempool is saved as [MempoolSnapshot], after restart its operations are validated again against the current head,
+//! operations with expired branch (older than `max_operations_ttl` of the current head) are dropped.
+//!
 //! Actor validates received operations and result of validate as a new MempoolState is send back to shell channel, where:
 //!     - is used by rpc_actor to show current mempool state - pending_operations
 //!     - is used by chain_manager to send new current head with current mempool to inform other peers throught P2P
@@ -16,6 +28,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver as QueueReceiver, Sender as QueueSender};
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
 
 use failure::{Error, Fail};
 use riker::actors::*;
@@ -24,19 +37,31 @@ use slog::{debug, info, Logger, trace, warn};
 use crypto::hash::{BlockHash, ChainId, HashType, OperationHash};
 use storage::{BlockStorage, BlockStorageReader, MempoolStorage, StorageError, StorageInitInfo};
 use storage::chain_meta_storage::{ChainMetaStorage, ChainMetaStorageReader};
-use storage::mempool_storage::MempoolOperationType;
+use storage::mempool_storage::{MempoolBlacklistReason, MempoolOperationType, MempoolSnapshot, MempoolSnapshotOperation, MempoolSnapshotStatus};
 use storage::persistent::PersistentStorage;
-use tezos_api::ffi::{BeginConstructionRequest, PrevalidatorWrapper, ValidateOperationRequest, ValidateOperationResult};
-use tezos_messages::p2p::encoding::block_header::BlockHeader;
+use tezos_api::ffi::{BeginConstructionRequest, Errored, OperationProtocolDataJsonWithErrorListJson, PrevalidatorWrapper, ValidateOperationRequest, ValidateOperationResult};
+use tezos_messages::p2p::encoding::block_header::{BlockHeader, Level};
 use tezos_messages::p2p::encoding::prelude::Operation;
 use tezos_wrapper::service::{ProtocolController, ProtocolServiceError};
 use tezos_wrapper::TezosApiConnectionPool;
 
+use crate::configuration::MempoolConfiguration;
+use crate::mempool_filter::{MempoolFilter, MempoolFilterRef};
+use crate::mempool_result::{MempoolValidationResult, OperationClassification};
 use crate::shell_channel::{CurrentMempoolState, ShellChannelMsg, ShellChannelRef, ShellChannelTopic};
+use crate::state::prioritized_operations::{OperationFees, OperationPriority, PrioritizedOperations};
+use crate::state::refused_operations::RefusedOperations;
+use crate::stats::mempool::DropReason;
 use crate::subscription::subscribe_to_shell_events;
 
 type SharedJoinHandle = Arc<Mutex<Option<JoinHandle<Result<(), Error>>>>>;
 
+/// Time to live of the reloaded operations in the mempool storage
+const RELOADED_OPERATION_TTL: Duration = Duration::from_secs(60);
+
+/// Error of the applied operations, which are refused, because they do not pay minimal fees of the mempool filter
+const MINIMAL_FEES_ERROR_JSON: &str = r#"[{"kind":"temporary","id":"node.mempool.minimal_fees"}]"#;
+
 /// Feeds blocks and operations to the tezos protocol (ocaml code).
 #[actor(ShellChannelMsg)]
 pub struct MempoolPrevalidator {
@@ -51,6 +76,7 @@ pub struct MempoolPrevalidator {
 enum Event {
     NewHead(BlockHash, Arc<BlockHeader>),
     ValidateOperation(OperationHash, MempoolOperationType),
+    BanOperation(OperationHash),
     ShuttingDown,
 }
 
@@ -64,6 +90,8 @@ impl MempoolPrevalidator {
         persistent_storage: &PersistentStorage,
         init_storage_data: &StorageInitInfo,
         tezos_readonly_api: Arc<TezosApiConnectionPool>,
+        mempool_config: MempoolConfiguration,
+        mempool_filter: MempoolFilterRef,
         log: Logger) -> Result<MempoolPrevalidatorRef, CreateError> {
 
         // spawn thread which processes event
@@ -88,6 +116,8 @@ impl MempoolPrevalidator {
                                 &mut chain_meta_storage,
                                 &mut mempool_storage,
                                 &chain_id,
+                                &mempool_config,
+                                &mempool_filter,
                                 &validator_run,
                                 &shell_channel,
                                 &protocol_controller.api,
@@ -139,6 +169,12 @@ impl MempoolPrevalidator {
                     Event::ValidateOperation(operation.operation_hash.clone(), operation.operation_type)
                 )?;
             }
+            ShellChannelMsg::MempoolOperationBanned(operation) => {
+                // remove banned operation from mempool
+                self.validator_event_sender.lock().unwrap().send(
+                    Event::BanOperation(operation.operation_hash.clone())
+                )?;
+            }
             ShellChannelMsg::ShuttingDown(_) => {
                 self.validator_event_sender.lock().unwrap().send(
                     Event::ShuttingDown
@@ -203,6 +239,9 @@ impl Receive<ShellChannelMsg> for MempoolPrevalidator {
 ///     - are being processed sequentially, after validation, they are moved to `validation_result`
 /// - `operations`
 ///     - kind of cache, contains operation data
+///     - limited by count and size, operations with the lowest priority are evicted (also from `pending` and `validation_result`)
+/// - `refused`
+///     - hashes of the recently refused operations, which are blacklisted in the mempool storage
 #[derive(Clone, Debug)]
 pub struct MempoolState {
     /// Original tezos prevalidator has prevalidator.fitness which is used for set_head comparision
@@ -211,24 +250,31 @@ pub struct MempoolState {
     predecessor: Option<BlockHash>,
 
     /// Actual cumulated operation results
-    validation_result: ValidateOperationResult,
+    validation_result: MempoolValidationResult,
 
-    /// In-memory store of actual operations
-    operations: HashMap<OperationHash, Operation>,
-    // TODO: pendings limit
-    // TODO: pendings as vec and order
+    /// In-memory store of actual operations ordered by priority
+    operations: PrioritizedOperations,
     pending: HashSet<OperationHash>,
+
+    /// Recently refused operations, the oldest are removed from the blacklist
+    refused: RefusedOperations,
 }
 
 impl MempoolState {
-    fn new(prevalidator: Option<PrevalidatorWrapper>, predecessor: Option<BlockHash>, pending_operations: HashMap<OperationHash, Operation>) -> MempoolState {
-        MempoolState {
+    /// Creates state with pending operations, which fit into the limits
+    fn new(prevalidator: Option<PrevalidatorWrapper>, predecessor: Option<BlockHash>, pending_operations: HashMap<OperationHash, Operation>, limits: MempoolConfiguration) -> MempoolState {
+        let mut state = MempoolState {
             prevalidator,
             predecessor,
-            pending: pending_operations.keys().cloned().collect(),
-            validation_result: ValidateOperationResult::default(),
-            operations: pending_operations,
+            pending: HashSet::new(),
+            validation_result: MempoolValidationResult::default(),
+            operations: PrioritizedOperations::new(limits),
+            refused: RefusedOperations::new(limits.max_refused_operations),
+        };
+        for (operation_hash, operation) in pending_operations {
+            let _ = state.add_to_pending(operation_hash, operation);
         }
+        state
     }
 
     /// Reinitialize state for new prevalidator and head, returns unneeded operation hashes
@@ -236,7 +282,7 @@ impl MempoolState {
 
         // we want to validate pending operations with new prevalidator, so other "already_validated" can be removed
         let unneeded_operations: Vec<OperationHash> = self.operations
-            .keys()
+            .hashes()
             .filter(|&key| !self.pending.contains(key))
             .map(|k| k.clone())
             .collect();
@@ -247,24 +293,97 @@ impl MempoolState {
         }
         self.predecessor = predecessor;
         self.prevalidator = prevalidator;
-        self.validation_result = ValidateOperationResult::default();
+        self.validation_result = MempoolValidationResult::default();
 
         unneeded_operations
     }
 
-    fn add_result(&mut self, new_result: ValidateOperationResult) -> bool {
-        self.validation_result.merge(new_result)
+    /// Merges new validation result, applied operations, which do not pass the filter, are removed from the mempool
+    /// and classified as refused (they are not blacklisted, because filter can be changed).
+    ///
+    /// Returns true, if state was changed, and hashes of the removed operations.
+    fn add_result(&mut self, mut new_result: ValidateOperationResult, filter: &MempoolFilter) -> (bool, Vec<OperationHash>) {
+        let operations = &mut self.operations;
+        let mut filtered = Vec::new();
+        let mut filtered_refused = Vec::new();
+        new_result.applied.retain(|applied| {
+            let size = operations.get_size(&applied.hash);
+            let fees = OperationFees::from_protocol_data_json(&applied.protocol_data_json);
+            match (size, fees) {
+                (Some(size), Some(fees)) => {
+                    if fees.is_manager_operation() && !filter.accepts(fees.fee, fees.gas_limit, size) {
+                        let _ = operations.reject(&applied.hash, DropReason::MinimalFees);
+                        filtered.push(applied.hash.clone());
+                        filtered_refused.push(Errored {
+                            hash: applied.hash.clone(),
+                            is_endorsement: None,
+                            protocol_data_json_with_error_json: OperationProtocolDataJsonWithErrorListJson {
+                                protocol_data_json: applied.protocol_data_json.clone(),
+                                error_json: MINIMAL_FEES_ERROR_JSON.to_string(),
+                            },
+                        });
+                        return false;
+                    }
+                    // priority of the applied operations is resolved by protocol
+                    operations.update_priority(&applied.hash, OperationPriority::from_fees(&fees, size));
+                    true
+                }
+                _ => true,
+            }
+        });
+        new_result.refused.extend(filtered_refused);
+        (self.validation_result.merge(new_result), filtered)
     }
 
-    fn add_to_pending(&mut self, operation_hash: OperationHash, operation: Operation) {
-        self.operations.insert(operation_hash.clone(), operation);
+    /// Adds operation to pending, returns evicted operations or reason, why the operation was rejected
+    fn add_to_pending(&mut self, operation_hash: OperationHash, operation: Operation) -> Result<Vec<OperationHash>, DropReason> {
+        let evicted = self.operations.insert(operation_hash.clone(), operation)?;
+        for evicted_hash in &evicted {
+            self.pending.remove(evicted_hash);
+            self.remove_result(evicted_hash);
+        }
         let _ = self.pending.insert(operation_hash);
+        Ok(evicted)
+    }
+
+    fn remove_result(&mut self, operation_hash: &OperationHash) {
+        let _ = self.validation_result.remove(operation_hash);
     }
 
     fn remove_from_pending(&mut self, operation_hash: &OperationHash) -> bool {
         self.pending.remove(operation_hash)
     }
 
+    /// Removes operation from the mempool, returns true, if the operation was in the mempool
+    fn remove_operation(&mut self, operation_hash: &OperationHash) -> bool {
+        let was_validated = self.validation_result.remove(operation_hash).is_some();
+        let was_pending = self.pending.remove(operation_hash);
+        self.operations.remove(operation_hash).is_some() || was_pending || was_validated
+    }
+
+    /// Creates snapshot of all operations with their validation status, if there is any predecessor
+    fn snapshot(&self, created_at: SystemTime) -> Option<MempoolSnapshot> {
+        let predecessor = self.predecessor.as_ref()?;
+        let operations = self.operations.to_map()
+            .into_iter()
+            .map(|(operation_hash, operation)| {
+                let status = match self.validation_result.classification(&operation_hash) {
+                    Some(OperationClassification::Applied) => MempoolSnapshotStatus::Applied,
+                    Some(OperationClassification::Refused) => MempoolSnapshotStatus::Refused,
+                    Some(OperationClassification::BranchRefused) => MempoolSnapshotStatus::BranchRefused,
+                    Some(OperationClassification::BranchDelayed) => MempoolSnapshotStatus::BranchDelayed,
+                    None => MempoolSnapshotStatus::Pending,
+                };
+                MempoolSnapshotOperation { operation_hash, operation, status }
+            })
+            .collect();
+        Some(MempoolSnapshot {
+            predecessor: predecessor.clone(),
+            created_at,
+            operations,
+        })
+    }
+
     /// Indicates, that pending operations can be handled
     fn can_handle_pending(&self) -> bool {
         !self.pending.is_empty() && self.prevalidator.is_some()
@@ -272,19 +391,7 @@ impl MempoolState {
 
     /// Indicates, that the operation was already validated and is in the mempool
     fn is_already_validated(&self, operation_hash: &OperationHash) -> bool {
-        if self.validation_result.applied.iter().any(|op| op.hash.eq(operation_hash)) {
-            return true;
-        }
-        if self.validation_result.branch_delayed.iter().any(|op| op.hash.eq(operation_hash)) {
-            return true;
-        }
-        if self.validation_result.branch_refused.iter().any(|op| op.hash.eq(operation_hash)) {
-            return true;
-        }
-        if self.validation_result.refused.iter().any(|op| op.hash.eq(operation_hash)) {
-            return true;
-        }
-        false
+        self.validation_result.contains(operation_hash)
     }
 }
 
@@ -316,8 +423,10 @@ impl From<StorageError> for PrevalidationError {
 fn process_prevalidation(
     block_storage: &BlockStorage,
     chain_meta_storage: &ChainMetaStorage,
-    mempool_storage: &MempoolStorage,
+    mempool_storage: &mut MempoolStorage,
     chain_id: &ChainId,
+    mempool_config: &MempoolConfiguration,
+    mempool_filter: &MempoolFilterRef,
     validator_run: &AtomicBool,
     shell_channel: &ShellChannelRef,
     protocol_controller: &ProtocolController,
@@ -334,6 +443,8 @@ fn process_prevalidation(
         mempool_storage,
         &protocol_controller,
         &chain_id,
+        mempool_config,
+        mempool_filter,
         &log,
     )?;
 
@@ -358,13 +469,15 @@ fn process_prevalidation(
                     // clear unneeded operations from mempool storage
                     operations_to_delete
                         .iter()
-                        .for_each(|oph| {
-                            if let Err(err) = mempool_storage.delete(&oph) {
-                                warn!(log, "Mempool - delete operation failed"; "hash" => HashType::OperationHash.bytes_to_string(&oph), "error" => format!("{:?}", err))
-                            }
-                        });
+                        .for_each(|oph| delete_from_mempool_storage(mempool_storage, oph, log));
                 }
                 Event::ValidateOperation(oph, mempool_operation_type) => {
+                    if mempool_storage.is_blacklisted(&oph)? {
+                        debug!(log, "Mempool - received validate operation event - operation is banned or was recently refused"; "hash" => HashType::OperationHash.bytes_to_string(&oph));
+                        delete_from_mempool_storage(mempool_storage, &oph, log);
+                        continue;
+                    }
+
                     // TODO: handling when operation not exists - can happen?
                     if let Some(operation) = mempool_storage.get(mempool_operation_type, oph.clone())? {
 
@@ -374,20 +487,45 @@ fn process_prevalidation(
                             debug!(log, "Mempool - received validate operation event - operation already validated"; "hash" => HashType::OperationHash.bytes_to_string(&oph));
                         } else {
                             // just add operations to pendings
-                            state.add_to_pending(oph, operation.into());
+                            match state.add_to_pending(oph.clone(), operation.into()) {
+                                Ok(evicted) => evicted.iter().for_each(|evicted_oph| {
+                                    debug!(log, "Mempool - operation evicted by operation with higher priority"; "hash" => HashType::OperationHash.bytes_to_string(&evicted_oph));
+                                    delete_from_mempool_storage(mempool_storage, evicted_oph, log);
+                                }),
+                                Err(reason) => {
+                                    debug!(log, "Mempool - operation rejected, mempool is full"; "hash" => HashType::OperationHash.bytes_to_string(&oph), "reason" => reason.to_string());
+                                    delete_from_mempool_storage(mempool_storage, &oph, log);
+                                }
+                            }
                         }
                     } else {
                         debug!(log, "Mempool - received validate operation event - operations was previously validated and removed from mempool storage"; "hash" => HashType::OperationHash.bytes_to_string(&oph));
                     }
                 }
+                Event::BanOperation(oph) => {
+                    info!(log, "Mempool - operation banned"; "hash" => HashType::OperationHash.bytes_to_string(&oph));
+                    if state.remove_operation(&oph) {
+                        notify_mempool_changed(&shell_channel, &state);
+                    }
+                    delete_from_mempool_storage(mempool_storage, &oph, log);
+                }
                 Event::ShuttingDown => {
+                    // save mempool, so it is not lost after restart
+                    if let Some(snapshot) = state.snapshot(SystemTime::now()) {
+                        match mempool_storage.put_snapshot(&snapshot) {
+                            Ok(()) => info!(log, "Mempool - snapshot saved"; "predecessor" => HashType::BlockHash.bytes_to_string(&snapshot.predecessor), "operations" => snapshot.operations.len()),
+                            Err(err) => warn!(log, "Mempool - failed to save snapshot"; "error" => format!("{:?}", err)),
+                        }
+                    }
                     validator_run.store(false, Ordering::Release);
+                    // pending operations are validated after restart
+                    break;
                 }
             }
         }
 
         // 2. lets handle pending operations (if any)
-        handle_pending_operations(&shell_channel, &protocol_controller, &mut state, &log);
+        handle_pending_operations(&shell_channel, &protocol_controller, mempool_storage, mempool_filter, &mut state, &log);
     }
 
     Ok(())
@@ -397,9 +535,11 @@ fn hydrate_state(
     shell_channel: &ShellChannelRef,
     block_storage: &BlockStorage,
     chain_meta_storage: &ChainMetaStorage,
-    mempool_storage: &MempoolStorage,
+    mempool_storage: &mut MempoolStorage,
     protocol_controller: &ProtocolController,
     chain_id: &ChainId,
+    mempool_config: &MempoolConfiguration,
+    mempool_filter: &MempoolFilterRef,
     log: &Logger) -> Result<MempoolState, PrevalidationError> {
 
     // load current head
@@ -415,23 +555,94 @@ fn hydrate_state(
     };
 
     // read from Mempool_storage (just pending) -> add to queue for validation -> pending
-    let pending = mempool_storage.iter()?
+    let mut pending: HashMap<OperationHash, Operation> = mempool_storage.iter()?
         .into_iter()
         .map(|(key, value)| (key, value.operation().clone()))
         .collect();
+    let stored_operations = pending.keys().cloned().collect::<HashSet<_>>();
+
+    // operations of the mempool saved on shutdown are validated again against the current head (refused are not needed)
+    if let Some(snapshot) = mempool_storage.take_snapshot()? {
+        info!(log, "Mempool - reloading snapshot"; "predecessor" => HashType::BlockHash.bytes_to_string(&snapshot.predecessor), "operations" => snapshot.operations.len());
+        snapshot.operations
+            .into_iter()
+            .filter(|snapshot_operation| snapshot_operation.status != MempoolSnapshotStatus::Refused)
+            .for_each(|MempoolSnapshotOperation { operation_hash, operation, .. }| {
+                pending.entry(operation_hash).or_insert(operation);
+            });
+    }
+
+    // operations with expired branch cannot be included anymore
+    if let Some(head) = &head {
+        if let Some(live_level) = resolve_min_live_level(block_storage, head)? {
+            let mut expired = Vec::new();
+            for (oph, operation) in &pending {
+                let branch_level = block_storage.get(operation.branch())?.map(|branch| branch.header.level());
+                if branch_level.map_or(true, |branch_level| branch_level < live_level) {
+                    expired.push(oph.clone());
+                }
+            }
+            expired.iter().for_each(|oph| {
+                debug!(log, "Mempool - operation with expired branch dropped"; "hash" => HashType::OperationHash.bytes_to_string(oph));
+                let _ = pending.remove(oph);
+            });
+        }
+    }
+
+    // banned or refused operations are not validated again
+    let mut blacklisted = Vec::new();
+    for oph in pending.keys() {
+        if mempool_storage.is_blacklisted(oph)? {
+            blacklisted.push(oph.clone());
+        }
+    }
+    blacklisted.iter().for_each(|oph| {
+        let _ = pending.remove(oph);
+    });
 
     // internal mempool state
-    let mut state = MempoolState::new(prevalidator, head, pending);
+    let mut state = MempoolState::new(prevalidator, head, pending, *mempool_config);
+
+    // remember refused operations from the oldest, so the oldest are forgotten first, if the limit was lowered
+    let mut refused = mempool_storage.iter_blacklisted()?
+        .into_iter()
+        .filter(|(_, reason, _)| *reason == MempoolBlacklistReason::Refused)
+        .map(|(oph, _, refused_at)| (oph, refused_at))
+        .collect::<Vec<_>>();
+    refused.sort_by_key(|(_, refused_at)| *refused_at);
+    for (oph, _) in refused {
+        for forgotten in state.refused.insert(oph) {
+            mempool_storage.delete_blacklisted(&forgotten)?;
+        }
+    }
+
+    // operations over the limits, expired or blacklisted are not needed anymore
+    stored_operations.iter()
+        .filter(|oph| !state.operations.contains(oph))
+        .for_each(|oph| delete_from_mempool_storage(mempool_storage, oph, log));
+
+    // reloaded operations are stored again, so they can be provided to the peers
+    for (oph, operation) in state.operations.to_map() {
+        if !stored_operations.contains(&oph) {
+            mempool_storage.put(MempoolOperationType::Pending, operation.into(), SystemTime::now() + RELOADED_OPERATION_TTL)?;
+        }
+    }
 
     // TODO: do we need this?
     // and process it immediatly on startup, before any event received to clean old stored unprocessed operations
     if state.can_handle_pending() {
-        handle_pending_operations(&shell_channel, &protocol_controller, &mut state, &log);
+        handle_pending_operations(&shell_channel, &protocol_controller, mempool_storage, mempool_filter, &mut state, &log);
     }
 
     Ok(state)
 }
 
+/// Returns the lowest level of the operation branch, which can be still included after the head (see `max_operations_ttl`)
+fn resolve_min_live_level(block_storage: &BlockStorage, head: &BlockHash) -> Result<Option<Level>, StorageError> {
+    Ok(block_storage.get_with_additional_data(head)?
+        .map(|(head, additional_data)| head.header.level() - i32::from(additional_data.max_operations_ttl())))
+}
+
 fn begin_construction(protocol_controller: &ProtocolController,
                       chain_id: &ChainId,
                       block_hash: BlockHash,
@@ -458,7 +669,13 @@ fn begin_construction(protocol_controller: &ProtocolController,
     Ok(result)
 }
 
-fn handle_pending_operations(shell_channel: &ShellChannelRef, protocol_controller: &ProtocolController, state: &mut MempoolState, log: &Logger) {
+fn handle_pending_operations(
+    shell_channel: &ShellChannelRef,
+    protocol_controller: &ProtocolController,
+    mempool_storage: &MempoolStorage,
+    mempool_filter: &MempoolFilterRef,
+    state: &mut MempoolState,
+    log: &Logger) {
     debug!(log, "Mempool - handle_pending_operations"; "pendings" => state.pending.len(), "can_handle" => state.can_handle_pending());
 
     if !state.can_handle_pending() {
@@ -473,12 +690,15 @@ fn handle_pending_operations(shell_channel: &ShellChannelRef, protocol_controlle
         return;
     };
 
+    // filter can be changed by rpc, so it is resolved for every batch
+    let filter = *mempool_filter.read().unwrap();
+
     // TODO: verify - probably does not needed 'state_changed'
     let mut state_changed = false;
-    // lets iterate pendings and validate them
-    let mut pending_ops = state.pending.clone();
+    // lets iterate pendings and validate them, the highest priority first
+    let pending_ops = state.operations.sorted_by_priority(state.pending.iter());
     pending_ops
-        .drain()
+        .into_iter()
         .for_each(|pending_op| {
             // handle validation
             match state.operations.get(&pending_op) {
@@ -496,8 +716,18 @@ fn handle_pending_operations(shell_channel: &ShellChannelRef, protocol_controlle
                             let result = response.result;
                             debug!(log, "Mempool - validate operation response finished with success"; "hash" => HashType::OperationHash.bytes_to_string(&pending_op), "result" => format!("{:?}", result));
 
+                            // refused operations are not validated again, when advertised by peers
+                            result.refused.iter().for_each(|refused| {
+                                blacklist_refused(mempool_storage, &mut state.refused, &refused.hash, log);
+                            });
+
                             // merge new result with existing one
-                            state_changed |= state.add_result(result);
+                            let (changed, filtered) = state.add_result(result, &filter);
+                            state_changed |= changed;
+                            filtered.iter().for_each(|filtered_oph| {
+                                debug!(log, "Mempool - operation does not pay minimal fees, so it is removed from mempool"; "hash" => HashType::OperationHash.bytes_to_string(filtered_oph));
+                                delete_from_mempool_storage(mempool_storage, filtered_oph, log);
+                            });
 
                             // TODO: handle Duplicate/ Outdated - if result is empty
                             // TODO: handle result like ocaml - branch_delayed (is_endorsement) add back to pending and so on - check handle_unprocessed
@@ -522,6 +752,31 @@ fn handle_pending_operations(shell_channel: &ShellChannelRef, protocol_controlle
     }
 }
 
+fn delete_from_mempool_storage(mempool_storage: &MempoolStorage, operation_hash: &OperationHash, log: &Logger) {
+    if let Err(err) = mempool_storage.delete(operation_hash) {
+        warn!(log, "Mempool - delete operation failed"; "hash" => HashType::OperationHash.bytes_to_string(operation_hash), "error" => format!("{:?}", err))
+    }
+}
+
+/// Blacklists refused operation, the oldest refused operations over the limit are removed from the blacklist
+fn blacklist_refused(mempool_storage: &MempoolStorage, refused: &mut RefusedOperations, operation_hash: &OperationHash, log: &Logger) {
+    if let Err(err) = mempool_storage.put_blacklisted(operation_hash, MempoolBlacklistReason::Refused, SystemTime::now()) {
+        warn!(log, "Mempool - blacklist refused operation failed"; "hash" => HashType::OperationHash.bytes_to_string(operation_hash), "error" => format!("{:?}", err));
+        return;
+    }
+    for forgotten in refused.insert(operation_hash.clone()) {
+        // operation could be banned meanwhile, so keep it
+        let result = match mempool_storage.get_blacklisted(&forgotten) {
+            Ok(Some(MempoolBlacklistReason::Refused)) => mempool_storage.delete_blacklisted(&forgotten),
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            warn!(log, "Mempool - remove forgotten refused operation from blacklist failed"; "hash" => HashType::OperationHash.bytes_to_string(&forgotten), "error" => format!("{:?}", err));
+        }
+    }
+}
+
 /// Notify other actors that mempool state changed
 fn notify_mempool_changed(shell_channel: &ShellChannelRef, mempool_state: &MempoolState) {
     let (protocol, fitness) = if let Some(prevalidator) = &mempool_state.prevalidator {
@@ -535,10 +790,11 @@ fn notify_mempool_changed(shell_channel: &ShellChannelRef, mempool_state: &Mempo
             msg: CurrentMempoolState {
                 head: mempool_state.predecessor.clone(),
                 result: mempool_state.validation_result.clone(),
-                operations: mempool_state.operations.clone(),
+                operations: mempool_state.operations.to_map(),
                 protocol,
                 fitness,
                 pending: mempool_state.pending.clone(),
+                stats: mempool_state.operations.stats(),
             }.into(),
             topic: ShellChannelTopic::ShellEvents.into(),
         },
@@ -549,26 +805,28 @@ fn notify_mempool_changed(shell_channel: &ShellChannelRef, mempool_state: &Mempo
 
 #[cfg(test)]
 mod tests {
+    use tezos_api::ffi::Applied;
     use tezos_messages::p2p::binary_message::BinaryMessage;
 
     use super::*;
 
-    #[test]
-    fn test_state_reinit() -> Result<(), failure::Error> {
+    /// Two pending operations with the same content
+    fn test_operations() -> Result<(OperationHash, OperationHash, HashMap<OperationHash, Operation>), failure::Error> {
         let op_hash1 = HashType::OperationHash.string_to_bytes("opJ4FdKumPfykAP9ZqwY7rNB8y1SiMupt44RqBDMWL7cmb4xbNr")?;
         let op_hash2 = HashType::OperationHash.string_to_bytes("onvN8U6QJ6DGJKVYkHXYRtFm3tgBJScj9P5bbPjSZUuFaGzwFuJ")?;
+        let operation = Operation::from_bytes(hex::decode("10490b79070cf19175cd7e3b9c1ee66f6e85799980404b119132ea7e58a4a97e000008c387fa065a181d45d47a9b78ddc77e92a881779ff2cbabbf9646eade4bf1405a08e00b725ed849eea46953b10b5cdebc518e6fd47e69b82d2ca18c4cf6d2f312dd08")?)?;
 
-        // init state
         let mut operations = HashMap::new();
-        operations.insert(
-            op_hash1.clone(),
-            Operation::from_bytes(hex::decode("10490b79070cf19175cd7e3b9c1ee66f6e85799980404b119132ea7e58a4a97e000008c387fa065a181d45d47a9b78ddc77e92a881779ff2cbabbf9646eade4bf1405a08e00b725ed849eea46953b10b5cdebc518e6fd47e69b82d2ca18c4cf6d2f312dd08")?)?,
-        );
-        operations.insert(
-            op_hash2.clone(),
-            Operation::from_bytes(hex::decode("10490b79070cf19175cd7e3b9c1ee66f6e85799980404b119132ea7e58a4a97e000008c387fa065a181d45d47a9b78ddc77e92a881779ff2cbabbf9646eade4bf1405a08e00b725ed849eea46953b10b5cdebc518e6fd47e69b82d2ca18c4cf6d2f312dd08")?)?,
-        );
-        let mut state = MempoolState::new(None, None, operations);
+        operations.insert(op_hash1.clone(), operation.clone());
+        operations.insert(op_hash2.clone(), operation);
+        Ok((op_hash1, op_hash2, operations))
+    }
+
+    #[test]
+    fn test_state_reinit() -> Result<(), failure::Error> {
+        // init state
+        let (op_hash1, op_hash2, operations) = test_operations()?;
+        let mut state = MempoolState::new(None, None, operations, MempoolConfiguration::default());
         assert_eq!(2, state.pending.len());
         assert_eq!(2, state.operations.len());
 
@@ -584,4 +842,87 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_add_result_filters_minimal_fees() -> Result<(), failure::Error> {
+        let (op_hash1, op_hash2, operations) = test_operations()?;
+        let mut state = MempoolState::new(None, None, operations, MempoolConfiguration::default());
+
+        let transaction = |fee: &str| format!(r#"{{"contents":[{{"kind":"transaction","fee":"{}","gas_limit":"10000"}}],"signature":"sig"}}"#, fee);
+        let result = ValidateOperationResult {
+            applied: vec![
+                Applied { hash: op_hash1.clone(), protocol_data_json: transaction("100") },
+                Applied { hash: op_hash2.clone(), protocol_data_json: transaction("10000") },
+            ],
+            ..Default::default()
+        };
+
+        let (changed, filtered) = state.add_result(result, &MempoolFilter::default());
+        assert!(changed);
+        assert_eq!(vec![op_hash1.clone()], filtered);
+        // filtered operation is reported as refused, so injection does not wait for it
+        assert!(state.is_already_validated(&op_hash1));
+        assert!(state.validation_result.refused().iter().any(|refused| refused.hash == op_hash1));
+        assert!(!state.operations.contains(&op_hash1));
+        assert!(state.is_already_validated(&op_hash2));
+        assert_eq!(Some(&1), state.operations.stats().rejected.get("minimal_fees"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot() -> Result<(), failure::Error> {
+        let (op_hash1, op_hash2, operations) = test_operations()?;
+
+        // nothing to validate against
+        let state = MempoolState::new(None, None, operations.clone(), MempoolConfiguration::default());
+        assert!(state.snapshot(SystemTime::now()).is_none());
+
+        let predecessor = HashType::BlockHash.string_to_bytes("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe")?;
+        let mut state = MempoolState::new(None, Some(predecessor.clone()), operations, MempoolConfiguration::default());
+        state.remove_from_pending(&op_hash1);
+        let _ = state.add_result(ValidateOperationResult {
+            applied: vec![Applied { hash: op_hash1.clone(), protocol_data_json: "{}".to_string() }],
+            ..Default::default()
+        }, &MempoolFilter::default());
+
+        let snapshot = state.snapshot(SystemTime::now()).expect("Snapshot with predecessor");
+        assert_eq!(predecessor, snapshot.predecessor);
+        let statuses = snapshot.operations
+            .iter()
+            .map(|operation| (operation.operation_hash.clone(), operation.status))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(2, statuses.len());
+        assert_eq!(Some(&MempoolSnapshotStatus::Applied), statuses.get(&op_hash1));
+        assert_eq!(Some(&MempoolSnapshotStatus::Pending), statuses.get(&op_hash2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_operation() -> Result<(), failure::Error> {
+        let (op_hash1, op_hash2, operations) = test_operations()?;
+        let mut state = MempoolState::new(None, None, operations, MempoolConfiguration::default());
+
+        // validated operation
+        state.remove_from_pending(&op_hash1);
+        let result = ValidateOperationResult {
+            applied: vec![Applied { hash: op_hash1.clone(), protocol_data_json: "{}".to_string() }],
+            ..Default::default()
+        };
+        let _ = state.add_result(result, &MempoolFilter::default());
+        assert!(state.remove_operation(&op_hash1));
+        assert!(!state.is_already_validated(&op_hash1));
+        assert!(!state.operations.contains(&op_hash1));
+
+        // pending operation
+        assert!(state.remove_operation(&op_hash2));
+        assert!(state.pending.is_empty());
+        assert!(state.operations.is_empty());
+
+        // unknown operation
+        assert!(!state.remove_operation(&op_hash2));
+
+        Ok(())
+    }
 }
\ No newline at end of file
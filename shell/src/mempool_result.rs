@@ -0,0 +1,207 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Validation results of the mempool operations indexed by operation hash.
+//!
+//! Results are kept in the ordered lists (the same as [ValidateOperationResult], in order of validation) for rpc,
+//! index is used to resolve classification of the operation without scanning the lists.
+
+use std::collections::HashMap;
+
+use crypto::hash::OperationHash;
+use tezos_api::ffi::{Applied, Errored, ValidateOperationResult};
+
+/// Result of the operation validation by protocol
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum OperationClassification {
+    Applied,
+    Refused,
+    BranchRefused,
+    BranchDelayed,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MempoolValidationResult {
+    result: ValidateOperationResult,
+    index: HashMap<OperationHash, OperationClassification>,
+}
+
+impl MempoolValidationResult {
+    pub fn applied(&self) -> &Vec<Applied> {
+        &self.result.applied
+    }
+
+    pub fn refused(&self) -> &Vec<Errored> {
+        &self.result.refused
+    }
+
+    pub fn branch_refused(&self) -> &Vec<Errored> {
+        &self.result.branch_refused
+    }
+
+    pub fn branch_delayed(&self) -> &Vec<Errored> {
+        &self.result.branch_delayed
+    }
+
+    /// Returns ordered lists of results
+    pub fn as_result(&self) -> &ValidateOperationResult {
+        &self.result
+    }
+
+    pub fn classification(&self, operation_hash: &OperationHash) -> Option<OperationClassification> {
+        self.index.get(operation_hash).copied()
+    }
+
+    pub fn contains(&self, operation_hash: &OperationHash) -> bool {
+        self.index.contains_key(operation_hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Merges result with new one, and returns `true/false` if something was changed.
+    ///
+    /// Already classified operation is replaced, if it was classified differently, it is moved to the end of the new list.
+    pub fn merge(&mut self, new_result: ValidateOperationResult) -> bool {
+        let ValidateOperationResult { applied, refused, branch_refused, branch_delayed } = new_result;
+        let changed = !(applied.is_empty() && refused.is_empty() && branch_refused.is_empty() && branch_delayed.is_empty());
+        for item in applied {
+            self.merge_item(OperationClassification::Applied, |result| &mut result.applied, item);
+        }
+        for item in refused {
+            self.merge_item(OperationClassification::Refused, |result| &mut result.refused, item);
+        }
+        for item in branch_refused {
+            self.merge_item(OperationClassification::BranchRefused, |result| &mut result.branch_refused, item);
+        }
+        for item in branch_delayed {
+            self.merge_item(OperationClassification::BranchDelayed, |result| &mut result.branch_delayed, item);
+        }
+        changed
+    }
+
+    fn merge_item<ITEM, F>(&mut self, classification: OperationClassification, list: F, item: ITEM)
+        where F: Fn(&mut ValidateOperationResult) -> &mut Vec<ITEM>,
+              ITEM: HasHash {
+        let operation_hash = item.hash().clone();
+        match self.index.get(&operation_hash).copied() {
+            Some(old_classification) if old_classification == classification => {
+                // replace, just this list is scanned
+                let items = list(&mut self.result);
+                if let Some(position) = items.iter().position(|old_item| old_item.hash().eq(&operation_hash)) {
+                    items[position] = item;
+                }
+            }
+            Some(_) => {
+                let _ = self.remove(&operation_hash);
+                list(&mut self.result).push(item);
+                self.index.insert(operation_hash, classification);
+            }
+            None => {
+                list(&mut self.result).push(item);
+                self.index.insert(operation_hash, classification);
+            }
+        }
+    }
+
+    /// Removes operation result, returns its classification, if it was found
+    pub fn remove(&mut self, operation_hash: &OperationHash) -> Option<OperationClassification> {
+        let classification = self.index.remove(operation_hash)?;
+        match classification {
+            OperationClassification::Applied => remove_item(&mut self.result.applied, operation_hash),
+            OperationClassification::Refused => remove_item(&mut self.result.refused, operation_hash),
+            OperationClassification::BranchRefused => remove_item(&mut self.result.branch_refused, operation_hash),
+            OperationClassification::BranchDelayed => remove_item(&mut self.result.branch_delayed, operation_hash),
+        }
+        Some(classification)
+    }
+}
+
+impl From<ValidateOperationResult> for MempoolValidationResult {
+    fn from(result: ValidateOperationResult) -> Self {
+        let mut indexed = MempoolValidationResult::default();
+        let _ = indexed.merge(result);
+        indexed
+    }
+}
+
+/// Results share the operation hash
+trait HasHash {
+    fn hash(&self) -> &OperationHash;
+}
+
+impl HasHash for Applied {
+    fn hash(&self) -> &OperationHash {
+        &self.hash
+    }
+}
+
+impl HasHash for Errored {
+    fn hash(&self) -> &OperationHash {
+        &self.hash
+    }
+}
+
+fn remove_item<ITEM: HasHash>(items: &mut Vec<ITEM>, operation_hash: &OperationHash) {
+    if let Some(position) = items.iter().position(|item| item.hash().eq(operation_hash)) {
+        // keep order for rpc
+        let _ = items.remove(position);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tezos_api::ffi::OperationProtocolDataJsonWithErrorListJson;
+
+    use super::*;
+
+    fn applied(hash: u8) -> Applied {
+        Applied { hash: vec![hash], protocol_data_json: "{}".to_string() }
+    }
+
+    fn errored(hash: u8) -> Errored {
+        Errored {
+            hash: vec![hash],
+            is_endorsement: None,
+            protocol_data_json_with_error_json: OperationProtocolDataJsonWithErrorListJson {
+                protocol_data_json: "{}".to_string(),
+                error_json: "[]".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_merge_and_remove() {
+        let mut result = MempoolValidationResult::from(ValidateOperationResult {
+            applied: vec![applied(1), applied(2)],
+            branch_delayed: vec![errored(3)],
+            ..Default::default()
+        });
+        assert_eq!(3, result.len());
+        assert_eq!(Some(OperationClassification::Applied), result.classification(&vec![1]));
+        assert_eq!(Some(OperationClassification::BranchDelayed), result.classification(&vec![3]));
+        assert_eq!(None, result.classification(&vec![4]));
+
+        // reclassified operation is moved
+        assert!(result.merge(ValidateOperationResult { refused: vec![errored(1)], ..Default::default() }));
+        assert_eq!(Some(OperationClassification::Refused), result.classification(&vec![1]));
+        assert_eq!(vec![vec![2]], result.applied().iter().map(|a| a.hash.clone()).collect::<Vec<_>>());
+        assert_eq!(1, result.refused().len());
+
+        // nothing to merge
+        assert!(!result.merge(ValidateOperationResult::default()));
+
+        // order of the lists is kept
+        assert!(result.merge(ValidateOperationResult { applied: vec![applied(5), applied(6)], ..Default::default() }));
+        assert_eq!(Some(OperationClassification::Applied), result.remove(&vec![5]));
+        assert_eq!(vec![vec![2], vec![6]], result.applied().iter().map(|a| a.hash.clone()).collect::<Vec<_>>());
+        assert_eq!(None, result.remove(&vec![5]));
+        assert!(!result.contains(&vec![5]));
+        assert_eq!(4, result.len());
+    }
+}
@@ -7,6 +7,7 @@ use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
 use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
@@ -20,18 +21,19 @@ use tokio::net::{TcpListener, TcpStream};
 use tokio::runtime::Handle;
 use tokio::time::timeout;
 
+use networking::p2p::capture::CaptureWriter;
 use networking::p2p::network_channel::{NetworkChannelMsg, NetworkChannelRef, NetworkChannelTopic, PeerBootstrapped, PeerCreated};
-use networking::p2p::peer::{Bootstrap, Peer, PeerRef, SendMessage};
+use networking::p2p::peer::{Bootstrap, Peer, PeerRef, PeerTimeouts, SendMessage};
 use networking::PeerId;
 use tezos_identity::Identity;
 use tezos_messages::p2p::encoding::prelude::*;
 
-use crate::PeerConnectionThreshold;
+use crate::{PeerConnectionThreshold, PeerRoles};
+use crate::clock::{ClockRef, Ping, Pong};
 use crate::shell_channel::{ShellChannelMsg, ShellChannelRef};
+use crate::stats::handshake::HandshakeStatsRef;
 use crate::subscription::*;
 
-/// Timeout for outgoing connections
-const CONNECT_TIMEOUT: Duration = Duration::from_secs(8);
 /// Whitelist all IP addresses after 30 minutes
 const WHITELIST_INTERVAL: Duration = Duration::from_secs(1_800);
 /// How often to do DNS peer discovery
@@ -69,6 +71,18 @@ pub struct P2p {
     pub peer_threshold: PeerConnectionThreshold,
     pub disable_mempool: bool,
     pub private_node: bool,
+    /// Trusted and sentry roles of the peers
+    pub peer_roles: PeerRoles,
+    /// If set, all decrypted p2p messages are recorded to this capture file
+    pub capture_file: Option<PathBuf>,
+    /// Timeout for outgoing connections
+    pub connect_timeout: Duration,
+    /// Handshake and idle timeouts of the peer connection
+    pub peer_timeouts: PeerTimeouts,
+    /// Max number of incoming connections with handshake in progress
+    pub max_pending_incoming_handshakes: usize,
+    /// Max number of outgoing connections with handshake in progress
+    pub max_pending_outgoing_handshakes: usize,
 }
 
 /// This actor is responsible for peer management.
@@ -76,7 +90,7 @@ pub struct P2p {
 /// It monitors number of connected peers. If the number of connected peers is too low it tries to
 /// connect to more peers. If the number of connected peers is too high, then randomly selected peers
 /// are disconnected.
-#[actor(CheckPeerCount, WhitelistAllIpAddresses, AcceptPeer, ConnectToPeer, NetworkChannelMsg, ShellChannelMsg, SystemEvent, DeadLetter)]
+#[actor(CheckPeerCount, WhitelistAllIpAddresses, AcceptPeer, ConnectToPeer, Ping, NetworkChannelMsg, ShellChannelMsg, SystemEvent, DeadLetter)]
 pub struct PeerManager {
     /// All events generated by the network layer will end up in this channel
     network_channel: NetworkChannelRef,
@@ -96,6 +110,8 @@ pub struct PeerManager {
     disable_mempool: bool,
     /// Indicates that p2p is working in private mode
     private_node: bool,
+    /// Trusted and sentry roles of the peers
+    peer_roles: PeerRoles,
     /// List of potential peers to connect to
     potential_peers: HashSet<SocketAddr>,
     /// Tokio runtime
@@ -104,8 +120,18 @@ pub struct PeerManager {
     listener_port: u16,
     /// Tezos identity
     identity: Arc<Identity>,
-    /// Network/protocol version
-    network_version: NetworkVersion,
+    /// Supported network/protocol versions
+    supported_versions: SupportedNetworkVersions,
+    /// Capture of the p2p messages shared by all peers, if enabled
+    capture: Option<Arc<CaptureWriter>>,
+    /// Timeout for outgoing connections
+    connect_timeout: Duration,
+    /// Handshake and idle timeouts of the peer connection
+    peer_timeouts: PeerTimeouts,
+    /// Max number of incoming connections with handshake in progress
+    max_pending_incoming_handshakes: usize,
+    /// Max number of outgoing connections with handshake in progress
+    max_pending_outgoing_handshakes: usize,
     /// Message receiver boolean indicating whether
     /// more connections should be accepted from network
     rx_run: Arc<AtomicBool>,
@@ -117,6 +143,12 @@ pub struct PeerManager {
     check_peer_count_last: Option<Instant>,
     /// Indicates that system is shutting down
     shutting_down: bool,
+    /// Handshake outcome counters shared with monitoring and rpc
+    handshake_stats: HandshakeStatsRef,
+    /// Source of time, can be simulated in tests
+    clock: ClockRef,
+    /// Count of all processed messages, answered to [Ping]
+    processed_messages: u64,
 }
 
 /// Reference to [peer manager](PeerManager) actor.
@@ -128,8 +160,11 @@ impl PeerManager {
                  shell_channel: ShellChannelRef,
                  tokio_executor: Handle,
                  identity: Arc<Identity>,
-                 network_version: NetworkVersion,
+                 supported_versions: SupportedNetworkVersions,
                  p2p_config: P2p,
+                 capture: Option<Arc<CaptureWriter>>,
+                 handshake_stats: HandshakeStatsRef,
+                 clock: ClockRef,
     ) -> Result<PeerManagerRef, CreateError> {
         sys.actor_of_props::<PeerManager>(
             PeerManager::name(),
@@ -138,12 +173,23 @@ impl PeerManager {
                 shell_channel,
                 tokio_executor,
                 identity,
-                network_version,
+                supported_versions,
                 p2p_config,
+                capture,
+                handshake_stats,
+                clock,
             )),
         )
     }
 
+    /// Periodic messages of the peer manager as (initial delay, interval, message)
+    pub fn timers() -> Vec<(Duration, Duration, PeerManagerMsg)> {
+        vec![
+            (Duration::from_secs(3), Duration::from_secs(10), CheckPeerCount.into()),
+            (WHITELIST_INTERVAL, WHITELIST_INTERVAL, WhitelistAllIpAddresses.into()),
+        ]
+    }
+
     /// The `PeerManager` is intended to serve as a singleton actor so that's why
     /// we won't support multiple names per instance.
     fn name() -> &'static str {
@@ -152,8 +198,9 @@ impl PeerManager {
 
     /// Try to discover new remote peers to connect
     fn discover_peers(&mut self, log: &Logger) {
-        if self.peers.is_empty() || self.discovery_last.filter(|discovery_last| discovery_last.elapsed() <= DISCOVERY_INTERVAL).is_none() {
-            self.discovery_last = Some(Instant::now());
+        let now = self.clock.now();
+        if self.peers.is_empty() || self.discovery_last.filter(|discovery_last| now.saturating_duration_since(*discovery_last) <= DISCOVERY_INTERVAL).is_none() {
+            self.discovery_last = Some(now);
 
             if !self.disable_bootstrap_lookup {
                 info!(log, "Doing peer DNS lookup"; "bootstrap_addresses" => format!("{:?}", &self.bootstrap_addresses));
@@ -178,7 +225,7 @@ impl PeerManager {
     }
 
     /// Create new peer actor
-    fn create_peer(&mut self, sys: &impl ActorRefFactory, socket_address: &SocketAddr) -> PeerRef {
+    fn create_peer(&mut self, sys: &impl ActorRefFactory, socket_address: &SocketAddr, incoming: bool) -> PeerRef {
         let peer = Peer::actor(
             sys,
             self.network_channel.clone(),
@@ -186,12 +233,15 @@ impl PeerManager {
             &self.identity.public_key,
             &self.identity.secret_key,
             &self.identity.proof_of_work_stamp,
-            self.network_version.clone(),
+            self.supported_versions.clone(),
+            self.peer_timeouts,
             self.tokio_executor.clone(),
             socket_address,
+            self.capture.clone(),
         ).unwrap();
 
-        self.peers.insert(peer.uri().clone(), PeerState { peer_ref: peer.clone(), address: *socket_address });
+        self.peers.insert(peer.uri().clone(), PeerState { peer_ref: peer.clone(), address: *socket_address, incoming, peer_id: None });
+        self.update_pending_handshakes_stats();
 
         self.network_channel.tell(
             Publish {
@@ -205,12 +255,70 @@ impl PeerManager {
         peer
     }
 
+    /// Count peers with handshake in progress
+    fn pending_handshakes(&self, incoming: bool) -> usize {
+        self.peers.values()
+            .filter(|peer_state| peer_state.peer_id.is_none() && peer_state.incoming == incoming)
+            .count()
+    }
+
+    /// Number of outgoing connections, which can be opened without exceeding the in-flight handshakes limit
+    fn available_outgoing_handshakes(&self) -> usize {
+        self.max_pending_outgoing_handshakes.saturating_sub(self.pending_handshakes(false))
+    }
+
+    fn update_pending_handshakes_stats(&self) {
+        let pending_incoming = self.pending_handshakes(true);
+        let pending_outgoing = self.pending_handshakes(false);
+        if let Ok(mut handshake_stats) = self.handshake_stats.write() {
+            handshake_stats.pending.incoming = pending_incoming;
+            handshake_stats.pending.outgoing = pending_outgoing;
+        }
+    }
+
+    fn record_pending_limit_reached(&self, incoming: bool) {
+        if let Ok(mut handshake_stats) = self.handshake_stats.write() {
+            if incoming {
+                handshake_stats.pending_limit_reached.incoming += 1;
+            } else {
+                handshake_stats.pending_limit_reached.outgoing += 1;
+            }
+        }
+    }
+
     /// Check if given ip address is blacklisted to connect to
     fn is_blacklisted(&self, ip_address: &IpAddr) -> bool {
         self.ip_blacklist.contains(ip_address)
     }
 
+    /// Check if peer with the same address is already connected (or connecting)
+    fn is_connected(&self, address: &SocketAddr) -> bool {
+        self.peers.values().any(|peer_state| peer_state.address == *address)
+    }
+
+    /// Trusted peers are connected regardless of the peer threshold
+    fn connect_trusted_peers(&mut self, ctx: &Context<PeerManagerMsg>) {
+        let addresses_to_connect = self.peer_roles.trusted_peers().iter()
+            .filter(|address| !self.is_connected(address))
+            .cloned()
+            .collect::<Vec<_>>();
+        addresses_to_connect.into_iter()
+            .for_each(|address| {
+                debug!(ctx.system.log(), "Trusted peer is not connected"; "ip" => address);
+                self.potential_peers.remove(&address);
+                ctx.myself().tell(ConnectToPeer { address }, ctx.myself().into())
+            });
+    }
+
     fn blacklist_address(&mut self, address: SocketAddr, reason: String, log: &Logger) {
+        if self.peer_roles.is_trusted(&address, None) {
+            info!(log, "Trusted peer will not be blacklisted";
+                       "ip" => format!("{}", address.ip()),
+                       "reason" => reason,
+            );
+            return;
+        }
+
         info!(log, "Blacklisting IP";
                    "ip" => format!("{}", address.ip()),
                    "reason" => reason,
@@ -222,6 +330,15 @@ impl PeerManager {
 
     fn blacklist_peer(&mut self, peer_id: Arc<PeerId>, reason: String, actor_system: &ActorSystem) {
         let log = actor_system.log();
+        if self.peer_roles.is_trusted(&peer_id.peer_address, Some(&peer_id.peer_id_marker)) {
+            warn!(log, "Trusted peer will not be blacklisted nor disconnected";
+                       "peer_actor_ref" => peer_id.peer_ref.uri().to_string(),
+                       "peer_id" => peer_id.peer_id_marker.clone(),
+                       "reason" => reason,
+            );
+            return;
+        }
+
         warn!(log, "Blacklisting peer";
                    "peer_actor_ref" => peer_id.peer_ref.uri().to_string(),
                    "peer_id" => peer_id.peer_id_marker.clone(),
@@ -257,12 +374,13 @@ impl PeerManager {
     }
 
     fn trigger_check_peer_count(&mut self, ctx: &Context<PeerManagerMsg>) {
+        let now = self.clock.now();
         let should_trigger = self.check_peer_count_last
-            .map(|check_peer_count_last| check_peer_count_last.elapsed() > CHECK_PEER_COUNT_LIMIT)
+            .map(|check_peer_count_last| now.saturating_duration_since(check_peer_count_last) > CHECK_PEER_COUNT_LIMIT)
             .unwrap_or(true);
 
         if should_trigger {
-            self.check_peer_count_last = Some(Instant::now());
+            self.check_peer_count_last = Some(now);
             ctx.myself().tell(CheckPeerCount, None);
         }
     }
@@ -276,9 +394,9 @@ impl PeerManager {
     }
 }
 
-impl ActorFactoryArgs<(NetworkChannelRef, ShellChannelRef, Handle, Arc<Identity>, NetworkVersion, P2p)> for PeerManager {
-    fn create_args((network_channel, shell_channel, tokio_executor, identity, network_version, p2p_config):
-                   (NetworkChannelRef, ShellChannelRef, Handle, Arc<Identity>, NetworkVersion, P2p)) -> Self
+impl ActorFactoryArgs<(NetworkChannelRef, ShellChannelRef, Handle, Arc<Identity>, SupportedNetworkVersions, P2p, Option<Arc<CaptureWriter>>, HandshakeStatsRef, ClockRef)> for PeerManager {
+    fn create_args((network_channel, shell_channel, tokio_executor, identity, supported_versions, p2p_config, capture, handshake_stats, clock):
+                   (NetworkChannelRef, ShellChannelRef, Handle, Arc<Identity>, SupportedNetworkVersions, P2p, Option<Arc<CaptureWriter>>, HandshakeStatsRef, ClockRef)) -> Self
     {
         PeerManager {
             network_channel,
@@ -290,9 +408,15 @@ impl ActorFactoryArgs<(NetworkChannelRef, ShellChannelRef, Handle, Arc<Identity>
             threshold: p2p_config.peer_threshold,
             listener_port: p2p_config.listener_port,
             identity,
-            network_version,
+            supported_versions,
+            capture,
+            connect_timeout: p2p_config.connect_timeout,
+            peer_timeouts: p2p_config.peer_timeouts,
+            max_pending_incoming_handshakes: p2p_config.max_pending_incoming_handshakes,
+            max_pending_outgoing_handshakes: p2p_config.max_pending_outgoing_handshakes,
             disable_mempool: p2p_config.disable_mempool,
             private_node: p2p_config.private_node,
+            peer_roles: p2p_config.peer_roles,
             rx_run: Arc::new(AtomicBool::new(true)),
             potential_peers: HashSet::new(),
             peers: HashMap::new(),
@@ -300,6 +424,9 @@ impl ActorFactoryArgs<(NetworkChannelRef, ShellChannelRef, Handle, Arc<Identity>
             discovery_last: None,
             check_peer_count_last: None,
             shutting_down: false,
+            handshake_stats,
+            clock,
+            processed_messages: 0,
         }
     }
 }
@@ -313,19 +440,12 @@ impl Actor for PeerManager {
         subscribe_to_shell_events(&self.shell_channel, ctx.myself());
         subscribe_to_dead_letters(ctx.system.dead_letters(), ctx.myself());
 
-        ctx.schedule::<Self::Msg, _>(
-            Duration::from_secs(3),
-            Duration::from_secs(10),
-            ctx.myself(),
-            None,
-            CheckPeerCount.into());
-        ctx.schedule::<Self::Msg, _>(
-            WHITELIST_INTERVAL,
-            WHITELIST_INTERVAL,
-            ctx.myself(),
-            None,
-            WhitelistAllIpAddresses.into());
-
+        // with simulated clock, timer messages are sent by the driver of the simulation
+        if !self.clock.is_simulated() {
+            for (initial_delay, interval, msg) in Self::timers() {
+                ctx.schedule::<Self::Msg, _>(initial_delay, interval, ctx.myself(), None, msg);
+            }
+        }
 
         let listener_port = self.listener_port;
         let myself = ctx.myself();
@@ -344,20 +464,33 @@ impl Actor for PeerManager {
 
     fn sys_recv(&mut self, ctx: &Context<Self::Msg>, msg: SystemMsg, sender: Option<BasicActorRef>) {
         if let SystemMsg::Event(evt) = msg {
+            self.processed_messages += 1;
             self.receive(ctx, evt, sender);
         }
     }
 
     fn recv(&mut self, ctx: &Context<Self::Msg>, msg: Self::Msg, sender: Sender) {
+        self.processed_messages += 1;
         self.receive(ctx, msg, sender);
     }
 }
 
+impl Receive<Ping> for PeerManager {
+    type Msg = PeerManagerMsg;
+
+    fn receive(&mut self, _ctx: &Context<Self::Msg>, _msg: Ping, sender: Sender) {
+        if let Some(sender) = sender {
+            let _ = sender.try_tell(Pong { processed_messages: self.processed_messages }, None);
+        }
+    }
+}
+
 impl Receive<DeadLetter> for PeerManager {
     type Msg = PeerManagerMsg;
 
     fn receive(&mut self, ctx: &Context<Self::Msg>, msg: DeadLetter, _sender: Option<BasicActorRef>) {
         if self.peers.remove(msg.recipient.uri()).is_some() {
+            self.update_pending_handshakes_stats();
             self.trigger_check_peer_count(ctx);
         }
     }
@@ -381,6 +514,7 @@ impl Receive<SystemEvent> for PeerManager {
     fn receive(&mut self, ctx: &Context<Self::Msg>, msg: SystemEvent, _sender: Option<BasicActorRef>) {
         if let SystemEvent::ActorTerminated(evt) = msg {
             if self.peers.remove(evt.actor.uri()).is_some() {
+                self.update_pending_handshakes_stats();
                 self.trigger_check_peer_count(ctx);
             }
         }
@@ -396,6 +530,8 @@ impl Receive<CheckPeerCount> for PeerManager {
             return;
         }
 
+        self.connect_trusted_peers(ctx);
+
         if self.peers.len() < self.threshold.low {
             // peer count is too low, try to connect to more peers
             warn!(ctx.system.log(), "Peer count is too low"; "actual" => self.peers.len(), "required" => self.threshold.low);
@@ -404,6 +540,15 @@ impl Receive<CheckPeerCount> for PeerManager {
             }
 
             let num_required_peers = cmp::max((self.threshold.high + 3 * self.threshold.low) / 4 - self.peers.len(), self.threshold.low);
+            let available_outgoing_handshakes = self.available_outgoing_handshakes();
+            if available_outgoing_handshakes < num_required_peers {
+                info!(ctx.system.log(), "Limit of pending outgoing handshakes reached, postponing new connections";
+                                        "required" => num_required_peers,
+                                        "pending" => self.pending_handshakes(false),
+                                        "limit" => self.max_pending_outgoing_handshakes);
+                self.record_pending_limit_reached(false);
+            }
+            let num_required_peers = cmp::min(num_required_peers, available_outgoing_handshakes);
             let mut addresses_to_connect = self.potential_peers.iter().cloned().collect::<Vec<SocketAddr>>();
             // randomize peers as a security measurement
             addresses_to_connect.shuffle(&mut rand::thread_rng());
@@ -417,13 +562,15 @@ impl Receive<CheckPeerCount> for PeerManager {
             // peer count is too high, disconnect some peers
             warn!(ctx.system.log(), "Peer count is too high. Some peers will be stopped"; "actual" => self.peers.len(), "limit" => self.threshold.high);
 
-            // stop some peers
+            // stop some peers, trusted peers are never disconnected
+            let peer_roles = &self.peer_roles;
             self.peers.values()
+                .filter(|peer_state| !peer_state.is_trusted(peer_roles))
                 .take(self.peers.len() - self.threshold.high)
                 .for_each(|peer_state| ctx.system.stop(peer_state.peer_ref.clone()))
         }
 
-        self.check_peer_count_last = Some(Instant::now());
+        self.check_peer_count_last = Some(self.clock.now());
     }
 }
 
@@ -445,8 +592,14 @@ impl Receive<NetworkChannelMsg> for PeerManager {
                         PeerMessage::Bootstrap => {
                             // to a bootstrap message we will respond with list of potential peers
                             trace!(ctx.system.log(), "Received bootstrap message"; "peer" => received.peer.name());
+                            // sentry node never leaks addresses of the trusted peers
+                            let recipient = match self.peers.get(received.peer.uri()) {
+                                Some(recipient) => recipient.address,
+                                None => return,
+                            };
                             let addresses = self.peers.values()
                                 .filter(|peer_state| peer_state.peer_ref != received.peer)
+                                .filter(|peer_state| self.peer_roles.is_advertisable(&peer_state.address, peer_state.peer_id_marker(), &recipient))
                                 .map(|peer_state| peer_state.address)
                                 .collect::<Vec<_>>();
                             let msg = AdvertiseMessage::new(&addresses);
@@ -456,8 +609,22 @@ impl Receive<NetworkChannelMsg> for PeerManager {
                     });
                 self.trigger_check_peer_count(ctx);
             }
-            NetworkChannelMsg::PeerBootstrapped(PeerBootstrapped::Failure { address, potential_peers_to_connect }) => {
+            NetworkChannelMsg::PeerBootstrapped(PeerBootstrapped::Success { peer_id, network_version, .. }) => {
+                if let Some(peer_state) = self.peers.get_mut(peer_id.peer_ref.uri()) {
+                    peer_state.peer_id = Some(peer_id.clone());
+                }
+                if let Ok(mut handshake_stats) = self.handshake_stats.write() {
+                    handshake_stats.record_success(&network_version);
+                }
+                self.update_pending_handshakes_stats();
+            }
+            NetworkChannelMsg::PeerBootstrapped(PeerBootstrapped::Failure { address, potential_peers_to_connect, stage, reason, remote_version }) => {
                 // received message that bootstrap process failed for the peer
+                debug!(ctx.system.log(), "Peer handshake failed"; "ip" => address, "stage" => stage.as_str(), "reason" => reason, "remote_version" => format!("{:?}", &remote_version));
+                if let Ok(mut handshake_stats) = self.handshake_stats.write() {
+                    handshake_stats.record_failure(stage, reason, remote_version.as_ref());
+                }
+
                 match potential_peers_to_connect {
                     Some(peers) => {
                         self.process_potential_peers(&peers);
@@ -471,6 +638,11 @@ impl Receive<NetworkChannelMsg> for PeerManager {
             NetworkChannelMsg::BlacklistPeer(peer_id, reason) => {
                 self.blacklist_peer(peer_id, reason, &ctx.system);
             }
+            NetworkChannelMsg::PeerIdleTimeout(_) => {
+                if let Ok(mut handshake_stats) = self.handshake_stats.write() {
+                    handshake_stats.idle_timeouts += 1;
+                }
+            }
             _ => ()
         }
     }
@@ -491,17 +663,26 @@ impl Receive<ConnectToPeer> for PeerManager {
     fn receive(&mut self, ctx: &Context<Self::Msg>, msg: ConnectToPeer, _sender: Sender) {
         // received message instructing this actor that it should open new p2p connection to the remote peer
 
-        if self.is_blacklisted(&msg.address.ip()) {
+        if self.is_blacklisted(&msg.address.ip()) && !self.peer_roles.is_trusted(&msg.address, None) {
             debug!(ctx.system.log(), "Peer is blacklisted - will not connect"; "ip" => format!("{}", msg.address.ip()));
+        } else if self.available_outgoing_handshakes() == 0 {
+            info!(ctx.system.log(), "Limit of pending outgoing handshakes reached - will not connect";
+                                    "ip" => msg.address,
+                                    "limit" => self.max_pending_outgoing_handshakes);
+            self.record_pending_limit_reached(false);
+            // try it later
+            self.potential_peers.insert(msg.address);
         } else {
-            let peer = self.create_peer(ctx, &msg.address);
+            let peer = self.create_peer(ctx, &msg.address, false);
             let system = ctx.system.clone();
             let disable_mempool = self.disable_mempool;
             let private_node = self.private_node;
+            let connect_timeout = self.connect_timeout;
+            let handshake_stats = self.handshake_stats.clone();
 
             self.tokio_executor.spawn(async move {
                 info!(system.log(), "Connecting to IP"; "ip" => msg.address, "peer" => peer.name());
-                match timeout(CONNECT_TIMEOUT, TcpStream::connect(&msg.address)).await {
+                match timeout(connect_timeout, TcpStream::connect(&msg.address)).await {
                     Ok(Ok(stream)) => {
                         info!(system.log(), "Connection successful"; "ip" => msg.address);
                         peer.tell(Bootstrap::outgoing(stream, msg.address, disable_mempool, private_node), None);
@@ -511,7 +692,10 @@ impl Receive<ConnectToPeer> for PeerManager {
                         system.stop(peer);
                     }
                     Err(_) => {
-                        info!(system.log(), "Connection timed out"; "ip" => msg.address, "peer" => peer.name());
+                        info!(system.log(), "Connection timed out"; "ip" => msg.address, "peer" => peer.name(), "secs" => connect_timeout.as_secs());
+                        if let Ok(mut handshake_stats) = handshake_stats.write() {
+                            handshake_stats.connect_timeouts += 1;
+                        }
                         system.stop(peer);
                     }
                 }
@@ -524,11 +708,18 @@ impl Receive<AcceptPeer> for PeerManager {
     type Msg = PeerManagerMsg;
 
     fn receive(&mut self, ctx: &Context<Self::Msg>, msg: AcceptPeer, _sender: Sender) {
+        // trust of the incoming connection is not known until the handshake with the peer is done
         if self.is_blacklisted(&msg.address.ip()) {
             warn!(ctx.system.log(), "Peer is blacklisted - will not accept connection"; "ip" => format!("{}", msg.address.ip()));
+        } else if self.pending_handshakes(true) >= self.max_pending_incoming_handshakes {
+            info!(ctx.system.log(), "Limit of pending incoming handshakes reached - will not accept connection";
+                                    "ip" => msg.address,
+                                    "limit" => self.max_pending_incoming_handshakes);
+            self.record_pending_limit_reached(true);
+            drop(msg.stream);
         } else if self.peers.len() < self.threshold.high {
             info!(ctx.system.log(), "Connection from"; "ip" => msg.address);
-            let peer = self.create_peer(ctx, &msg.address);
+            let peer = self.create_peer(ctx, &msg.address, true);
             peer.tell(Bootstrap::incoming(msg.stream, msg.address, self.disable_mempool, self.private_node), None);
         } else {
             debug!(ctx.system.log(), "Cannot accept incoming peer connection because peer limit was reached");
@@ -587,4 +778,84 @@ struct PeerState {
     peer_ref: PeerRef,
     /// Peer IP address
     address: SocketAddr,
+    /// Connection was initiated by the remote peer
+    incoming: bool,
+    /// Id of the remote peer, set when handshake with the peer finished successfully
+    peer_id: Option<Arc<PeerId>>,
+}
+
+impl PeerState {
+    fn peer_id_marker(&self) -> Option<&str> {
+        self.peer_id.as_ref().map(|peer_id| peer_id.peer_id_marker.as_str())
+    }
+
+    fn is_trusted(&self, peer_roles: &PeerRoles) -> bool {
+        peer_roles.is_trusted(&self.address, self.peer_id_marker())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use riker::system::SystemBuilder;
+
+    use networking::p2p::network_channel::NetworkChannel;
+
+    use crate::shell_channel::ShellChannel;
+    use crate::stats::handshake::{ConnectionDirections, HandshakeStats};
+
+    use super::*;
+
+    #[test]
+    fn test_pending_handshakes() {
+        let actor_system = SystemBuilder::new().name("test_pending_handshakes").create().expect("Failed to create actor system");
+        let network_channel = NetworkChannel::actor(&actor_system).expect("Failed to create network channel");
+        let shell_channel = ShellChannel::actor(&actor_system).expect("Failed to create shell channel");
+        let tokio_runtime = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()
+            .expect("Failed to create tokio runtime");
+        let handshake_stats = HandshakeStats::new_ref();
+        let p2p_config = P2p {
+            listener_port: 0,
+            disable_bootstrap_lookup: true,
+            bootstrap_lookup_addresses: vec![],
+            initial_peers: vec![],
+            peer_threshold: PeerConnectionThreshold::new(0, 10),
+            disable_mempool: false,
+            private_node: false,
+            peer_roles: PeerRoles::default(),
+            capture_file: None,
+            connect_timeout: Duration::from_secs(8),
+            peer_timeouts: PeerTimeouts::default(),
+            max_pending_incoming_handshakes: 1,
+            max_pending_outgoing_handshakes: 2,
+        };
+        let mut peer_manager = PeerManager::create_args((
+            network_channel,
+            shell_channel,
+            tokio_runtime.handle().clone(),
+            Arc::new(Identity::generate(0f64)),
+            NetworkVersion::new("TEST_CHAIN".to_string(), 0, 0).into(),
+            p2p_config,
+            None,
+            handshake_stats.clone(),
+            crate::clock::system_clock(),
+        ));
+
+        let outgoing = peer_manager.create_peer(&actor_system, &"127.0.0.1:9732".parse().unwrap(), false);
+        let _ = peer_manager.create_peer(&actor_system, &"127.0.0.1:9733".parse().unwrap(), true);
+        assert_eq!(1, peer_manager.pending_handshakes(true));
+        assert_eq!(1, peer_manager.available_outgoing_handshakes());
+        assert_eq!(ConnectionDirections { incoming: 1, outgoing: 1 }, handshake_stats.read().unwrap().pending);
+
+        // bootstrapped peer does not count to the limit anymore
+        peer_manager.peers.get_mut(outgoing.uri()).unwrap().peer_id = Some(Arc::new(PeerId::new(outgoing.clone(), vec![1; 16], "127.0.0.1:9732".parse().unwrap())));
+        peer_manager.update_pending_handshakes_stats();
+        assert_eq!(2, peer_manager.available_outgoing_handshakes());
+        assert_eq!(ConnectionDirections { incoming: 1, outgoing: 0 }, handshake_stats.read().unwrap().pending);
+
+        peer_manager.record_pending_limit_reached(true);
+        assert_eq!(ConnectionDirections { incoming: 1, outgoing: 0 }, handshake_stats.read().unwrap().pending_limit_reached);
+    }
 }
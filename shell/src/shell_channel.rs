@@ -9,15 +9,17 @@ use std::sync::{Arc, RwLock};
 use getset::Getters;
 use riker::actors::*;
 
-use crypto::hash::{BlockHash, OperationHash, ProtocolHash};
+use crypto::hash::{BlockHash, ChainId, OperationHash, ProtocolHash};
 use storage::block_storage::BlockJsonData;
 use storage::BlockHeaderWithHash;
 use storage::mempool_storage::MempoolOperationType;
-use tezos_api::ffi::{ApplyBlockRequest, ValidateOperationResult};
 use tezos_messages::Head;
 use tezos_messages::p2p::encoding::block_header::Fitness;
 use tezos_messages::p2p::encoding::prelude::{BlockHeader, Operation, Path};
 
+use crate::mempool_result::MempoolValidationResult;
+use crate::stats::mempool::MempoolStats;
+
 /// Message informing actors about successful block application by protocol
 #[derive(Clone, Debug, Getters)]
 pub struct BlockApplied {
@@ -51,11 +53,68 @@ pub struct AllBlockOperationsReceived {
     pub level: i32,
 }
 
+/// Where the mempool operation came from
+#[derive(Clone, Debug, PartialEq)]
+pub enum MempoolOperationSource {
+    /// Injected by rpc
+    Rpc,
+    /// Received from the peer (peer id)
+    P2p(String),
+    /// Operation of the block, which is not on the main chain anymore
+    Reinjected,
+}
+
 // Notify actors that operations should by validated by mempool
 #[derive(Clone, Debug)]
 pub struct MempoolOperationReceived {
     pub operation_hash: OperationHash,
     pub operation_type: MempoolOperationType,
+    pub source: MempoolOperationSource,
+}
+
+/// Notify actors, that mempool operations were advertised to the peers in `CurrentHead`
+#[derive(Clone, Debug)]
+pub struct MempoolOperationsAdvertised {
+    pub operation_hashes: Vec<OperationHash>,
+    /// Count of the peers
+    pub peers: usize,
+}
+
+/// Notify mempool, that operation was banned (by rpc), so it should be removed from the mempool
+#[derive(Clone, Debug)]
+pub struct MempoolOperationBanned {
+    pub operation_hash: OperationHash,
+}
+
+/// Ask peers (by rpc) for their mempool operations, peers respond to `GetCurrentHead` with their current mempool
+#[derive(Clone, Debug)]
+pub struct RequestMempoolOperations {
+    /// Peer id (e.g. `idtqxHUjbjbCfaDn4jczoPGsnhacKX`), if none, all peers are asked
+    pub peer_id: Option<String>,
+}
+
+/// Message informing actors that current head was switched to another branch,
+/// so blocks above the fork point are no longer part of the main chain
+#[derive(Clone, Debug)]
+pub struct ChainReorganized {
+    /// Common ancestor of the previous and the new current head
+    pub fork_point: BlockHash,
+    /// Blocks removed from the main chain, ordered from the previous head down to the fork point (excluded)
+    pub removed: Vec<BlockHash>,
+    /// Blocks added to the main chain, ordered from the fork point (excluded) up to the new head
+    pub added: Vec<BlockHash>,
+    /// Operations from removed blocks (not included in added blocks), which were returned back to the mempool
+    pub reinjected_operations: Vec<OperationHash>,
+}
+
+/// Message informing actors that applied block forked a test chain
+#[derive(Clone, Debug)]
+pub struct TestChainForked {
+    /// Chain of the forking block
+    pub chain_id: ChainId,
+    pub test_chain_id: ChainId,
+    /// Applied block, which forked the test chain
+    pub forking_block_hash: BlockHash,
 }
 
 #[derive(Clone, Debug)]
@@ -63,9 +122,11 @@ pub struct CurrentMempoolState {
     pub head: Option<BlockHash>,
     pub protocol: Option<ProtocolHash>,
     pub fitness: Option<Fitness>,
-    pub result: ValidateOperationResult,
+    pub result: MempoolValidationResult,
     pub operations: HashMap<OperationHash, Operation>,
     pub pending: HashSet<OperationHash>,
+    /// Size of the mempool and counters of the dropped operations
+    pub stats: MempoolStats,
 }
 
 #[derive(Clone, Debug)]
@@ -80,13 +141,21 @@ pub struct InjectBlock {
 pub enum ShellChannelMsg {
     /// If chain_manager resolved new current head for chain
     NewCurrentHead(Head, BlockApplied),
+    /// If chain_manager switched current head to another branch, it is published before NewCurrentHead
+    ChainReorganized(ChainReorganized),
+    /// Chain_feeder propagates if applied block forked a test chain, it is published after BlockApplied
+    TestChainForked(TestChainForked),
     /// Chain_feeder propagates if block successfully validated and applied
     /// This is not the same as NewCurrentHead, not every applied block is set as NewCurrentHead (reorg - several headers on same level, duplicate header ...)
     BlockApplied(BlockApplied),
-    ApplyBlock(BlockHash, Arc<ApplyBlockRequest>),
+    /// Chain_manager resolved, that block can be applied, chain_feeder prepares data and applies it
+    ApplyBlock(BlockHash),
     BlockReceived(BlockReceived),
     AllBlockOperationsReceived(AllBlockOperationsReceived),
     MempoolOperationReceived(MempoolOperationReceived),
+    MempoolOperationsAdvertised(MempoolOperationsAdvertised),
+    MempoolOperationBanned(MempoolOperationBanned),
+    RequestMempoolOperations(RequestMempoolOperations),
     MempoolStateChanged(Arc<RwLock<CurrentMempoolState>>),
     InjectBlock(InjectBlock),
     ShuttingDown(ShuttingDown),
@@ -104,12 +173,42 @@ impl From<BlockApplied> for ShellChannelMsg {
     }
 }
 
+impl From<TestChainForked> for ShellChannelMsg {
+    fn from(msg: TestChainForked) -> Self {
+        ShellChannelMsg::TestChainForked(msg)
+    }
+}
+
+impl From<ChainReorganized> for ShellChannelMsg {
+    fn from(msg: ChainReorganized) -> Self {
+        ShellChannelMsg::ChainReorganized(msg)
+    }
+}
+
 impl From<MempoolOperationReceived> for ShellChannelMsg {
     fn from(msg: MempoolOperationReceived) -> Self {
         ShellChannelMsg::MempoolOperationReceived(msg)
     }
 }
 
+impl From<MempoolOperationsAdvertised> for ShellChannelMsg {
+    fn from(msg: MempoolOperationsAdvertised) -> Self {
+        ShellChannelMsg::MempoolOperationsAdvertised(msg)
+    }
+}
+
+impl From<MempoolOperationBanned> for ShellChannelMsg {
+    fn from(msg: MempoolOperationBanned) -> Self {
+        ShellChannelMsg::MempoolOperationBanned(msg)
+    }
+}
+
+impl From<RequestMempoolOperations> for ShellChannelMsg {
+    fn from(msg: RequestMempoolOperations) -> Self {
+        ShellChannelMsg::RequestMempoolOperations(msg)
+    }
+}
+
 impl From<CurrentMempoolState> for ShellChannelMsg {
     fn from(msg: CurrentMempoolState) -> Self {
         ShellChannelMsg::MempoolStateChanged(Arc::new(RwLock::new(msg)))
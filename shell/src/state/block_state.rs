@@ -18,6 +18,8 @@ use storage::block_meta_storage::Meta;
 use storage::chain_meta_storage::ChainMetaStorageReader;
 use storage::persistent::PersistentStorage;
 use tezos_messages::Head;
+use tezos_messages::protocol;
+use tezos_messages::p2p::binary_message::MessageHash;
 use tezos_messages::p2p::encoding::block_header::{BlockHeader, Level};
 use tezos_messages::p2p::encoding::current_branch::{CurrentBranchMessage, HISTORY_MAX_SIZE};
 use tezos_messages::p2p::encoding::prelude::CurrentHeadMessage;
@@ -26,6 +28,7 @@ use tezos_wrapper::service::{ProtocolController, ProtocolServiceError};
 use crate::collections::{BlockData, UniqueBlockData};
 use crate::shell_channel::{BlockApplied, CurrentMempoolState};
 use crate::validation;
+
 pub enum BlockAcceptanceResult {
     AcceptBlock,
     IgnoreBlock,
@@ -33,6 +36,17 @@ pub enum BlockAcceptanceResult {
     MutlipassValidationError(ProtocolServiceError),
 }
 
+/// Result of the [BlockchainState::prevalidate_block_header]
+#[derive(Debug)]
+pub enum BlockPrevalidationResult {
+    Valid,
+    /// Received block header itself is invalid
+    InvalidBlock(validation::BlockPrevalidationError),
+    /// Received block header is valid, but its already stored successor is not,
+    /// so the peer, which sent the block header, is not the one to blame
+    InvalidSuccessor(BlockHash, validation::BlockPrevalidationError),
+}
+
 /// Holds state of all known blocks
 pub struct BlockchainState {
     /// persistent block storage
@@ -49,6 +63,9 @@ pub struct BlockchainState {
     /// of the [`chain_manager`](crate::chain_manager::ChainManager) to return the block to this queue.
     missing_blocks: UniqueBlockData<MissingBlock>,
     chain_id: ChainId,
+    /// Trusted checkpoint (if configured), blocks below the checkpoint are not downloaded
+    /// and blocks contradicting the checkpoint are rejected
+    checkpoint: Option<Head>,
 }
 
 impl BlockchainState {
@@ -59,9 +76,66 @@ impl BlockchainState {
             chain_meta_storage: ChainMetaStorage::new(persistent_storage),
             missing_blocks: UniqueBlockData::new(),
             chain_id,
+            checkpoint: None,
         }
     }
 
+    #[inline]
+    pub fn get_checkpoint(&self) -> Option<&Head> {
+        self.checkpoint.as_ref()
+    }
+
+    /// Returns true, if ancestor of the block at the checkpoint level is not the checkpoint.
+    ///
+    /// Predecessors are walked through stored metadata down to the checkpoint level or to the first applied block
+    /// (applied blocks above the checkpoint are always built on the checkpoint). If some predecessor is not known yet,
+    /// block cannot be resolved now and is checked again, when its predecessor is downloaded.
+    pub fn contradicts_checkpoint(&self, block_hash: &BlockHash, block_header: &BlockHeader) -> Result<bool, StorageError> {
+        let checkpoint = match &self.checkpoint {
+            Some(checkpoint) => checkpoint,
+            None => return Ok(false),
+        };
+
+        let mut level = block_header.level();
+        if level < *checkpoint.level() {
+            return Ok(false);
+        }
+
+        let mut block_hash = block_hash.clone();
+        let mut predecessor = Some(block_header.predecessor().clone());
+        loop {
+            if level == *checkpoint.level() {
+                return Ok(block_hash != *checkpoint.block_hash());
+            }
+            match predecessor {
+                Some(predecessor_hash) => {
+                    block_hash = predecessor_hash;
+                    level -= 1;
+                    predecessor = match self.block_meta_storage.get(&block_hash)? {
+                        Some(meta) if meta.is_applied() && meta.level() >= *checkpoint.level() => return Ok(false),
+                        Some(meta) => meta.predecessor().clone(),
+                        None => None,
+                    };
+                }
+                None => return Ok(false),
+            }
+        }
+    }
+
+    /// Returns true, if level is below the checkpoint, so we are not interested in such blocks
+    #[inline]
+    fn is_below_checkpoint(&self, level: Level) -> bool {
+        match &self.checkpoint {
+            Some(checkpoint) => level < *checkpoint.level(),
+            None => false,
+        }
+    }
+
+    #[inline]
+    fn is_checkpoint(&self, block_hash: &BlockHash) -> bool {
+        self.checkpoint.as_ref().filter(|checkpoint| checkpoint.block_hash() == block_hash).is_some()
+    }
+
     /// Validate if we can accept branch
     pub fn can_accept_branch(&self, branch: &CurrentBranchMessage, current_head: &Option<Head>) -> bool {
         // validate chain which we operate on
@@ -73,6 +147,17 @@ impl BlockchainState {
             return false;
         }
 
+        // branch must not contradict checkpoint
+        let branch_head = branch.current_branch().current_head();
+        if self.is_below_checkpoint(branch_head.level()) {
+            return false;
+        }
+        if let Ok(branch_head_hash) = branch_head.message_hash() {
+            if let Ok(true) = self.contradicts_checkpoint(&branch_head_hash, branch_head) {
+                return false;
+            }
+        }
+
         if let Some(current_head) = current_head.as_ref() {
             // (only_if_fitness_increases) we can accept branch if increases fitness
             if validation::is_fitness_increases(current_head, branch.current_branch().current_head().fitness()) {
@@ -101,6 +186,11 @@ impl BlockchainState {
             return Ok(BlockAcceptanceResult::IgnoreBlock);
         }
 
+        // head must not contradict checkpoint
+        if self.is_below_checkpoint(validated_header.level()) || self.contradicts_checkpoint(&validated_header.message_hash()?, validated_header)? {
+            return Ok(BlockAcceptanceResult::IgnoreBlock);
+        }
+
         // we need our current head at first
         if let Some(current_head) = current_head.as_ref() {
             // (future block)
@@ -362,14 +452,68 @@ impl BlockchainState {
         Ok(Some((head, head_result)))
     }
 
+    /// Pre-validates received block header (see [validation::prevalidate_block_header]) against stored predecessor
+    /// and against stored successors, because headers are downloaded backwards during bootstrap.
+    pub fn prevalidate_block_header(&self, block_header: &BlockHeaderWithHash) -> Result<BlockPrevalidationResult, StorageError> {
+        // genesis is its own predecessor
+        let predecessor = if *block_header.header.predecessor() != block_header.hash {
+            self.block_storage.get(block_header.header.predecessor())?
+        } else {
+            None
+        };
+        let validation_passes = self.resolve_validation_passes(block_header.header.predecessor())?;
+        if let Err(error) = validation::prevalidate_block_header(&block_header.header, predecessor.as_ref().map(|predecessor| predecessor.header.as_ref()), validation_passes) {
+            return Ok(BlockPrevalidationResult::InvalidBlock(error));
+        }
+
+        if let Some(block_metadata) = self.block_meta_storage.get(&block_header.hash)? {
+            let validation_passes = self.resolve_validation_passes(&block_header.hash)?;
+            for successor in block_metadata.successors() {
+                if let Some(successor) = self.block_storage.get(successor)? {
+                    if let Err(error) = validation::prevalidate_block_header(&successor.header, Some(block_header.header.as_ref()), validation_passes) {
+                        return Ok(BlockPrevalidationResult::InvalidSuccessor(successor.hash, error));
+                    }
+                }
+            }
+        }
+
+        Ok(BlockPrevalidationResult::Valid)
+    }
+
+    /// Returns count of validation passes of the protocol for successors of the block,
+    /// which is known only if the block is already applied (`next_protocol` of the block)
+    fn resolve_validation_passes(&self, block_hash: &BlockHash) -> Result<Option<u8>, StorageError> {
+        match self.block_meta_storage.get(block_hash)? {
+            Some(meta) if meta.is_applied() => (),
+            _ => return Ok(None),
+        }
+
+        let json_data = match self.block_storage.get_with_json_data(block_hash)? {
+            Some((_, json_data)) => json_data,
+            None => return Ok(None),
+        };
+        let metadata: HashMap<String, serde_json::Value> = match serde_json::from_str(json_data.block_header_proto_metadata_json()) {
+            Ok(metadata) => metadata,
+            Err(_) => return Ok(None),
+        };
+        Ok(
+            metadata.get("next_protocol")
+                .and_then(|protocol_hash| protocol_hash.as_str())
+                .and_then(|protocol_hash| HashType::ProtocolHash.string_to_bytes(protocol_hash).ok())
+                .and_then(|protocol_hash| protocol::get_validation_passes(&protocol_hash))
+        )
+    }
+
     pub fn process_block_header(&mut self, block_header: &BlockHeaderWithHash, log: &Logger) -> Result<(Meta, bool), StorageError> {
-        // check if we already have seen predecessor
-        self.push_missing_block(
-            MissingBlock::with_level_guess(
-                block_header.header.predecessor().clone(),
-                block_header.header.level() - 1,
-            )
-        )?;
+        // check if we already have seen predecessor (we dont need blocks below checkpoint)
+        if !self.is_below_checkpoint(block_header.header.level() - 1) && !self.is_checkpoint(&block_header.hash) {
+            self.push_missing_block(
+                MissingBlock::with_level_guess(
+                    block_header.header.predecessor().clone(),
+                    block_header.header.level() - 1,
+                )
+            )?;
+        }
 
         // store block
         let is_new_block = self.block_storage.put_block_header(block_header)?;
@@ -434,9 +578,11 @@ impl BlockchainState {
     }
 
     pub fn hydrate(&mut self) -> Result<(), StorageError> {
+        self.checkpoint = self.chain_meta_storage.get_checkpoint(&self.chain_id)?;
+
         for (key, value) in self.block_meta_storage.iter(IteratorMode::Start)? {
             let (block_hash, meta) = (key?, value?);
-            if meta.predecessor().is_none() && (meta.chain_id() == &self.chain_id) {
+            if meta.predecessor().is_none() && (meta.chain_id() == &self.chain_id) && !self.is_below_checkpoint(meta.level()) {
                 self.missing_blocks.push(
                     MissingBlock::with_level(
                         block_hash,
@@ -464,6 +610,64 @@ impl BlockchainState {
         )
     }
 
+    /// Resolves chain reorganization between previous and new current head.
+    ///
+    /// Returns None, if heads are not on the different branches or if common ancestor cannot be resolved from stored metadata
+    pub fn find_reorganization(&self, previous_head: &BlockHash, new_head: &BlockHash) -> Result<Option<Reorganization>, StorageError> {
+        Self::compute_reorganization(&self.block_meta_storage, previous_head, new_head)
+    }
+
+    /// Walks predecessors of both heads down to the fork point (common ancestor)
+    fn compute_reorganization(block_meta_storage: &BlockMetaStorage, previous_head: &BlockHash, new_head: &BlockHash) -> Result<Option<Reorganization>, StorageError> {
+        let (mut previous_block, mut previous_meta) = match block_meta_storage.get(previous_head)? {
+            Some(meta) => (previous_head.clone(), meta),
+            None => return Ok(None),
+        };
+        let (mut new_block, mut new_meta) = match block_meta_storage.get(new_head)? {
+            Some(meta) => (new_head.clone(), meta),
+            None => return Ok(None),
+        };
+
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+
+        while previous_block != new_block {
+            // always step back the higher block, so both walks meet at the fork point
+            let (block, meta, walked) = if previous_meta.level() >= new_meta.level() {
+                (&mut previous_block, &mut previous_meta, &mut removed)
+            } else {
+                (&mut new_block, &mut new_meta, &mut added)
+            };
+
+            let predecessor = match meta.predecessor() {
+                // genesis is predecessor of itself
+                Some(predecessor) if *predecessor != *block => predecessor.clone(),
+                _ => return Ok(None),
+            };
+            let predecessor_meta = match block_meta_storage.get(&predecessor)? {
+                Some(predecessor_meta) => predecessor_meta,
+                None => return Ok(None),
+            };
+
+            walked.push(std::mem::replace(block, predecessor));
+            *meta = predecessor_meta;
+        }
+
+        // simple head increment is not a reorganization
+        if removed.is_empty() {
+            return Ok(None);
+        }
+
+        // added blocks are ordered from the fork point to the new head
+        added.reverse();
+
+        Ok(Some(Reorganization {
+            fork_point: previous_block,
+            removed,
+            added,
+        }))
+    }
+
     /// Resulted history is sorted: "from oldest block to newest"
     fn compute_history(block_meta_storage: &BlockMetaStorage, caboose: Option<Head>, head: &BlockHash, max_size: u8, seed: &Seed) -> Result<Vec<BlockHash>, StorageError> {
         if max_size == 0 {
@@ -622,6 +826,17 @@ impl Ord for MissingBlock {
     }
 }
 
+/// Blocks removed from and added to the main chain, when current head is switched to another branch
+#[derive(Clone, Debug)]
+pub struct Reorganization {
+    /// Common ancestor of the previous and the new current head
+    pub fork_point: BlockHash,
+    /// Blocks removed from the main chain, ordered from the previous head down to the fork point (excluded)
+    pub removed: Vec<BlockHash>,
+    /// Blocks added to the main chain, ordered from the fork point (excluded) up to the new head
+    pub added: Vec<BlockHash>,
+}
+
 pub enum HeadResult {
     BranchSwitch,
     HeadIncrement,
@@ -641,6 +856,7 @@ mod tests {
     use slog::{Drain, Level, Logger};
 
     use crypto::hash::chain_id_from_block_hash;
+    use storage::{BlockAdditionalDataBuilder, BlockJsonDataBuilder};
     use storage::tests_common::TmpStorage;
 
     use super::*;
@@ -873,6 +1089,118 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_compute_reorganization() -> Result<(), failure::Error> {
+        let log = create_logger(Level::Debug);
+        let storage = TmpStorage::create_to_out_dir("__test_compute_reorganization")?;
+        let block_meta_storage = BlockMetaStorage::new(storage.storage());
+        let block_storage = BlockStorage::new(storage.storage());
+
+        /*
+         * Genesis - A1 - A2 - A3 - A4 - A5 - A6 - A7 - A8
+         *                      \
+         *                       B1 - B2 - B3 - B4 - B5 - B6 - B7 - B8
+         */
+        let blocksdb = data::init_blocks();
+
+        // init with genesis
+        let (genesis_hash, genesis_header) = (blocksdb.block_hash("Genesis"), blocksdb.header("Genesis"));
+        let chain_id = chain_id_from_block_hash(&genesis_hash);
+        block_storage.put_block_header(&genesis_header)?;
+        block_meta_storage.put(&genesis_hash, &Meta::genesis_meta(&genesis_hash, &chain_id, true))?;
+
+        data::store_branch(&vec!["A1", "A2", "A3", "A4", "A5", "A6", "A7", "A8"],
+                           &chain_id, &blocksdb, &block_storage, &block_meta_storage, &log);
+        data::store_branch(&vec!["B1", "B2", "B3", "B4", "B5", "B6", "B7", "B8"],
+                           &chain_id, &blocksdb, &block_storage, &block_meta_storage, &log);
+
+        // switch from A8 to B8
+        let reorg = BlockchainState::compute_reorganization(&block_meta_storage, &blocksdb.block_hash("A8"), &blocksdb.block_hash("B8"))?
+            .expect("reorganization not found");
+        assert_eq!("A3", blocksdb.name(&reorg.fork_point));
+        data::assert_history(&["A8", "A7", "A6", "A5", "A4"], &blocksdb, reorg.removed);
+        data::assert_history(&["B1", "B2", "B3", "B4", "B5", "B6", "B7", "B8"], &blocksdb, reorg.added);
+
+        // switch from B2 to lower A6
+        let reorg = BlockchainState::compute_reorganization(&block_meta_storage, &blocksdb.block_hash("B2"), &blocksdb.block_hash("A6"))?
+            .expect("reorganization not found");
+        assert_eq!("A3", blocksdb.name(&reorg.fork_point));
+        data::assert_history(&["B2", "B1"], &blocksdb, reorg.removed);
+        data::assert_history(&["A4", "A5", "A6"], &blocksdb, reorg.added);
+
+        // head increment is not reorganization
+        assert!(BlockchainState::compute_reorganization(&block_meta_storage, &blocksdb.block_hash("A3"), &blocksdb.block_hash("B2"))?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bootstrap_from_checkpoint() -> Result<(), failure::Error> {
+        let log = create_logger(Level::Debug);
+        let storage = TmpStorage::create_to_out_dir("__test_bootstrap_from_checkpoint")?;
+        let blocksdb = data::init_blocks();
+        let chain_id = chain_id_from_block_hash(&blocksdb.block_hash("Genesis"));
+        let block_storage = BlockStorage::new(storage.storage());
+        let block_meta_storage = BlockMetaStorage::new(storage.storage());
+        let chain_meta_storage = ChainMetaStorage::new(storage.storage());
+
+        // checkpoint is A4, which is not imported yet
+        let checkpoint = Head::new(blocksdb.block_hash("A4"), 4, vec![]);
+        storage::initialize_checkpoint(&block_storage, &block_meta_storage, &chain_meta_storage, &chain_id, &checkpoint, &log)?;
+
+        let mut chain_state = BlockchainState::new(storage.storage(), chain_id.clone());
+        chain_state.hydrate()?;
+        assert!(chain_state.get_checkpoint().is_some());
+
+        // B1 is at the checkpoint level, B2 is built on B1
+        assert!(!chain_state.contradicts_checkpoint(&blocksdb.block_hash("A4"), &blocksdb.header("A4").header)?);
+        assert!(!chain_state.contradicts_checkpoint(&blocksdb.block_hash("A5"), &blocksdb.header("A5").header)?);
+        assert!(chain_state.contradicts_checkpoint(&blocksdb.block_hash("B1"), &blocksdb.header("B1").header)?);
+        assert!(chain_state.contradicts_checkpoint(&blocksdb.block_hash("B2"), &blocksdb.header("B2").header)?);
+
+        // B3 cannot be resolved without B2, but it is rejected, when B2 is known
+        assert!(!chain_state.contradicts_checkpoint(&blocksdb.block_hash("B3"), &blocksdb.header("B3").header)?);
+        chain_state.process_block_header(&blocksdb.header("B2"), &log)?;
+        assert!(chain_state.contradicts_checkpoint(&blocksdb.block_hash("B3"), &blocksdb.header("B3").header)?);
+        let missing_blocks_count = chain_state.missing_blocks_count();
+
+        // A5 schedules checkpoint as missing predecessor
+        chain_state.process_block_header(&blocksdb.header("A5"), &log)?;
+        assert_eq!(missing_blocks_count + 1, chain_state.missing_blocks_count());
+
+        // checkpoint does not schedule its predecessor, but it is not applied without imported metadata
+        let (a4_meta, _) = chain_state.process_block_header(&blocksdb.header("A4"), &log)?;
+        assert_eq!(missing_blocks_count + 1, chain_state.missing_blocks_count());
+        assert!(!a4_meta.is_applied());
+        let a5_meta = block_meta_storage.get(&blocksdb.block_hash("A5"))?.unwrap();
+        assert!(!chain_state.can_apply_block((&blocksdb.block_hash("A5"), &a5_meta), |_| Ok(true))?);
+
+        // import checkpoint metadata (e.g. from snapshot)
+        block_storage.put_block_json_data(
+            &blocksdb.block_hash("A4"),
+            BlockJsonDataBuilder::default()
+                .block_header_proto_json("{}".to_string())
+                .block_header_proto_metadata_json("{}".to_string())
+                .operations_proto_metadata_json("[]".to_string())
+                .build().unwrap(),
+        )?;
+        block_storage.put_block_additional_data(
+            &blocksdb.block_hash("A4"),
+            BlockAdditionalDataBuilder::default()
+                .max_operations_ttl(120)
+                .last_allowed_fork_level(0)
+                .build().unwrap(),
+        )?;
+        storage::initialize_checkpoint(&block_storage, &block_meta_storage, &chain_meta_storage, &chain_id, &checkpoint, &log)?;
+        assert!(block_meta_storage.get(&blocksdb.block_hash("A4"))?.unwrap().is_applied());
+        assert_eq!(120, block_storage.get_with_additional_data(&blocksdb.block_hash("A4"))?.unwrap().1.max_operations_ttl());
+
+        // so A5 can be applied on top of the checkpoint
+        assert!(chain_state.can_apply_block((&blocksdb.block_hash("A5"), &a5_meta), |_| Ok(true))?);
+
+        Ok(())
+    }
+
     fn create_logger(level: Level) -> Logger {
         let drain = slog_async::Async::new(
             slog_term::FullFormat::new(
@@ -0,0 +1,238 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Per-peer bookkeeping for the download scheduler of block headers and block operations.
+//!
+//! Each peer keeps its in-flight requests together with the time they were sent.
+//! From response times we measure peer throughput, which resolves how many requests
+//! can be in-flight for the peer, so fast peers get more work and slow peers do not block bootstrap.
+//! Requests, which are not answered on time, are returned back to the missing queue and rescheduled to other peers,
+//! but late responses are still accepted, because they carry valid data.
+//! Current time is always passed by the caller (see [crate::clock]), so timeouts can be simulated.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crypto::hash::BlockHash;
+
+use crate::collections::BlockData;
+
+/// Count of in-flight requests for a peer, which was not measured yet
+pub const INITIAL_IN_FLIGHT_REQUESTS: usize = 10;
+/// Minimal count of in-flight requests for a (slow) peer
+pub const MIN_IN_FLIGHT_REQUESTS: usize = 1;
+/// Maximal count of in-flight requests for a (fast) peer
+pub const MAX_IN_FLIGHT_REQUESTS: usize = 50;
+/// In-flight requests of the peer should cover this time window, so the peer is never idle
+const IN_FLIGHT_WINDOW: Duration = Duration::from_secs(2);
+/// After this time request is considered as timed out and it is rescheduled to other peers
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// After request timeout, no new requests are assigned to the peer for this duration
+const REQUEST_TIMEOUT_BACKOFF: Duration = Duration::from_secs(15);
+/// Late responses are remembered only for limited count of timed out requests
+const MAX_TIMED_OUT_REQUESTS: usize = 1024;
+
+/// Measured throughput of the peer
+#[derive(Clone, Debug)]
+pub struct PeerThroughput {
+    /// Exponential moving average of the response time, None if not measured yet
+    average_response_time: Option<Duration>,
+    /// Last time a request to the peer timed out
+    timeout_last: Option<Instant>,
+}
+
+impl PeerThroughput {
+    pub fn new() -> Self {
+        PeerThroughput {
+            average_response_time: None,
+            timeout_last: None,
+        }
+    }
+
+    /// Records response to the request sent at `requested_at`
+    pub fn record_response(&mut self, requested_at: Instant, now: Instant) {
+        self.record_response_time(now.saturating_duration_since(requested_at))
+    }
+
+    /// Records timed out request, which also penalizes average response time
+    pub fn record_timeout(&mut self, now: Instant) {
+        self.timeout_last = Some(now);
+        self.record_response_time(REQUEST_TIMEOUT);
+    }
+
+    fn record_response_time(&mut self, response_time: Duration) {
+        self.average_response_time = Some(match self.average_response_time {
+            Some(average) => (average * 4 + response_time) / 5,
+            None => response_time,
+        });
+    }
+
+    /// Average response time of the peer
+    pub fn average_response_time(&self) -> Option<Duration> {
+        self.average_response_time
+    }
+
+    /// Expected response time of the peer, lower means higher throughput.
+    /// Not measured peer is expected to fill the in-flight window with initial count of requests.
+    pub fn expected_response_time(&self) -> Duration {
+        self.average_response_time.unwrap_or(IN_FLIGHT_WINDOW / INITIAL_IN_FLIGHT_REQUESTS as u32)
+    }
+
+    /// Resolves how many requests can be in-flight for the peer
+    pub fn target_in_flight(&self, now: Instant) -> usize {
+        if let Some(timeout_last) = self.timeout_last {
+            if now.saturating_duration_since(timeout_last) < REQUEST_TIMEOUT_BACKOFF {
+                // let other peers retry timed out requests
+                return 0;
+            }
+        }
+
+        match self.average_response_time {
+            Some(average) => {
+                let average_millis = cmp::max(1, average.as_millis());
+                let target = IN_FLIGHT_WINDOW.as_millis() / average_millis;
+                cmp::min(cmp::max(target as usize, MIN_IN_FLIGHT_REQUESTS), MAX_IN_FLIGHT_REQUESTS)
+            }
+            None => INITIAL_IN_FLIGHT_REQUESTS,
+        }
+    }
+}
+
+/// Requests sent to the peer, which are waiting for response
+pub(crate) struct InFlightRequests<T> {
+    requests: HashMap<BlockHash, (T, Instant)>,
+    /// Requests removed because of timeout, so late responses are still processed and not handled as unexpected
+    timed_out: HashSet<BlockHash>,
+}
+
+impl<T: BlockData> InFlightRequests<T> {
+    pub(crate) fn new() -> Self {
+        InFlightRequests {
+            requests: HashMap::new(),
+            timed_out: HashSet::new(),
+        }
+    }
+
+    /// Returns true, if request was not already in-flight
+    pub(crate) fn insert(&mut self, request: T, now: Instant) -> bool {
+        let block_hash = request.block_hash().clone();
+        self.timed_out.remove(&block_hash);
+        if self.requests.contains_key(&block_hash) {
+            false
+        } else {
+            self.requests.insert(block_hash, (request, now));
+            true
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, block_hash: &BlockHash) -> Option<&mut (T, Instant)> {
+        self.requests.get_mut(block_hash)
+    }
+
+    pub(crate) fn remove(&mut self, block_hash: &BlockHash) -> Option<(T, Instant)> {
+        self.requests.remove(block_hash)
+    }
+
+    /// Returns true, if the response is for request, which was already rescheduled because of timeout
+    pub(crate) fn is_late_response(&self, block_hash: &BlockHash) -> bool {
+        self.timed_out.contains(block_hash)
+    }
+
+    /// Removes and returns all requests which were not responded within [REQUEST_TIMEOUT]
+    pub(crate) fn drain_timed_out(&mut self, now: Instant) -> Vec<T> {
+        let timed_out_hashes = self.requests.iter()
+            .filter(|(_, (_, requested_at))| now.saturating_duration_since(*requested_at) > REQUEST_TIMEOUT)
+            .map(|(block_hash, _)| block_hash.clone())
+            .collect::<Vec<_>>();
+
+        if self.timed_out.len() + timed_out_hashes.len() > MAX_TIMED_OUT_REQUESTS {
+            self.timed_out.clear();
+        }
+
+        timed_out_hashes.into_iter()
+            .filter_map(|block_hash| {
+                self.timed_out.insert(block_hash.clone());
+                self.requests.remove(&block_hash).map(|(request, _)| request)
+            })
+            .collect()
+    }
+
+    /// Removes and returns all in-flight requests
+    pub(crate) fn drain(&mut self) -> Vec<T> {
+        self.timed_out.clear();
+        self.requests.drain().map(|(_, (request, _))| request).collect()
+    }
+
+    /// How many new requests can be sent, so in-flight requests reach target
+    pub(crate) fn available_capacity(&self, target_in_flight: usize) -> usize {
+        target_in_flight.saturating_sub(self.requests.len())
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.requests.clear();
+        self.timed_out.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::block_state::MissingBlock;
+
+    use super::*;
+
+    #[test]
+    fn test_target_in_flight() {
+        let now = Instant::now();
+        let mut throughput = PeerThroughput::new();
+        assert_eq!(INITIAL_IN_FLIGHT_REQUESTS, throughput.target_in_flight(now));
+
+        // fast peer
+        throughput.record_response(now, now);
+        assert_eq!(MAX_IN_FLIGHT_REQUESTS, throughput.target_in_flight(now));
+
+        // slow peer
+        let mut throughput = PeerThroughput::new();
+        throughput.record_response(now, now + Duration::from_secs(5));
+        assert_eq!(MIN_IN_FLIGHT_REQUESTS, throughput.target_in_flight(now));
+        assert!(throughput.expected_response_time() > PeerThroughput::new().expected_response_time());
+
+        // timed out peer gets nothing during backoff
+        let mut throughput = PeerThroughput::new();
+        throughput.record_timeout(now);
+        assert_eq!(0, throughput.target_in_flight(now));
+        assert_eq!(0, throughput.target_in_flight(now + REQUEST_TIMEOUT_BACKOFF - Duration::from_secs(1)));
+        assert!(throughput.target_in_flight(now + REQUEST_TIMEOUT_BACKOFF) > 0);
+    }
+
+    #[test]
+    fn test_in_flight_requests_timeout() {
+        let now = Instant::now();
+        let mut requests = InFlightRequests::new();
+        assert!(requests.insert(MissingBlock::with_level(vec![1; 32], 1), now));
+        assert!(!requests.insert(MissingBlock::with_level(vec![1; 32], 1), now));
+        assert!(requests.insert(MissingBlock::with_level(vec![2; 32], 2), now + REQUEST_TIMEOUT));
+        assert_eq!(8, requests.available_capacity(10));
+
+        // nothing timed out yet
+        assert!(requests.drain_timed_out(now + REQUEST_TIMEOUT).is_empty());
+
+        // first request is old enough
+        let timed_out = requests.drain_timed_out(now + REQUEST_TIMEOUT + Duration::from_secs(1));
+        assert_eq!(1, timed_out.len());
+        assert_eq!(vec![1; 32], timed_out[0].block_hash);
+        assert_eq!(1, requests.len());
+        assert!(requests.is_late_response(&vec![1; 32]));
+        assert!(!requests.is_late_response(&vec![2; 32]));
+    }
+}
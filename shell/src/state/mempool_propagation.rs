@@ -0,0 +1,151 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Propagation of the mempool operations to the peers.
+//!
+//! Every peer remembers operations it already knows (advertised by the peer in `CurrentHead`, downloaded from the peer,
+//! or announced to the peer by us), so just the unknown operations are announced.
+//! Announcements are batched and sent to a limited count of peers at once, see `ChainManager`.
+//!
+//! [MempoolPropagation] measures, how long it takes to announce an operation after it appears in the mempool.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crypto::hash::OperationHash;
+
+/// Operations already known by the peer, they are not announced to the peer again
+#[derive(Clone, Debug, Default)]
+pub struct KnownOperations {
+    operations: HashSet<OperationHash>,
+}
+
+impl KnownOperations {
+    pub fn insert(&mut self, operation_hash: OperationHash) {
+        self.operations.insert(operation_hash);
+    }
+
+    pub fn extend<'a>(&mut self, operation_hashes: impl IntoIterator<Item=&'a OperationHash>) {
+        self.operations.extend(operation_hashes.into_iter().cloned());
+    }
+
+    /// Returns operations unknown to the peer
+    pub fn unknown(&self, operation_hashes: &[OperationHash]) -> Vec<OperationHash> {
+        operation_hashes.iter()
+            .filter(|operation_hash| !self.operations.contains(*operation_hash))
+            .cloned()
+            .collect()
+    }
+
+    /// Forgets operations, which are not relevant anymore (e.g. operations included in the block)
+    pub fn retain<F: Fn(&OperationHash) -> bool>(&mut self, keep: F) {
+        self.operations.retain(|operation_hash| keep(operation_hash))
+    }
+
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+}
+
+/// Measures latency of the propagation of the mempool operations
+#[derive(Clone, Debug, Default)]
+pub struct MempoolPropagation {
+    /// Operations of the mempool, with the time they appeared in the mempool, if they were not announced yet
+    operations: HashMap<OperationHash, Option<Instant>>,
+    /// Count of operations announced at least to one peer
+    announced_count: usize,
+    /// Count of the announcements (to one peer) skipped, because the peer already knew the operation
+    skipped_known_count: usize,
+    latency_total: Duration,
+    latency_max: Duration,
+}
+
+impl MempoolPropagation {
+    /// Registers new operations of the mempool, operations removed from the mempool are forgotten
+    pub fn mempool_changed(&mut self, mempool_operations: &HashSet<OperationHash>, now: Instant) {
+        self.operations.retain(|operation_hash, _| mempool_operations.contains(operation_hash));
+        for operation_hash in mempool_operations {
+            self.operations.entry(operation_hash.clone()).or_insert(Some(now));
+        }
+    }
+
+    /// Records announcement of the operations, latency is measured just for the first announcement
+    pub fn announced(&mut self, operation_hashes: &HashSet<OperationHash>, now: Instant) {
+        for operation_hash in operation_hashes {
+            if let Some(first_seen) = self.operations.get_mut(operation_hash).and_then(Option::take) {
+                let latency = now.saturating_duration_since(first_seen);
+                self.announced_count += 1;
+                self.latency_total += latency;
+                self.latency_max = self.latency_max.max(latency);
+            }
+        }
+    }
+
+    pub fn skipped_known(&mut self, count: usize) {
+        self.skipped_known_count += count;
+    }
+
+    pub fn announced_count(&self) -> usize {
+        self.announced_count
+    }
+
+    pub fn skipped_known_count(&self) -> usize {
+        self.skipped_known_count
+    }
+
+    /// Count of the mempool operations waiting for the first announcement
+    pub fn unannounced_count(&self) -> usize {
+        self.operations.values().filter(|first_seen| first_seen.is_some()).count()
+    }
+
+    pub fn average_latency(&self) -> Option<Duration> {
+        if self.announced_count > 0 {
+            Some(self.latency_total / self.announced_count as u32)
+        } else {
+            None
+        }
+    }
+
+    pub fn max_latency(&self) -> Duration {
+        self.latency_max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_operations() {
+        let mut known = KnownOperations::default();
+        known.extend(&[vec![1], vec![2]]);
+        assert_eq!(vec![vec![3]], known.unknown(&[vec![1], vec![3]]));
+
+        known.retain(|operation_hash| operation_hash != &vec![1]);
+        assert_eq!(vec![vec![1]], known.unknown(&[vec![1], vec![2]]));
+        assert_eq!(1, known.len());
+    }
+
+    #[test]
+    fn test_propagation_latency() {
+        let start = Instant::now();
+        let mut propagation = MempoolPropagation::default();
+        propagation.mempool_changed(&[vec![1], vec![2]].iter().cloned().collect(), start);
+        assert_eq!(2, propagation.unannounced_count());
+        assert!(propagation.average_latency().is_none());
+
+        propagation.announced(&[vec![1]].iter().cloned().collect(), start + Duration::from_millis(100));
+        // repeated announcement is not measured
+        propagation.announced(&[vec![1]].iter().cloned().collect(), start + Duration::from_millis(500));
+        propagation.announced(&[vec![2]].iter().cloned().collect(), start + Duration::from_millis(300));
+        assert_eq!(2, propagation.announced_count());
+        assert_eq!(0, propagation.unannounced_count());
+        assert_eq!(Some(Duration::from_millis(200)), propagation.average_latency());
+        assert_eq!(Duration::from_millis(300), propagation.max_latency());
+
+        // operation removed from the mempool is forgotten, so it is measured again, when it is back
+        propagation.mempool_changed(&[vec![2]].iter().cloned().collect(), start);
+        propagation.mempool_changed(&[vec![1], vec![2]].iter().cloned().collect(), start);
+        assert_eq!(1, propagation.unannounced_count());
+    }
+}
@@ -2,4 +2,9 @@
 // SPDX-License-Identifier: MIT
 
 pub mod block_state;
+pub mod download_scheduler;
+pub mod mempool_propagation;
 pub mod operations_state;
+pub mod prioritized_operations;
+pub mod refused_operations;
+pub mod test_chain;
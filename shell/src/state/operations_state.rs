@@ -3,10 +3,12 @@
 
 use std::cmp;
 use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
+use std::sync::Arc;
 
 use crypto::hash::{BlockHash, ChainId};
+use networking::PeerId;
 use storage::{BlockHeaderWithHash, IteratorMode, OperationsMetaStorage, OperationsStorage, StorageError};
 use storage::persistent::PersistentStorage;
 use tezos_messages::p2p::encoding::prelude::*;
@@ -17,15 +19,21 @@ pub struct OperationsState {
     operations_storage: OperationsStorage,
     operations_meta_storage: OperationsMetaStorage,
     missing_operations_for_blocks: UniqueBlockData<MissingOperations>,
+    /// Operations received before block header, so they cannot be validated against operations_hash yet
+    unvalidated_operations: HashMap<BlockHash, Vec<(Arc<PeerId>, OperationsForBlocksMessage)>>,
     chain_id: ChainId,
 }
 
+/// Maximal count of blocks, whose operations wait for block header
+const MAX_UNVALIDATED_OPERATIONS_BLOCKS: usize = 512;
+
 impl OperationsState {
     pub fn new(persistent_storage: &PersistentStorage, chain_id: ChainId) -> Self {
         OperationsState {
             operations_storage: OperationsStorage::new(persistent_storage),
             operations_meta_storage: OperationsMetaStorage::new(persistent_storage),
             missing_operations_for_blocks: UniqueBlockData::new(),
+            unvalidated_operations: HashMap::new(),
             chain_id,
         }
     }
@@ -85,6 +93,25 @@ impl OperationsState {
         self.operations_meta_storage.put_operations(message)
     }
 
+    /// Keeps operations received before block header, they are validated, when block header arrives.
+    ///
+    /// Returns false, if operations were dropped, because too many blocks are waiting for header
+    pub fn add_unvalidated_operations(&mut self, peer_id: Arc<PeerId>, message: OperationsForBlocksMessage) -> bool {
+        let block_hash = message.operations_for_block().hash();
+        if !self.unvalidated_operations.contains_key(block_hash) && self.unvalidated_operations.len() >= MAX_UNVALIDATED_OPERATIONS_BLOCKS {
+            return false;
+        }
+        self.unvalidated_operations.entry(block_hash.clone())
+            .or_insert_with(Vec::new)
+            .push((peer_id, message));
+        true
+    }
+
+    /// Takes operations received before block header (together with the peer, which sent them)
+    pub fn take_unvalidated_operations(&mut self, block_hash: &BlockHash) -> Vec<(Arc<PeerId>, OperationsForBlocksMessage)> {
+        self.unvalidated_operations.remove(block_hash).unwrap_or_default()
+    }
+
     pub fn drain_missing_block_operations(&mut self, n: usize, level_max: i32) -> Vec<MissingOperations> {
         (0..cmp::min(self.missing_operations_for_blocks.len(), n))
             .filter_map(|_| {
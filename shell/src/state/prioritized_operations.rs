@@ -0,0 +1,520 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Bounded set of the mempool operations ordered by priority.
+//!
+//! Operations are ordered by validation pass (consensus operations first) and then by the fee relative to the operation cost.
+//! When the limits of the mempool are reached, operations with the lowest priority are evicted,
+//! received operation is rejected, if it does not have higher priority than the operations, which would have to be evicted.
+//!
+//! Before validation, priority is estimated from the binary data of the operation (tag of the first content),
+//! after successful validation it is resolved from the json of the operation provided by the protocol.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeSet, HashMap};
+
+use crypto::hash::OperationHash;
+use tezos_messages::p2p::encoding::prelude::Operation;
+
+use crate::configuration::MempoolConfiguration;
+use crate::stats::mempool::{DropReason, MempoolStats};
+
+/// Validation pass of the consensus operations (endorsements)
+const CONSENSUS_VALIDATION_PASS: u8 = 0;
+/// Validation pass of the voting operations (proposals, ballots)
+const VOTING_VALIDATION_PASS: u8 = 1;
+/// Validation pass of the anonymous operations (nonce revelations, denunciations, activations)
+const ANONYMOUS_VALIDATION_PASS: u8 = 2;
+/// Validation pass of the manager operations (reveals, transactions, originations, delegations)
+const MANAGER_VALIDATION_PASS: u8 = 3;
+
+/// Default minimal fees of the baker, used as a cost of the operation
+const MINIMAL_NANOTEZ_PER_GAS_UNIT: u128 = 100;
+const MINIMAL_NANOTEZ_PER_BYTE: u128 = 1000;
+/// Fee ratio is stored in thousandths
+const FEE_RATIO_SCALE: u128 = 1000;
+
+/// Tags of the manager operations in the binary encoding since protocol 005 (reveal, transaction, origination, delegation)
+const MANAGER_OPERATION_TAGS: std::ops::RangeInclusive<u8> = 107..=110;
+/// Size of the source (public key hash) of the manager operation since protocol 005
+const MANAGER_OPERATION_SOURCE_SIZE: usize = 21;
+
+/// Priority of the operation in the mempool, greater is better.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OperationPriority {
+    validation_pass: u8,
+    /// Fee relative to the minimal fee for the gas and size of the operation, in thousandths (1000 means exactly minimal fee)
+    fee_ratio: u64,
+}
+
+impl OperationPriority {
+    pub fn new(validation_pass: u8, fee_ratio: u64) -> Self {
+        OperationPriority { validation_pass, fee_ratio }
+    }
+
+    pub fn validation_pass(&self) -> u8 {
+        self.validation_pass
+    }
+
+    /// Estimates priority of not yet validated operation.
+    ///
+    /// Fee is resolved only for manager operations encoded by protocols 005+, and only from the first content of the batch.
+    pub fn estimate(operation: &Operation) -> Self {
+        let data = operation.data();
+        match data.first().copied() {
+            // endorsement, endorsement_with_slot
+            Some(0) | Some(10) => OperationPriority::new(CONSENSUS_VALIDATION_PASS, 0),
+            Some(5) | Some(6) => OperationPriority::new(VOTING_VALIDATION_PASS, 0),
+            Some(1..=4) => OperationPriority::new(ANONYMOUS_VALIDATION_PASS, 0),
+            Some(tag) if MANAGER_OPERATION_TAGS.contains(&tag) => {
+                let fee_ratio = parse_manager_fee_and_gas(&data[1..])
+                    .map(|(fee, gas_limit)| fee_ratio(fee, gas_limit, operation_size(operation)))
+                    .unwrap_or(0);
+                OperationPriority::new(MANAGER_VALIDATION_PASS, fee_ratio)
+            }
+            _ => OperationPriority::new(MANAGER_VALIDATION_PASS, 0),
+        }
+    }
+
+    /// Resolves priority of the validated operation, see [OperationFees::from_protocol_data_json]
+    pub fn from_fees(fees: &OperationFees, size: usize) -> Self {
+        if fees.is_manager_operation() {
+            OperationPriority::new(fees.validation_pass, fee_ratio(fees.fee, fees.gas_limit, size))
+        } else {
+            OperationPriority::new(fees.validation_pass, 0)
+        }
+    }
+}
+
+/// Validation pass and fees of the validated operation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OperationFees {
+    pub validation_pass: u8,
+    /// Fee of the whole batch in mutez (zero for non-manager operations)
+    pub fee: u64,
+    /// Gas limit of the whole batch (zero for non-manager operations)
+    pub gas_limit: u64,
+}
+
+impl OperationFees {
+    /// Resolves fees from the json of the validated operation (e.g. `{"contents": [{"kind": "transaction", "fee": "1420", "gas_limit": "10307", ...}], ...}`)
+    pub fn from_protocol_data_json(protocol_data_json: &str) -> Option<Self> {
+        let protocol_data: serde_json::Value = serde_json::from_str(protocol_data_json).ok()?;
+        let contents = protocol_data.get("contents")?.as_array()?;
+        let kind = contents.first()?.get("kind")?.as_str()?;
+
+        let validation_pass = match kind {
+            "endorsement" | "endorsement_with_slot" => CONSENSUS_VALIDATION_PASS,
+            "proposals" | "ballot" => VOTING_VALIDATION_PASS,
+            "seed_nonce_revelation" | "double_endorsement_evidence" | "double_baking_evidence" | "activate_account" => ANONYMOUS_VALIDATION_PASS,
+            _ => MANAGER_VALIDATION_PASS,
+        };
+        if validation_pass != MANAGER_VALIDATION_PASS {
+            return Some(OperationFees { validation_pass, fee: 0, gas_limit: 0 });
+        }
+
+        // fee and gas of the whole batch
+        let number = |content: &serde_json::Value, field: &str| content.get(field)
+            .and_then(|value| value.as_str())
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(0);
+        let (fee, gas_limit) = contents.iter()
+            .fold((0u64, 0u64), |(fee, gas_limit), content| {
+                (fee.saturating_add(number(content, "fee")), gas_limit.saturating_add(number(content, "gas_limit")))
+            });
+        Some(OperationFees { validation_pass, fee, gas_limit })
+    }
+
+    /// Only manager operations pay fees
+    pub fn is_manager_operation(&self) -> bool {
+        self.validation_pass == MANAGER_VALIDATION_PASS
+    }
+}
+
+impl Ord for OperationPriority {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // lower validation pass has higher priority
+        other.validation_pass.cmp(&self.validation_pass)
+            .then(self.fee_ratio.cmp(&other.fee_ratio))
+    }
+}
+
+impl PartialOrd for OperationPriority {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Size of the operation, as it is sent to the peers
+pub fn operation_size(operation: &Operation) -> usize {
+    operation.branch().len() + operation.data().len()
+}
+
+fn fee_ratio(fee_mutez: u64, gas_limit: u64, size: usize) -> u64 {
+    let cost_nanotez = (gas_limit as u128) * MINIMAL_NANOTEZ_PER_GAS_UNIT + (size as u128) * MINIMAL_NANOTEZ_PER_BYTE;
+    if cost_nanotez == 0 {
+        return 0;
+    }
+    let ratio = (fee_mutez as u128) * 1000 * FEE_RATIO_SCALE / cost_nanotez;
+    if ratio > u64::MAX as u128 {
+        u64::MAX
+    } else {
+        ratio as u64
+    }
+}
+
+/// Parses fee and gas_limit of the manager operation (without tag): source, fee, counter, gas_limit, storage_limit, ...
+fn parse_manager_fee_and_gas(data: &[u8]) -> Option<(u64, u64)> {
+    let data = data.get(MANAGER_OPERATION_SOURCE_SIZE..)?;
+    let (fee, data) = parse_natural(data)?;
+    let (_counter, data) = parse_natural(data)?;
+    let (gas_limit, _) = parse_natural(data)?;
+    Some((fee, gas_limit))
+}
+
+/// Parses zarith natural number, returns value and remaining data
+fn parse_natural(data: &[u8]) -> Option<(u64, &[u8])> {
+    let mut value: u64 = 0;
+    for (idx, byte) in data.iter().enumerate() {
+        let shift = 7 * idx as u32;
+        if shift >= 64 {
+            // does not fit, fee and gas are much lower
+            return None;
+        }
+        value |= ((byte & 0x7f) as u64).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some((value, &data[idx + 1..]));
+        }
+    }
+    None
+}
+
+#[derive(Clone, Debug)]
+struct PrioritizedOperation {
+    operation: Operation,
+    priority: OperationPriority,
+    size: usize,
+    sequence: u64,
+}
+
+/// Operations of the mempool limited by count and size.
+#[derive(Clone, Debug)]
+pub struct PrioritizedOperations {
+    limits: MempoolConfiguration,
+    operations: HashMap<OperationHash, PrioritizedOperation>,
+    /// Operations ordered from the lowest priority, the newest operations are dropped first from the same priority
+    order: BTreeSet<(OperationPriority, Reverse<u64>, OperationHash)>,
+    total_bytes: usize,
+    next_sequence: u64,
+    stats: MempoolStats,
+}
+
+impl PrioritizedOperations {
+    pub fn new(limits: MempoolConfiguration) -> Self {
+        PrioritizedOperations {
+            limits,
+            operations: HashMap::new(),
+            order: BTreeSet::new(),
+            total_bytes: 0,
+            next_sequence: 0,
+            stats: MempoolStats::default(),
+        }
+    }
+
+    /// Adds operation with estimated priority, see [PrioritizedOperations::insert_with_priority]
+    pub fn insert(&mut self, operation_hash: OperationHash, operation: Operation) -> Result<Vec<OperationHash>, DropReason> {
+        let priority = OperationPriority::estimate(&operation);
+        self.insert_with_priority(operation_hash, operation, priority)
+    }
+
+    /// Adds operation, if limits are reached, operations with lower priority are evicted.
+    ///
+    /// Returns hashes of the evicted operations, or reason, why the operation was rejected (nothing is evicted then).
+    /// The same operation already present is replaced, but only if the new one is accepted.
+    pub fn insert_with_priority(&mut self, operation_hash: OperationHash, operation: Operation, priority: OperationPriority) -> Result<Vec<OperationHash>, DropReason> {
+        let size = operation_size(&operation);
+        if size > self.limits.max_bytes {
+            self.stats.record_rejected(DropReason::TooLarge, priority.validation_pass());
+            return Err(DropReason::TooLarge);
+        }
+
+        // resolve operations, which have to be evicted to make room (replaced operation does not count)
+        let replaced_size = self.operations.get(&operation_hash).map(|replaced| replaced.size);
+        let mut count = self.operations.len() + 1 - replaced_size.map(|_| 1).unwrap_or(0);
+        let mut bytes = self.total_bytes + size - replaced_size.unwrap_or(0);
+        let mut to_evict = Vec::new();
+        for (evicted_priority, _, evicted_hash) in self.order.iter().filter(|(_, _, evicted_hash)| evicted_hash != &operation_hash) {
+            let reason = if count > self.limits.max_operations {
+                DropReason::MaxOperations
+            } else if bytes > self.limits.max_bytes {
+                DropReason::MaxBytes
+            } else {
+                break;
+            };
+
+            if *evicted_priority >= priority {
+                self.stats.record_rejected(reason, priority.validation_pass());
+                return Err(reason);
+            }
+
+            let evicted_size = self.operations[evicted_hash].size;
+            count -= 1;
+            bytes -= evicted_size;
+            to_evict.push((evicted_hash.clone(), evicted_priority.validation_pass(), reason));
+        }
+
+        // replace the same operation
+        self.remove(&operation_hash);
+
+        let evicted = to_evict.into_iter()
+            .map(|(evicted_hash, validation_pass, reason)| {
+                self.remove(&evicted_hash);
+                self.stats.record_evicted(reason, validation_pass);
+                evicted_hash
+            })
+            .collect();
+
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.order.insert((priority, Reverse(sequence), operation_hash.clone()));
+        self.operations.insert(operation_hash, PrioritizedOperation { operation, priority, size, sequence });
+        self.total_bytes += size;
+
+        Ok(evicted)
+    }
+
+    /// Changes priority of the operation (e.g. after validation), returns false, if operation is not present
+    pub fn update_priority(&mut self, operation_hash: &OperationHash, priority: OperationPriority) -> bool {
+        match self.operations.get_mut(operation_hash) {
+            Some(prioritized) => {
+                self.order.remove(&(prioritized.priority, Reverse(prioritized.sequence), operation_hash.clone()));
+                prioritized.priority = priority;
+                self.order.insert((priority, Reverse(prioritized.sequence), operation_hash.clone()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&mut self, operation_hash: &OperationHash) -> Option<Operation> {
+        let removed = self.operations.remove(operation_hash)?;
+        self.order.remove(&(removed.priority, Reverse(removed.sequence), operation_hash.clone()));
+        self.total_bytes -= removed.size;
+        Some(removed.operation)
+    }
+
+    /// Removes operation, which was not accepted after validation
+    pub fn reject(&mut self, operation_hash: &OperationHash, reason: DropReason) -> Option<Operation> {
+        let validation_pass = self.operations.get(operation_hash)?.priority.validation_pass();
+        self.stats.record_rejected(reason, validation_pass);
+        self.remove(operation_hash)
+    }
+
+    pub fn get(&self, operation_hash: &OperationHash) -> Option<&Operation> {
+        self.operations.get(operation_hash).map(|prioritized| &prioritized.operation)
+    }
+
+    pub fn get_size(&self, operation_hash: &OperationHash) -> Option<usize> {
+        self.operations.get(operation_hash).map(|prioritized| prioritized.size)
+    }
+
+    pub fn contains(&self, operation_hash: &OperationHash) -> bool {
+        self.operations.contains_key(operation_hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    pub fn hashes(&self) -> impl Iterator<Item=&OperationHash> {
+        self.operations.keys()
+    }
+
+    /// Returns hashes sorted from the highest priority
+    pub fn sorted_by_priority<'a>(&self, operation_hashes: impl Iterator<Item=&'a OperationHash>) -> Vec<OperationHash> {
+        let mut sorted = operation_hashes
+            .filter_map(|operation_hash| self.operations.get(operation_hash)
+                .map(|prioritized| (prioritized.priority, Reverse(prioritized.sequence), operation_hash.clone())))
+            .collect::<Vec<_>>();
+        sorted.sort_unstable_by(|a, b| b.cmp(a));
+        sorted.into_iter().map(|(_, _, operation_hash)| operation_hash).collect()
+    }
+
+    pub fn to_map(&self) -> HashMap<OperationHash, Operation> {
+        self.operations.iter()
+            .map(|(operation_hash, prioritized)| (operation_hash.clone(), prioritized.operation.clone()))
+            .collect()
+    }
+
+    pub fn stats(&self) -> MempoolStats {
+        MempoolStats {
+            operations: self.operations.len(),
+            bytes: self.total_bytes,
+            ..self.stats.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tezos_messages::p2p::binary_message::BinaryMessage;
+
+    use super::*;
+
+    fn operation(data: Vec<u8>) -> Result<Operation, failure::Error> {
+        let mut bytes = vec![0u8; 32];
+        bytes.extend(data);
+        Ok(Operation::from_bytes(bytes)?)
+    }
+
+    /// Transaction (protocol 005+) with fee and gas_limit encoded in one byte
+    fn transaction(fee: u8, gas_limit: u8, padding: usize) -> Result<Operation, failure::Error> {
+        let mut data = vec![108];
+        data.extend(vec![0u8; MANAGER_OPERATION_SOURCE_SIZE]);
+        data.extend(vec![fee & 0x7f, 1, gas_limit & 0x7f, 0]);
+        data.extend(vec![0u8; padding]);
+        operation(data)
+    }
+
+    fn endorsement() -> Result<Operation, failure::Error> {
+        operation(vec![0, 0, 0, 0, 1])
+    }
+
+    fn endorsement_with_slot() -> Result<Operation, failure::Error> {
+        operation(vec![10, 0, 0, 0, 1])
+    }
+
+    #[test]
+    fn test_estimate_priority() -> Result<(), failure::Error> {
+        assert_eq!(CONSENSUS_VALIDATION_PASS, OperationPriority::estimate(&endorsement()?).validation_pass());
+        assert_eq!(CONSENSUS_VALIDATION_PASS, OperationPriority::estimate(&endorsement_with_slot()?).validation_pass());
+        assert_eq!(VOTING_VALIDATION_PASS, OperationPriority::estimate(&operation(vec![6, 1, 2])?).validation_pass());
+        assert_eq!(ANONYMOUS_VALIDATION_PASS, OperationPriority::estimate(&operation(vec![4, 1, 2])?).validation_pass());
+
+        let cheap = OperationPriority::estimate(&transaction(10, 100, 0)?);
+        let expensive = OperationPriority::estimate(&transaction(100, 100, 0)?);
+        assert_eq!(MANAGER_VALIDATION_PASS, cheap.validation_pass());
+        assert!(expensive > cheap);
+        assert!(OperationPriority::estimate(&endorsement()?) > expensive);
+
+        // bigger operation with the same fee has lower priority
+        assert!(OperationPriority::estimate(&transaction(10, 100, 100)?) < cheap);
+        Ok(())
+    }
+
+    #[test]
+    fn test_priority_from_protocol_data_json() {
+        let transaction = r#"{"contents":[{"kind":"reveal","fee":"1000","gas_limit":"10000"},{"kind":"transaction","fee":"2000","gas_limit":"10000"}],"signature":"sig"}"#;
+        let fees = OperationFees::from_protocol_data_json(transaction).unwrap();
+        assert_eq!(OperationFees { validation_pass: MANAGER_VALIDATION_PASS, fee: 3000, gas_limit: 20000 }, fees);
+        let priority = OperationPriority::from_fees(&fees, 200);
+        assert_eq!(MANAGER_VALIDATION_PASS, priority.validation_pass());
+        // 3000 mutez / (20000 * 100 + 200 * 1000 nanotez)
+        assert_eq!(1363, priority.fee_ratio);
+
+        let endorsement = r#"{"contents":[{"kind":"endorsement","level":100}],"signature":"sig"}"#;
+        let fees = OperationFees::from_protocol_data_json(endorsement).unwrap();
+        assert!(!fees.is_manager_operation());
+        assert_eq!(OperationPriority::new(CONSENSUS_VALIDATION_PASS, 0), OperationPriority::from_fees(&fees, 100));
+
+        assert_eq!(None, OperationFees::from_protocol_data_json("{}"));
+    }
+
+    #[test]
+    fn test_evict_lowest_priority_by_count() -> Result<(), failure::Error> {
+        let mut operations = PrioritizedOperations::new(MempoolConfiguration { max_operations: 2, max_bytes: 1024 * 1024, ..Default::default() });
+
+        assert_eq!(Ok(vec![]), operations.insert(vec![1], transaction(10, 100, 0)?));
+        assert_eq!(Ok(vec![]), operations.insert(vec![2], transaction(50, 100, 0)?));
+
+        // lower fee is rejected
+        assert_eq!(Err(DropReason::MaxOperations), operations.insert(vec![3], transaction(5, 100, 0)?));
+        // the same fee is rejected too, older operation wins
+        assert_eq!(Err(DropReason::MaxOperations), operations.insert(vec![3], transaction(10, 100, 0)?));
+
+        // endorsement evicts the cheapest operation
+        assert_eq!(Ok(vec![vec![1]]), operations.insert(vec![4], endorsement()?));
+        assert_eq!(2, operations.len());
+        assert!(operations.contains(&vec![2]));
+        assert!(operations.contains(&vec![4]));
+
+        let stats = operations.stats();
+        assert_eq!(Some(&2), stats.rejected.get("max_operations"));
+        assert_eq!(Some(&1), stats.evicted.get("max_operations"));
+        assert_eq!(Some(&3), stats.dropped_per_validation_pass.get(&MANAGER_VALIDATION_PASS));
+        Ok(())
+    }
+
+    #[test]
+    fn test_evict_lowest_priority_by_bytes() -> Result<(), failure::Error> {
+        let small = transaction(10, 100, 0)?;
+        let size = operation_size(&small);
+        let mut operations = PrioritizedOperations::new(MempoolConfiguration { max_operations: 100, max_bytes: 3 * size, ..Default::default() });
+
+        assert!(operations.insert(vec![1], small.clone()).is_ok());
+        assert!(operations.insert(vec![2], transaction(20, 100, 0)?).is_ok());
+        assert!(operations.insert(vec![3], transaction(30, 100, 0)?).is_ok());
+        assert_eq!(3 * size, operations.stats().bytes);
+
+        // endorsement is small, so just one operation has to be evicted
+        assert_eq!(Ok(vec![vec![1]]), operations.insert(vec![4], endorsement()?));
+
+        // operation, which is too large for the whole mempool
+        assert_eq!(Err(DropReason::TooLarge), operations.insert(vec![5], transaction(100, 100, 3 * size)?));
+        assert_eq!(3, operations.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_spam_burst_is_bounded() -> Result<(), failure::Error> {
+        let mut operations = PrioritizedOperations::new(MempoolConfiguration { max_operations: 100, max_bytes: 1024 * 1024, ..Default::default() });
+        assert!(operations.insert(vec![0, 0, 0], endorsement()?).is_ok());
+
+        for i in 0..1000u16 {
+            let _ = operations.insert(i.to_be_bytes().to_vec(), transaction((i % 100) as u8, 100, 0)?);
+        }
+        assert_eq!(100, operations.len());
+        // endorsement was not evicted by spam
+        assert!(operations.contains(&vec![0, 0, 0]));
+
+        // the highest priority first
+        let sorted = operations.sorted_by_priority(operations.hashes());
+        assert_eq!(&vec![0, 0, 0], &sorted[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_priority() -> Result<(), failure::Error> {
+        let mut operations = PrioritizedOperations::new(MempoolConfiguration { max_operations: 1, max_bytes: 1024 * 1024, ..Default::default() });
+        assert!(operations.insert(vec![1], transaction(10, 100, 0)?).is_ok());
+
+        // validated priority is lower than estimated
+        assert!(operations.update_priority(&vec![1], OperationPriority::new(MANAGER_VALIDATION_PASS, 0)));
+        assert_eq!(Ok(vec![vec![1]]), operations.insert(vec![2], transaction(1, 100, 0)?));
+        assert!(!operations.update_priority(&vec![1], OperationPriority::new(MANAGER_VALIDATION_PASS, 0)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejected_replacement_keeps_operation() -> Result<(), failure::Error> {
+        let small = transaction(10, 100, 0)?;
+        let size = operation_size(&small);
+        let mut operations = PrioritizedOperations::new(MempoolConfiguration { max_operations: 2, max_bytes: 2 * size, ..Default::default() });
+        assert!(operations.insert(vec![1], transaction(50, 100, 0)?).is_ok());
+        assert_eq!(Ok(vec![]), operations.insert(vec![2], small));
+
+        // bigger replacement would have to evict operation with higher priority, so original operation stays
+        assert_eq!(Err(DropReason::MaxBytes), operations.insert(vec![2], transaction(10, 100, 10)?));
+        assert!(operations.contains(&vec![2]));
+        assert_eq!(2 * size, operations.stats().bytes);
+
+        // replacement of the same size does not count to the limits
+        assert_eq!(Ok(vec![]), operations.insert(vec![2], transaction(20, 100, 0)?));
+        assert_eq!(2, operations.len());
+        Ok(())
+    }
+}
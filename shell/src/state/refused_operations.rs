@@ -0,0 +1,84 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Bounded cache of the recently refused operations.
+//!
+//! Peers keep advertising refused operations in their mempools, hashes of the refused operations are remembered
+//! (and stored in the mempool storage), so they are not downloaded and validated again.
+//! When the cache is full, the oldest refused operations are forgotten.
+
+use std::collections::{HashSet, VecDeque};
+
+use crypto::hash::OperationHash;
+
+#[derive(Clone, Debug)]
+pub struct RefusedOperations {
+    capacity: usize,
+    /// Refused operations from the oldest
+    order: VecDeque<OperationHash>,
+    operations: HashSet<OperationHash>,
+}
+
+impl RefusedOperations {
+    pub fn new(capacity: usize) -> Self {
+        RefusedOperations {
+            capacity,
+            order: VecDeque::new(),
+            operations: HashSet::new(),
+        }
+    }
+
+    /// Remembers refused operation, returns the oldest operations, which were forgotten to make room
+    pub fn insert(&mut self, operation_hash: OperationHash) -> Vec<OperationHash> {
+        if !self.operations.insert(operation_hash.clone()) {
+            return Vec::new();
+        }
+        self.order.push_back(operation_hash);
+
+        let mut forgotten = Vec::new();
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.operations.remove(&oldest);
+                forgotten.push(oldest);
+            }
+        }
+        forgotten
+    }
+
+    pub fn contains(&self, operation_hash: &OperationHash) -> bool {
+        self.operations.contains(operation_hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forget_oldest() {
+        let mut refused = RefusedOperations::new(2);
+        assert!(refused.insert(vec![1]).is_empty());
+        assert!(refused.insert(vec![2]).is_empty());
+        // duplicate is ignored
+        assert!(refused.insert(vec![1]).is_empty());
+        assert_eq!(2, refused.len());
+
+        assert_eq!(vec![vec![1]], refused.insert(vec![3]));
+        assert!(!refused.contains(&vec![1]));
+        assert!(refused.contains(&vec![2]));
+        assert!(refused.contains(&vec![3]));
+
+        // nothing is remembered without capacity
+        let mut refused = RefusedOperations::new(0);
+        assert_eq!(vec![vec![1]], refused.insert(vec![1]));
+        assert!(refused.is_empty());
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Support for the test chain, which is forked by the protocol from the main chain (at the end of the testing vote period).
+//!
+//! The test chain is identified by its own chain_id and genesis (derived from the forking block),
+//! it is synchronized with peers independently from the main chain and it is stopped at expiration.
+
+use std::convert::TryInto;
+
+use crypto::blake2b;
+use crypto::hash::{BlockHash, ChainId, ContextHash, HashType, ProtocolHash};
+use storage::context::{ContextApi, ContextError};
+use storage::context_key;
+
+/// Context key, where protocol stores [TestChainStatus]
+const TEST_CHAIN_STATUS_KEY: &str = "test_chain";
+
+/// Genesis of the test chain is computed from the hash of the forking block (see Tezos `Context.compute_testchain_genesis`)
+pub fn test_chain_genesis(forking_block_hash: &BlockHash) -> BlockHash {
+    blake2b::digest_256(forking_block_hash)
+}
+
+/// Status of the test chain stored in the context (see Tezos `Test_chain_status`)
+#[derive(Clone, Debug, PartialEq)]
+pub enum TestChainStatus {
+    NotRunning,
+    Forking {
+        protocol: ProtocolHash,
+        expiration: i64,
+    },
+    Running {
+        chain_id: ChainId,
+        genesis: BlockHash,
+        protocol: ProtocolHash,
+        expiration: i64,
+    },
+}
+
+impl TestChainStatus {
+    const TAG_NOT_RUNNING: u8 = 0;
+    const TAG_FORKING: u8 = 1;
+    const TAG_RUNNING: u8 = 2;
+
+    /// Decodes binary encoded status, returns None, if bytes are not valid
+    pub fn from_bytes(bytes: &[u8]) -> Option<TestChainStatus> {
+        let chain_id_size = HashType::ChainId.size();
+        let block_hash_size = HashType::BlockHash.size();
+        let protocol_hash_size = HashType::ProtocolHash.size();
+
+        let (tag, bytes) = bytes.split_first()?;
+        match *tag {
+            Self::TAG_NOT_RUNNING if bytes.is_empty() => Some(TestChainStatus::NotRunning),
+            Self::TAG_FORKING if bytes.len() == protocol_hash_size + 8 => {
+                let (protocol, expiration) = bytes.split_at(protocol_hash_size);
+                Some(TestChainStatus::Forking {
+                    protocol: protocol.to_vec(),
+                    expiration: i64::from_be_bytes(expiration.try_into().ok()?),
+                })
+            }
+            Self::TAG_RUNNING if bytes.len() == chain_id_size + block_hash_size + protocol_hash_size + 8 => {
+                let (chain_id, bytes) = bytes.split_at(chain_id_size);
+                let (genesis, bytes) = bytes.split_at(block_hash_size);
+                let (protocol, expiration) = bytes.split_at(protocol_hash_size);
+                Some(TestChainStatus::Running {
+                    chain_id: chain_id.to_vec(),
+                    genesis: genesis.to_vec(),
+                    protocol: protocol.to_vec(),
+                    expiration: i64::from_be_bytes(expiration.try_into().ok()?),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns protocol of the test chain, if forking or running
+    pub fn protocol(&self) -> Option<&ProtocolHash> {
+        match self {
+            TestChainStatus::NotRunning => None,
+            TestChainStatus::Forking { protocol, .. } => Some(protocol),
+            TestChainStatus::Running { protocol, .. } => Some(protocol),
+        }
+    }
+
+    /// Returns expiration of the test chain, if forking or running
+    pub fn expiration(&self) -> Option<i64> {
+        match self {
+            TestChainStatus::NotRunning => None,
+            TestChainStatus::Forking { expiration, .. } => Some(*expiration),
+            TestChainStatus::Running { expiration, .. } => Some(*expiration),
+        }
+    }
+}
+
+/// Reads test chain status from the context of the block.
+///
+/// Context is stored asynchronously by [context_listener], so None is returned also if context is not stored yet.
+pub fn get_test_chain_status(context: &dyn ContextApi, context_hash: &ContextHash) -> Result<Option<TestChainStatus>, ContextError> {
+    Ok(
+        context.get_key_from_history(context_hash, &context_key!(TEST_CHAIN_STATUS_KEY))?
+            .and_then(|bytes| TestChainStatus::from_bytes(&bytes))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_test_chain_status() {
+        assert_eq!(Some(TestChainStatus::NotRunning), TestChainStatus::from_bytes(&[0]));
+
+        let mut forking = vec![1];
+        forking.extend_from_slice(&[2; 32]);
+        forking.extend_from_slice(&1_600_000_000i64.to_be_bytes());
+        assert_eq!(
+            Some(TestChainStatus::Forking { protocol: vec![2; 32], expiration: 1_600_000_000 }),
+            TestChainStatus::from_bytes(&forking)
+        );
+
+        let mut running = vec![2];
+        running.extend_from_slice(&[3; 4]);
+        running.extend_from_slice(&[4; 32]);
+        running.extend_from_slice(&[2; 32]);
+        running.extend_from_slice(&1_600_000_000i64.to_be_bytes());
+        let status = TestChainStatus::from_bytes(&running).unwrap();
+        assert_eq!(
+            TestChainStatus::Running { chain_id: vec![3; 4], genesis: vec![4; 32], protocol: vec![2; 32], expiration: 1_600_000_000 },
+            status
+        );
+        assert_eq!(Some(1_600_000_000), status.expiration());
+        assert_eq!(Some(&vec![2; 32]), status.protocol());
+
+        // invalid data
+        assert_eq!(None, TestChainStatus::from_bytes(&[]));
+        assert_eq!(None, TestChainStatus::from_bytes(&[0, 1]));
+        assert_eq!(None, TestChainStatus::from_bytes(&running[..running.len() - 1]));
+        assert_eq!(None, TestChainStatus::from_bytes(&[3]));
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Counters of the handshake outcomes with remote peers.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use serde::Serialize;
+
+use networking::p2p::peer::HandshakeStage;
+use tezos_messages::p2p::encoding::version::NetworkVersion;
+
+/// Thread safe reference to the shared handshake stats
+pub type HandshakeStatsRef = Arc<RwLock<HandshakeStats>>;
+
+/// Key used for handshakes, where remote peer did not send its network version
+const UNKNOWN_VERSION: &str = "unknown";
+
+#[derive(Serialize, Default, PartialEq, Clone, Debug)]
+pub struct HandshakeOutcomes {
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// Counters split by the direction of the connection.
+#[derive(Serialize, Default, PartialEq, Clone, Debug)]
+pub struct ConnectionDirections {
+    pub incoming: usize,
+    pub outgoing: usize,
+}
+
+/// Handshake outcome counters aggregated per stage, reason and remote network version.
+#[derive(Serialize, Default, PartialEq, Clone, Debug)]
+pub struct HandshakeStats {
+    /// Total outcomes of all handshakes
+    pub total: HandshakeOutcomes,
+    /// Failures per handshake stage and per failure reason
+    pub failures: HashMap<String, HashMap<String, usize>>,
+    /// Outcomes per network version, negotiated version for successful handshakes,
+    /// otherwise version announced by the remote peer
+    pub remote_versions: HashMap<String, HandshakeOutcomes>,
+    /// Number of handshakes in progress
+    pub pending: ConnectionDirections,
+    /// How many times was the limit of in-flight handshakes reached
+    pub pending_limit_reached: ConnectionDirections,
+    /// Number of outgoing connections, which timed out before handshake started
+    pub connect_timeouts: usize,
+    /// Number of established connections closed, because no message was received within the idle timeout
+    pub idle_timeouts: usize,
+}
+
+impl HandshakeStats {
+    pub fn new_ref() -> HandshakeStatsRef {
+        Arc::new(RwLock::new(HandshakeStats::default()))
+    }
+
+    pub fn record_success(&mut self, negotiated_version: &NetworkVersion) {
+        self.total.succeeded += 1;
+        self.remote_versions.entry(version_key(Some(negotiated_version)))
+            .or_default()
+            .succeeded += 1;
+    }
+
+    pub fn record_failure(&mut self, stage: HandshakeStage, reason: &str, remote_version: Option<&NetworkVersion>) {
+        self.total.failed += 1;
+        *self.failures.entry(stage.to_string())
+            .or_default()
+            .entry(reason.to_string())
+            .or_default() += 1;
+        self.remote_versions.entry(version_key(remote_version))
+            .or_default()
+            .failed += 1;
+    }
+}
+
+fn version_key(version: Option<&NetworkVersion>) -> String {
+    match version {
+        Some(version) => format!("{}/{}/{}", version.chain_name(), version.distributed_db_version(), version.p2p_version()),
+        None => UNKNOWN_VERSION.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_outcomes() {
+        let version = NetworkVersion::new("TEZOS_CARTHAGENET_2019-11-28T13:02:13Z".to_string(), 0, 1);
+        let mut stats = HandshakeStats::default();
+
+        stats.record_success(&version);
+        stats.record_failure(HandshakeStage::Metadata, "io_timeout", Some(&version));
+        stats.record_failure(HandshakeStage::Metadata, "io_timeout", None);
+        stats.record_failure(HandshakeStage::Version, "unsupported_protocol", Some(&version));
+
+        assert_eq!(HandshakeOutcomes { succeeded: 1, failed: 3 }, stats.total);
+        assert_eq!(Some(&2), stats.failures["metadata"].get("io_timeout"));
+        assert_eq!(Some(&1), stats.failures["version"].get("unsupported_protocol"));
+        assert_eq!(HandshakeOutcomes { succeeded: 1, failed: 2 }, stats.remote_versions["TEZOS_CARTHAGENET_2019-11-28T13:02:13Z/0/1"]);
+        assert_eq!(HandshakeOutcomes { succeeded: 0, failed: 1 }, stats.remote_versions[UNKNOWN_VERSION]);
+    }
+}
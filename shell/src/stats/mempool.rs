@@ -0,0 +1,60 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Counters of the operations dropped by the bounded mempool.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Serialize;
+
+/// Why was operation dropped from the mempool
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DropReason {
+    /// Mempool reached max count of operations
+    MaxOperations,
+    /// Mempool reached max size of operations
+    MaxBytes,
+    /// Operation alone is bigger than max size of the mempool
+    TooLarge,
+    /// Fee of the validated operation is lower than required by the mempool filter
+    MinimalFees,
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DropReason::MaxOperations => write!(f, "max_operations"),
+            DropReason::MaxBytes => write!(f, "max_bytes"),
+            DropReason::TooLarge => write!(f, "too_large"),
+            DropReason::MinimalFees => write!(f, "minimal_fees"),
+        }
+    }
+}
+
+/// Size of the mempool and counters of the dropped operations.
+#[derive(Serialize, Default, PartialEq, Clone, Debug)]
+pub struct MempoolStats {
+    /// Count of operations in the mempool
+    pub operations: usize,
+    /// Size of operations in the mempool
+    pub bytes: usize,
+    /// Operations removed from the mempool to make room for operations with higher priority, per reason
+    pub evicted: HashMap<String, usize>,
+    /// Received operations, which were not added to the mempool, per reason
+    pub rejected: HashMap<String, usize>,
+    /// All dropped operations (evicted and rejected) per validation pass
+    pub dropped_per_validation_pass: HashMap<u8, usize>,
+}
+
+impl MempoolStats {
+    pub fn record_evicted(&mut self, reason: DropReason, validation_pass: u8) {
+        *self.evicted.entry(reason.to_string()).or_default() += 1;
+        *self.dropped_per_validation_pass.entry(validation_pass).or_default() += 1;
+    }
+
+    pub fn record_rejected(&mut self, reason: DropReason, validation_pass: u8) {
+        *self.rejected.entry(reason.to_string()).or_default() += 1;
+        *self.dropped_per_validation_pass.entry(validation_pass).or_default() += 1;
+    }
+}
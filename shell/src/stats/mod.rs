@@ -3,4 +3,6 @@
 
 //! This module contains all structs used to hold shell stats.
 
-pub mod memory;
\ No newline at end of file
+pub mod memory;
+pub mod handshake;
+pub mod mempool;
\ No newline at end of file
@@ -14,12 +14,14 @@ use failure::Fail;
 
 use crypto::hash::{ChainId, HashType, OperationHash, ProtocolHash};
 use storage::{BlockHeaderWithHash, BlockMetaStorageReader, BlockStorageReader, StorageError};
-use tezos_api::ffi::{BeginApplicationRequest, BeginConstructionRequest, ValidateOperationRequest, ValidateOperationResult};
+use tezos_api::ffi::{BeginApplicationRequest, BeginConstructionRequest, ValidateOperationRequest};
 use tezos_messages::Head;
-use tezos_messages::p2p::encoding::block_header::Fitness;
-use tezos_messages::p2p::encoding::prelude::{BlockHeader, Operation};
+use tezos_messages::p2p::binary_message::MessageHash;
+use tezos_messages::p2p::encoding::block_header::{Fitness, Level};
+use tezos_messages::p2p::encoding::prelude::{BlockHeader, Operation, OperationsForBlocksMessage};
 use tezos_wrapper::service::{ProtocolController, ProtocolServiceError};
 
+use crate::mempool_result::{MempoolValidationResult, OperationClassification};
 use crate::shell_channel::CurrentMempoolState;
 use crate::validation::fitness_comparator::FitnessWrapper;
 
@@ -51,47 +53,119 @@ pub fn is_future_block(block_header: &BlockHeader) -> Result<bool, failure::Erro
     Ok(block_timestamp > future_margin)
 }
 
-/// Returns true, if we can accept injected operation from rpc
-pub fn can_accept_operation_from_rpc(operation_hash: &OperationHash, result: &ValidateOperationResult) -> bool {
-    // we can accept from rpc, only if it is [applied]
-    result.applied
-        .iter()
-        .find(|operation_result| operation_result.hash.eq(operation_hash))
-        .is_some()
+/// Error produced by a [prevalidate_block_header] or [prevalidate_block_operations].
+#[derive(Debug, Fail, PartialEq)]
+pub enum BlockPrevalidationError {
+    #[fail(display = "Invalid level - expected: {}, found: {}", expected, found)]
+    InvalidLevel {
+        expected: Level,
+        found: Level,
+    },
+    #[fail(display = "Timestamp {} is not after predecessor timestamp {}", timestamp, predecessor_timestamp)]
+    InvalidTimestamp {
+        timestamp: i64,
+        predecessor_timestamp: i64,
+    },
+    #[fail(display = "Invalid validation_pass - expected: {}, found: {}", expected, found)]
+    InvalidValidationPass {
+        expected: u8,
+        found: u8,
+    },
+    #[fail(display = "Invalid proto level - predecessor: {}, found: {}", predecessor_proto, proto)]
+    InvalidProtoLevel {
+        predecessor_proto: u8,
+        proto: u8,
+    },
+    #[fail(display = "Operations for validation_pass: {} do not match operations_hash: {}", validation_pass, operations_hash)]
+    InvalidOperationsHash {
+        validation_pass: i8,
+        operations_hash: String,
+    },
+    #[fail(display = "Failed to hash operation! Reason: {}", reason)]
+    OperationHashError {
+        reason: String,
+    },
 }
 
-/// Returns true, if we can accept received operation from p2p
-pub fn can_accept_operation_from_p2p(operation_hash: &OperationHash, result: &ValidateOperationResult) -> bool {
-    // we can accept from p2p, only if it is [not refused]
-    if result.refused
-        .iter()
-        .any(|operation_result| operation_result.hash.eq(operation_hash)) {
-        return false;
+/// Checks block header (before it is stored and applied) without calling protocol:
+/// - validation_pass matches count of validation passes of the block's protocol (see [tezos_messages::protocol::get_validation_passes])
+/// - level is predecessor's level + 1
+/// - timestamp is after predecessor's timestamp
+/// - proto level is the same as predecessor's or incremented by one (protocol switch)
+///
+/// Predecessor checks are skipped, if predecessor is not known yet,
+/// validation_pass check is skipped, if protocol of the block is not known yet
+pub fn prevalidate_block_header(block_header: &BlockHeader, predecessor: Option<&BlockHeader>, validation_passes: Option<u8>) -> Result<(), BlockPrevalidationError> {
+    if let Some(validation_passes) = validation_passes {
+        if block_header.validation_pass() != validation_passes {
+            return Err(BlockPrevalidationError::InvalidValidationPass {
+                expected: validation_passes,
+                found: block_header.validation_pass(),
+            });
+        }
     }
 
-    // true, if contained in applied
-    if result.applied
-        .iter()
-        .any(|operation_result| operation_result.hash.eq(operation_hash)) {
-        return true;
-    }
+    if let Some(predecessor) = predecessor {
+        if block_header.level() != predecessor.level() + 1 {
+            return Err(BlockPrevalidationError::InvalidLevel {
+                expected: predecessor.level() + 1,
+                found: block_header.level(),
+            });
+        }
 
-    // true, if contained in branch_refused
-    if result.branch_refused
-        .iter()
-        .any(|operation_result| operation_result.hash.eq(operation_hash)) {
-        return true;
+        if block_header.timestamp() <= predecessor.timestamp() {
+            return Err(BlockPrevalidationError::InvalidTimestamp {
+                timestamp: block_header.timestamp(),
+                predecessor_timestamp: predecessor.timestamp(),
+            });
+        }
+
+        if block_header.proto() != predecessor.proto() && block_header.proto() != predecessor.proto().wrapping_add(1) {
+            return Err(BlockPrevalidationError::InvalidProtoLevel {
+                predecessor_proto: predecessor.proto(),
+                proto: block_header.proto(),
+            });
+        }
     }
 
-    // true, if contained in branch_refused
-    if result.branch_delayed
+    Ok(())
+}
+
+/// Checks, that received operations for validation pass match block header's operations_hash,
+/// by computing the merkle root from operation hashes and the received operation_hashes_path
+pub fn prevalidate_block_operations(block_header: &BlockHeader, operations: &OperationsForBlocksMessage) -> Result<(), BlockPrevalidationError> {
+    let operation_hashes = operations.operations()
         .iter()
-        .any(|operation_result| operation_result.hash.eq(operation_hash)) {
-        return true;
+        .map(|operation| operation.message_hash())
+        .collect::<Result<Vec<OperationHash>, _>>()
+        .map_err(|e| BlockPrevalidationError::OperationHashError { reason: format!("{:?}", e) })?;
+
+    let operation_list_hash = operations_hash::compute(&operation_hashes);
+    let operations_hash = operations_hash::compute_path_root(operations.operation_hashes_path(), &operation_list_hash);
+
+    if operations_hash != *block_header.operations_hash() {
+        return Err(BlockPrevalidationError::InvalidOperationsHash {
+            validation_pass: operations.operations_for_block().validation_pass(),
+            operations_hash: HashType::OperationListListHash.bytes_to_string(block_header.operations_hash()),
+        });
     }
 
-    // any just false
-    false
+    Ok(())
+}
+
+/// Returns true, if we can accept injected operation from rpc
+pub fn can_accept_operation_from_rpc(operation_hash: &OperationHash, result: &MempoolValidationResult) -> bool {
+    // we can accept from rpc, only if it is [applied]
+    result.classification(operation_hash) == Some(OperationClassification::Applied)
+}
+
+/// Returns true, if we can accept received operation from p2p
+pub fn can_accept_operation_from_p2p(operation_hash: &OperationHash, result: &MempoolValidationResult) -> bool {
+    // we can accept from p2p, only if it is [not refused] (applied, branch_refused or branch_delayed)
+    match result.classification(operation_hash) {
+        Some(OperationClassification::Refused) | None => false,
+        Some(_) => true,
+    }
 }
 
 /// Error produced by a [prevalidate_operation].
@@ -138,7 +212,7 @@ pub fn prevalidate_operation(
     api: &ProtocolController,
     block_storage: &Box<dyn BlockStorageReader>,
     block_meta_storage: &Box<dyn BlockMetaStorageReader>,
-) -> Result<ValidateOperationResult, PrevalidateOperationError> {
+) -> Result<MempoolValidationResult, PrevalidateOperationError> {
 
     // just check if we know block from operation (and is applied)
     let operation_branch = operation.branch();
@@ -197,7 +271,7 @@ pub fn prevalidate_operation(
 
     // validate operation to new empty/dummpy block
     api.validate_operation(ValidateOperationRequest { prevalidator, operation: operation.clone() })
-        .map(|r| r.result)
+        .map(|r| r.result.into())
         .map_err(|e| PrevalidateOperationError::ValidationError {
             operation_hash: HashType::OperationHash.bytes_to_string(operation_hash),
             reason: e,
@@ -238,6 +312,54 @@ pub fn check_multipass_validation(
     None
 }
 
+/// Merkle tree hashes for block operations (compatible with Tezos Blake2B.Make_merkle_tree):
+///     - OperationListHash is the merkle root of operation hashes of one validation pass
+///     - OperationListListHash (header's operations_hash) is the merkle root of all OperationListHash-es
+pub mod operations_hash {
+    use crypto::blake2b;
+    use crypto::hash::Hash;
+    use tezos_messages::p2p::encoding::operations_for_blocks::Path;
+
+    fn leaf(hash: &Hash) -> Hash {
+        blake2b::digest_256(hash)
+    }
+
+    fn node(left: &Hash, right: &Hash) -> Hash {
+        let mut bytes = Vec::with_capacity(left.len() + right.len());
+        bytes.extend_from_slice(left);
+        bytes.extend_from_slice(right);
+        blake2b::digest_256(&bytes)
+    }
+
+    /// Computes merkle root of the hashes, odd levels are padded with the last element
+    pub fn compute(hashes: &[Hash]) -> Hash {
+        if hashes.is_empty() {
+            return blake2b::digest_256(&[]);
+        }
+
+        let mut nodes: Vec<Hash> = hashes.iter().map(leaf).collect();
+        while nodes.len() > 1 {
+            if nodes.len() % 2 == 1 {
+                let last = nodes[nodes.len() - 1].clone();
+                nodes.push(last);
+            }
+            nodes = nodes.chunks(2)
+                .map(|pair| node(&pair[0], &pair[1]))
+                .collect();
+        }
+        nodes.remove(0)
+    }
+
+    /// Computes merkle root for the element placed in the tree by the path
+    pub fn compute_path_root(path: &Path, hash: &Hash) -> Hash {
+        match path {
+            Path::Op => leaf(hash),
+            Path::Left(left) => node(&compute_path_root(left.path(), hash), left.right()),
+            Path::Right(right) => node(right.left(), &compute_path_root(right.path(), hash)),
+        }
+    }
+}
+
 /// Fitness comparison:
 ///     - shortest lists are smaller
 ///     - lexicographical order for lists of the same length.
@@ -402,6 +524,85 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_prevalidate_block_header() -> Result<(), failure::Error> {
+        let predecessor = block_header(33, 5_635_000, 1, 4)?;
+
+        assert_eq!(Ok(()), prevalidate_block_header(&block_header(34, 5_635_634, 1, 4)?, Some(&predecessor), Some(4)));
+        assert_eq!(Ok(()), prevalidate_block_header(&block_header(34, 5_635_634, 2, 4)?, Some(&predecessor), None));
+        assert_eq!(Ok(()), prevalidate_block_header(&block_header(50, 1, 1, 4)?, None, None));
+
+        assert_eq!(
+            Err(BlockPrevalidationError::InvalidLevel { expected: 34, found: 35 }),
+            prevalidate_block_header(&block_header(35, 5_635_634, 1, 4)?, Some(&predecessor), Some(4))
+        );
+        assert_eq!(
+            Err(BlockPrevalidationError::InvalidTimestamp { timestamp: 5_635_000, predecessor_timestamp: 5_635_000 }),
+            prevalidate_block_header(&block_header(34, 5_635_000, 1, 4)?, Some(&predecessor), Some(4))
+        );
+        assert_eq!(
+            Err(BlockPrevalidationError::InvalidProtoLevel { predecessor_proto: 1, proto: 3 }),
+            prevalidate_block_header(&block_header(34, 5_635_634, 3, 4)?, Some(&predecessor), Some(4))
+        );
+        assert_eq!(
+            Err(BlockPrevalidationError::InvalidValidationPass { expected: 4, found: 5 }),
+            prevalidate_block_header(&block_header(34, 5_635_634, 1, 5)?, None, Some(4))
+        );
+        assert_eq!(
+            Err(BlockPrevalidationError::InvalidValidationPass { expected: 4, found: 3 }),
+            prevalidate_block_header(&block_header(34, 5_635_634, 1, 3)?, Some(&predecessor), Some(4))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_operations_hash_path() {
+        use tezos_messages::p2p::encoding::operations_for_blocks::{Path, PathLeft, PathRight};
+
+        let hashes: Vec<Vec<u8>> = vec![vec![1; 32], vec![2; 32], vec![3; 32]];
+        let root = operations_hash::compute(&hashes);
+
+        // single element tree
+        assert_eq!(operations_hash::compute(&hashes[..1]), operations_hash::compute_path_root(&Path::Op, &hashes[0]));
+
+        // tree of three elements is padded with the last one: ((h1, h2), (h3, h3))
+        let left_subtree = operations_hash::compute(&hashes[..2]);
+        let right_subtree = operations_hash::compute(&[hashes[2].clone(), hashes[2].clone()]);
+        let path_to_h2 = Path::Left(Box::new(PathLeft::new(
+            Path::Right(Box::new(PathRight::new(operations_hash::compute(&hashes[..1]), Path::Op, Default::default()))),
+            right_subtree.clone(),
+            Default::default(),
+        )));
+        assert_eq!(root, operations_hash::compute_path_root(&path_to_h2, &hashes[1]));
+
+        let path_to_h3 = Path::Right(Box::new(PathRight::new(
+            left_subtree,
+            Path::Left(Box::new(PathLeft::new(Path::Op, operations_hash::compute(&hashes[2..]), Default::default()))),
+            Default::default(),
+        )));
+        assert_eq!(root, operations_hash::compute_path_root(&path_to_h3, &hashes[2]));
+
+        // wrong element does not match
+        assert_ne!(root, operations_hash::compute_path_root(&path_to_h3, &hashes[0]));
+    }
+
+    fn block_header(level: i32, timestamp: i64, proto: u8, validation_pass: u8) -> Result<BlockHeader, failure::Error> {
+        Ok(
+            BlockHeaderBuilder::default()
+                .level(level)
+                .proto(proto)
+                .predecessor(HashType::BlockHash.string_to_bytes("BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET")?)
+                .timestamp(timestamp)
+                .validation_pass(validation_pass)
+                .operations_hash(HashType::OperationListListHash.string_to_bytes("LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc")?)
+                .fitness(fitness!([0], [0, 0, 1]))
+                .context(HashType::ContextHash.string_to_bytes("CoVmAcMV64uAQo8XvfLr9VDuz7HVZLT4cgK1w1qYmTjQNbGwQwDd")?)
+                .protocol_data(vec![0, 1, 2, 3, 4, 5, 6, 7, 8])
+                .build().unwrap()
+        )
+    }
+
     fn new_head(fitness: Fitness) -> Result<BlockHeaderWithHash, failure::Error> {
         Ok(
             BlockHeaderWithHash {
@@ -16,7 +16,7 @@ use riker::actors::*;
 use slog::{info, Logger};
 
 use crypto::hash::{BlockHash, ContextHash, HashType, OperationHash};
-use shell::shell_channel::{CurrentMempoolState, MempoolOperationReceived, ShellChannelRef, ShellChannelTopic};
+use shell::shell_channel::{CurrentMempoolState, MempoolOperationReceived, MempoolOperationSource, ShellChannelRef, ShellChannelTopic};
 use storage::{BlockHeaderWithHash, BlockMetaStorage, BlockStorage, BlockStorageReader, ChainMetaStorage, context_key, MempoolStorage, OperationsMetaStorage, OperationsStorage};
 use storage::chain_meta_storage::ChainMetaStorageReader;
 use storage::context::{ContextApi, TezedgeContext};
@@ -239,10 +239,10 @@ fn test_scenario_for_add_operations_to_mempool_and_check_state(
     assert_eq!(*mempool_head, last_applied_block);
 
     // check operations in mempool - should by empty all
-    assert!(current_mempool_state.result.applied.is_empty());
-    assert!(current_mempool_state.result.branch_delayed.is_empty());
-    assert!(current_mempool_state.result.branch_refused.is_empty());
-    assert!(current_mempool_state.result.refused.is_empty());
+    assert!(current_mempool_state.result.applied().is_empty());
+    assert!(current_mempool_state.result.branch_delayed().is_empty());
+    assert!(current_mempool_state.result.branch_refused().is_empty());
+    assert!(current_mempool_state.result.refused().is_empty());
 
     // add operations from 1325 to mempool - should by applied
     let operations_from_1325 = add_operations_to_mempool(request_1325, shell_channel.clone(), &mut mempool_storage)?;
@@ -262,10 +262,10 @@ fn test_scenario_for_add_operations_to_mempool_and_check_state(
     // check mempool current state after operations 1325
     assert!(current_mempool_state.is_some());
     let current_mempool_state = current_mempool_state.unwrap();
-    assert_eq!(operations_from_1325_count, current_mempool_state.result.applied.len());
-    assert!(current_mempool_state.result.branch_delayed.is_empty());
-    assert!(current_mempool_state.result.branch_refused.is_empty());
-    assert!(current_mempool_state.result.refused.is_empty());
+    assert_eq!(operations_from_1325_count, current_mempool_state.result.applied().len());
+    assert!(current_mempool_state.result.branch_delayed().is_empty());
+    assert!(current_mempool_state.result.branch_refused().is_empty());
+    assert!(current_mempool_state.result.refused().is_empty());
 
     // add operations from 1326 to mempool - should by branch_delay
     let operations_from_1326 = add_operations_to_mempool(request_1326, shell_channel.clone(), &mut mempool_storage)?;
@@ -285,10 +285,10 @@ fn test_scenario_for_add_operations_to_mempool_and_check_state(
     // check mempool current state after operations 1326
     assert!(current_mempool_state.is_some());
     let current_mempool_state = current_mempool_state.unwrap();
-    assert_eq!(operations_from_1325_count, current_mempool_state.result.applied.len());
-    assert_eq!(operations_from_1326_count, current_mempool_state.result.branch_delayed.len());
-    assert!(current_mempool_state.result.branch_refused.is_empty());
-    assert!(current_mempool_state.result.refused.is_empty());
+    assert_eq!(operations_from_1325_count, current_mempool_state.result.applied().len());
+    assert_eq!(operations_from_1326_count, current_mempool_state.result.branch_delayed().len());
+    assert!(current_mempool_state.result.branch_refused().is_empty());
+    assert!(current_mempool_state.result.refused().is_empty());
 
     Ok(())
 }
@@ -316,6 +316,7 @@ fn add_operations_to_mempool(request: &String, shell_channel: ShellChannelRef, m
                     msg: MempoolOperationReceived {
                         operation_hash: operation_hash.clone(),
                         operation_type: MempoolOperationType::Pending,
+                        source: MempoolOperationSource::Rpc,
                     }.into(),
                     topic: ShellChannelTopic::ShellEvents.into(),
                 },
@@ -0,0 +1,355 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+/// Integration test for recovery of block application, which was interrupted by crash of the chain feeder.
+///
+/// Chain feeder is really killed (in child process) in the middle of the block application by fake protocol,
+/// other stages are prepared just in storage, in the state, which the feeder leaves behind.
+
+use std::{env, fs, thread};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use riker::actors::*;
+use slog::{Level, Logger};
+
+use crypto::hash::{ChainId, ContextHash, ProtocolHash};
+use shell::chain_feeder::{ApplyBlockRecovery, ChainFeeder, check_applied_block_context, recover_block_application};
+use shell::shell_channel::{ShellChannel, ShellChannelMsg};
+use storage::{BlockAdditionalDataBuilder, BlockHeaderWithHash, BlockMetaStorage, BlockStorage, ChainMetaStorage, StorageInitInfo, store_applied_block_result};
+use storage::block_meta_storage::Meta;
+use storage::chain_meta_storage::{ApplyBlockIntent, ChainMetaStorageReader};
+use storage::context::TezedgeContext;
+use storage::tests_common::TmpStorage;
+use tezos_api::environment::{TEZOS_ENV, TezosEnvironment};
+use tezos_api::ffi::*;
+use tezos_messages::Head;
+use tezos_wrapper::protocol::ProtocolApi;
+use tezos_wrapper::service::{process_protocol_commands, ProtocolEndpointConfiguration, ProtocolRunner, ProtocolRunnerEndpoint, ProtocolServiceError};
+
+mod common;
+mod samples;
+
+struct Storages {
+    block_storage: BlockStorage,
+    block_meta_storage: BlockMetaStorage,
+    chain_meta_storage: ChainMetaStorage,
+    context: TezedgeContext,
+    chain_id: ChainId,
+    genesis: BlockHeaderWithHash,
+    block: BlockHeaderWithHash,
+}
+
+/// Opens storages for genesis and block at level 1
+fn open_storages(tmp_storage: &TmpStorage) -> Result<Storages, failure::Error> {
+    let (requests, _, _) = samples::read_data_apply_block_request_until_1326();
+    let request = samples::from_captured_bytes(&requests[0])?;
+
+    Ok(
+        Storages {
+            block_storage: BlockStorage::new(tmp_storage.storage()),
+            block_meta_storage: BlockMetaStorage::new(tmp_storage.storage()),
+            chain_meta_storage: ChainMetaStorage::new(tmp_storage.storage()),
+            context: TezedgeContext::new(BlockStorage::new(tmp_storage.storage()), tmp_storage.storage().merkle()),
+            chain_id: request.chain_id.clone(),
+            genesis: BlockHeaderWithHash::new(request.pred_header)?,
+            block: BlockHeaderWithHash::new(request.block_header)?,
+        }
+    )
+}
+
+/// Stores applied genesis and not applied block at level 1
+fn prepare_storages(tmp_storage: &TmpStorage, log: &Logger) -> Result<Storages, failure::Error> {
+    let storages = open_storages(tmp_storage)?;
+
+    storages.block_storage.put_block_header(&storages.genesis)?;
+    storages.block_meta_storage.put(&storages.genesis.hash, &Meta::genesis_meta(&storages.genesis.hash, &storages.chain_id, true))?;
+    storages.block_storage.put_block_header(&storages.block)?;
+    storages.block_meta_storage.put_block_header(&storages.block, &storages.chain_id, log)?;
+
+    Ok(storages)
+}
+
+fn apply_block_response(storages: &Storages) -> ApplyBlockResponse {
+    ApplyBlockResponse {
+        validation_result_message: "applied".to_string(),
+        context_hash: storages.block.header.context().clone(),
+        block_header_proto_json: "{}".to_string(),
+        block_header_proto_metadata_json: "{}".to_string(),
+        operations_proto_metadata_json: "{}".to_string(),
+        max_operations_ttl: 1,
+        last_allowed_fork_level: 0,
+        forking_testchain: false,
+        forking_testchain_data: None,
+    }
+}
+
+fn recover(storages: &Storages, log: &Logger) -> Result<ApplyBlockRecovery, failure::Error> {
+    Ok(
+        recover_block_application(
+            &storages.block_storage,
+            &storages.block_meta_storage,
+            &storages.chain_meta_storage,
+            &storages.context,
+            &storages.chain_id,
+            log,
+        )?
+    )
+}
+
+fn is_applied(storages: &Storages) -> Result<bool, failure::Error> {
+    Ok(storages.block_meta_storage.get(&storages.block.hash)?.unwrap().is_applied())
+}
+
+#[test]
+fn test_recover_block_application_killed_before_apply_finished() -> Result<(), failure::Error> {
+    let log = common::create_logger(common::log_level());
+    let tmp_storage = TmpStorage::create(common::prepare_empty_dir("__test_recover_killed_before_apply_finished"))?;
+    let storages = prepare_storages(&tmp_storage, &log)?;
+
+    // killed inside protocol_controller.apply_block
+    storages.chain_meta_storage.set_apply_block_intent(&storages.chain_id, ApplyBlockIntent::new(storages.block.hash.clone(), storages.block.header.level()))?;
+
+    assert_eq!(ApplyBlockRecovery::ReApply(storages.block.hash.clone()), recover(&storages, &log)?);
+    assert!(!is_applied(&storages)?);
+    assert!(storages.chain_meta_storage.get_apply_block_intent(&storages.chain_id)?.is_none());
+
+    // next start is clean
+    assert_eq!(ApplyBlockRecovery::Clean, recover(&storages, &log)?);
+    Ok(())
+}
+
+#[test]
+fn test_recover_block_application_killed_before_intent_removed() -> Result<(), failure::Error> {
+    let log = common::create_logger(common::log_level());
+    let tmp_storage = TmpStorage::create(common::prepare_empty_dir("__test_recover_killed_before_intent_removed"))?;
+    let storages = prepare_storages(&tmp_storage, &log)?;
+
+    // killed after store_applied_block_result, but before intent was removed
+    storages.chain_meta_storage.set_apply_block_intent(&storages.chain_id, ApplyBlockIntent::new(storages.block.hash.clone(), storages.block.header.level()))?;
+    let mut meta = storages.block_meta_storage.get(&storages.block.hash)?.unwrap();
+    store_applied_block_result(&storages.block_storage, &storages.block_meta_storage, &storages.block.hash, apply_block_response(&storages), &mut meta)?;
+
+    assert_eq!(ApplyBlockRecovery::AlreadyStored(storages.block.hash.clone()), recover(&storages, &log)?);
+    assert!(is_applied(&storages)?);
+    assert!(storages.chain_meta_storage.get_apply_block_intent(&storages.chain_id)?.is_none());
+
+    // context listener did not commit context of the block
+    assert_eq!(
+        Some(storages.block.header.context().clone()),
+        check_applied_block_context(&storages.block_storage, &storages.context, &storages.block.hash, &log)?,
+    );
+    Ok(())
+}
+
+#[test]
+fn test_recover_current_head_not_applied() -> Result<(), failure::Error> {
+    let log = common::create_logger(common::log_level());
+    let tmp_storage = TmpStorage::create(common::prepare_empty_dir("__test_recover_current_head_not_applied"))?;
+    let storages = prepare_storages(&tmp_storage, &log)?;
+
+    // current head points to the block, which was not applied
+    storages.chain_meta_storage.set_current_head(
+        &storages.chain_id,
+        Head::new(storages.block.hash.clone(), storages.block.header.level(), storages.block.header.fitness().clone()),
+    )?;
+
+    assert_eq!(ApplyBlockRecovery::Clean, recover(&storages, &log)?);
+    let current_head = storages.chain_meta_storage.get_current_head(&storages.chain_id)?.unwrap();
+    assert_eq!(&storages.genesis.hash, current_head.block_hash());
+    assert_eq!(storages.genesis.header.level(), *current_head.level());
+    Ok(())
+}
+
+/// Storage path of the child process, which runs chain feeder to be killed
+const KILLED_FEEDER_STORAGE_ENV: &str = "KILLED_FEEDER_STORAGE";
+const KILLED_FEEDER_TEST: &str = "test_recover_block_application_killed_during_apply";
+
+static APPLY_BLOCK_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Protocol, which never finishes block application
+struct InterruptedProtocol;
+
+impl ProtocolApi for InterruptedProtocol {
+    fn apply_block(_: ApplyBlockRequest) -> Result<ApplyBlockResponse, ApplyBlockError> {
+        APPLY_BLOCK_STARTED.store(true, Ordering::Release);
+        loop {
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    fn begin_application(_: BeginApplicationRequest) -> Result<BeginApplicationResponse, BeginApplicationError> {
+        unimplemented!()
+    }
+
+    fn begin_construction(_: BeginConstructionRequest) -> Result<PrevalidatorWrapper, BeginConstructionError> {
+        unimplemented!()
+    }
+
+    fn validate_operation(_: ValidateOperationRequest) -> Result<ValidateOperationResponse, ValidateOperationError> {
+        unimplemented!()
+    }
+
+    fn call_protocol_rpc(_: ProtocolRpcRequest) -> Result<ProtocolRpcResponse, ProtocolRpcError> {
+        unimplemented!()
+    }
+
+    fn helpers_preapply_operations(_: ProtocolRpcRequest) -> Result<HelpersPreapplyResponse, HelpersPreapplyError> {
+        unimplemented!()
+    }
+
+    fn helpers_preapply_block(_: ProtocolRpcRequest) -> Result<HelpersPreapplyResponse, HelpersPreapplyError> {
+        unimplemented!()
+    }
+
+    fn change_runtime_configuration(_: TezosRuntimeConfiguration) -> Result<(), TezosRuntimeConfigurationError> {
+        Ok(())
+    }
+
+    fn init_protocol_context(_: String, _: GenesisChain, _: ProtocolOverrides, _: bool, _: bool, _: bool, _: Option<PatchContext>) -> Result<InitProtocolContextResult, TezosStorageInitError> {
+        Ok(InitProtocolContextResult { supported_protocol_hashes: vec![], genesis_commit_hash: None })
+    }
+
+    fn genesis_result_data(_: &ContextHash, _: &ChainId, _: &ProtocolHash, _: u16) -> Result<CommitGenesisResult, GetDataError> {
+        unimplemented!()
+    }
+
+    fn commit_test_chain_genesis(_: CommitTestChainGenesisRequest) -> Result<CommitTestChainGenesisResult, TestChainGenesisError> {
+        unimplemented!()
+    }
+
+    fn compute_path(_: ComputePathRequest) -> Result<ComputePathResponse, ComputePathError> {
+        unimplemented!()
+    }
+
+    fn assert_encoding_for_protocol_data(_: ProtocolHash, _: Vec<u8>) -> Result<(), ProtocolDataError> {
+        unimplemented!()
+    }
+}
+
+/// Runs [InterruptedProtocol] in thread instead of protocol-runner sub-process
+#[derive(Clone)]
+struct InterruptedProtocolRunner {
+    sock_cmd_path: PathBuf,
+}
+
+impl ProtocolRunner for InterruptedProtocolRunner {
+    type Subprocess = ();
+
+    fn new(_: ProtocolEndpointConfiguration, sock_cmd_path: &Path, _: Option<PathBuf>, _: String) -> Self {
+        InterruptedProtocolRunner {
+            sock_cmd_path: sock_cmd_path.to_path_buf(),
+        }
+    }
+
+    fn spawn(&self) -> Result<Self::Subprocess, ProtocolServiceError> {
+        let sock_cmd_path = self.sock_cmd_path.clone();
+        thread::spawn(move || process_protocol_commands::<InterruptedProtocol, _>(sock_cmd_path));
+        Ok(())
+    }
+
+    fn terminate(_: Self::Subprocess) {}
+
+    fn terminate_ref(_: &mut Self::Subprocess) {}
+
+    fn is_running(_: &mut Self::Subprocess) -> bool {
+        true
+    }
+}
+
+/// Child process: starts chain feeder, sends block to the [InterruptedProtocol] and waits to be killed
+fn run_killed_feeder(storage_path: &str, log: Logger) -> Result<(), failure::Error> {
+    let tmp_storage = TmpStorage::create(storage_path)?;
+    let storages = prepare_storages(&tmp_storage, &log)?;
+    storages.block_storage.put_block_additional_data(
+        &storages.genesis.hash,
+        BlockAdditionalDataBuilder::default().max_operations_ttl(0).last_allowed_fork_level(0).build().unwrap(),
+    )?;
+    storages.chain_meta_storage.set_current_head(
+        &storages.chain_id,
+        Head::new(storages.genesis.hash.clone(), storages.genesis.header.level(), storages.genesis.header.fitness().clone()),
+    )?;
+
+    let tezos_env = TEZOS_ENV.get(&TezosEnvironment::Carthagenet).expect("no environment configuration");
+    let endpoint = ProtocolRunnerEndpoint::<InterruptedProtocolRunner>::new(
+        "interrupted_protocol",
+        ProtocolEndpointConfiguration::new(
+            TezosRuntimeConfiguration {
+                log_enabled: false,
+                no_of_ffi_calls_treshold_for_gc: 1000,
+                debug_mode: false,
+            },
+            tezos_env.clone(),
+            false,
+            storage_path,
+            storage_path,
+            Level::Info,
+            false,
+        ),
+        log.clone(),
+    );
+    endpoint.start()?;
+
+    let actor_system = SystemBuilder::new().name("killed_feeder").log(log.clone()).create().expect("Failed to create actor system");
+    let shell_channel = ShellChannel::actor(&actor_system).expect("Failed to create shell channel");
+    let init_storage_data = StorageInitInfo {
+        chain_id: storages.chain_id.clone(),
+        genesis_block_header_hash: storages.genesis.hash.clone(),
+        patch_context: None,
+    };
+    let chain_feeder = ChainFeeder::actor(&actor_system, shell_channel, tmp_storage.storage(), &init_storage_data, tezos_env, endpoint.commands, log).expect("Failed to create chain feeder");
+    chain_feeder.tell(ShellChannelMsg::ApplyBlock(storages.block.hash.clone()), None);
+
+    // let parent know, that block is being applied
+    while !APPLY_BLOCK_STARTED.load(Ordering::Acquire) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    fs::write(format!("{}.apply_started", storage_path), "")?;
+
+    loop {
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+#[test]
+fn test_recover_block_application_killed_during_apply() -> Result<(), failure::Error> {
+    let log = common::create_logger(common::log_level());
+    if let Ok(storage_path) = env::var(KILLED_FEEDER_STORAGE_ENV) {
+        return run_killed_feeder(&storage_path, log);
+    }
+
+    let storage_path = common::prepare_empty_dir("__test_recover_killed_during_apply");
+    let apply_started_marker = PathBuf::from(format!("{}.apply_started", storage_path));
+    let _ = fs::remove_file(&apply_started_marker);
+
+    // run chain feeder in the child process (this test) and kill it, while protocol applies block
+    let mut child = Command::new(env::current_exe()?)
+        .args(&["--exact", KILLED_FEEDER_TEST, "--nocapture"])
+        .env(KILLED_FEEDER_STORAGE_ENV, &storage_path)
+        .spawn()?;
+    let started = Instant::now();
+    while !apply_started_marker.exists() {
+        if let Some(status) = child.try_wait()? {
+            panic!("Chain feeder process finished before block application: {:?}", status);
+        }
+        assert!(started.elapsed() < Duration::from_secs(60), "Block application was not started");
+        thread::sleep(Duration::from_millis(50));
+    }
+    child.kill()?;
+    child.wait()?;
+    fs::remove_file(&apply_started_marker)?;
+
+    // "restart" with storage left behind by killed feeder
+    let tmp_storage = TmpStorage::open(&storage_path)?;
+    let storages = open_storages(&tmp_storage)?;
+    let intent = storages.chain_meta_storage.get_apply_block_intent(&storages.chain_id)?.expect("Apply block intent was not stored");
+    assert_eq!(storages.block.hash, intent.block_hash);
+    assert!(!is_applied(&storages)?);
+
+    assert_eq!(ApplyBlockRecovery::ReApply(storages.block.hash.clone()), recover(&storages, &log)?);
+    assert!(!is_applied(&storages)?);
+    assert!(storages.chain_meta_storage.get_apply_block_intent(&storages.chain_id)?.is_none());
+    Ok(())
+}
@@ -0,0 +1,90 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+/// Synchronization scenarios of the chain manager against simulated peers and simulated time.
+///
+/// Tests do not need protocol-runner, blocks are applied by the simulated block applier (see [common::simulation]).
+
+use std::time::Duration;
+
+use common::simulation::{PeerBehavior, SimulatedChain, Simulation};
+
+mod common;
+
+const NO_LATENCY: Duration = Duration::from_secs(0);
+const SYNC_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[test]
+fn test_simulation_sync_from_single_peer() -> Result<(), failure::Error> {
+    let log = common::create_logger(common::log_level());
+    let mut simulation = Simulation::start("__sim_sync_from_single_peer", log)?;
+
+    let chain = SimulatedChain::new(&simulation.genesis, 25);
+    simulation.connect_peer("peer_1", &chain, PeerBehavior::Honest, NO_LATENCY)?;
+
+    simulation.run_until_head(&chain.head().hash, SYNC_TIMEOUT)?;
+    Ok(())
+}
+
+#[test]
+fn test_simulation_switch_to_heavier_fork() -> Result<(), failure::Error> {
+    let log = common::create_logger(common::log_level());
+    let mut simulation = Simulation::start("__sim_switch_to_heavier_fork", log)?;
+
+    let chain = SimulatedChain::new(&simulation.genesis, 10);
+    simulation.connect_peer("peer_1", &chain, PeerBehavior::Honest, NO_LATENCY)?;
+    simulation.run_until_head(&chain.head().hash, SYNC_TIMEOUT)?;
+
+    // fork from level 5 is longer, so it has higher fitness
+    let fork = chain.fork(5, 8, 1);
+    assert_ne!(chain.block(6).hash, fork.block(6).hash);
+    simulation.connect_peer("peer_2", &fork, PeerBehavior::Honest, NO_LATENCY)?;
+    simulation.run_until_head(&fork.head().hash, SYNC_TIMEOUT)?;
+    Ok(())
+}
+
+#[test]
+fn test_simulation_stalled_peer_requests_are_rescheduled() -> Result<(), failure::Error> {
+    let log = common::create_logger(common::log_level());
+    let mut simulation = Simulation::start("__sim_stalled_peer", log)?;
+
+    // stalled peer gets requests first, which are rescheduled to the honest peer after request timeout
+    let chain = SimulatedChain::new(&simulation.genesis, 15);
+    simulation.connect_peer("stalled_peer", &chain, PeerBehavior::Stalled, NO_LATENCY)?;
+    simulation.advance(Duration::from_secs(1));
+    simulation.connect_peer("honest_peer", &chain, PeerBehavior::Honest, Duration::from_secs(1))?;
+
+    simulation.run_until_head(&chain.head().hash, Duration::from_secs(100))?;
+    Ok(())
+}
+
+#[test]
+fn test_simulation_invalid_peer_is_blacklisted() -> Result<(), failure::Error> {
+    let log = common::create_logger(common::log_level());
+    let mut simulation = Simulation::start("__sim_invalid_peer", log)?;
+
+    let chain = SimulatedChain::new(&simulation.genesis, 10).with_invalid_block(6);
+    simulation.connect_peer("invalid_peer", &chain, PeerBehavior::Honest, NO_LATENCY)?;
+    simulation.advance(Duration::from_secs(20));
+
+    assert!(simulation.is_blacklisted("invalid_peer"));
+    let current_head = simulation.current_head()?.expect("Current head should be set");
+    assert!(*current_head.level() < 6);
+    Ok(())
+}
+
+#[test]
+fn test_simulation_sync_from_slow_peer() -> Result<(), failure::Error> {
+    let log = common::create_logger(common::log_level());
+    let mut simulation = Simulation::start("__sim_slow_peer", log)?;
+
+    let latency = Duration::from_secs(3);
+    let chain = SimulatedChain::new(&simulation.genesis, 10);
+    simulation.connect_peer("slow_peer", &chain, PeerBehavior::Honest, latency)?;
+
+    // at least current branch and block headers are waiting for the peer
+    let elapsed = simulation.run_until_head(&chain.head().hash, SYNC_TIMEOUT)?;
+    assert!(elapsed >= latency * 2);
+    assert!(!simulation.is_blacklisted("slow_peer"));
+    Ok(())
+}
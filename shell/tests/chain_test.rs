@@ -15,8 +15,9 @@ use std::time::{Duration, Instant};
 use lazy_static::lazy_static;
 use serial_test::serial;
 
+use networking::p2p::peer::PeerTimeouts;
 use shell::peer_manager::P2p;
-use shell::PeerConnectionThreshold;
+use shell::{PeerConnectionThreshold, PeerRoles};
 use storage::{BlockMetaStorage, BlockMetaStorageReader};
 use storage::tests_common::TmpStorage;
 use tezos_identity::Identity;
@@ -37,8 +38,14 @@ lazy_static! {
             disable_bootstrap_lookup: true,
             disable_mempool: false,
             private_node: false,
+            peer_roles: PeerRoles::default(),
             initial_peers: vec![],
             peer_threshold: PeerConnectionThreshold::new(0, 10),
+            capture_file: None,
+            connect_timeout: Duration::from_secs(8),
+            peer_timeouts: PeerTimeouts::default(),
+            max_pending_incoming_handshakes: 20,
+            max_pending_outgoing_handshakes: 20,
         },
         NETWORK_VERSION.clone(),
     );
@@ -769,7 +776,7 @@ mod test_node_peer {
     use tokio::time::timeout;
 
     use networking::p2p::peer;
-    use networking::p2p::peer::{Bootstrap, BootstrapOutput, Local};
+    use networking::p2p::peer::{Bootstrap, BootstrapOutput, Local, PeerTimeouts};
     use networking::p2p::stream::{EncryptedMessageReader, EncryptedMessageWriter};
     use tezos_identity::Identity;
     use tezos_messages::p2p::encoding::prelude::{PeerMessage, PeerMessageResponse};
@@ -817,7 +824,8 @@ mod test_node_peer {
                                 identity.public_key,
                                 identity.secret_key,
                                 identity.proof_of_work_stamp,
-                                network_version,
+                                network_version.into(),
+                                PeerTimeouts::default(),
                             ));
                             let bootstrap = Bootstrap::outgoing(
                                 stream,
@@ -1077,6 +1085,7 @@ mod test_actor {
             match msg {
                 NetworkChannelMsg::PeerMessageReceived(_) => {}
                 NetworkChannelMsg::PeerCreated(_) => {}
+                NetworkChannelMsg::PeerIdleTimeout(_) => {}
                 NetworkChannelMsg::PeerBootstrapped(peer) => {
                     if let PeerBootstrapped::Success { peer_id, .. } = peer {
                         let peer_public_key = HashType::CryptoboxPublicKeyHash.bytes_to_string(peer_id.peer_public_key.as_ref());
@@ -61,6 +61,10 @@ pub fn protocol_runner_executable_path() -> PathBuf {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NoopMessage;
 
+/// Module which runs chain manager against simulated time and simulated network
+#[allow(dead_code)]
+pub mod simulation;
+
 /// Module which runs actor's very similar than real node runs
 #[allow(dead_code)]
 pub mod infra {
@@ -79,11 +83,14 @@ pub mod infra {
     use networking::p2p::network_channel::{NetworkChannel, NetworkChannelRef};
     use shell::chain_feeder::ChainFeeder;
     use shell::chain_manager::ChainManager;
+    use shell::clock::system_clock;
+    use shell::configuration::{MempoolConfiguration, ShellConfiguration};
     use shell::context_listener::ContextListener;
     use shell::mempool_prevalidator::MempoolPrevalidator;
     use shell::peer_manager::{P2p, PeerManager, PeerManagerRef, WhitelistAllIpAddresses};
-    use shell::PeerConnectionThreshold;
+    use shell::{PeerConnectionThreshold, PeerRoles};
     use shell::shell_channel::{ShellChannel, ShellChannelRef, ShellChannelTopic, ShuttingDown};
+    use shell::stats::handshake::HandshakeStats;
     use storage::{BlockStorage, ChainMetaStorage, context_key, resolve_storage_init_chain_data};
     use storage::chain_meta_storage::ChainMetaStorageReader;
     use storage::context::{ContextApi, TezedgeContext};
@@ -214,8 +221,12 @@ pub mod infra {
                 tezos_readonly_api.clone(),
                 &init_storage_data.chain_id,
                 is_sandbox,
+                false,
                 &p2p_threshold,
+                &p2p.as_ref().map(|(p2p_config, _)| p2p_config.peer_roles.clone()).unwrap_or_default(),
                 identity.clone(),
+                system_clock(),
+                ShellConfiguration::default().into_ref(),
             ).expect("Failed to create chain manager");
             let _ = MempoolPrevalidator::actor(
                 &actor_system,
@@ -223,6 +234,8 @@ pub mod infra {
                 &persistent_storage,
                 &init_storage_data,
                 tezos_readonly_api.clone(),
+                MempoolConfiguration::default(),
+                MempoolFilter::default().into_ref(),
                 log.clone(),
             ).expect("Failed to create chain feeder");
 
@@ -234,8 +247,11 @@ pub mod infra {
                     shell_channel.clone(),
                     tokio_runtime.handle().clone(),
                     identity,
-                    network_version,
+                    network_version.into(),
                     p2p_config,
+                    None,
+                    HandshakeStats::new_ref(),
+                    system_clock(),
                 ).expect("Failed to create peer manager");
                 Some(peer_manager)
             } else {
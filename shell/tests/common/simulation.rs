@@ -0,0 +1,793 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Deterministic simulation harness for the [ChainManager].
+//!
+//! Time is simulated by [SimulatedClock], so timers of the chain manager and the peer manager are fired by the harness
+//! and request timeouts are resolved against simulated time (no sleeping on real timers).
+//! Each simulation step waits, until all actors processed all messages (see [Simulation::settle]),
+//! so the result of the step does not depend on the speed of the machine.
+//! Network is simulated by fake peers, which are actors attached directly to the network channel (no sockets, no handshake),
+//! each peer serves its own scripted chain and can be slow, stalled or serving invalid data.
+//! Peer manager is the real one, but it never opens connections (no bootstrap addresses, no initial peers),
+//! it just blacklists and stops the misbehaving simulated peers.
+//! Blocks are "applied" by a fake applier, which just marks them as applied in the storage (no protocol runner is needed),
+//! so the harness can assert on the current head stored in the [ChainMetaStorage].
+//!
+//! Simulated chains contain only blocks without operations (validation_pass = 0).
+
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+use failure::format_err;
+use riker::actors::*;
+use riker::system::SystemBuilder;
+use slog::{Level, Logger, warn};
+
+use crypto::hash::{BlockHash, ChainId, ContextHash};
+use networking::p2p::network_channel::{NetworkChannel, NetworkChannelMsg, NetworkChannelRef, NetworkChannelTopic, PeerBootstrapped, PeerIdleTimeout, PeerMessageReceived};
+use networking::p2p::peer::{PeerMsg, PeerRef, PeerTimeouts, SendMessage};
+use networking::PeerId;
+use shell::chain_manager::{ChainManager, ChainManagerMsg, ChainManagerRef};
+use shell::clock::{Clock, ClockRef, Ping, Pong, SimulatedClock};
+use shell::configuration::ShellConfiguration;
+use shell::peer_manager::{P2p, PeerManager, PeerManagerMsg, PeerManagerRef};
+use shell::PeerConnectionThreshold;
+use shell::PeerRoles;
+use shell::shell_channel::{BlockApplied, ShellChannel, ShellChannelMsg, ShellChannelRef, ShellChannelTopic, ShuttingDown};
+use shell::stats::handshake::HandshakeStats;
+use storage::{BlockHeaderWithHash, BlockMetaStorage, BlockMetaStorageReader, BlockStorage, BlockStorageReader, ChainMetaStorage, initialize_storage_with_genesis_block, OperationsMetaStorage, resolve_storage_init_chain_data, store_applied_block_result, store_commit_genesis_result, StorageInitInfo};
+use storage::chain_meta_storage::ChainMetaStorageReader;
+use storage::persistent::PersistentStorage;
+use storage::tests_common::TmpStorage;
+use tezos_api::environment::{OPERATION_LIST_LIST_HASH_EMPTY, TEZOS_ENV, TezosEnvironment, TezosEnvironmentConfiguration};
+use tezos_api::ffi::{ApplyBlockResponse, CommitGenesisResult, TezosRuntimeConfiguration};
+use tezos_identity::Identity;
+use tezos_messages::Head;
+use tezos_messages::p2p::encoding::block_header::Level as BlockLevel;
+use tezos_messages::p2p::encoding::prelude::*;
+use tezos_messages::p2p::encoding::version::NetworkVersion;
+use tezos_wrapper::{TezosApiConnectionPool, TezosApiConnectionPoolConfiguration};
+use tezos_wrapper::service::ProtocolEndpointConfiguration;
+
+/// Simulated time is advanced by this step, timers and deliveries are resolved after each step
+const SIMULATION_STEP: Duration = Duration::from_millis(500);
+/// Actors are expected to answer ping immediately, this just stops the broken simulation instead of hanging
+const ACK_TIMEOUT: Duration = Duration::from_secs(30);
+/// Topic of the channels, which is used just to flush messages published to the channel before
+const SIMULATION_TOPIC: &str = "simulation";
+/// Simulated peer answers request for the current branch of this chain just to the harness (see [Simulation::settle])
+const PING_CHAIN_ID: [u8; 4] = [0xff; 4];
+/// Pings are sent by the harness on behalf of this actor
+const PONG_RECEIVER_NAME: &str = "simulated-pong-receiver";
+/// Simulated time between two blocks
+const BLOCK_INTERVAL_SECS: i64 = 60;
+
+/// Scripted chain served by the simulated peers.
+///
+/// Blocks are generated on top of the genesis, block at index `i` has level `i`.
+#[derive(Clone)]
+pub struct SimulatedChain {
+    blocks: Vec<BlockHeaderWithHash>,
+}
+
+impl SimulatedChain {
+    /// Creates chain with `length` blocks on top of genesis
+    pub fn new(genesis: &BlockHeaderWithHash, length: BlockLevel) -> Self {
+        let mut chain = SimulatedChain { blocks: vec![genesis.clone()] };
+        chain.extend(length, 0);
+        chain
+    }
+
+    /// Creates new chain, which shares blocks up to `fork_level` and continues with `length` own blocks
+    pub fn fork(&self, fork_level: BlockLevel, length: BlockLevel, fork_id: u8) -> Self {
+        let mut chain = SimulatedChain { blocks: self.blocks[..=fork_level as usize].to_vec() };
+        chain.extend(length, fork_id);
+        chain
+    }
+
+    /// Creates copy of the chain, where block at `level` has the same timestamp as its predecessor,
+    /// so it does not pass prevalidation, all successors are rebuilt on top of it
+    pub fn with_invalid_block(&self, level: BlockLevel) -> Self {
+        let mut chain = SimulatedChain { blocks: self.blocks[..level as usize].to_vec() };
+        let predecessor = chain.head().clone();
+        chain.push(&predecessor, predecessor.header.timestamp(), 0);
+        chain.extend(self.head_level() - level, 0);
+        chain
+    }
+
+    pub fn head(&self) -> &BlockHeaderWithHash {
+        self.blocks.last().unwrap()
+    }
+
+    pub fn head_level(&self) -> BlockLevel {
+        self.head().header.level()
+    }
+
+    pub fn block(&self, level: BlockLevel) -> &BlockHeaderWithHash {
+        &self.blocks[level as usize]
+    }
+
+    pub fn get(&self, block_hash: &BlockHash) -> Option<&BlockHeaderWithHash> {
+        self.blocks.iter().find(|block| &block.hash == block_hash)
+    }
+
+    /// History for the current branch message, the most recent blocks are first
+    fn history(&self) -> Vec<BlockHash> {
+        self.blocks.iter()
+            .skip(1)
+            .take(self.blocks.len().saturating_sub(2))
+            .rev()
+            .map(|block| block.hash.clone())
+            .collect()
+    }
+
+    fn extend(&mut self, length: BlockLevel, fork_id: u8) {
+        for _ in 0..length {
+            let predecessor = self.head().clone();
+            self.push(&predecessor, predecessor.header.timestamp() + BLOCK_INTERVAL_SECS, fork_id);
+        }
+    }
+
+    fn push(&mut self, predecessor: &BlockHeaderWithHash, timestamp: i64, fork_id: u8) {
+        let level = predecessor.header.level() + 1;
+        let header = BlockHeaderBuilder::default()
+            .level(level)
+            .proto(predecessor.header.proto())
+            .predecessor(predecessor.hash.clone())
+            .timestamp(timestamp)
+            .validation_pass(0)
+            .operations_hash(OPERATION_LIST_LIST_HASH_EMPTY.clone())
+            .fitness(vec![vec![1], (level as u64).to_be_bytes().to_vec()])
+            .context(vec![fork_id; 32])
+            .protocol_data(vec![fork_id; 8])
+            .build()
+            .unwrap();
+        self.blocks.push(BlockHeaderWithHash::new(header).unwrap());
+    }
+}
+
+/// How does the simulated peer respond to the requests
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PeerBehavior {
+    /// Serves everything what is requested
+    Honest,
+    /// Announces its current branch, but never sends requested blocks
+    Stalled,
+}
+
+/// Message sent by simulated peer, which is delivered to the node after peer's latency
+struct InFlightMessage {
+    deliver_at: Instant,
+    seq: u64,
+    peer: PeerRef,
+    message: PeerMessageResponse,
+}
+
+/// Shared state of the simulated network
+#[derive(Default)]
+struct NetworkState {
+    in_flight: Vec<InFlightMessage>,
+    seq: u64,
+    blacklisted: HashSet<String>,
+    /// Peers stopped by chain manager or peer manager, they do not answer ping anymore
+    stopped: HashSet<String>,
+    /// Count of messages processed by the simulated actors, harness waits, until it stops to change
+    processed_messages: u64,
+}
+
+type NetworkStateRef = Arc<Mutex<NetworkState>>;
+
+/// Answer of the simulated actor to the harness
+#[derive(Debug)]
+enum Ack {
+    /// Actor processed all messages received before the harness request
+    Idle,
+    /// Real actor answered [Ping]
+    Pong(u64),
+}
+
+type AckSender = Arc<Mutex<mpsc::Sender<Ack>>>;
+
+fn send_ack(acks: &AckSender, ack: Ack) {
+    let _ = acks.lock().unwrap().send(ack);
+}
+
+/// Remote peer, which serves scripted chain
+struct SimulatedPeer {
+    chain_id: ChainId,
+    chain: SimulatedChain,
+    behavior: PeerBehavior,
+    latency: Duration,
+    clock: ClockRef,
+    network: NetworkStateRef,
+    acks: AckSender,
+}
+
+impl SimulatedPeer {
+    fn serve(&self, message: &PeerMessage) -> Vec<PeerMessageResponse> {
+        match message {
+            PeerMessage::GetCurrentBranch(request) if request.chain_id == self.chain_id => {
+                vec![CurrentBranchMessage::new(
+                    self.chain_id.clone(),
+                    CurrentBranch::new((*self.chain.head().header).clone(), self.chain.history()),
+                ).into()]
+            }
+            PeerMessage::GetBlockHeaders(request) if self.behavior == PeerBehavior::Honest => {
+                request.get_block_headers().iter()
+                    .filter_map(|block_hash| self.chain.get(block_hash))
+                    .map(|block| BlockHeaderMessage::from((*block.header).clone()).into())
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+}
+
+impl Actor for SimulatedPeer {
+    type Msg = PeerMsg;
+
+    fn recv(&mut self, ctx: &Context<Self::Msg>, msg: Self::Msg, _sender: Sender) {
+        if let PeerMsg::SendMessage(msg) = msg {
+            let is_ping = msg.message().messages().iter()
+                .any(|message| matches!(message, PeerMessage::GetCurrentBranch(request) if request.chain_id[..] == PING_CHAIN_ID));
+            if is_ping {
+                send_ack(&self.acks, Ack::Idle);
+                return;
+            }
+
+            let deliver_at = self.clock.now() + self.latency;
+            let responses = msg.message().messages().iter()
+                .flat_map(|message| self.serve(message))
+                .collect::<Vec<_>>();
+
+            let mut network = self.network.lock().unwrap();
+            network.processed_messages += 1;
+            for message in responses {
+                network.seq += 1;
+                let seq = network.seq;
+                network.in_flight.push(InFlightMessage { deliver_at, seq, peer: ctx.myself(), message });
+            }
+        }
+    }
+}
+
+impl ActorFactoryArgs<(ChainId, SimulatedChain, PeerBehavior, Duration, ClockRef, NetworkStateRef, AckSender)> for SimulatedPeer {
+    fn create_args((chain_id, chain, behavior, latency, clock, network, acks): (ChainId, SimulatedChain, PeerBehavior, Duration, ClockRef, NetworkStateRef, AckSender)) -> Self {
+        SimulatedPeer { chain_id, chain, behavior, latency, clock, network, acks }
+    }
+}
+
+/// Records peers blacklisted by the peer manager and stopped peers, which did not receive ping
+///
+/// There are no real peers in the simulation, so [PeerIdleTimeout] is published just by the harness,
+/// to flush the network channel (see [Simulation::settle]).
+#[actor(NetworkChannelMsg, DeadLetter)]
+struct SimulatedNetworkObserver {
+    network_channel: NetworkChannelRef,
+    network: NetworkStateRef,
+    acks: AckSender,
+}
+
+impl Actor for SimulatedNetworkObserver {
+    type Msg = SimulatedNetworkObserverMsg;
+
+    fn pre_start(&mut self, ctx: &Context<Self::Msg>) {
+        for topic in &[NetworkChannelTopic::NetworkEvents.into(), Topic::from(SIMULATION_TOPIC)] {
+            self.network_channel.tell(Subscribe {
+                actor: Box::new(ctx.myself()),
+                topic: topic.clone(),
+            }, None);
+        }
+        ctx.system.dead_letters().tell(Subscribe {
+            actor: Box::new(ctx.myself()),
+            topic: All.into(),
+        }, None);
+    }
+
+    fn recv(&mut self, ctx: &Context<Self::Msg>, msg: Self::Msg, sender: Sender) {
+        self.receive(ctx, msg, sender);
+    }
+}
+
+impl ActorFactoryArgs<(NetworkChannelRef, NetworkStateRef, AckSender)> for SimulatedNetworkObserver {
+    fn create_args((network_channel, network, acks): (NetworkChannelRef, NetworkStateRef, AckSender)) -> Self {
+        SimulatedNetworkObserver { network_channel, network, acks }
+    }
+}
+
+impl Receive<NetworkChannelMsg> for SimulatedNetworkObserver {
+    type Msg = SimulatedNetworkObserverMsg;
+
+    fn receive(&mut self, _ctx: &Context<Self::Msg>, msg: NetworkChannelMsg, _sender: Sender) {
+        match msg {
+            NetworkChannelMsg::PeerBlacklisted(peer_id) => {
+                self.network.lock().unwrap().blacklisted.insert(peer_id.peer_ref.name().to_string());
+            }
+            NetworkChannelMsg::PeerIdleTimeout(_) => send_ack(&self.acks, Ack::Idle),
+            _ => (),
+        }
+    }
+}
+
+impl Receive<DeadLetter> for SimulatedNetworkObserver {
+    type Msg = SimulatedNetworkObserverMsg;
+
+    fn receive(&mut self, _ctx: &Context<Self::Msg>, msg: DeadLetter, _sender: Sender) {
+        let is_ping = msg.sender.as_ref().map(|sender| sender.name() == PONG_RECEIVER_NAME).unwrap_or(false);
+        if is_ping {
+            self.network.lock().unwrap().stopped.insert(msg.recipient.name().to_string());
+            send_ack(&self.acks, Ack::Idle);
+        }
+    }
+}
+
+/// Receives [Pong] from the chain manager and the peer manager
+struct SimulatedPongReceiver {
+    acks: AckSender,
+}
+
+impl Actor for SimulatedPongReceiver {
+    type Msg = Pong;
+
+    fn recv(&mut self, _ctx: &Context<Self::Msg>, msg: Self::Msg, _sender: Sender) {
+        send_ack(&self.acks, Ack::Pong(msg.processed_messages));
+    }
+}
+
+impl ActorFactoryArgs<AckSender> for SimulatedPongReceiver {
+    fn create_args(acks: AckSender) -> Self {
+        SimulatedPongReceiver { acks }
+    }
+}
+
+/// Replaces chain feeder, blocks are marked as applied without protocol runner
+#[actor(ShellChannelMsg)]
+struct SimulatedBlockApplier {
+    shell_channel: ShellChannelRef,
+    block_storage: BlockStorage,
+    block_meta_storage: BlockMetaStorage,
+    network: NetworkStateRef,
+    acks: AckSender,
+}
+
+impl SimulatedBlockApplier {
+    fn apply_block(&mut self, ctx: &Context<SimulatedBlockApplierMsg>, block_hash: &BlockHash) -> Result<(), failure::Error> {
+        let mut block_meta = self.block_meta_storage.get(block_hash)?.ok_or_else(|| format_err!("Missing block metadata"))?;
+        if block_meta.is_applied() {
+            return Ok(());
+        }
+        let block = self.block_storage.get(block_hash)?.ok_or_else(|| format_err!("Missing block header"))?;
+
+        let (block_json_data, _) = store_applied_block_result(
+            &self.block_storage,
+            &self.block_meta_storage,
+            block_hash,
+            ApplyBlockResponse {
+                validation_result_message: String::new(),
+                context_hash: block.header.context().clone(),
+                block_header_proto_json: String::new(),
+                block_header_proto_metadata_json: String::new(),
+                operations_proto_metadata_json: String::new(),
+                max_operations_ttl: 60,
+                last_allowed_fork_level: 0,
+                forking_testchain: false,
+                forking_testchain_data: None,
+            },
+            &mut block_meta,
+        )?;
+
+        self.shell_channel.tell(
+            Publish {
+                msg: BlockApplied::new(block, block_json_data).into(),
+                topic: ShellChannelTopic::ShellEvents.into(),
+            }, Some(ctx.myself().into()));
+        Ok(())
+    }
+}
+
+impl Actor for SimulatedBlockApplier {
+    type Msg = SimulatedBlockApplierMsg;
+
+    fn pre_start(&mut self, ctx: &Context<Self::Msg>) {
+        for topic in &[ShellChannelTopic::ShellEvents.into(), Topic::from(SIMULATION_TOPIC)] {
+            self.shell_channel.tell(Subscribe {
+                actor: Box::new(ctx.myself()),
+                topic: topic.clone(),
+            }, None);
+        }
+    }
+
+    fn recv(&mut self, ctx: &Context<Self::Msg>, msg: Self::Msg, sender: Sender) {
+        self.receive(ctx, msg, sender);
+    }
+}
+
+impl ActorFactoryArgs<(ShellChannelRef, PersistentStorage, NetworkStateRef, AckSender)> for SimulatedBlockApplier {
+    fn create_args((shell_channel, persistent_storage, network, acks): (ShellChannelRef, PersistentStorage, NetworkStateRef, AckSender)) -> Self {
+        SimulatedBlockApplier {
+            shell_channel,
+            block_storage: BlockStorage::new(&persistent_storage),
+            block_meta_storage: BlockMetaStorage::new(&persistent_storage),
+            network,
+            acks,
+        }
+    }
+}
+
+impl Receive<ShellChannelMsg> for SimulatedBlockApplier {
+    type Msg = SimulatedBlockApplierMsg;
+
+    fn receive(&mut self, ctx: &Context<Self::Msg>, msg: ShellChannelMsg, _sender: Sender) {
+        match msg {
+            ShellChannelMsg::ApplyBlock(block_hash) => {
+                self.network.lock().unwrap().processed_messages += 1;
+                if let Err(e) = self.apply_block(ctx, &block_hash) {
+                    warn!(ctx.system.log(), "Failed to apply simulated block"; "reason" => format!("{:?}", e));
+                }
+            }
+            // nobody shuts down the simulated shell, so it is published just by the harness to flush the shell channel
+            ShellChannelMsg::ShuttingDown(_) => send_ack(&self.acks, Ack::Idle),
+            _ => (),
+        }
+    }
+}
+
+/// Periodic message of the chain manager or the peer manager fired by the simulation
+struct Timer {
+    due: Instant,
+    interval: Duration,
+    msg: TimerMsg,
+}
+
+enum TimerMsg {
+    ChainManager(ChainManagerMsg),
+    PeerManager(PeerManagerMsg),
+}
+
+/// Chain manager running against simulated time and simulated network
+pub struct Simulation {
+    pub log: Logger,
+    pub clock: SimulatedClock,
+    pub genesis: BlockHeaderWithHash,
+    pub chain_id: ChainId,
+    actor_system: ActorSystem,
+    shell_channel: ShellChannelRef,
+    network_channel: NetworkChannelRef,
+    chain_manager: ChainManagerRef,
+    peer_manager: PeerManagerRef,
+    tmp_storage: TmpStorage,
+    network: NetworkStateRef,
+    peers: HashMap<String, PeerRef>,
+    timers: Vec<Timer>,
+    /// Simulated peer, which is never connected, it is used just to publish [PeerIdleTimeout]
+    unconnected_peer: PeerRef,
+    pong_receiver: BasicActorRef,
+    acks: mpsc::Receiver<Ack>,
+    ack_sender: AckSender,
+    /// Peer manager needs tokio runtime, even if it never opens a connection
+    _tokio_runtime: tokio::runtime::Runtime,
+}
+
+impl Simulation {
+    pub fn start(name: &str, log: Logger) -> Result<Self, failure::Error> {
+        let tezos_env: &TezosEnvironmentConfiguration = TEZOS_ENV.get(&TezosEnvironment::Sandbox).expect("no environment configuration");
+        let tmp_storage = TmpStorage::create(super::prepare_empty_dir(name))?;
+        let clock = SimulatedClock::new();
+
+        // storage is initialized with applied genesis, as chain feeder would do it
+        let init_storage_data = resolve_storage_init_chain_data(tezos_env, tmp_storage.path(), tmp_storage.path(), &None, &log)?;
+        let genesis = Self::init_genesis(&tmp_storage, &init_storage_data, tezos_env, &log)?;
+
+        let actor_system = SystemBuilder::new().name(name).log(log.clone()).create().expect("Failed to create actor system");
+        let shell_channel = ShellChannel::actor(&actor_system).expect("Failed to create shell channel");
+        let network_channel = NetworkChannel::actor(&actor_system).expect("Failed to create network channel");
+        let network: NetworkStateRef = Arc::new(Mutex::new(NetworkState::default()));
+        let (ack_sender, acks) = mpsc::channel();
+        let ack_sender: AckSender = Arc::new(Mutex::new(ack_sender));
+
+        actor_system.actor_of_props::<SimulatedNetworkObserver>(
+            "simulated-network-observer",
+            Props::new_args((network_channel.clone(), network.clone(), ack_sender.clone())),
+        ).expect("Failed to create simulated network observer");
+        actor_system.actor_of_props::<SimulatedBlockApplier>(
+            "simulated-block-applier",
+            Props::new_args((shell_channel.clone(), tmp_storage.storage().clone(), network.clone(), ack_sender.clone())),
+        ).expect("Failed to create simulated block applier");
+        let pong_receiver = actor_system.actor_of_props::<SimulatedPongReceiver>(
+            PONG_RECEIVER_NAME,
+            Props::new_args(ack_sender.clone()),
+        ).expect("Failed to create simulated pong receiver");
+        let unconnected_peer = actor_system.actor_of_props::<SimulatedPeer>(
+            "simulated-unconnected-peer",
+            Props::new_args((init_storage_data.chain_id.clone(), SimulatedChain::new(&genesis, 0), PeerBehavior::Stalled, Duration::from_secs(0), Arc::new(clock.clone()) as ClockRef, network.clone(), ack_sender.clone())),
+        ).expect("Failed to create simulated peer");
+
+        let tokio_runtime = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()?;
+        let peer_manager = PeerManager::actor(
+            &actor_system,
+            network_channel.clone(),
+            shell_channel.clone(),
+            tokio_runtime.handle().clone(),
+            Arc::new(Identity::generate(0f64)),
+            NetworkVersion::new("SIMULATION".to_string(), 0, 0).into(),
+            P2p {
+                listener_port: 0,
+                disable_bootstrap_lookup: true,
+                bootstrap_lookup_addresses: vec![],
+                initial_peers: vec![],
+                peer_threshold: PeerConnectionThreshold::new(0, 100),
+                disable_mempool: false,
+                private_node: false,
+                peer_roles: PeerRoles::default(),
+                capture_file: None,
+                connect_timeout: Duration::from_secs(8),
+                peer_timeouts: PeerTimeouts::default(),
+                max_pending_incoming_handshakes: 1,
+                max_pending_outgoing_handshakes: 1,
+            },
+            None,
+            HandshakeStats::new_ref(),
+            Arc::new(clock.clone()),
+        ).expect("Failed to create peer manager");
+
+        let shell_config = ShellConfiguration::default();
+        let chain_manager = ChainManager::actor(
+            &actor_system,
+            network_channel.clone(),
+            shell_channel.clone(),
+            tmp_storage.storage(),
+            Arc::new(Self::create_unused_pool(name, tezos_env, &log)),
+            &init_storage_data.chain_id,
+            false,
+            false,
+            &PeerConnectionThreshold::new(1, 1),
+            &PeerRoles::default(),
+            Arc::new(Identity::generate(0f64)),
+            Arc::new(clock.clone()),
+            shell_config.into_ref(),
+        ).expect("Failed to create chain manager");
+
+        let now = clock.now();
+        let chain_manager_timers = ChainManager::timers(&shell_config, false).into_iter()
+            .map(|(initial_delay, interval, msg)| (initial_delay, interval, TimerMsg::ChainManager(msg)));
+        let peer_manager_timers = PeerManager::timers().into_iter()
+            .map(|(initial_delay, interval, msg)| (initial_delay, interval, TimerMsg::PeerManager(msg)));
+        let timers = chain_manager_timers.chain(peer_manager_timers)
+            .map(|(initial_delay, interval, msg)| Timer { due: now + initial_delay, interval, msg })
+            .collect();
+
+        Ok(Simulation {
+            log,
+            clock,
+            genesis,
+            chain_id: init_storage_data.chain_id,
+            actor_system,
+            shell_channel,
+            network_channel,
+            chain_manager,
+            peer_manager,
+            tmp_storage,
+            network,
+            peers: HashMap::new(),
+            timers,
+            unconnected_peer,
+            pong_receiver: pong_receiver.into(),
+            acks,
+            ack_sender,
+            _tokio_runtime: tokio_runtime,
+        })
+    }
+
+    /// Connects simulated peer, which serves `chain` and responds after `latency`
+    pub fn connect_peer(&mut self, name: &str, chain: &SimulatedChain, behavior: PeerBehavior, latency: Duration) -> Result<(), failure::Error> {
+        let clock: ClockRef = Arc::new(self.clock.clone());
+        let peer_ref = self.actor_system.actor_of_props::<SimulatedPeer>(
+            name,
+            Props::new_args((self.chain_id.clone(), chain.clone(), behavior, latency, clock, self.network.clone(), self.ack_sender.clone())),
+        ).expect("Failed to create simulated peer");
+
+        let peer_index = self.peers.len() as u8 + 1;
+        let peer_address: SocketAddr = format!("127.0.0.{}:9732", peer_index).parse()?;
+        self.network_channel.tell(
+            Publish {
+                msg: PeerBootstrapped::Success {
+                    peer_id: Arc::new(PeerId::new(peer_ref.clone(), vec![peer_index; 32], peer_address)),
+                    peer_metadata: MetadataMessage::new(false, false),
+                    network_version: NetworkVersion::new("SIMULATION".to_string(), 0, 0),
+                }.into(),
+                topic: NetworkChannelTopic::NetworkEvents.into(),
+            }, None);
+        self.peers.insert(name.to_string(), peer_ref);
+        Ok(())
+    }
+
+    /// Advances simulated time, messages and timers are processed in simulation steps
+    pub fn advance(&mut self, duration: Duration) {
+        let until = self.clock.now() + duration;
+        while self.clock.now() < until {
+            self.step();
+        }
+    }
+
+    /// Runs simulation until current head is `expected_head`, returns simulated time it took
+    pub fn run_until_head(&mut self, expected_head: &BlockHash, timeout: Duration) -> Result<Duration, failure::Error> {
+        let started_at = self.clock.now();
+        loop {
+            if self.current_head()?.map(|head| head.block_hash() == expected_head).unwrap_or(false) {
+                return Ok(self.clock.now() - started_at);
+            }
+            if self.clock.now() - started_at > timeout {
+                return Err(format_err!("Expected head was not reached in simulated time: {:?}, current head level: {:?}", timeout, self.current_head()?.map(|head| *head.level())));
+            }
+            self.step();
+        }
+    }
+
+    /// Current head of the main chain stored by chain manager
+    pub fn current_head(&self) -> Result<Option<Head>, failure::Error> {
+        Ok(ChainMetaStorage::new(self.tmp_storage.storage()).get_current_head(&self.chain_id)?)
+    }
+
+    pub fn is_blacklisted(&self, peer_name: &str) -> bool {
+        self.network.lock().unwrap().blacklisted.contains(peer_name)
+    }
+
+    fn step(&mut self) {
+        self.clock.advance(SIMULATION_STEP);
+        let now = self.clock.now();
+
+        // deliver messages in the order they were sent by the peers
+        let mut deliveries = {
+            let mut network = self.network.lock().unwrap();
+            let (due, in_flight): (Vec<_>, Vec<_>) = network.in_flight.drain(..).partition(|message| message.deliver_at <= now);
+            network.in_flight = in_flight;
+            due
+        };
+        deliveries.sort_by_key(|message| (message.deliver_at, message.seq));
+        for InFlightMessage { peer, message, .. } in deliveries {
+            self.network_channel.tell(
+                Publish {
+                    msg: PeerMessageReceived { peer, message: Arc::new(message) }.into(),
+                    topic: NetworkChannelTopic::NetworkEvents.into(),
+                }, None);
+        }
+
+        for timer in self.timers.iter_mut().filter(|timer| timer.due <= now) {
+            match &timer.msg {
+                TimerMsg::ChainManager(msg) => self.chain_manager.tell(msg.clone(), None),
+                TimerMsg::PeerManager(msg) => self.peer_manager.tell(msg.clone(), None),
+            }
+            timer.due += timer.interval;
+        }
+
+        self.settle();
+    }
+
+    /// Waits until all actors are idle, messages are processed in rounds:
+    /// - channels are flushed, so published messages are in the mailboxes of the subscribers,
+    /// - chain manager and peer manager answer ping, after they processed their mailboxes,
+    /// - simulated peers answer ping, after they processed their mailboxes.
+    ///
+    /// Actors are idle, if in the whole round chain manager and peer manager processed just the ping
+    /// and simulated actors processed nothing, so nobody could send a new message.
+    fn settle(&mut self) {
+        let mut last_pongs = None;
+        loop {
+            let processed_messages = self.network.lock().unwrap().processed_messages;
+
+            self.shell_channel.tell(
+                Publish {
+                    msg: ShuttingDown.into(),
+                    topic: SIMULATION_TOPIC.into(),
+                }, None);
+            self.wait_for_idle();
+            self.network_channel.tell(
+                Publish {
+                    msg: PeerIdleTimeout { peer: self.unconnected_peer.clone(), idle_timeout: Duration::from_secs(0) }.into(),
+                    topic: SIMULATION_TOPIC.into(),
+                }, None);
+            self.wait_for_idle();
+
+            self.chain_manager.tell(Ping, Some(self.pong_receiver.clone()));
+            let chain_manager_pong = self.wait_for_pong();
+            self.peer_manager.tell(Ping, Some(self.pong_receiver.clone()));
+            let peer_manager_pong = self.wait_for_pong();
+
+            let pongs = (chain_manager_pong, peer_manager_pong);
+            if last_pongs.map(|(chain_manager, peer_manager)| (chain_manager + 1, peer_manager + 1)) == Some(pongs) {
+                // ping of the stopped peer ends in dead letters, which is answered by the network observer
+                let connected_peers = {
+                    let network = self.network.lock().unwrap();
+                    self.peers.iter()
+                        .filter(|(name, _)| !network.blacklisted.contains(*name) && !network.stopped.contains(*name))
+                        .map(|(_, peer)| peer.clone())
+                        .collect::<Vec<_>>()
+                };
+                for peer in &connected_peers {
+                    peer.tell(SendMessage::new(GetCurrentBranchMessage::new(PING_CHAIN_ID.to_vec()).into()), Some(self.pong_receiver.clone()));
+                }
+                connected_peers.iter().for_each(|_| self.wait_for_idle());
+
+                if self.network.lock().unwrap().processed_messages == processed_messages {
+                    return;
+                }
+            }
+            last_pongs = Some(pongs);
+        }
+    }
+
+    fn wait_for_idle(&self) {
+        match self.acks.recv_timeout(ACK_TIMEOUT) {
+            Ok(Ack::Idle) => (),
+            other => panic!("Simulated actor did not answer, received: {:?}", other),
+        }
+    }
+
+    fn wait_for_pong(&self) -> u64 {
+        match self.acks.recv_timeout(ACK_TIMEOUT) {
+            Ok(Ack::Pong(processed_messages)) => processed_messages,
+            other => panic!("Actor did not answer ping, received: {:?}", other),
+        }
+    }
+
+    fn init_genesis(tmp_storage: &TmpStorage, init_storage_data: &StorageInitInfo, tezos_env: &TezosEnvironmentConfiguration, log: &Logger) -> Result<BlockHeaderWithHash, failure::Error> {
+        let persistent_storage = tmp_storage.storage();
+        let block_storage = BlockStorage::new(persistent_storage);
+        let genesis_context_hash: ContextHash = vec![0; 32];
+        let genesis = initialize_storage_with_genesis_block(&block_storage, init_storage_data, tezos_env, &genesis_context_hash, log)?;
+        store_commit_genesis_result(
+            &block_storage,
+            &BlockMetaStorage::new(persistent_storage),
+            &ChainMetaStorage::new(persistent_storage),
+            &OperationsMetaStorage::new(persistent_storage),
+            init_storage_data,
+            CommitGenesisResult {
+                block_header_proto_json: String::new(),
+                block_header_proto_metadata_json: String::new(),
+                operations_proto_metadata_json: String::new(),
+            },
+        )?;
+        Ok(genesis)
+    }
+
+    /// Prevalidation pool is required by chain manager, but it is not used by the simulated scenarios (no current heads nor mempool)
+    fn create_unused_pool(name: &str, tezos_env: &TezosEnvironmentConfiguration, log: &Logger) -> TezosApiConnectionPool {
+        let context_dir = super::prepare_empty_dir(&format!("{}_context", name));
+        TezosApiConnectionPool::new_without_context(
+            format!("{}_pool", name),
+            TezosApiConnectionPoolConfiguration {
+                connection_timeout: Duration::from_secs(1),
+                idle_timeout: Duration::from_secs(1),
+                max_lifetime: Duration::from_secs(1),
+                min_connections: 0,
+                max_connections: 1,
+            },
+            ProtocolEndpointConfiguration::new(
+                TezosRuntimeConfiguration {
+                    log_enabled: false,
+                    no_of_ffi_calls_treshold_for_gc: 0,
+                    debug_mode: false,
+                },
+                tezos_env.clone(),
+                false,
+                context_dir.as_str(),
+                "--no-executable-needed-here--",
+                Level::Debug,
+                false,
+            ),
+            log.clone(),
+        )
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        let _ = self.actor_system.shutdown();
+    }
+}
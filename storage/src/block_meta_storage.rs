@@ -4,7 +4,7 @@
 use std::sync::Arc;
 
 use getset::{CopyGetters, Getters, Setters};
-use rocksdb::{Cache, ColumnFamilyDescriptor, MergeOperands};
+use rocksdb::{Cache, ColumnFamilyDescriptor, MergeOperands, WriteBatch};
 use slog::{Logger, warn};
 
 use crypto::hash::{BlockHash, ChainId, HashType};
@@ -139,6 +139,20 @@ impl BlockMetaStorage {
             .map_err(StorageError::from)
     }
 
+    /// Same as [put], but metadata is written together with other changes in the batch
+    #[inline]
+    pub fn put_batch(&self, batch: &mut WriteBatch, block_hash: &BlockHash, meta: &Meta) -> Result<(), StorageError> {
+        self.kv.merge_batch(batch, block_hash, meta)
+            .map_err(StorageError::from)
+    }
+
+    /// Atomically writes all batched changes
+    #[inline]
+    pub fn write_batch(&self, batch: WriteBatch) -> Result<(), StorageError> {
+        self.kv.write_batch(batch)
+            .map_err(StorageError::from)
+    }
+
     #[inline]
     pub fn get(&self, block_hash: &BlockHash) -> Result<Option<Meta>, StorageError> {
         self.kv.get(block_hash)
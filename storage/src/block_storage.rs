@@ -5,6 +5,7 @@ use std::sync::Arc;
 
 use derive_builder::Builder;
 use getset::{CopyGetters, Getters};
+use rocksdb::WriteBatch;
 use serde::{Deserialize, Serialize};
 
 use crypto::hash::{BlockHash, ContextHash};
@@ -124,6 +125,28 @@ impl BlockStorage {
             .and(self.by_level_index.put(block_header.header.level(), &updated_column_location))
     }
 
+    /// Stores block json data and additional data (result of block application) with single index update.
+    /// Index changes are added to the `batch`, so they can be written atomically with other changes.
+    pub fn put_block_json_data_and_additional_data_batch(
+        &self,
+        batch: &mut WriteBatch,
+        block_hash: &BlockHash,
+        json_data: BlockJsonData,
+        additional_data: BlockAdditionalData) -> Result<(), StorageError> {
+        let updated_column_location = {
+            let block_json_data_location = self.clog.append(&BlockStorageColumn::BlockJsonData(json_data))?;
+            let block_additional_data_location = self.clog.append(&BlockStorageColumn::BlockAdditionalData(additional_data))?;
+            let mut column_location = self.primary_index.get(block_hash)?.ok_or(StorageError::MissingKey)?;
+            column_location.block_json_data = Some(block_json_data_location);
+            column_location.block_additional_data = Some(block_additional_data_location);
+            column_location
+        };
+        let block_header = self.get_block_header_by_location(&updated_column_location)?;
+        // update indexes
+        self.primary_index.put_batch(batch, &block_header.hash, &updated_column_location)
+            .and(self.by_level_index.put_batch(batch, block_header.header.level(), &updated_column_location))
+    }
+
     pub fn assign_to_context(&self, block_hash: &BlockHash, context_hash: &ContextHash) -> Result<(), StorageError> {
         match self.primary_index.get(block_hash)? {
             Some(location) => self.by_context_hash_index.put(context_hash, &location),
@@ -295,6 +318,12 @@ impl BlockPrimaryIndex {
             .map_err(StorageError::from)
     }
 
+    #[inline]
+    fn put_batch(&self, batch: &mut WriteBatch, block_hash: &BlockHash, location: &BlockStorageColumnsLocation) -> Result<(), StorageError> {
+        self.kv.put_batch(batch, block_hash, &location)
+            .map_err(StorageError::from)
+    }
+
     #[inline]
     fn get(&self, block_hash: &BlockHash) -> Result<Option<BlockStorageColumnsLocation>, StorageError> {
         self.kv.get(block_hash)
@@ -337,6 +366,11 @@ impl BlockByLevelIndex {
             .map_err(StorageError::from)
     }
 
+    fn put_batch(&self, batch: &mut WriteBatch, level: BlockLevel, location: &BlockStorageColumnsLocation) -> Result<(), StorageError> {
+        self.kv.put_batch(batch, &level, location)
+            .map_err(StorageError::from)
+    }
+
     fn get_blocks(&self, from_level: BlockLevel, limit: usize) -> Result<Vec<BlockStorageColumnsLocation>, StorageError> {
         self.kv.iterator(IteratorMode::From(&from_level, Direction::Reverse))?
             .take(limit)
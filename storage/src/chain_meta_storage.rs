@@ -6,10 +6,11 @@ use std::sync::Arc;
 use rocksdb::{Cache, ColumnFamilyDescriptor};
 use serde::{Deserialize, Serialize};
 
-use crypto::hash::{ChainId, HashType};
+use crypto::hash::{BlockHash, ChainId, HashType, ProtocolHash};
 use tezos_messages::Head;
 
 use crate::persistent::{BincodeEncoded, Decoder, default_table_options, Encoder, KeyValueSchema, KeyValueStoreWithSchema, PersistentStorage, SchemaError};
+use crate::persistent::database::IteratorMode;
 use crate::StorageError;
 
 pub type ChainMetaStorageKv = dyn KeyValueStoreWithSchema<ChainMetaStorage> + Sync + Send;
@@ -27,6 +28,25 @@ pub trait ChainMetaStorageReader: Sync + Send {
 
     /// Load genesis for chain_id from dedicated storage
     fn get_genesis(&self, chain_id: &ChainId) -> Result<Option<Head>, StorageError>;
+
+    /// Load checkpoint for chain_id from dedicated storage
+    ///
+    /// Checkpoint is trusted block, chain is bootstrapped just back to the checkpoint
+    /// and every branch, which contradicts the checkpoint, is rejected.
+    /// Checkpoint does not have fitness, so it is stored with empty one.
+    fn get_checkpoint(&self, chain_id: &ChainId) -> Result<Option<Head>, StorageError>;
+
+    /// Load intent record of the block application for chain_id from dedicated storage
+    ///
+    /// Intent is written before block is applied and removed after apply result is stored,
+    /// so if it is present on startup, block application was interrupted.
+    fn get_apply_block_intent(&self, chain_id: &ChainId) -> Result<Option<ApplyBlockIntent>, StorageError>;
+
+    /// Load test chain data (forking block, genesis, expiration) for test_chain_id from dedicated storage
+    fn get_test_chain_data(&self, test_chain_id: &ChainId) -> Result<Option<TestChainData>, StorageError>;
+
+    /// Load all chains registered as active, which are synchronized by the node (besides the main chain)
+    fn get_active_chains(&self) -> Result<Vec<(ChainId, ActiveChain)>, StorageError>;
 }
 
 /// Represents storage of the chain metadata (current_head, test_chain, ...).
@@ -86,6 +106,33 @@ impl ChainMetaStorage {
             .map_err(StorageError::from)
     }
 
+    #[inline]
+    pub fn set_checkpoint(&self, chain_id: &ChainId, head: Head) -> Result<(), StorageError> {
+        self.kv
+            .put(
+                &MetaKey::key_checkpoint(chain_id.clone()),
+                &MetadataValue::Head(head),
+            )
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_apply_block_intent(&self, chain_id: &ChainId, intent: ApplyBlockIntent) -> Result<(), StorageError> {
+        self.kv
+            .put(
+                &MetaKey::key_apply_block_intent(chain_id.clone()),
+                &MetadataValue::ApplyBlockIntent(intent),
+            )
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn remove_apply_block_intent(&self, chain_id: &ChainId) -> Result<(), StorageError> {
+        self.kv
+            .delete(&MetaKey::key_apply_block_intent(chain_id.clone()))
+            .map_err(StorageError::from)
+    }
+
     #[inline]
     pub fn get_test_chain_id(&self, chain_id: &ChainId) -> Result<Option<ChainId>, StorageError> {
         self.kv
@@ -113,6 +160,40 @@ impl ChainMetaStorage {
             .delete(&MetaKey::key_test_chain_id(chain_id.clone()))
             .map_err(StorageError::from)
     }
+
+    #[inline]
+    pub fn set_test_chain_data(&self, test_chain_id: &ChainId, test_chain_data: TestChainData) -> Result<(), StorageError> {
+        self.kv
+            .put(
+                &MetaKey::key_test_chain_data(test_chain_id.clone()),
+                &MetadataValue::TestChainData(test_chain_data),
+            )
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn remove_test_chain_data(&self, test_chain_id: &ChainId) -> Result<(), StorageError> {
+        self.kv
+            .delete(&MetaKey::key_test_chain_data(test_chain_id.clone()))
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_active_chain(&self, chain_id: &ChainId, active_chain: ActiveChain) -> Result<(), StorageError> {
+        self.kv
+            .put(
+                &MetaKey::key_active_chain(chain_id.clone()),
+                &MetadataValue::ActiveChain(active_chain),
+            )
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn remove_active_chain(&self, chain_id: &ChainId) -> Result<(), StorageError> {
+        self.kv
+            .delete(&MetaKey::key_active_chain(chain_id.clone()))
+            .map_err(StorageError::from)
+    }
 }
 
 impl ChainMetaStorageReader for ChainMetaStorage {
@@ -148,6 +229,50 @@ impl ChainMetaStorageReader for ChainMetaStorage {
             })
             .map_err(StorageError::from)
     }
+
+    #[inline]
+    fn get_checkpoint(&self, chain_id: &ChainId) -> Result<Option<Head>, StorageError> {
+        self.kv
+            .get(&MetaKey::key_checkpoint(chain_id.clone()))
+            .map(|result| match result {
+                Some(MetadataValue::Head(value)) => Some(value),
+                _ => None
+            })
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    fn get_apply_block_intent(&self, chain_id: &ChainId) -> Result<Option<ApplyBlockIntent>, StorageError> {
+        self.kv
+            .get(&MetaKey::key_apply_block_intent(chain_id.clone()))
+            .map(|result| match result {
+                Some(MetadataValue::ApplyBlockIntent(value)) => Some(value),
+                _ => None
+            })
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    fn get_test_chain_data(&self, test_chain_id: &ChainId) -> Result<Option<TestChainData>, StorageError> {
+        self.kv
+            .get(&MetaKey::key_test_chain_data(test_chain_id.clone()))
+            .map(|result| match result {
+                Some(MetadataValue::TestChainData(value)) => Some(value),
+                _ => None
+            })
+            .map_err(StorageError::from)
+    }
+
+    fn get_active_chains(&self) -> Result<Vec<(ChainId, ActiveChain)>, StorageError> {
+        // chain meta storage holds just few records per chain, so it is ok to iterate all of them
+        let mut active_chains = Vec::new();
+        for (key, value) in self.kv.iterator(IteratorMode::Start)? {
+            if let (key, MetadataValue::ActiveChain(active_chain)) = (key?, value?) {
+                active_chains.push((key.chain_id, active_chain));
+            }
+        }
+        Ok(active_chains)
+    }
 }
 
 impl KeyValueSchema for ChainMetaStorage {
@@ -180,6 +305,10 @@ impl MetaKey {
     const KEY_CABOOSE: &'static str = "cbs";
     const KEY_GENESIS: &'static str = "gns";
     const KEY_TEST_CHAIN_ID: &'static str = "tcid";
+    const KEY_CHECKPOINT: &'static str = "cp";
+    const KEY_APPLY_BLOCK_INTENT: &'static str = "abi";
+    const KEY_TEST_CHAIN_DATA: &'static str = "tcd";
+    const KEY_ACTIVE_CHAIN: &'static str = "ac";
 
     fn key_current_head(chain_id: ChainId) -> MetaKey {
         MetaKey {
@@ -202,12 +331,40 @@ impl MetaKey {
         }
     }
 
+    fn key_checkpoint(chain_id: ChainId) -> MetaKey {
+        MetaKey {
+            chain_id,
+            key: Self::KEY_CHECKPOINT.to_string(),
+        }
+    }
+
+    fn key_apply_block_intent(chain_id: ChainId) -> MetaKey {
+        MetaKey {
+            chain_id,
+            key: Self::KEY_APPLY_BLOCK_INTENT.to_string(),
+        }
+    }
+
     fn key_test_chain_id(chain_id: ChainId) -> MetaKey {
         MetaKey {
             chain_id,
             key: Self::KEY_TEST_CHAIN_ID.to_string(),
         }
     }
+
+    fn key_test_chain_data(test_chain_id: ChainId) -> MetaKey {
+        MetaKey {
+            chain_id: test_chain_id,
+            key: Self::KEY_TEST_CHAIN_DATA.to_string(),
+        }
+    }
+
+    fn key_active_chain(chain_id: ChainId) -> MetaKey {
+        MetaKey {
+            chain_id,
+            key: Self::KEY_ACTIVE_CHAIN.to_string(),
+        }
+    }
 }
 
 impl Encoder for MetaKey {
@@ -239,6 +396,54 @@ impl Decoder for MetaKey {
 pub enum MetadataValue {
     Head(Head),
     TestChainId(ChainId),
+    ApplyBlockIntent(ApplyBlockIntent),
+    TestChainData(TestChainData),
+    ActiveChain(ActiveChain),
+}
+
+/// Write-ahead record of the block application.
+///
+/// Record is written before block is sent to the protocol and removed, when the result is stored,
+/// so interrupted application is detected on startup and the block is applied again.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApplyBlockIntent {
+    pub block_hash: BlockHash,
+    pub level: i32,
+}
+
+impl ApplyBlockIntent {
+    pub fn new(block_hash: BlockHash, level: i32) -> Self {
+        ApplyBlockIntent {
+            block_hash,
+            level,
+        }
+    }
+}
+
+/// Test chain forked from the main chain
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TestChainData {
+    /// Block of the main chain, which forked the test chain
+    pub forking_block_hash: BlockHash,
+    /// Genesis of the test chain (derived from forking block)
+    pub genesis_block_hash: BlockHash,
+    /// Timestamp, after which the test chain is stopped, None if it is not resolved yet
+    pub expiration: Option<i64>,
+    /// Protocol of the test chain, resolved together with expiration
+    pub test_protocol: Option<ProtocolHash>,
+}
+
+/// Chain registered as active, it is synchronized by the node besides the main chain
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ActiveChain {
+    /// Chain, from which this (test) chain was forked, None for independent chain
+    pub forked_from: Option<ChainId>,
+}
+
+impl ActiveChain {
+    pub fn new(forked_from: Option<ChainId>) -> Self {
+        ActiveChain { forked_from }
+    }
 }
 
 impl BincodeEncoded for MetadataValue {}
@@ -394,6 +599,61 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_checkpoint() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__test_checkpoint")?;
+        let index = ChainMetaStorage::new(tmp_storage.storage());
+
+        let chain_id1 = HashType::ChainId.string_to_bytes("NetXgtSLGNJvNye")?;
+        let chain_id2 = HashType::ChainId.string_to_bytes("NetXjD3HPJJjmcd")?;
+        let block_1 = Head::new(
+            HashType::BlockHash.string_to_bytes("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe")?,
+            1,
+            vec![],
+        );
+
+        // no checkpoints
+        assert!(index.get_checkpoint(&chain_id1)?.is_none());
+        assert!(index.get_checkpoint(&chain_id2)?.is_none());
+
+        // set for chain_id1
+        index.set_checkpoint(&chain_id1, block_1.clone())?;
+        assert_eq!(index.get_checkpoint(&chain_id1)?.unwrap().block_hash(), block_1.block_hash());
+        assert_eq!(index.get_checkpoint(&chain_id1)?.unwrap().level(), &1);
+        assert!(index.get_checkpoint(&chain_id2)?.is_none());
+
+        // checkpoint does not affect caboose
+        assert!(index.get_caboose(&chain_id1)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_block_intent() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__test_apply_block_intent")?;
+        let index = ChainMetaStorage::new(tmp_storage.storage());
+
+        let chain_id1 = HashType::ChainId.string_to_bytes("NetXgtSLGNJvNye")?;
+        let chain_id2 = HashType::ChainId.string_to_bytes("NetXjD3HPJJjmcd")?;
+        let block_hash = HashType::BlockHash.string_to_bytes("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe")?;
+
+        // no intents
+        assert!(index.get_apply_block_intent(&chain_id1)?.is_none());
+
+        // set for chain_id1
+        index.set_apply_block_intent(&chain_id1, ApplyBlockIntent::new(block_hash.clone(), 1))?;
+        let intent = index.get_apply_block_intent(&chain_id1)?.unwrap();
+        assert_eq!(intent.block_hash, block_hash);
+        assert_eq!(intent.level, 1);
+        assert!(index.get_apply_block_intent(&chain_id2)?.is_none());
+
+        // remove for chain_id1
+        index.remove_apply_block_intent(&chain_id1)?;
+        assert!(index.get_apply_block_intent(&chain_id1)?.is_none());
+
+        Ok(())
+    }
+
     #[test]
     fn test_test_chain_id() -> Result<(), Error> {
         let tmp_storage = TmpStorage::create("__test_test_chain_id")?;
@@ -434,4 +694,74 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_test_chain_data() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__test_test_chain_data")?;
+        let index = ChainMetaStorage::new(tmp_storage.storage());
+
+        let chain_id1 = HashType::ChainId.string_to_bytes("NetXgtSLGNJvNye")?;
+        let chain_id2 = HashType::ChainId.string_to_bytes("NetXjD3HPJJjmcd")?;
+        let block_hash = HashType::BlockHash.string_to_bytes("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe")?;
+
+        assert!(index.get_test_chain_data(&chain_id1)?.is_none());
+
+        // set for chain_id1
+        let data = TestChainData {
+            forking_block_hash: block_hash.clone(),
+            genesis_block_hash: block_hash.clone(),
+            expiration: None,
+            test_protocol: None,
+        };
+        index.set_test_chain_data(&chain_id1, data.clone())?;
+        assert_eq!(index.get_test_chain_data(&chain_id1)?, Some(data.clone()));
+        assert!(index.get_test_chain_data(&chain_id2)?.is_none());
+
+        // update expiration
+        index.set_test_chain_data(&chain_id1, TestChainData { expiration: Some(1_600_000_000), ..data })?;
+        assert_eq!(index.get_test_chain_data(&chain_id1)?.unwrap().expiration, Some(1_600_000_000));
+
+        // remove for chain_id1
+        index.remove_test_chain_data(&chain_id1)?;
+        assert!(index.get_test_chain_data(&chain_id1)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_active_chains() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__test_active_chains")?;
+        let index = ChainMetaStorage::new(tmp_storage.storage());
+
+        let chain_id1 = HashType::ChainId.string_to_bytes("NetXgtSLGNJvNye")?;
+        let chain_id2 = HashType::ChainId.string_to_bytes("NetXjD3HPJJjmcd")?;
+        let block_1 = Head::new(
+            HashType::BlockHash.string_to_bytes("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe")?,
+            1,
+            vec![],
+        );
+
+        assert!(index.get_active_chains()?.is_empty());
+
+        // other metadata are not listed
+        index.set_current_head(&chain_id1, block_1)?;
+        assert!(index.get_active_chains()?.is_empty());
+
+        index.set_active_chain(&chain_id1, ActiveChain::new(None))?;
+        index.set_active_chain(&chain_id2, ActiveChain::new(Some(chain_id1.clone())))?;
+        let mut active_chains = index.get_active_chains()?;
+        active_chains.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut expected = vec![
+            (chain_id1.clone(), ActiveChain::new(None)),
+            (chain_id2.clone(), ActiveChain::new(Some(chain_id1.clone()))),
+        ];
+        expected.sort_by(|(a, _), (b, _)| a.cmp(b));
+        assert_eq!(expected, active_chains);
+
+        // remove chain_id1
+        index.remove_active_chain(&chain_id1)?;
+        assert_eq!(vec![(chain_id2, ActiveChain::new(Some(chain_id1)))], index.get_active_chains()?);
+
+        Ok(())
+    }
 }
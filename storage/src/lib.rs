@@ -8,9 +8,9 @@ use std::path::PathBuf;
 use std::sync::Arc;
 
 use failure::Fail;
-use rocksdb::Cache;
+use rocksdb::{Cache, WriteBatch};
 use serde::{Deserialize, Serialize};
-use slog::{error, info, Logger};
+use slog::{error, info, Logger, warn};
 
 use crypto::hash::{BlockHash, ChainId, ContextHash, HashType};
 use tezos_api::environment::{OPERATION_LIST_LIST_HASH_EMPTY, TezosEnvironmentConfiguration, TezosEnvironmentError};
@@ -22,6 +22,7 @@ use tezos_messages::p2p::encoding::prelude::BlockHeader;
 pub use crate::block_meta_storage::{BlockMetaStorage, BlockMetaStorageKV, BlockMetaStorageReader};
 pub use crate::block_storage::{BlockAdditionalData, BlockAdditionalDataBuilder, BlockJsonData, BlockJsonDataBuilder, BlockStorage, BlockStorageReader};
 pub use crate::chain_meta_storage::ChainMetaStorage;
+use crate::chain_meta_storage::ChainMetaStorageReader;
 pub use crate::context_action_storage::{ContextActionByBlockHashKey, ContextActionRecordValue, ContextActionStorage};
 pub use crate::mempool_storage::{MempoolStorage, MempoolStorageKV};
 pub use crate::operations_meta_storage::{OperationsMetaStorage, OperationsMetaStorageKV};
@@ -109,6 +110,10 @@ pub enum StorageError {
     MessageHashError {
         error: MessageHashError
     },
+    #[fail(display = "Invalid checkpoint: {}", reason)]
+    InvalidCheckpoint {
+        reason: String
+    },
 }
 
 impl From<DBError> for StorageError {
@@ -203,21 +208,22 @@ pub fn store_applied_block_result(
         .block_header_proto_metadata_json(block_result.block_header_proto_metadata_json)
         .operations_proto_metadata_json(block_result.operations_proto_metadata_json)
         .build().unwrap();
-    block_storage.put_block_json_data(&block_hash, block_json_data.clone())?;
-
-    // store additional data
     let block_additional_data = BlockAdditionalDataBuilder::default()
         .max_operations_ttl(block_result.max_operations_ttl.try_into().unwrap())
         .last_allowed_fork_level(block_result.last_allowed_fork_level)
         .build().unwrap();
-    block_storage.put_block_additional_data(&block_hash, block_additional_data.clone())?;
+
+    // all index changes are written in one batch
+    let mut batch = WriteBatch::default();
+    block_storage.put_block_json_data_and_additional_data_batch(&mut batch, &block_hash, block_json_data.clone(), block_additional_data.clone())?;
 
     // TODO: check context checksum or context_hash
 
     // if everything is stored and ok, we can considere this block as applied
     // mark current head as applied
     block_metadata.set_is_applied(true);
-    block_meta_storage.put(&block_hash, &block_metadata)?;
+    block_meta_storage.put_batch(&mut batch, &block_hash, &block_metadata)?;
+    block_meta_storage.write_batch(batch)?;
 
     Ok((block_json_data, block_additional_data))
 }
@@ -259,9 +265,11 @@ pub fn store_commit_genesis_result(
                 genesis.header.fitness().clone(),
             );
 
-            // init chain data
+            // init chain data, caboose could be already set by checkpoint
             chain_meta_storage.set_genesis(&chain_id, head.clone())?;
-            chain_meta_storage.set_caboose(&chain_id, head.clone())?;
+            if chain_meta_storage.get_caboose(&chain_id)?.is_none() {
+                chain_meta_storage.set_caboose(&chain_id, head.clone())?;
+            }
             chain_meta_storage.set_current_head(&chain_id, head)?;
 
             Ok(block_json_data)
@@ -270,6 +278,94 @@ pub fn store_commit_genesis_result(
     }
 }
 
+/// Stores genesis of the test chain committed to the context by the protocol and marks it as applied,
+/// so blocks of the test chain can be applied on its context. Genesis becomes the current head of the test chain.
+pub fn store_test_chain_genesis(
+    block_storage: &BlockStorage,
+    block_meta_storage: &BlockMetaStorage,
+    chain_meta_storage: &ChainMetaStorage,
+    operations_meta_storage: &OperationsMetaStorage,
+    test_chain_id: &ChainId,
+    genesis: &BlockHeaderWithHash,
+    log: &Logger) -> Result<Head, StorageError> {
+    let _ = block_storage.put_block_header(genesis)?;
+    block_storage.put_block_additional_data(
+        &genesis.hash,
+        BlockAdditionalDataBuilder::default().max_operations_ttl(0).last_allowed_fork_level(0).build().unwrap(),
+    )?;
+    block_storage.assign_to_context(&genesis.hash, genesis.header.context())?;
+
+    // successors could be already downloaded, so stored metadata is just updated
+    let mut meta = block_meta_storage.put_block_header(genesis, test_chain_id, log)?;
+    meta.set_is_applied(true);
+    block_meta_storage.put(&genesis.hash, &meta)?;
+    operations_meta_storage.put(&genesis.hash, &operations_meta_storage::Meta::genesis_meta(test_chain_id))?;
+
+    let head = Head::new(genesis.hash.clone(), genesis.header.level(), genesis.header.fitness().clone());
+    chain_meta_storage.set_genesis(test_chain_id, head.clone())?;
+    chain_meta_storage.set_caboose(test_chain_id, head.clone())?;
+    chain_meta_storage.set_current_head(test_chain_id, head.clone())?;
+    Ok(head)
+}
+
+/// Stores trusted checkpoint for the chain, so chain is bootstrapped just back to the checkpoint (and not to the genesis).
+/// Checkpoint also becomes the caboose, because we never download blocks below the checkpoint.
+///
+/// Blocks are applied from the checkpoint, so the checkpoint block (header, json data and additional data)
+/// and its context must be imported to the storage (e.g. from the snapshot). Only imported checkpoint is marked as applied,
+/// otherwise checkpoint just limits bootstrap and rejects contradicting branches.
+pub fn initialize_checkpoint(
+    block_storage: &BlockStorage,
+    block_meta_storage: &BlockMetaStorage,
+    chain_meta_storage: &ChainMetaStorage,
+    chain_id: &ChainId,
+    checkpoint: &Head,
+    log: &Logger) -> Result<(), StorageError> {
+    if let Some(stored_checkpoint) = chain_meta_storage.get_checkpoint(chain_id)? {
+        if stored_checkpoint.block_hash() != checkpoint.block_hash() {
+            warn!(log, "Checkpoint is changed";
+                       "stored_checkpoint" => HashType::BlockHash.bytes_to_string(stored_checkpoint.block_hash()),
+                       "stored_checkpoint_level" => stored_checkpoint.level());
+        }
+    }
+
+    chain_meta_storage.set_checkpoint(chain_id, checkpoint.clone())?;
+    chain_meta_storage.set_caboose(chain_id, checkpoint.clone())?;
+
+    // checkpoint can be applied only with imported metadata (max_operations_ttl, protocol data, ...)
+    let imported = match (block_storage.get_with_json_data(checkpoint.block_hash())?, block_storage.get_with_additional_data(checkpoint.block_hash())?) {
+        (Some((block_header, _)), Some(_)) => {
+            if block_header.header.level() != *checkpoint.level() {
+                return Err(StorageError::InvalidCheckpoint {
+                    reason: format!("imported checkpoint block has level {}, but expected level is {}", block_header.header.level(), checkpoint.level())
+                });
+            }
+            let mut metadata = match block_meta_storage.get(checkpoint.block_hash())? {
+                Some(metadata) => metadata,
+                None => block_meta_storage.put_block_header(&block_header, chain_id, log)?,
+            };
+            if !metadata.is_applied() {
+                metadata.set_is_applied(true);
+                block_meta_storage.put(checkpoint.block_hash(), &metadata)?;
+            }
+            block_storage.assign_to_context(checkpoint.block_hash(), block_header.header.context())?;
+            true
+        }
+        _ => false,
+    };
+
+    if imported {
+        info!(log, "Checkpoint initialized, blocks will be applied from the checkpoint";
+                   "checkpoint" => HashType::BlockHash.bytes_to_string(checkpoint.block_hash()),
+                   "level" => checkpoint.level());
+    } else {
+        warn!(log, "Checkpoint initialized, but checkpoint block with metadata is not imported, so blocks cannot be applied from the checkpoint";
+                   "checkpoint" => HashType::BlockHash.bytes_to_string(checkpoint.block_hash()),
+                   "level" => checkpoint.level());
+    }
+    Ok(())
+}
+
 /// Genesis block needs extra handling because predecessor of the genesis block is genesis itself.
 /// Which means that successor of the genesis block is also genesis block. By combining those
 /// two statements we get cyclic relationship and everything breaks..
@@ -373,6 +469,7 @@ pub mod tests_common {
 
     use crate::block_storage;
     use crate::chain_meta_storage::ChainMetaStorage;
+    use crate::mempool_storage;
     use crate::mempool_storage::MempoolStorage;
     use crate::persistent::*;
     use crate::persistent::sequence::Sequences;
@@ -400,6 +497,12 @@ pub mod tests_common {
                 fs::remove_dir_all(&path).unwrap();
             }
 
+            Self::open(path)
+        }
+
+        /// Opens storage with previous data, e.g. left behind by killed process
+        pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+            let path = path.as_ref().to_path_buf();
             let cfg = DbConfiguration::default();
 
             // create common RocksDB block cache to be shared among column families
@@ -421,6 +524,8 @@ pub mod tests_common {
                 Lane::descriptor(&cache),
                 ListValue::descriptor(&cache),
                 MempoolStorage::descriptor(&cache),
+                mempool_storage::MempoolBlacklistIndex::descriptor(&cache),
+                mempool_storage::MempoolSnapshotIndex::descriptor(&cache),
                 ContextActionStorage::descriptor(&cache),
                 ChainMetaStorage::descriptor(&cache),
             ], &cfg)?;
@@ -8,9 +8,9 @@ use std::time::SystemTime;
 
 use serde::{Deserialize, Serialize};
 
-use crypto::hash::{HashType, OperationHash};
+use crypto::hash::{BlockHash, HashType, OperationHash};
 use tezos_messages::p2p::binary_message::MessageHash;
-use tezos_messages::p2p::encoding::operation::OperationMessage;
+use tezos_messages::p2p::encoding::operation::{Operation, OperationMessage};
 
 use crate::{IteratorMode, num_from_slice, StorageError};
 use crate::persistent::{BincodeEncoded, Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema, PersistentStorage, SchemaError};
@@ -52,15 +52,59 @@ impl fmt::Display for MempoolOperationTypeParseError {
     }
 }
 
+/// Why the operation is not accepted to the mempool anymore
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum MempoolBlacklistReason {
+    /// Operation was banned by rpc, it stays banned until unbanned by rpc
+    Banned,
+    /// Operation was refused by protocol, count of refused operations is limited by the mempool
+    Refused,
+}
+
+/// Validation status of the operation in the [MempoolSnapshot]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum MempoolSnapshotStatus {
+    Pending,
+    Applied,
+    Refused,
+    BranchRefused,
+    BranchDelayed,
+}
+
+/// Operation of the mempool with data, so it can be validated again
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MempoolSnapshotOperation {
+    pub operation_hash: OperationHash,
+    pub operation: Operation,
+    pub status: MempoolSnapshotStatus,
+}
+
+/// Mempool saved on shutdown, its operations are validated again after restart
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MempoolSnapshot {
+    /// Head of the mempool, operations were validated against
+    pub predecessor: BlockHash,
+    pub created_at: SystemTime,
+    pub operations: Vec<MempoolSnapshotOperation>,
+}
+
+impl BincodeEncoded for MempoolSnapshot {}
+
 /// Operation metadata storage
 #[derive(Clone)]
 pub struct MempoolStorage {
-    kv: Arc<MempoolStorageKV>
+    kv: Arc<MempoolStorageKV>,
+    blacklist: MempoolBlacklistIndex,
+    snapshots: MempoolSnapshotIndex,
 }
 
 impl MempoolStorage {
     pub fn new(persistent_storage: &PersistentStorage) -> Self {
-        Self { kv: persistent_storage.kv() }
+        Self {
+            kv: persistent_storage.kv(),
+            blacklist: MempoolBlacklistIndex::new(persistent_storage.kv()),
+            snapshots: MempoolSnapshotIndex::new(persistent_storage.kv()),
+        }
     }
 
     #[inline]
@@ -137,6 +181,47 @@ impl MempoolStorage {
         }
         Ok(operations)
     }
+
+    /// Operation will not be accepted to the mempool, until it is removed by [MempoolStorage::delete_blacklisted]
+    #[inline]
+    pub fn put_blacklisted(&self, operation_hash: &OperationHash, reason: MempoolBlacklistReason, blacklisted_at: SystemTime) -> Result<(), StorageError> {
+        self.blacklist.put(operation_hash, &MempoolBlacklistValue { reason, blacklisted_at })
+    }
+
+    #[inline]
+    pub fn get_blacklisted(&self, operation_hash: &OperationHash) -> Result<Option<MempoolBlacklistReason>, StorageError> {
+        self.blacklist.get(operation_hash)
+            .map(|value| value.map(|value| value.reason))
+    }
+
+    #[inline]
+    pub fn is_blacklisted(&self, operation_hash: &OperationHash) -> Result<bool, StorageError> {
+        self.get_blacklisted(operation_hash)
+            .map(|reason| reason.is_some())
+    }
+
+    #[inline]
+    pub fn delete_blacklisted(&self, operation_hash: &OperationHash) -> Result<(), StorageError> {
+        self.blacklist.delete(operation_hash)
+    }
+
+    /// Returns all blacklisted operations with reason and time, when they were blacklisted
+    #[inline]
+    pub fn iter_blacklisted(&self) -> Result<Vec<(OperationHash, MempoolBlacklistReason, SystemTime)>, StorageError> {
+        self.blacklist.iter()
+    }
+
+    /// Stores snapshot of the mempool, it is available by [MempoolStorage::take_snapshot] just once
+    #[inline]
+    pub fn put_snapshot(&self, snapshot: &MempoolSnapshot) -> Result<(), StorageError> {
+        self.snapshots.put(snapshot)
+    }
+
+    /// Removes all stored snapshots and returns the latest one
+    #[inline]
+    pub fn take_snapshot(&self) -> Result<Option<MempoolSnapshot>, StorageError> {
+        self.snapshots.take_latest()
+    }
 }
 
 impl KeyValueSchema for MempoolStorage {
@@ -197,4 +282,101 @@ pub struct MempoolValue {
     time_to_live: SystemTime,
 }
 
-impl BincodeEncoded for MempoolValue {}
\ No newline at end of file
+impl BincodeEncoded for MempoolValue {}
+
+/// Index of the operations, which are not accepted to the mempool (banned or refused)
+#[derive(Clone)]
+pub struct MempoolBlacklistIndex {
+    kv: Arc<MempoolBlacklistIndexKV>,
+}
+
+pub type MempoolBlacklistIndexKV = dyn KeyValueStoreWithSchema<MempoolBlacklistIndex> + Sync + Send;
+
+impl MempoolBlacklistIndex {
+    fn new(kv: Arc<MempoolBlacklistIndexKV>) -> Self {
+        Self { kv }
+    }
+
+    fn put(&self, operation_hash: &OperationHash, value: &MempoolBlacklistValue) -> Result<(), StorageError> {
+        self.kv.put(operation_hash, value)
+            .map_err(StorageError::from)
+    }
+
+    fn get(&self, operation_hash: &OperationHash) -> Result<Option<MempoolBlacklistValue>, StorageError> {
+        self.kv.get(operation_hash)
+            .map_err(StorageError::from)
+    }
+
+    fn delete(&self, operation_hash: &OperationHash) -> Result<(), StorageError> {
+        self.kv.delete(operation_hash)
+            .map_err(StorageError::from)
+    }
+
+    fn iter(&self) -> Result<Vec<(OperationHash, MempoolBlacklistReason, SystemTime)>, StorageError> {
+        let mut blacklisted = Vec::new();
+        for (key, value) in self.kv.iterator(IteratorMode::Start)? {
+            let (key, value) = (key?, value?);
+            blacklisted.push((key, value.reason, value.blacklisted_at));
+        }
+        Ok(blacklisted)
+    }
+}
+
+impl KeyValueSchema for MempoolBlacklistIndex {
+    type Key = OperationHash;
+    type Value = MempoolBlacklistValue;
+
+    #[inline]
+    fn name() -> &'static str {
+        "mempool_blacklist_index"
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MempoolBlacklistValue {
+    reason: MempoolBlacklistReason,
+    blacklisted_at: SystemTime,
+}
+
+impl BincodeEncoded for MempoolBlacklistValue {}
+
+/// Snapshots of the mempool by predecessor (see [MempoolSnapshot])
+#[derive(Clone)]
+pub struct MempoolSnapshotIndex {
+    kv: Arc<MempoolSnapshotIndexKV>,
+}
+
+pub type MempoolSnapshotIndexKV = dyn KeyValueStoreWithSchema<MempoolSnapshotIndex> + Sync + Send;
+
+impl MempoolSnapshotIndex {
+    fn new(kv: Arc<MempoolSnapshotIndexKV>) -> Self {
+        Self { kv }
+    }
+
+    fn put(&self, snapshot: &MempoolSnapshot) -> Result<(), StorageError> {
+        self.kv.put(&snapshot.predecessor, snapshot)
+            .map_err(StorageError::from)
+    }
+
+    fn take_latest(&self) -> Result<Option<MempoolSnapshot>, StorageError> {
+        let mut latest: Option<MempoolSnapshot> = None;
+        for (key, value) in self.kv.iterator(IteratorMode::Start)? {
+            let (key, snapshot) = (key?, value?);
+            self.kv.delete(&key)?;
+            if latest.as_ref().map_or(true, |latest| latest.created_at < snapshot.created_at) {
+                latest = Some(snapshot);
+            }
+        }
+        Ok(latest)
+    }
+}
+
+impl KeyValueSchema for MempoolSnapshotIndex {
+    type Key = BlockHash;
+    type Value = MempoolSnapshot;
+
+    #[inline]
+    fn name() -> &'static str {
+        "mempool_snapshot_index"
+    }
+}
\ No newline at end of file
@@ -107,6 +107,13 @@ pub trait KeyValueStoreWithSchema<S: KeyValueSchema> {
     /// * `value` - Value to be inserted associated with given key, specified by schema
     fn put_batch(&self, batch: &mut WriteBatch, key: &S::Key, value: &S::Value) -> Result<(), DBError>;
 
+    /// Insert new merge operation into WriteBatch.
+    ///
+    /// # Arguments
+    /// * `key` - Value of key specified by schema
+    /// * `value` - Value to be merged with value associated with given key, specified by schema
+    fn merge_batch(&self, batch: &mut WriteBatch, key: &S::Key, value: &S::Value) -> Result<(), DBError>;
+
     /// Write batch into DB atomically
     ///
     /// # Arguments
@@ -200,6 +207,17 @@ impl<S: KeyValueSchema> KeyValueStoreWithSchema<S> for DB {
         Ok(())
     }
 
+    fn merge_batch(&self, batch: &mut WriteBatch, key: &S::Key, value: &S::Value) -> Result<(), DBError> {
+        let key = key.encode()?;
+        let value = value.encode()?;
+        let cf = self.cf_handle(S::name())
+            .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
+
+        batch.merge_cf(cf, &key, &value);
+
+        Ok(())
+    }
+
     fn write_batch(&self, batch: WriteBatch) -> Result<(), DBError> {
         self.write_opt(batch, &default_write_options())?;
         Ok(())
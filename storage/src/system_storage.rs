@@ -27,6 +27,7 @@ impl SystemStorage {
     const CHAIN_ID: &'static str = "chain_id";
     const DB_VERSION: &'static str = "db_version";
     const CHAIN_NAME: &'static str = "chain_name";
+    const MEMPOOL_FILTER: &'static str = "mempool_filter";
 
     pub fn new(kv: Arc<SystemStorageKv>) -> Self {
         SystemStorage { kv }
@@ -79,6 +80,23 @@ impl SystemStorage {
         self.kv.put(&Self::CHAIN_NAME.to_string(), &SystemValue::String(chain_name.clone()))
             .map_err(StorageError::from)
     }
+
+    /// Returns mempool filter configuration (as json) set by the rpc
+    #[inline]
+    pub fn get_mempool_filter(&self) -> Result<Option<String>, StorageError> {
+        self.kv.get(&Self::MEMPOOL_FILTER.to_string())
+            .map(|result| match result {
+                Some(SystemValue::String(value)) => Some(value),
+                _ => None
+            })
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_mempool_filter(&mut self, mempool_filter: &str) -> Result<(), StorageError> {
+        self.kv.put(&Self::MEMPOOL_FILTER.to_string(), &SystemValue::String(mempool_filter.to_string()))
+            .map_err(StorageError::from)
+    }
 }
 
 impl KeyValueSchema for SystemStorage {
@@ -2,10 +2,12 @@
 // SPDX-License-Identifier: MIT
 
 use failure::Error;
+use slog::Logger;
 
 use crypto::hash::HashType;
 use storage::*;
 use storage::tests_common::TmpStorage;
+use tezos_api::ffi::ApplyBlockResponse;
 use tezos_messages::p2p::binary_message::BinaryMessage;
 use tezos_messages::p2p::encoding::prelude::*;
 
@@ -68,6 +70,46 @@ fn block_storage_assign_context() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn block_storage_store_applied_block_result() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__block_store_applied_block_result")?;
+    let storage = BlockStorage::new(tmp_storage.storage());
+    let meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+
+    let block_header = make_test_block_header()?;
+    let chain_id = vec![1; HashType::ChainId.size()];
+    storage.put_block_header(&block_header)?;
+    let mut meta = meta_storage.put_block_header(&block_header, &chain_id, &Logger::root(slog::Discard, slog::o!()))?;
+    assert!(!meta.is_applied());
+
+    let apply_result = ApplyBlockResponse {
+        validation_result_message: "applied".to_string(),
+        context_hash: vec![2; HashType::ContextHash.size()],
+        block_header_proto_json: "{}".to_string(),
+        block_header_proto_metadata_json: "{}".to_string(),
+        operations_proto_metadata_json: "[]".to_string(),
+        max_operations_ttl: 60,
+        last_allowed_fork_level: 5,
+        forking_testchain: false,
+        forking_testchain_data: None,
+    };
+    store_applied_block_result(&storage, &meta_storage, &block_header.hash, apply_result, &mut meta)?;
+
+    // json data, additional data and applied flag are written together
+    let (_, json_data) = storage.get_with_json_data(&block_header.hash)?.unwrap();
+    assert_eq!("[]", json_data.operations_proto_metadata_json().as_str());
+    let (_, additional_data) = storage.get_with_additional_data(&block_header.hash)?.unwrap();
+    assert_eq!(60, additional_data.max_operations_ttl());
+    assert_eq!(5, additional_data.last_allowed_fork_level());
+    assert!(meta_storage.get(&block_header.hash)?.unwrap().is_applied());
+
+    // level index is updated too
+    let blocks = storage.get_multiple_with_json_data(&block_header.hash, 1)?;
+    assert_eq!(1, blocks.len());
+
+    Ok(())
+}
+
 fn make_test_block_header() -> Result<BlockHeaderWithHash, Error> {
     let message_bytes = hex::decode("00006d6e0102dd00defaf70c53e180ea148b349a6feb4795610b2abc7b07fe91ce50a90814000000005c1276780432bc1d3a28df9a67b363aa1638f807214bb8987e5f9c0abcbd69531facffd1c80000001100000001000000000800000000000c15ef15a6f54021cb353780e2847fb9c546f1d72c1dc17c3db510f45553ce501ce1de000000000003c762c7df00a856b8bfcaf0676f069f825ca75f37f2bee9fe55ba109cec3d1d041d8c03519626c0c0faa557e778cb09d2e0c729e8556ed6a7a518c84982d1f2682bc6aa753f")?;
     let block_header = BlockHeaderWithHash::new(BlockHeader::from_bytes(message_bytes)?)?;
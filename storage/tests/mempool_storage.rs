@@ -1,11 +1,11 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use failure::Error;
 
-use storage::mempool_storage::MempoolOperationType;
+use storage::mempool_storage::{MempoolBlacklistReason, MempoolOperationType, MempoolSnapshot, MempoolSnapshotOperation, MempoolSnapshotStatus};
 use storage::MempoolStorage;
 use storage::tests_common::TmpStorage;
 use tezos_messages::p2p::binary_message::BinaryMessage;
@@ -32,6 +32,64 @@ fn mempool_storage_read_write() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn mempool_storage_blacklist() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__mempool_storage_blacklist")?;
+    let storage = MempoolStorage::new(tmp_storage.storage());
+
+    let operation_hash = make_test_operation_message()?.message_hash()?;
+    assert!(!storage.is_blacklisted(&operation_hash)?);
+
+    storage.put_blacklisted(&operation_hash, MempoolBlacklistReason::Banned, SystemTime::now())?;
+    assert_eq!(Some(MempoolBlacklistReason::Banned), storage.get_blacklisted(&operation_hash)?);
+    // blacklist does not interfere with mempool operations
+    assert!(storage.iter()?.is_empty());
+
+    let blacklisted = storage.iter_blacklisted()?;
+    assert_eq!(1, blacklisted.len());
+    assert_eq!((operation_hash.clone(), MempoolBlacklistReason::Banned), (blacklisted[0].0.clone(), blacklisted[0].1));
+
+    storage.delete_blacklisted(&operation_hash)?;
+    assert!(!storage.is_blacklisted(&operation_hash)?);
+
+    Ok(())
+}
+
+#[test]
+fn mempool_storage_snapshot() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__mempool_storage_snapshot")?;
+    let storage = MempoolStorage::new(tmp_storage.storage());
+    assert!(storage.take_snapshot()?.is_none());
+
+    let operation = make_test_operation_message()?;
+    let operation_hash = operation.message_hash()?;
+    let created_at = SystemTime::now();
+    let snapshot = |predecessor: u8, created_at: SystemTime| MempoolSnapshot {
+        predecessor: vec![predecessor; 32],
+        created_at,
+        operations: vec![MempoolSnapshotOperation {
+            operation_hash: operation_hash.clone(),
+            operation: operation.operation().clone(),
+            status: MempoolSnapshotStatus::Applied,
+        }],
+    };
+    storage.put_snapshot(&snapshot(1, created_at))?;
+    storage.put_snapshot(&snapshot(2, created_at - Duration::from_secs(10)))?;
+
+    // the latest snapshot is returned
+    let taken = storage.take_snapshot()?.expect("Snapshot is stored");
+    assert_eq!(vec![1; 32], taken.predecessor);
+    assert_eq!(1, taken.operations.len());
+    assert_eq!(operation_hash, taken.operations[0].operation_hash);
+    assert_eq!(operation.operation(), &taken.operations[0].operation);
+    assert_eq!(MempoolSnapshotStatus::Applied, taken.operations[0].status);
+
+    // snapshot is taken just once
+    assert!(storage.take_snapshot()?.is_none());
+
+    Ok(())
+}
+
 fn make_test_operation_message() -> Result<OperationMessage, Error> {
     let message_bytes = hex::decode("10490b79070cf19175cd7e3b9c1ee66f6e85799980404b119132ea7e58a4a97e000008c387fa065a181d45d47a9b78ddc77e92a881779ff2cbabbf9646eade4bf1405a08e00b725ed849eea46953b10b5cdebc518e6fd47e69b82d2ca18c4cf6d2f312dd08")?;
     let operation = Operation::from_bytes(message_bytes)?;
@@ -300,6 +300,18 @@ pub struct CommitGenesisResult {
     pub operations_proto_metadata_json: String,
 }
 
+/// Request to commit genesis of the test chain to the context of the forking block
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct CommitTestChainGenesisRequest {
+    pub forking_block_header: BlockHeader,
+}
+
+/// Commit test chain genesis result, context of the genesis header is the committed context
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct CommitTestChainGenesisResult {
+    pub genesis_block_header: BlockHeader,
+}
+
 /// Forking test chain data
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct ForkingTestchainData {
@@ -384,6 +396,22 @@ impl From<OCamlError> for GetDataError {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Fail)]
+pub enum TestChainGenesisError {
+    #[fail(display = "Ocaml failed to commit test chain genesis, message: {}!", message)]
+    CommitError { message: String },
+}
+
+impl From<OCamlError> for TestChainGenesisError {
+    fn from(error: OCamlError) -> Self {
+        match error {
+            OCamlError::Exception(exception) => TestChainGenesisError::CommitError {
+                message: exception.message().unwrap_or_else(|| "unknown".to_string()),
+            },
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Fail, PartialEq)]
 pub enum ApplyBlockError {
     #[fail(
@@ -5,15 +5,18 @@ use crypto::hash::{ChainId, ContextHash, ProtocolHash};
 use tezos_api::ffi::{
     ApplyBlockError, ApplyBlockRequest, ApplyBlockResponse, BeginApplicationError,
     BeginApplicationRequest, BeginApplicationResponse, BeginConstructionError,
-    BeginConstructionRequest, CommitGenesisResult, ComputePathError, ComputePathRequest,
-    ComputePathResponse, ContextDataError, GenesisChain, GetDataError, HelpersPreapplyError,
-    HelpersPreapplyResponse, InitProtocolContextResult, PatchContext, PrevalidatorWrapper,
-    ProtocolDataError, ProtocolOverrides, ProtocolRpcError, ProtocolRpcRequest,
-    ProtocolRpcResponse, TezosRuntimeConfiguration, TezosRuntimeConfigurationError,
+    BeginConstructionRequest, CommitGenesisResult, CommitTestChainGenesisRequest,
+    CommitTestChainGenesisResult, ComputePathError, ComputePathRequest, ComputePathResponse,
+    ContextDataError, GenesisChain, GetDataError, HelpersPreapplyError, HelpersPreapplyResponse,
+    InitProtocolContextResult, PatchContext, PrevalidatorWrapper, ProtocolDataError,
+    ProtocolOverrides, ProtocolRpcError, ProtocolRpcRequest, ProtocolRpcResponse,
+    TestChainGenesisError, TezosRuntimeConfiguration, TezosRuntimeConfigurationError,
     TezosStorageInitError, ValidateOperationError, ValidateOperationRequest,
     ValidateOperationResponse,
 };
 use tezos_interop::ffi;
+use tezos_messages::p2p::binary_message::BinaryMessage;
+use tezos_messages::p2p::encoding::block_header::BlockHeader;
 
 /// Override runtime configuration for OCaml runtime
 pub fn change_runtime_configuration(
@@ -67,6 +70,29 @@ pub fn genesis_result_data(
     }
 }
 
+/// Commits genesis of the test chain to the context of the forking block,
+/// returns header of the test chain genesis (its hash is not the hash of the header, see Tezos `Context.compute_testchain_genesis`)
+pub fn commit_test_chain_genesis(
+    request: CommitTestChainGenesisRequest,
+) -> Result<CommitTestChainGenesisResult, TestChainGenesisError> {
+    let forking_block_header = request.forking_block_header.as_bytes()
+        .map_err(|e| TestChainGenesisError::CommitError {
+            message: format!("Failed to encode forking block header! Reason: {:?}", e),
+        })?;
+    match ffi::commit_test_chain_genesis(forking_block_header) {
+        Ok(result) => {
+            let genesis_block_header = BlockHeader::from_bytes(result?)
+                .map_err(|e| TestChainGenesisError::CommitError {
+                    message: format!("Failed to decode test chain genesis header! Reason: {:?}", e),
+                })?;
+            Ok(CommitTestChainGenesisResult { genesis_block_header })
+        }
+        Err(e) => Err(TestChainGenesisError::CommitError {
+            message: format!("FFI 'commit_test_chain_genesis' failed! Reason: {:?}", e),
+        }),
+    }
+}
+
 /// Applies new block to Tezos ocaml storage, means:
 /// - block and operations are decoded by the protocol
 /// - block and operations data are correctly stored in Tezos chain/storage
@@ -54,6 +54,7 @@ mod tezos_ffi {
             protocol_hash: OCamlBytes,
             genesis_max_operations_ttl: OCamlInt
         ) -> (OCamlBytes, OCamlBytes, OCamlBytes);
+        pub fn commit_test_chain_genesis(forking_block_header: OCamlBytes) -> OCamlBytes;
         pub fn decode_context_data(
             protocol_hash: OCamlBytes,
             key: OCamlList<OCamlBytes>,
@@ -217,6 +218,25 @@ pub fn genesis_result_data(
     })
 }
 
+pub fn commit_test_chain_genesis(
+    forking_block_header: RustBytes,
+) -> Result<Result<RustBytes, TestChainGenesisError>, OcamlError> {
+    runtime::execute(move || {
+        ocaml_frame!(gc, {
+            let forking_block_header = to_ocaml!(gc, forking_block_header);
+
+            let result = ocaml_call!(tezos_ffi::commit_test_chain_genesis(
+                gc,
+                forking_block_header
+            ));
+            match result {
+                Ok(genesis_block_header) => Ok(genesis_block_header.to_rust()),
+                Err(e) => Err(TestChainGenesisError::from(e)),
+            }
+        })
+    })
+}
+
 type CallRequestFn<REQUEST, RESPONSE> = OCamlFn1<REQUEST, RESPONSE>;
 
 /// Calls ffi function like request/response
@@ -19,7 +19,7 @@ pub enum AckMessage {
     Nack(NackInfo),
 }
 
-#[derive(Serialize, Deserialize, PartialEq)]
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 pub enum NackMotive {
     NoMotive,
     TooManyConnections,
@@ -43,5 +43,5 @@ pub mod prelude {
     pub use super::peer::{PeerMessage, PeerMessageResponse};
     pub use super::protocol::{Component, GetProtocolsMessage, Protocol, ProtocolMessage};
     pub use super::swap::SwapMessage;
-    pub use super::version::NetworkVersion;
+    pub use super::version::{NetworkVersion, SupportedNetworkVersions};
 }
@@ -3,18 +3,23 @@
 
 use std::fmt;
 
+use getset::{CopyGetters, Getters};
 use serde::{Deserialize, Serialize};
 
 use tezos_encoding::encoding::{Encoding, Field, HasEncoding};
 use tezos_encoding::has_encoding;
 
 use crate::cached_data;
+use crate::p2p::encoding::ack::NackMotive;
 use crate::p2p::binary_message::cache::BinaryDataCache;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Getters, CopyGetters)]
 pub struct NetworkVersion {
+    #[get = "pub"]
     chain_name: String,
+    #[get_copy = "pub"]
     distributed_db_version: u16,
+    #[get_copy = "pub"]
     p2p_version: u16,
     #[serde(skip_serializing)]
     body: BinaryDataCache,
@@ -68,3 +73,84 @@ impl PartialEq for NetworkVersion {
             && self.p2p_version == other.p2p_version
     }
 }
+
+/// Network versions supported by the node.
+///
+/// Node announces the best (highest) supported versions and negotiates with every remote peer
+/// the best common `distributed_db_version` and `p2p_version`, the same way as the OCaml node does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SupportedNetworkVersions {
+    chain_name: String,
+    /// Supported distributed_db versions, sorted in descending order
+    distributed_db_versions: Vec<u16>,
+    /// Supported p2p versions, sorted in descending order
+    p2p_versions: Vec<u16>,
+}
+
+impl SupportedNetworkVersions {
+    /// Create new set of supported versions.
+    ///
+    /// `distributed_db_versions` and `p2p_versions` cannot be empty, otherwise function will panic
+    pub fn new(chain_name: String, mut distributed_db_versions: Vec<u16>, mut p2p_versions: Vec<u16>) -> Self {
+        assert!(!distributed_db_versions.is_empty(), "at least one distributed_db_version must be supported");
+        assert!(!p2p_versions.is_empty(), "at least one p2p_version must be supported");
+
+        distributed_db_versions.sort_unstable_by(|a, b| b.cmp(a));
+        distributed_db_versions.dedup();
+        p2p_versions.sort_unstable_by(|a, b| b.cmp(a));
+        p2p_versions.dedup();
+
+        SupportedNetworkVersions {
+            chain_name,
+            distributed_db_versions,
+            p2p_versions,
+        }
+    }
+
+    /// Version announced to the remote peers in the connection message
+    pub fn announced(&self) -> NetworkVersion {
+        NetworkVersion::new(
+            self.chain_name.clone(),
+            self.distributed_db_versions[0],
+            self.p2p_versions[0],
+        )
+    }
+
+    /// Select the best common version with the version announced by the remote peer.
+    ///
+    /// Returns motive for the nack message, if there is no compatible version.
+    pub fn select(&self, remote: &NetworkVersion) -> Result<NetworkVersion, NackMotive> {
+        if self.chain_name != remote.chain_name {
+            return Err(NackMotive::UnknownChainName);
+        }
+        let distributed_db_version = select_version(&self.distributed_db_versions, remote.distributed_db_version)
+            .ok_or(NackMotive::DeprecatedDistributedDbVersion)?;
+        let p2p_version = select_version(&self.p2p_versions, remote.p2p_version)
+            .ok_or(NackMotive::DeprecatedP2pVersion)?;
+
+        Ok(NetworkVersion::new(self.chain_name.clone(), distributed_db_version, p2p_version))
+    }
+}
+
+impl From<NetworkVersion> for SupportedNetworkVersions {
+    fn from(version: NetworkVersion) -> Self {
+        SupportedNetworkVersions::new(
+            version.chain_name,
+            vec![version.distributed_db_version],
+            vec![version.p2p_version],
+        )
+    }
+}
+
+/// Our best version is selected, when remote peer supports it (remote announces its best version),
+/// otherwise remote version is selected, if we support it.
+fn select_version(accepted_versions: &[u16], remote_version: u16) -> Option<u16> {
+    let best_local_version = accepted_versions[0];
+    if best_local_version <= remote_version {
+        Some(best_local_version)
+    } else if accepted_versions.contains(&remote_version) {
+        Some(remote_version)
+    } else {
+        None
+    }
+}
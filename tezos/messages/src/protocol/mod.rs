@@ -112,6 +112,22 @@ pub trait ToRpcJsonMap {
     fn as_map(&self) -> RpcJsonMap;
 }
 
+/// Returns count of `validation_passes` defined by the protocol, None for not supported protocol (e.g. genesis protocol)
+pub fn get_validation_passes(protocol: &ProtocolHash) -> Option<u8> {
+    let hash: &str = &HashType::ProtocolHash.bytes_to_string(protocol);
+    match hash {
+        proto_001::PROTOCOL_HASH => Some(proto_001::VALIDATION_PASSES),
+        proto_002::PROTOCOL_HASH => Some(proto_002::VALIDATION_PASSES),
+        proto_003::PROTOCOL_HASH => Some(proto_003::VALIDATION_PASSES),
+        proto_004::PROTOCOL_HASH => Some(proto_004::VALIDATION_PASSES),
+        proto_005::PROTOCOL_HASH => Some(proto_005::VALIDATION_PASSES),
+        proto_005_2::PROTOCOL_HASH => Some(proto_005_2::VALIDATION_PASSES),
+        proto_006::PROTOCOL_HASH => Some(proto_006::VALIDATION_PASSES),
+        proto_007::PROTOCOL_HASH => Some(proto_007::VALIDATION_PASSES),
+        _ => None,
+    }
+}
+
 pub fn get_constants_for_rpc(
     bytes: &[u8],
     protocol: ProtocolHash,
@@ -4,3 +4,4 @@ pub mod constants;
 pub mod rights;
 
 pub const PROTOCOL_HASH: &str = "PtCJ7pwoxe8JasnHY8YonnLYjcVHmhiARPJvqcC6VfHT5s8k8sY";
+pub const VALIDATION_PASSES: u8 = 4;
@@ -4,3 +4,4 @@ pub mod constants;
 pub mod rights;
 
 pub const PROTOCOL_HASH: &str = "PsYLVpVvgbLhAhoqAkMFUo6gudkJ9weNXhUYCiLDzcUpFpkk8Wt";
+pub const VALIDATION_PASSES: u8 = 4;
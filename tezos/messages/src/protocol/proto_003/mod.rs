@@ -4,3 +4,4 @@ pub mod constants;
 pub mod rights;
 
 pub const PROTOCOL_HASH: &str = "PsddFKi32cMJ2qPjf43Qv5GDWLDPZb3T3bF6fLKiF5HtvHNU7aP";
+pub const VALIDATION_PASSES: u8 = 4;
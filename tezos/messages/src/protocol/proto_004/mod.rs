@@ -4,3 +4,4 @@ pub mod constants;
 pub mod rights;
 
 pub const PROTOCOL_HASH: &str = "Pt24m4xiPbLDhVgVfABUjirbmda3yohdN82Sp9FeuAXJ4eV9otd";
+pub const VALIDATION_PASSES: u8 = 4;
@@ -4,3 +4,4 @@ pub mod constants;
 pub mod rights;
 
 pub const PROTOCOL_HASH: &str = "PsBABY5HQTSkA4297zNHfsZNKtxULfL18y95qb3m53QJiXGmrbU";
+pub const VALIDATION_PASSES: u8 = 4;
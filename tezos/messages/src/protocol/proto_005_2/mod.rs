@@ -5,3 +5,4 @@ pub mod contract;
 pub mod rights;
 
 pub const PROTOCOL_HASH: &str = "PsBabyM1eUXZseaJdmXFApDSBqj8YBfwELoxZHHW77EMcAbbwAS";
+pub const VALIDATION_PASSES: u8 = 4;
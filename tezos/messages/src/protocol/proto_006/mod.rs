@@ -5,3 +5,4 @@ pub mod contract;
 pub mod rights;
 
 pub const PROTOCOL_HASH: &str = "PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb";
+pub const VALIDATION_PASSES: u8 = 4;
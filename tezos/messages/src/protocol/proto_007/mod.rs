@@ -5,3 +5,4 @@ pub mod contract;
 pub mod rights;
 
 pub const PROTOCOL_HASH: &str = "PsDELPH1Kxsxt8f9eWbxQeRxkjfbxoqM52jvs5Y5fBxWWh4ifpo";
+pub const VALIDATION_PASSES: u8 = 4;
@@ -0,0 +1,40 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use tezos_messages::p2p::encoding::ack::NackMotive;
+use tezos_messages::p2p::encoding::prelude::*;
+
+const CHAIN_NAME: &str = "TEZOS_MAINNET";
+
+#[test]
+fn announced_version_is_the_best_supported() {
+    let supported = SupportedNetworkVersions::new(CHAIN_NAME.to_string(), vec![0, 1], vec![1, 0]);
+    assert_eq!(NetworkVersion::new(CHAIN_NAME.to_string(), 1, 1), supported.announced());
+}
+
+#[test]
+fn select_best_common_version() {
+    let supported = SupportedNetworkVersions::new(CHAIN_NAME.to_string(), vec![0, 1], vec![0, 1]);
+
+    // remote supports newer versions than we do
+    let selected = supported.select(&NetworkVersion::new(CHAIN_NAME.to_string(), 2, 3));
+    assert_eq!(Ok(NetworkVersion::new(CHAIN_NAME.to_string(), 1, 1)), selected);
+
+    // remote supports older versions
+    let selected = supported.select(&NetworkVersion::new(CHAIN_NAME.to_string(), 0, 0));
+    assert_eq!(Ok(NetworkVersion::new(CHAIN_NAME.to_string(), 0, 0)), selected);
+}
+
+#[test]
+fn select_fails_for_incompatible_version() {
+    let supported = SupportedNetworkVersions::new(CHAIN_NAME.to_string(), vec![1], vec![1]);
+
+    let selected = supported.select(&NetworkVersion::new("TEZOS_CARTHAGENET".to_string(), 1, 1));
+    assert_eq!(Err(NackMotive::UnknownChainName), selected);
+
+    let selected = supported.select(&NetworkVersion::new(CHAIN_NAME.to_string(), 0, 1));
+    assert_eq!(Err(NackMotive::DeprecatedDistributedDbVersion), selected);
+
+    let selected = supported.select(&NetworkVersion::new(CHAIN_NAME.to_string(), 1, 0));
+    assert_eq!(Err(NackMotive::DeprecatedP2pVersion), selected);
+}
@@ -63,6 +63,11 @@ pub trait ProtocolApi {
         genesis_max_operations_ttl: u16,
     ) -> Result<CommitGenesisResult, GetDataError>;
 
+    /// Command tezos ocaml code to commit genesis of the test chain to the context of the forking block
+    fn commit_test_chain_genesis(
+        request: CommitTestChainGenesisRequest,
+    ) -> Result<CommitTestChainGenesisResult, TestChainGenesisError>;
+
     /// Command tezos ocaml code to compute the operations path
     fn compute_path(request: ComputePathRequest) -> Result<ComputePathResponse, ComputePathError>;
 
@@ -55,6 +55,7 @@ enum ProtocolMessage {
     ChangeRuntimeConfigurationCall(TezosRuntimeConfiguration),
     InitProtocolContextCall(InitProtocolContextParams),
     GenesisResultDataCall(GenesisResultDataParams),
+    CommitTestChainGenesisCall(CommitTestChainGenesisRequest),
     ShutdownCall,
 }
 
@@ -91,6 +92,7 @@ enum NodeMessage {
     ChangeRuntimeConfigurationResult(Result<(), TezosRuntimeConfigurationError>),
     InitProtocolContextResult(Result<InitProtocolContextResult, TezosStorageInitError>),
     CommitGenesisResultData(Result<CommitGenesisResult, GetDataError>),
+    CommitTestChainGenesisResult(Result<CommitTestChainGenesisResult, TestChainGenesisError>),
     ComputePathResponse(Result<ComputePathResponse, ComputePathError>),
     ShutdownResult,
 }
@@ -182,6 +184,10 @@ pub fn process_protocol_commands<Proto: ProtocolApi, P: AsRef<Path>>(
                 );
                 tx.send(&NodeMessage::CommitGenesisResultData(res))?;
             }
+            ProtocolMessage::CommitTestChainGenesisCall(request) => {
+                let res = Proto::commit_test_chain_genesis(request);
+                tx.send(&NodeMessage::CommitTestChainGenesisResult(res))?;
+            }
             ProtocolMessage::ShutdownCall => {
                 context_send(ContextAction::Shutdown)
                     .expect("Failed to send shutdown command to context channel");
@@ -225,6 +231,9 @@ pub enum ProtocolError {
     /// OCaml part failed to get genesis data.
     #[fail(display = "Failed to get genesis data: {}", reason)]
     GenesisResultDataError { reason: GetDataError },
+    /// OCaml part failed to commit test chain genesis.
+    #[fail(display = "Failed to commit test chain genesis: {}", reason)]
+    CommitTestChainGenesisError { reason: TestChainGenesisError },
 }
 
 /// Errors generated by `protocol_runner`.
@@ -788,6 +797,11 @@ impl ProtocolController {
         )
     }
 
+    /// Returns true, if protocol runner was configured with enabled test chain.
+    pub fn is_testchain_enabled(&self) -> bool {
+        self.configuration.enable_testchain()
+    }
+
     /// Gets data for genesis.
     pub fn genesis_result_data(
         &self,
@@ -825,6 +839,23 @@ impl ProtocolController {
             }),
         }
     }
+
+    /// Commits genesis of the test chain forked by the block.
+    pub fn commit_test_chain_genesis(
+        &self,
+        request: CommitTestChainGenesisRequest,
+    ) -> Result<CommitTestChainGenesisResult, ProtocolServiceError> {
+        let mut io = self.io.borrow_mut();
+        io.tx.send(&ProtocolMessage::CommitTestChainGenesisCall(request))?;
+        match io.rx.receive()? {
+            NodeMessage::CommitTestChainGenesisResult(result) => {
+                result.map_err(|err| ProtocolError::CommitTestChainGenesisError { reason: err }.into())
+            }
+            message => Err(ProtocolServiceError::UnexpectedMessage {
+                message: message.into(),
+            }),
+        }
+    }
 }
 
 impl Drop for ProtocolController {